@@ -0,0 +1,131 @@
+//! Both macros replace the async function with a zero-argument function of the same
+//! name that builds the service, moving the original body into a nested function so
+//! extractor arguments work exactly as they do with `handler_fn`.
+
+#![warn(missing_docs)]
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    Ident, ItemFn, LitStr, Token,
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+};
+
+/// Turns `async fn name(extractors..) -> impl IntoResponse` into `fn name() -> DynService`.
+#[proc_macro_attribute]
+pub fn handler(args: TokenStream, item: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        let args = TokenStream2::from(args);
+        return syn::Error::new_spanned(args, "`#[handler]` takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let item = parse_macro_input!(item as ItemFn);
+    expand(
+        item,
+        |name| {
+            quote! {
+                ::libserver::__private::BoxCloneSyncService::new(::libserver::handler_fn(#name))
+            }
+        },
+        quote!(::libserver::DynService),
+    )
+    .unwrap_or_else(syn::Error::into_compile_error)
+    .into()
+}
+
+struct RouteArgs {
+    methods: Vec<Ident>,
+    path: LitStr,
+}
+
+impl Parse for RouteArgs {
+    fn parse(input: ParseStream) -> syn::Result<RouteArgs> {
+        let mut methods = vec![];
+        while input.peek(Ident) {
+            methods.push(input.parse()?);
+            input.parse::<Token![,]>()?;
+        }
+        let path = input.parse()?;
+        input.parse::<Option<Token![,]>>()?;
+        if methods.is_empty() {
+            return Err(input.error("expected at least one method, e.g. `#[route(GET, \"/\")]`"));
+        }
+        Ok(RouteArgs { methods, path })
+    }
+}
+
+/// Turns a handler into `fn name() -> DynRoute` answering the listed methods at a path, as in `#[route(GET, POST, "/items/:id")]`.
+#[proc_macro_attribute]
+pub fn route(args: TokenStream, item: TokenStream) -> TokenStream {
+    let RouteArgs { methods, path } = parse_macro_input!(args as RouteArgs);
+    let item = parse_macro_input!(item as ItemFn);
+    for method in &methods {
+        let known = [
+            "GET", "POST", "PUT", "DELETE", "PATCH", "HEAD", "OPTIONS", "CONNECT", "TRACE",
+        ];
+        if !known.contains(&method.to_string().as_str()) {
+            return syn::Error::new(method.span(), "unknown HTTP method")
+                .to_compile_error()
+                .into();
+        }
+    }
+    expand(
+        item,
+        |name| {
+            quote! {
+                ::libserver::Route::from_parts(
+                    ::libserver::PathRouter::new(#path),
+                    ::libserver::handler_fn(#name),
+                )
+                .with_methods([#(::libserver::__private::Method::#methods),*])
+                .make_dyn()
+            }
+        },
+        quote!(::libserver::DynRoute),
+    )
+    .unwrap_or_else(syn::Error::into_compile_error)
+    .into()
+}
+
+fn expand(
+    item: ItemFn,
+    build: impl FnOnce(&Ident) -> TokenStream2,
+    output: TokenStream2,
+) -> syn::Result<TokenStream2> {
+    if item.sig.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(
+            item.sig.fn_token,
+            "handlers must be `async fn`",
+        ));
+    }
+    if !item.sig.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &item.sig.generics,
+            "handlers cannot be generic",
+        ));
+    }
+    if let Some(receiver) = item.sig.receiver() {
+        return Err(syn::Error::new_spanned(
+            receiver,
+            "handlers cannot take `self`",
+        ));
+    }
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = item;
+    let name = &sig.ident;
+    let body = build(name);
+    Ok(quote! {
+        #(#attrs)*
+        #vis fn #name() -> #output {
+            #sig #block
+            #body
+        }
+    })
+}
@@ -0,0 +1,427 @@
+use std::{
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    net::IpAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+
+use futures::{Stream, StreamExt};
+use hyper::{
+    Method, StatusCode, Uri, Version,
+    header::{self, HeaderMap},
+};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    BodyInner, BoxedBodyStream, ConnectionInfo, Request, ServiceBoxFuture, ServiceError,
+    ServiceResponse, date::DateTime, json, make_body_from_stream, trace,
+};
+
+/// One logged request, passed to the formatter once its response body has finished.
+#[derive(Clone, Debug)]
+pub struct AccessLogEntry {
+    /// The request method.
+    pub method: Method,
+    /// The request URI as received.
+    pub uri: Uri,
+    /// The HTTP version of the request.
+    pub version: Version,
+    /// The response status.
+    pub status: StatusCode,
+    /// The client address, when the connection has one.
+    pub remote_addr: Option<IpAddr>,
+    /// The `User-Agent` request header.
+    pub user_agent: Option<String>,
+    /// The `Referer` request header.
+    pub referer: Option<String>,
+    /// The request body size, taken from `Content-Length`.
+    pub bytes_in: u64,
+    /// The response body bytes actually sent.
+    pub bytes_out: u64,
+    /// When the request arrived.
+    pub time: SystemTime,
+    /// Time from the request arriving to the response body finishing.
+    pub latency: Duration,
+}
+
+impl AccessLogEntry {
+    /// The entry in Common Log Format.
+    pub fn common(&self) -> String {
+        let remote = self
+            .remote_addr
+            .map_or_else(|| "-".to_string(), |ip| ip.to_string());
+        let bytes = match self.bytes_out {
+            0 => "-".to_string(),
+            n => n.to_string(),
+        };
+        format!(
+            "{remote} - - [{}] \"{} {} {:?}\" {} {bytes}",
+            DateTime::from_system(self.time).clf(),
+            self.method,
+            self.uri,
+            self.version,
+            self.status.as_u16(),
+        )
+    }
+
+    /// The entry in Combined Log Format: Common plus referer and user agent.
+    pub fn combined(&self) -> String {
+        format!(
+            "{} \"{}\" \"{}\"",
+            self.common(),
+            self.referer.as_deref().unwrap_or("-"),
+            self.user_agent.as_deref().unwrap_or("-"),
+        )
+    }
+
+    /// The entry as a single-line JSON object.
+    pub fn json(&self) -> String {
+        let mut out = String::from("{");
+        let mut field = |name: &str, value: Option<&str>, quoted: bool| {
+            if out.len() > 1 {
+                out.push(',');
+            }
+            let _ = write!(out, "\"{name}\":");
+            match (value, quoted) {
+                (None, _) => out.push_str("null"),
+                (Some(v), true) => json::push_string(&mut out, v),
+                (Some(v), false) => out.push_str(v),
+            }
+        };
+        field(
+            "time",
+            Some(&DateTime::from_system(self.time).rfc3339()),
+            true,
+        );
+        field(
+            "remote_addr",
+            self.remote_addr.map(|ip| ip.to_string()).as_deref(),
+            true,
+        );
+        field("method", Some(self.method.as_str()), true);
+        field("uri", Some(&self.uri.to_string()), true);
+        field("version", Some(&format!("{:?}", self.version)), true);
+        field("status", Some(&self.status.as_u16().to_string()), false);
+        field("bytes_in", Some(&self.bytes_in.to_string()), false);
+        field("bytes_out", Some(&self.bytes_out.to_string()), false);
+        field(
+            "latency_ms",
+            Some(&format!("{:.3}", self.latency.as_secs_f64() * 1000.0)),
+            false,
+        );
+        field("user_agent", self.user_agent.as_deref(), true);
+        field("referer", self.referer.as_deref(), true);
+        out.push('}');
+        out
+    }
+}
+
+type Formatter = Arc<dyn Fn(&AccessLogEntry) -> String + Send + Sync>;
+
+/// How each entry is turned into a line.
+#[derive(Clone)]
+pub enum LogFormat {
+    /// Common Log Format.
+    Common,
+    /// Combined Log Format.
+    Combined,
+    /// One JSON object per line.
+    JsonLines,
+    /// A caller-supplied formatter.
+    Custom(Formatter),
+}
+
+impl LogFormat {
+    /// A format backed by `f`.
+    pub fn custom(f: impl Fn(&AccessLogEntry) -> String + Send + Sync + 'static) -> LogFormat {
+        LogFormat::Custom(Arc::new(f))
+    }
+
+    fn format(&self, entry: &AccessLogEntry) -> String {
+        match self {
+            LogFormat::Common => entry.common(),
+            LogFormat::Combined => entry.combined(),
+            LogFormat::JsonLines => entry.json(),
+            LogFormat::Custom(f) => f(entry),
+        }
+    }
+}
+
+/// Where formatted entries go. Closures taking `&str` are sinks.
+pub trait LogSink: Send + Sync + 'static {
+    /// Writes one formatted entry; the sink adds the line terminator.
+    fn write_line(&self, line: &str);
+}
+
+impl<F: Fn(&str) + Send + Sync + 'static> LogSink for F {
+    fn write_line(&self, line: &str) {
+        self(line)
+    }
+}
+
+/// Prints each entry to standard output.
+pub struct Stdout;
+
+impl LogSink for Stdout {
+    fn write_line(&self, line: &str) {
+        println!("{line}");
+    }
+}
+
+/// Writes each entry to an `io::Write`, such as a socket or buffer.
+pub struct WriterSink<W>(Mutex<W>);
+
+impl<W: Write + Send + 'static> WriterSink<W> {
+    /// A sink writing to `writer`.
+    pub fn new(writer: W) -> WriterSink<W> {
+        WriterSink(Mutex::new(writer))
+    }
+}
+
+impl<W: Write + Send + 'static> LogSink for WriterSink<W> {
+    fn write_line(&self, line: &str) {
+        let mut writer = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writeln!(writer, "{line}") {
+            trace::warn("writing access log failed", &e);
+        }
+    }
+}
+
+/// Appends to `path`, rotating to `path.1`, `path.2`, ... once the file grows past the limit.
+pub struct FileSink {
+    path: PathBuf,
+    max_size: Option<u64>,
+    max_files: usize,
+    state: Mutex<(File, u64)>,
+}
+
+impl FileSink {
+    /// Opens `path` for appending, creating it if needed. Rotation is off until `with_rotation`.
+    pub fn new(path: impl Into<PathBuf>) -> io::Result<FileSink> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let len = file.metadata()?.len();
+        Ok(FileSink {
+            path,
+            max_size: None,
+            max_files: 5,
+            state: Mutex::new((file, len)),
+        })
+    }
+
+    /// Rotates once the file would exceed `max_size` bytes, keeping at most `max_files` old files.
+    pub fn with_rotation(mut self, max_size: u64, max_files: usize) -> FileSink {
+        self.max_size = Some(max_size);
+        self.max_files = max_files;
+        self
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        name.into()
+    }
+
+    fn rotate(&self, state: &mut (File, u64)) -> io::Result<()> {
+        for n in (1..self.max_files).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                std::fs::rename(&from, self.rotated(n + 1))?;
+            }
+        }
+        match self.max_files {
+            0 => std::fs::remove_file(&self.path)?,
+            _ => std::fs::rename(&self.path, self.rotated(1))?,
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        *state = (file, 0);
+        Ok(())
+    }
+}
+
+impl LogSink for FileSink {
+    fn write_line(&self, line: &str) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let res = (|| {
+            if self
+                .max_size
+                .is_some_and(|max| state.1 + line.len() as u64 + 1 > max && state.1 > 0)
+            {
+                self.rotate(&mut state)?;
+            }
+            writeln!(state.0, "{line}")?;
+            state.1 += line.len() as u64 + 1;
+            io::Result::Ok(())
+        })();
+        if let Err(e) = res {
+            trace::warn("writing access log failed", &e);
+        }
+    }
+}
+
+/// Logs every request and response through a format and a sink.
+#[derive(Clone)]
+pub struct AccessLogLayer {
+    format: LogFormat,
+    sink: Arc<dyn LogSink>,
+}
+
+impl Default for AccessLogLayer {
+    fn default() -> AccessLogLayer {
+        AccessLogLayer::new()
+    }
+}
+
+impl AccessLogLayer {
+    /// Common Log Format to standard output.
+    pub fn new() -> AccessLogLayer {
+        AccessLogLayer {
+            format: LogFormat::Common,
+            sink: Arc::new(Stdout),
+        }
+    }
+
+    /// Sets the line format.
+    pub fn with_format(mut self, format: LogFormat) -> AccessLogLayer {
+        self.format = format;
+        self
+    }
+
+    /// Sets where lines are written.
+    pub fn with_sink(mut self, sink: impl LogSink) -> AccessLogLayer {
+        self.sink = Arc::new(sink);
+        self
+    }
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLog<S>;
+
+    fn layer(&self, inner: S) -> AccessLog<S> {
+        AccessLog {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// The service produced by `AccessLogLayer`.
+#[derive(Clone)]
+pub struct AccessLog<S> {
+    inner: S,
+    config: AccessLogLayer,
+}
+
+impl<S> TowerService<Request> for AccessLog<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let headers = req.headers();
+        let mut entry = AccessLogEntry {
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            version: req.version(),
+            status: StatusCode::OK,
+            remote_addr: req
+                .extensions()
+                .get::<ConnectionInfo>()
+                .and_then(ConnectionInfo::client_ip),
+            user_agent: header_string(headers, header::USER_AGENT),
+            referer: header_string(headers, header::REFERER),
+            bytes_in: header_string(headers, header::CONTENT_LENGTH)
+                .and_then(|len| len.parse().ok())
+                .unwrap_or(0),
+            bytes_out: 0,
+            time: SystemTime::now(),
+            latency: Duration::ZERO,
+        };
+        let start = Instant::now();
+        let fut = self.inner.call(req);
+        let AccessLogLayer { format, sink } = self.config.clone();
+
+        Box::pin(async move {
+            let resp = match fut.await {
+                Ok(resp) => resp,
+                Err(e) => {
+                    entry.status = StatusCode::INTERNAL_SERVER_ERROR;
+                    entry.latency = start.elapsed();
+                    sink.write_line(&format.format(&entry));
+                    return Err(e);
+                }
+            };
+            entry.status = resp.status();
+            let (parts, body) = resp.into_parts();
+            let body = LoggedBody {
+                inner: Box::new(body),
+                log: Some((entry, start, format, sink)),
+            };
+            Ok(ServiceResponse::from_parts(
+                parts,
+                make_body_from_stream(body),
+            ))
+        })
+    }
+}
+
+fn header_string(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Writes the entry once the body has been fully sent, or dropped by a departing client.
+struct LoggedBody {
+    inner: BoxedBodyStream,
+    log: Option<(AccessLogEntry, Instant, LogFormat, Arc<dyn LogSink>)>,
+}
+
+impl Stream for LoggedBody {
+    type Item = BodyInner;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<BodyInner>> {
+        let item = futures::ready!(self.inner.poll_next_unpin(cx));
+        match &item {
+            Some(Ok(frame)) => {
+                if let (Some(data), Some((entry, ..))) = (frame.data_ref(), &mut self.log) {
+                    entry.bytes_out += data.len() as u64;
+                }
+            }
+            Some(Err(_)) | None => self.finish(),
+        }
+        Poll::Ready(item)
+    }
+}
+
+impl LoggedBody {
+    fn finish(&mut self) {
+        if let Some((mut entry, start, format, sink)) = self.log.take() {
+            entry.latency = start.elapsed();
+            sink.write_line(&format.format(&entry));
+        }
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
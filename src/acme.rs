@@ -0,0 +1,262 @@
+//! The server side of ACME's HTTP-01 challenge (RFC 8555, section 8.3). An ACME client
+//! orders a certificate, puts each challenge's key authorization in `AcmeChallenges`,
+//! and tells the CA to validate; `AcmeLayer` answers the CA's requests for
+//! `/.well-known/acme-challenge/{token}` on port 80.
+//!
+//! Talking to the CA and TLS-ALPN-01 need an HTTPS client and a TLS stack, which this
+//! crate doesn't have; the TLS acceptor is the caller's, and `ReloadableAcceptor`
+//! installs renewed certificates without a restart.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use hyper::{
+    Method, StatusCode,
+    header::{self, HeaderValue},
+};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    Request, ResponseExt, ServiceBoxFuture, ServiceError, ServiceResponse, base64, json,
+    sha256::sha256,
+};
+
+/// Where CAs fetch HTTP-01 key authorizations, followed by the token.
+pub const ACME_CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+
+/// The public half of an ACME account key, with its JWK members base64url-encoded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AccountKey {
+    /// An elliptic curve key.
+    Ec {
+        /// The curve, e.g. `P-256`.
+        crv: String,
+        /// The x coordinate.
+        x: String,
+        /// The y coordinate.
+        y: String,
+    },
+    /// An RSA key.
+    Rsa {
+        /// The modulus.
+        n: String,
+        /// The public exponent.
+        e: String,
+    },
+}
+
+impl AccountKey {
+    /// The JWK thumbprint of RFC 7638: the SHA-256 of the required members, sorted
+    /// and without whitespace.
+    pub fn thumbprint(&self) -> String {
+        let members = match self {
+            AccountKey::Ec { crv, x, y } => {
+                vec![("crv", crv.as_str()), ("kty", "EC"), ("x", x), ("y", y)]
+            }
+            AccountKey::Rsa { n, e } => vec![("e", e.as_str()), ("kty", "RSA"), ("n", n)],
+        };
+        let mut jwk = String::from("{");
+        for (i, (name, value)) in members.into_iter().enumerate() {
+            if i > 0 {
+                jwk.push(',');
+            }
+            json::push_string(&mut jwk, name);
+            jwk.push(':');
+            json::push_string(&mut jwk, value);
+        }
+        jwk.push('}');
+        base64::encode_url(&sha256(jwk.as_bytes()))
+    }
+
+    /// The key authorization a challenge with `token` is answered with.
+    pub fn key_authorization(&self, token: &str) -> String {
+        format!("{token}.{}", self.thumbprint())
+    }
+}
+
+/// The HTTP-01 challenges being validated; clones share them.
+#[derive(Clone, Default)]
+pub struct AcmeChallenges {
+    pending: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl AcmeChallenges {
+    /// No pending challenges.
+    pub fn new() -> AcmeChallenges {
+        AcmeChallenges::default()
+    }
+
+    /// Answers requests for `token` with `key_authorization` until it is removed.
+    pub fn insert(&self, token: &str, key_authorization: String) {
+        self.lock().insert(token.to_string(), key_authorization);
+    }
+
+    /// Answers the challenge for `token` on behalf of `key`.
+    pub fn insert_for(&self, key: &AccountKey, token: &str) {
+        self.insert(token, key.key_authorization(token));
+    }
+
+    /// Stops answering `token`, once the CA has validated it or the order failed.
+    pub fn remove(&self, token: &str) -> Option<String> {
+        self.lock().remove(token)
+    }
+
+    fn get(&self, token: &str) -> Option<String> {
+        self.lock().get(token).cloned()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, String>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Answers GET and HEAD requests under `ACME_CHALLENGE_PATH` from `AcmeChallenges`,
+/// with 404 for unknown tokens, and passes everything else on. `HttpsRedirectLayer`
+/// leaves these paths alone by default, so the CA can still reach them over HTTP.
+#[derive(Clone)]
+pub struct AcmeLayer {
+    challenges: AcmeChallenges,
+}
+
+impl AcmeLayer {
+    /// Serves `challenges`.
+    pub fn new(challenges: AcmeChallenges) -> AcmeLayer {
+        AcmeLayer { challenges }
+    }
+
+    fn respond(&self, req: &Request) -> Option<ServiceResponse> {
+        if !matches!(*req.method(), Method::GET | Method::HEAD) {
+            return None;
+        }
+        let token = req.uri().path().strip_prefix(ACME_CHALLENGE_PATH)?;
+        Some(match self.challenges.get(token) {
+            Some(key_authorization) => ServiceResponse::from_string(key_authorization).with_header(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/octet-stream"),
+            ),
+            None => ServiceResponse::from_status(StatusCode::NOT_FOUND),
+        })
+    }
+}
+
+impl<S> Layer<S> for AcmeLayer {
+    type Service = Acme<S>;
+
+    fn layer(&self, inner: S) -> Acme<S> {
+        Acme {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// The service produced by `AcmeLayer`.
+#[derive(Clone)]
+pub struct Acme<S> {
+    inner: S,
+    config: AcmeLayer,
+}
+
+impl<S> TowerService<Request> for Acme<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        match self.config.respond(&req) {
+            Some(resp) => Box::pin(async { Ok(resp) }),
+            None => Box::pin(self.inner.call(req)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{service_fn, testing::TestRequestExt};
+
+    /// The example key of RFC 7638, section 3.1.
+    fn rsa_key() -> AccountKey {
+        AccountKey::Rsa {
+            n: "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw".to_string(),
+            e: "AQAB".to_string(),
+        }
+    }
+
+    #[test]
+    fn thumbprints() {
+        assert_eq!(
+            rsa_key().thumbprint(),
+            "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+        let ec = AccountKey::Ec {
+            crv: "P-256".to_string(),
+            x: "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU".to_string(),
+            y: "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0".to_string(),
+        };
+        assert_eq!(
+            ec.key_authorization("tok"),
+            "tok.oKIywvGUpTVTyxMQ3bwIIeQUudfr_CkLMjCE19ECD-U"
+        );
+    }
+
+    async fn send(challenges: &AcmeChallenges, method: Method, path: &str) -> ServiceResponse {
+        let service = AcmeLayer::new(challenges.clone()).layer(service_fn(|_req: Request| async {
+            Ok::<_, ServiceError>(ServiceResponse::from_string("app"))
+        }));
+        service.oneshot(Request::test(method, path)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn answers_pending_challenges() {
+        let challenges = AcmeChallenges::new();
+        challenges.insert_for(&rsa_key(), "abc_-1");
+        let path = format!("{ACME_CHALLENGE_PATH}abc_-1");
+
+        let resp = send(&challenges, Method::GET, &path).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()[header::CONTENT_TYPE],
+            "application/octet-stream"
+        );
+        assert_eq!(
+            resp.into_bytes().await.unwrap(),
+            "abc_-1.NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+        assert_eq!(
+            send(&challenges, Method::HEAD, &path).await.status(),
+            StatusCode::OK
+        );
+
+        let unknown = format!("{ACME_CHALLENGE_PATH}other");
+        assert_eq!(
+            send(&challenges, Method::GET, &unknown).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        let resp = send(&challenges, Method::POST, &path).await;
+        assert_eq!(resp.into_bytes().await.unwrap(), "app");
+        let resp = send(&challenges, Method::GET, "/").await;
+        assert_eq!(resp.into_bytes().await.unwrap(), "app");
+
+        assert!(challenges.remove("abc_-1").is_some());
+        assert_eq!(
+            send(&challenges, Method::GET, &path).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -0,0 +1,411 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use hyper::{StatusCode, header::HeaderName, http::request::Parts};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    Error, FromRequestParts, Request, RequestExt, ResponseExt, ServiceBoxFuture, ServiceError,
+    ServiceResponse, StoreFuture, sha256::sha256,
+};
+
+/// The header `ApiKeyLayer` reads keys from by default.
+pub const X_API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+
+/// What a key grants, attached to requests that presented it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApiKeyInfo {
+    /// Who the key belongs to.
+    pub owner: String,
+    /// What the key may be used for.
+    pub scopes: Vec<String>,
+}
+
+impl ApiKeyInfo {
+    /// A key for `owner` with no scopes.
+    pub fn new(owner: impl Into<String>) -> ApiKeyInfo {
+        ApiKeyInfo {
+            owner: owner.into(),
+            scopes: vec![],
+        }
+    }
+
+    /// Adds a scope.
+    pub fn with_scope(mut self, scope: impl Into<String>) -> ApiKeyInfo {
+        self.scopes.push(scope.into());
+        self
+    }
+
+    /// Whether the key has `scope`.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    /// The key info `ApiKeyLayer` attached to `req`.
+    pub fn from_request(req: &Request) -> Option<&ApiKeyInfo> {
+        req.extensions().get()
+    }
+}
+
+impl FromRequestParts for ApiKeyInfo {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<ApiKeyInfo, Error> {
+        parts
+            .extensions
+            .get()
+            .cloned()
+            .ok_or(Error::MissingExtension("ApiKeyInfo"))
+    }
+}
+
+/// Looks up the keys clients present.
+pub trait KeyStore: Send + Sync + 'static {
+    /// The info for `key`, or `None` if it is unknown.
+    fn lookup(&self, key: &str) -> StoreFuture<Option<ApiKeyInfo>>;
+}
+
+/// Keys are held as SHA-256 digests, so the stores never keep them in memory.
+type KeyDigests = HashMap<[u8; 32], ApiKeyInfo>;
+type LoadedKeys = Option<(SystemTime, Arc<KeyDigests>)>;
+
+/// Keys held in process memory; clones share the same keys.
+#[derive(Clone, Default)]
+pub struct MemoryKeyStore {
+    keys: Arc<Mutex<KeyDigests>>,
+}
+
+impl MemoryKeyStore {
+    /// An empty store.
+    pub fn new() -> MemoryKeyStore {
+        MemoryKeyStore::default()
+    }
+
+    /// Adds `key`, consuming and returning the store.
+    pub fn with_key(self, key: &str, info: ApiKeyInfo) -> MemoryKeyStore {
+        self.insert(key, info);
+        self
+    }
+
+    /// Adds or replaces `key`.
+    pub fn insert(&self, key: &str, info: ApiKeyInfo) {
+        self.keys
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(sha256(key.as_bytes()), info);
+    }
+
+    /// Removes `key`, returning its info.
+    pub fn remove(&self, key: &str) -> Option<ApiKeyInfo> {
+        self.keys
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&sha256(key.as_bytes()))
+    }
+}
+
+impl KeyStore for MemoryKeyStore {
+    fn lookup(&self, key: &str) -> StoreFuture<Option<ApiKeyInfo>> {
+        let info = self
+            .keys
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&sha256(key.as_bytes()))
+            .cloned();
+        Box::pin(async move { Ok(info) })
+    }
+}
+
+/// Reads keys from a file with one `key owner [scope,scope...]` line per key; blank
+/// lines and lines starting with `#` are skipped. The file is reread whenever its
+/// modification time changes, so keys can be rotated without a restart.
+#[derive(Clone)]
+pub struct FileKeyStore {
+    path: Arc<PathBuf>,
+    cache: Arc<Mutex<LoadedKeys>>,
+}
+
+impl FileKeyStore {
+    /// A store backed by the file at `path`, read on first lookup.
+    pub fn new(path: impl Into<PathBuf>) -> FileKeyStore {
+        FileKeyStore {
+            path: Arc::new(path.into()),
+            cache: Arc::default(),
+        }
+    }
+
+    async fn keys(&self) -> io::Result<Arc<KeyDigests>> {
+        let modified = tokio::fs::metadata(&*self.path).await?.modified()?;
+        if let Some((at, keys)) = &*self.cache.lock().unwrap_or_else(|e| e.into_inner())
+            && *at == modified
+        {
+            return Ok(keys.clone());
+        }
+        let contents = tokio::fs::read_to_string(&*self.path).await?;
+        let keys: KeyDigests = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let key = fields.next()?;
+                let mut info = ApiKeyInfo::new(fields.next()?);
+                if let Some(scopes) = fields.next() {
+                    info.scopes = scopes
+                        .split(',')
+                        .filter(|s| !s.is_empty())
+                        .map(str::to_string)
+                        .collect();
+                }
+                Some((sha256(key.as_bytes()), info))
+            })
+            .collect();
+        let keys = Arc::new(keys);
+        *self.cache.lock().unwrap_or_else(|e| e.into_inner()) = Some((modified, keys.clone()));
+        Ok(keys)
+    }
+}
+
+impl KeyStore for FileKeyStore {
+    fn lookup(&self, key: &str) -> StoreFuture<Option<ApiKeyInfo>> {
+        let digest = sha256(key.as_bytes());
+        let store = self.clone();
+        Box::pin(async move {
+            let keys = store.keys().await?;
+            Ok(keys.get(&digest).cloned())
+        })
+    }
+}
+
+/// Looks for a key in the `X-API-Key` header, or another header or query parameter,
+/// and answers 401 when there is none or the store doesn't know it. Known keys'
+/// `ApiKeyInfo` is added to the request extensions.
+#[derive(Clone)]
+pub struct ApiKeyLayer {
+    store: Arc<dyn KeyStore>,
+    header: Option<HeaderName>,
+    query: Option<Arc<str>>,
+}
+
+impl ApiKeyLayer {
+    /// Checks keys against `store`, read from the `X-API-Key` header.
+    pub fn new(store: impl KeyStore) -> ApiKeyLayer {
+        ApiKeyLayer {
+            store: Arc::new(store),
+            header: Some(X_API_KEY),
+            query: None,
+        }
+    }
+
+    /// Reads the key from `header` instead.
+    pub fn with_header(mut self, header: HeaderName) -> ApiKeyLayer {
+        self.header = Some(header);
+        self
+    }
+
+    /// Also accept the key as this query parameter; the header wins when both are set.
+    pub fn with_query(mut self, name: &str) -> ApiKeyLayer {
+        self.query = Some(Arc::from(name));
+        self
+    }
+
+    /// Only accept the key as a query parameter.
+    pub fn with_query_only(mut self, name: &str) -> ApiKeyLayer {
+        self.header = None;
+        self.with_query(name)
+    }
+
+    fn key(&self, req: &Request) -> Option<String> {
+        let header = self
+            .header
+            .as_ref()
+            .and_then(|name| req.headers().get(name))
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string);
+        header.or_else(|| {
+            let name = self.query.as_deref()?;
+            req.query_pairs()
+                .find(|(k, v)| k == name && !v.is_empty())
+                .map(|(_, v)| v.into_owned())
+        })
+    }
+}
+
+impl<S> Layer<S> for ApiKeyLayer {
+    type Service = ApiKey<S>;
+
+    fn layer(&self, inner: S) -> ApiKey<S> {
+        ApiKey {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// The service produced by `ApiKeyLayer`.
+#[derive(Clone)]
+pub struct ApiKey<S> {
+    inner: S,
+    config: ApiKeyLayer,
+}
+
+impl<S> TowerService<Request> for ApiKey<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        let Some(key) = self.config.key(&req) else {
+            return Box::pin(async { Ok(ServiceResponse::from_status(StatusCode::UNAUTHORIZED)) });
+        };
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let store = self.config.store.clone();
+        Box::pin(async move {
+            let Some(info) = store.lookup(&key).await? else {
+                return Ok(ServiceResponse::from_status(StatusCode::UNAUTHORIZED));
+            };
+            req.extensions_mut().insert(info);
+            inner.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{
+        Method,
+        header::{self, HeaderValue},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{random, service_fn, testing::TestRequestExt};
+
+    const KEY: &str = "k3y-0123456789";
+
+    fn store() -> MemoryKeyStore {
+        MemoryKeyStore::new().with_key(KEY, ApiKeyInfo::new("alice").with_scope("read"))
+    }
+
+    async fn send(layer: &ApiKeyLayer, path: &str, key: Option<&str>) -> ServiceResponse {
+        let service = layer.layer(service_fn(|req: Request| async move {
+            let info = ApiKeyInfo::from_request(&req).unwrap();
+            Ok::<_, ServiceError>(ServiceResponse::from_string(info.owner.clone()))
+        }));
+        let mut req = Request::test(Method::GET, path);
+        if let Some(key) = key {
+            req.headers_mut()
+                .insert(X_API_KEY, HeaderValue::from_str(key).unwrap());
+        }
+        service.oneshot(req).await.unwrap()
+    }
+
+    async fn assert_unauthorized(resp: ServiceResponse) {
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(!resp.headers().contains_key(header::WWW_AUTHENTICATE));
+        assert_eq!(resp.into_bytes().await.unwrap(), "401 Unauthorized");
+    }
+
+    #[tokio::test]
+    async fn header_keys() {
+        let layer = ApiKeyLayer::new(store());
+        let resp = send(&layer, "/", Some(KEY)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.into_bytes().await.unwrap(), "alice");
+        assert_eq!(
+            send(&layer, "/", Some(&format!(" {KEY} "))).await.status(),
+            StatusCode::OK
+        );
+        // Not read from the query unless asked.
+        assert_unauthorized(send(&layer, &format!("/?api_key={KEY}"), None).await).await;
+    }
+
+    #[tokio::test]
+    async fn near_misses_are_rejected() {
+        let layer = ApiKeyLayer::new(store());
+        let prefix = &KEY[..KEY.len() - 1];
+        let upper = KEY.to_uppercase();
+        for key in ["", "   ", prefix, &format!("{KEY}x"), &upper, "other"] {
+            assert_unauthorized(send(&layer, "/", Some(key)).await).await;
+        }
+        assert_unauthorized(send(&layer, "/", None).await).await;
+    }
+
+    #[tokio::test]
+    async fn query_keys() {
+        let layer = ApiKeyLayer::new(store()).with_query("api_key");
+        let query = format!("/?api_key={KEY}");
+        assert_eq!(send(&layer, &query, None).await.status(), StatusCode::OK);
+        // The header wins when both are present, and an empty one falls through.
+        assert_unauthorized(send(&layer, &query, Some("wrong")).await).await;
+        assert_eq!(
+            send(&layer, &query, Some("")).await.status(),
+            StatusCode::OK
+        );
+        assert_unauthorized(send(&layer, "/?api_key=", None).await).await;
+
+        let layer = ApiKeyLayer::new(store()).with_query_only("api_key");
+        assert_unauthorized(send(&layer, "/", Some(KEY)).await).await;
+        assert_eq!(
+            send(&layer, &query, Some("wrong")).await.status(),
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn stores_hold_digests_only() {
+        // Lookups hash the presented key first, so timing can't reveal how much of a
+        // guess matched a real key.
+        let store = store();
+        let keys = store.keys.lock().unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[&sha256(KEY.as_bytes())].owner, "alice");
+    }
+
+    #[tokio::test]
+    async fn file_store_reloads() {
+        let path = std::env::temp_dir().join(format!("libserver-keys-{}", random::hex_token(8)));
+        std::fs::write(&path, "# comment\n\nk1 alice read,write\nk2 bob\nbroken\n").unwrap();
+        let store = FileKeyStore::new(&path);
+        let alice = store.lookup("k1").await.unwrap().unwrap();
+        assert_eq!(alice.owner, "alice");
+        assert!(alice.has_scope("write"));
+        assert!(store.lookup("k2").await.unwrap().unwrap().scopes.is_empty());
+        assert_eq!(store.lookup("broken").await.unwrap(), None);
+
+        std::fs::write(&path, "k3 carol\n").unwrap();
+        // Make sure the change is visible even on coarse mtime clocks.
+        let later = SystemTime::now() + std::time::Duration::from_secs(5);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert_eq!(store.lookup("k1").await.unwrap(), None);
+        assert_eq!(store.lookup("k3").await.unwrap().unwrap().owner, "carol");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,317 @@
+use std::sync::Arc;
+
+use hyper::{
+    StatusCode,
+    header::{self, HeaderValue},
+    http::request::Parts,
+};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    Error, FromRequestParts, Request, RequestExt, ResponseExt, ServiceBoxFuture, ServiceError,
+    ServiceResponse,
+    sha256::{constant_time_eq, sha256},
+};
+
+type BasicVerifier<P> = Arc<dyn Fn(&str, &str) -> Option<P> + Send + Sync>;
+type BearerValidator<P> = Arc<dyn Fn(&str) -> Option<P> + Send + Sync>;
+
+/// Whoever the credentials belonged to, as returned by the verifier.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Principal<P>(pub P);
+
+impl<P: Send + Sync + 'static> Principal<P> {
+    /// The principal `AuthLayer` attached to `req`.
+    pub fn from_request(req: &Request) -> Option<&Principal<P>> {
+        req.extensions().get()
+    }
+}
+
+impl<P: Clone + Send + Sync + 'static> FromRequestParts for Principal<P> {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Principal<P>, Error> {
+        parts
+            .extensions
+            .get()
+            .cloned()
+            .ok_or(Error::MissingExtension("Principal"))
+    }
+}
+
+/// Compares secrets without leaking where they differ, or their lengths.
+pub fn secure_eq(a: &[u8], b: &[u8]) -> bool {
+    constant_time_eq(&sha256(a), &sha256(b))
+}
+
+enum Scheme<P> {
+    Basic(BasicVerifier<P>),
+    Bearer(BearerValidator<P>),
+}
+
+impl<P> Clone for Scheme<P> {
+    fn clone(&self) -> Scheme<P> {
+        match self {
+            Scheme::Basic(verify) => Scheme::Basic(verify.clone()),
+            Scheme::Bearer(validate) => Scheme::Bearer(validate.clone()),
+        }
+    }
+}
+
+/// Requires an `Authorization` header and answers 401 with a `WWW-Authenticate`
+/// challenge when it is missing or rejected by the callback. On success the callback's
+/// value is stored as a `Principal<P>` extension.
+pub struct AuthLayer<P> {
+    scheme: Scheme<P>,
+    realm: Arc<str>,
+}
+
+impl<P> Clone for AuthLayer<P> {
+    fn clone(&self) -> AuthLayer<P> {
+        AuthLayer {
+            scheme: self.scheme.clone(),
+            realm: self.realm.clone(),
+        }
+    }
+}
+
+impl<P: Clone + Send + Sync + 'static> AuthLayer<P> {
+    /// `verify` gets the user id and password; use `secure_eq` to check the password.
+    pub fn basic(verify: impl Fn(&str, &str) -> Option<P> + Send + Sync + 'static) -> AuthLayer<P> {
+        AuthLayer {
+            scheme: Scheme::Basic(Arc::new(verify)),
+            realm: Arc::from("restricted"),
+        }
+    }
+
+    /// Requires a bearer token, which `validate` turns into a principal.
+    pub fn bearer(validate: impl Fn(&str) -> Option<P> + Send + Sync + 'static) -> AuthLayer<P> {
+        AuthLayer {
+            scheme: Scheme::Bearer(Arc::new(validate)),
+            realm: Arc::from("restricted"),
+        }
+    }
+
+    /// Sets the realm named in the `WWW-Authenticate` challenge; `restricted` by default.
+    pub fn with_realm(mut self, realm: &str) -> AuthLayer<P> {
+        self.realm = Arc::from(realm);
+        self
+    }
+}
+
+impl AuthLayer<String> {
+    /// Basic auth against a fixed set of users; the principal is the user id.
+    pub fn basic_users<'a>(
+        users: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> AuthLayer<String> {
+        let users: Vec<(String, String)> = users
+            .into_iter()
+            .map(|(user, password)| (user.to_string(), password.to_string()))
+            .collect();
+        AuthLayer::basic(move |user, password| {
+            // Check every entry so the timing doesn't reveal which users exist.
+            let mut found = None;
+            for (u, p) in &users {
+                let matched = secure_eq(u.as_bytes(), user.as_bytes())
+                    & secure_eq(p.as_bytes(), password.as_bytes());
+                if matched && found.is_none() {
+                    found = Some(u.clone());
+                }
+            }
+            found
+        })
+    }
+}
+
+impl<S, P> Layer<S> for AuthLayer<P> {
+    type Service = Auth<S, P>;
+
+    fn layer(&self, inner: S) -> Auth<S, P> {
+        Auth {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// The service produced by `AuthLayer`.
+pub struct Auth<S, P> {
+    inner: S,
+    config: AuthLayer<P>,
+}
+
+impl<S: Clone, P> Clone for Auth<S, P> {
+    fn clone(&self) -> Auth<S, P> {
+        Auth {
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<S, P> Auth<S, P> {
+    /// Errs with the bearer `error` code to put in the challenge, if any.
+    fn authenticate(&self, req: &Request) -> Result<P, Option<&'static str>> {
+        let credentials = req.authorization();
+        match &self.config.scheme {
+            Scheme::Basic(verify) => {
+                let (user, password) = credentials
+                    .and_then(|c| c.basic_credentials())
+                    .ok_or(None)?;
+                verify(&user, &password).ok_or(None)
+            }
+            Scheme::Bearer(validate) => {
+                let credentials = credentials.ok_or(None)?;
+                let token = credentials.bearer_token().ok_or(None)?;
+                validate(token).ok_or(Some("invalid_token"))
+            }
+        }
+    }
+
+    fn challenge(&self, error: Option<&str>) -> ServiceResponse {
+        let realm = self.config.realm.replace('\\', "\\\\").replace('"', "\\\"");
+        let challenge = match (&self.config.scheme, error) {
+            (Scheme::Basic(_), _) => format!("Basic realm=\"{realm}\", charset=\"UTF-8\""),
+            (Scheme::Bearer(_), None) => format!("Bearer realm=\"{realm}\""),
+            (Scheme::Bearer(_), Some(error)) => {
+                format!("Bearer realm=\"{realm}\", error=\"{error}\"")
+            }
+        };
+        let resp = ServiceResponse::from_status(StatusCode::UNAUTHORIZED);
+        match HeaderValue::from_str(&challenge) {
+            Ok(value) => resp.with_header(header::WWW_AUTHENTICATE, value),
+            Err(_) => resp,
+        }
+    }
+}
+
+impl<S, P> TowerService<Request> for Auth<S, P>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+    P: Clone + Send + Sync + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        match self.authenticate(&req) {
+            Ok(principal) => {
+                req.extensions_mut().insert(Principal(principal));
+                Box::pin(self.inner.call(req))
+            }
+            Err(error) => {
+                let resp = self.challenge(error);
+                Box::pin(async { Ok(resp) })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Method;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{base64, service_fn, testing::TestRequestExt};
+
+    async fn send<P: Clone + Send + Sync + 'static>(
+        layer: &AuthLayer<P>,
+        authorization: Option<&str>,
+    ) -> ServiceResponse {
+        let service = layer.layer(service_fn(|req: Request| async move {
+            assert!(Principal::<P>::from_request(&req).is_some());
+            Ok::<_, ServiceError>(ServiceResponse::empty())
+        }));
+        let mut req = Request::test(Method::GET, "/");
+        if let Some(value) = authorization {
+            let value = HeaderValue::from_str(value).unwrap();
+            req.headers_mut().insert(header::AUTHORIZATION, value);
+        }
+        service.oneshot(req).await.unwrap()
+    }
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", base64::encode(credentials.as_bytes()))
+    }
+
+    fn challenge(resp: &ServiceResponse) -> &str {
+        resp.headers()[header::WWW_AUTHENTICATE].to_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn basic_credentials() {
+        let layer = AuthLayer::basic_users([("alice", "s3cret:with colon")]);
+        let resp = send(&layer, Some(&basic("alice:s3cret:with colon"))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        for value in [
+            None,
+            Some(basic("alice:wrong")),
+            Some(basic("bob:s3cret:with colon")),
+            // No colon between user and password.
+            Some(basic("alice")),
+            // Not base64, or not UTF-8 once decoded.
+            Some("Basic !!!!".to_string()),
+            Some("Basic YWxpY2U6*".to_string()),
+            Some(format!("Basic {}", base64::encode(b"alice:\xff"))),
+            // The right credentials under another scheme.
+            Some(basic("alice:s3cret:with colon").replace("Basic", "Bearer")),
+            Some("Basic".to_string()),
+        ] {
+            let resp = send(&layer, value.as_deref()).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{value:?}");
+            assert_eq!(
+                challenge(&resp),
+                r#"Basic realm="restricted", charset="UTF-8""#
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn schemes_are_case_insensitive() {
+        let layer = AuthLayer::basic_users([("alice", "pw")]);
+        for scheme in ["basic", "BASIC", "bAsIc"] {
+            let value = basic("alice:pw").replace("Basic", scheme);
+            assert_eq!(send(&layer, Some(&value)).await.status(), StatusCode::OK);
+        }
+        let layer = AuthLayer::bearer(|token| (token == "t0k").then_some(()));
+        for value in ["Bearer t0k", "bearer t0k", "BEARER  t0k"] {
+            assert_eq!(send(&layer, Some(value)).await.status(), StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn bearer_challenges() {
+        let layer =
+            AuthLayer::bearer(|token| (token == "t0k").then_some(())).with_realm("api \"v2\"");
+        let resp = send(&layer, None).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(challenge(&resp), r#"Bearer realm="api \"v2\"""#);
+        let resp = send(&layer, Some("Basic dDBr")).await;
+        assert_eq!(challenge(&resp), r#"Bearer realm="api \"v2\"""#);
+        let resp = send(&layer, Some("Bearer nope")).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            challenge(&resp),
+            r#"Bearer realm="api \"v2\"", error="invalid_token""#
+        );
+    }
+
+    #[test]
+    fn secure_eq_compares_contents() {
+        assert!(secure_eq(b"secret", b"secret"));
+        assert!(!secure_eq(b"secret", b"secreT"));
+        assert!(!secure_eq(b"secret", b"secret2"));
+        assert!(secure_eq(b"", b""));
+    }
+}
@@ -0,0 +1,140 @@
+const STANDARD: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate) fn encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = match chunk {
+            [a, b, c] => u32::from_be_bytes([0, *a, *b, *c]),
+            [a, b] => u32::from_be_bytes([0, *a, *b, 0]),
+            [a] => u32::from_be_bytes([0, *a, 0, 0]),
+            _ => unreachable!(),
+        };
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(STANDARD[(n >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+/// The unpadded URL-safe form of RFC 7515.
+#[cfg(any(test, feature = "acme"))]
+pub(crate) fn encode_url(data: &[u8]) -> String {
+    encode(data)
+        .trim_end_matches('=')
+        .replace('+', "-")
+        .replace('/', "_")
+}
+
+/// Accepts both the standard and URL-safe alphabets, with or without padding.
+pub(crate) fn decode(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=').as_bytes();
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    for chunk in input.chunks(4) {
+        let mut n = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            n |= (value(c)? as u32) << (18 - 6 * i);
+        }
+        let bytes = n.to_be_bytes();
+        match chunk.len() {
+            4 => out.extend_from_slice(&bytes[1..4]),
+            3 => out.extend_from_slice(&bytes[1..3]),
+            2 => out.push(bytes[1]),
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+/// The unpadded URL-safe form of RFC 7515: no `=`, no `+` or `/`, and the unused bits
+/// of the last character must be zero, so every value has exactly one encoding.
+#[cfg(any(test, feature = "jwt"))]
+pub(crate) fn decode_url_strict(input: &str) -> Option<Vec<u8>> {
+    if input.bytes().any(|c| matches!(c, b'=' | b'+' | b'/')) {
+        return None;
+    }
+    let unused_bits = match input.len() % 4 {
+        2 => 0x0f,
+        3 => 0x03,
+        _ => 0,
+    };
+    match input.bytes().last().map(value) {
+        Some(Some(last)) if last & unused_bits != 0 => None,
+        _ => decode(input),
+    }
+}
+
+fn value(c: u8) -> Option<u8> {
+    match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' | b'-' => Some(62),
+        b'/' | b'_' => Some(63),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let data: Vec<u8> = (0..=255).collect();
+        for len in 0..40 {
+            let encoded = encode(&data[..len]);
+            assert_eq!(encoded.len() % 4, 0);
+            assert_eq!(decode(&encoded).unwrap(), &data[..len], "{encoded}");
+            let unpadded = encoded
+                .trim_end_matches('=')
+                .replace('+', "-")
+                .replace('/', "_");
+            assert_eq!(encode_url(&data[..len]), unpadded);
+            assert_eq!(
+                decode_url_strict(&unpadded).unwrap(),
+                &data[..len],
+                "{unpadded}"
+            );
+        }
+        assert_eq!(encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(encode(b"fo"), "Zm8=");
+        assert_eq!(encode(b"f"), "Zg==");
+    }
+
+    #[test]
+    fn padding_is_optional_when_lenient() {
+        assert_eq!(decode("Zm8=").unwrap(), b"fo");
+        assert_eq!(decode("Zm8").unwrap(), b"fo");
+        assert_eq!(decode("Zg==").unwrap(), b"f");
+        assert_eq!(decode("").unwrap(), b"");
+        // A lone character can't hold a whole byte.
+        assert_eq!(decode("Zm9vY"), None);
+    }
+
+    #[test]
+    fn invalid_characters_are_rejected() {
+        for input in ["Zm9v!", "Zm 9v", "Zm9v\n", "Zé==", "Zm9v.YmFy"] {
+            assert_eq!(decode(input), None, "{input}");
+            assert_eq!(decode_url_strict(input), None, "{input}");
+        }
+    }
+
+    #[test]
+    fn strict_mode_rejects_other_encodings() {
+        assert_eq!(decode_url_strict("_-8").unwrap(), [0xff, 0xef]);
+        for input in [
+            // Padding.
+            "Zm8=", "Zg==",
+            // The standard alphabet, alone or mixed with the URL-safe one.
+            "+/8", "_/8", "-+8",
+            // Non-zero unused bits: `Zh` and `Zm9` decode like `Zg` and `Zm8`.
+            "Zh", "Zm9", // Too short to hold a byte.
+            "Z",
+        ] {
+            assert_eq!(decode_url_strict(input), None, "{input}");
+        }
+    }
+}
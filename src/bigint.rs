@@ -0,0 +1,322 @@
+use std::cmp::Ordering;
+
+/// Arbitrary-precision unsigned integers, just enough for verifying RSA and ECDSA
+/// signatures. Limbs are little-endian with no high zero limbs. None of this is
+/// constant time, which is fine for public keys and signatures.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct BigUint(Vec<u32>);
+
+impl BigUint {
+    fn normalized(mut limbs: Vec<u32>) -> BigUint {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        BigUint(limbs)
+    }
+
+    pub(crate) fn from_u32(n: u32) -> BigUint {
+        BigUint::normalized(vec![n])
+    }
+
+    pub(crate) fn from_be_bytes(bytes: &[u8]) -> BigUint {
+        let limbs = bytes
+            .rchunks(4)
+            .map(|chunk| chunk.iter().fold(0u32, |n, &b| (n << 8) | b as u32))
+            .collect();
+        BigUint::normalized(limbs)
+    }
+
+    pub(crate) fn from_hex(hex: &str) -> BigUint {
+        let limbs = hex
+            .as_bytes()
+            .rchunks(8)
+            .map(|chunk| {
+                u32::from_str_radix(std::str::from_utf8(chunk).expect("ascii"), 16)
+                    .expect("valid hex constant")
+            })
+            .collect();
+        BigUint::normalized(limbs)
+    }
+
+    /// Big-endian, left-padded to `len` bytes; higher bytes that don't fit are dropped.
+    pub(crate) fn to_be_bytes(&self, len: usize) -> Vec<u8> {
+        let mut out = vec![0u8; len];
+        for (i, limb) in self.0.iter().enumerate() {
+            for (j, b) in limb.to_le_bytes().into_iter().enumerate() {
+                if let Some(pos) = (i * 4 + j).checked_add(1).and_then(|k| len.checked_sub(k)) {
+                    out[pos] = b;
+                }
+            }
+        }
+        out
+    }
+
+    pub(crate) fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn bits(&self) -> usize {
+        match self.0.last() {
+            Some(top) => self.0.len() * 32 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    pub(crate) fn bit(&self, i: usize) -> bool {
+        self.0
+            .get(i / 32)
+            .is_some_and(|limb| limb >> (i % 32) & 1 == 1)
+    }
+
+    pub(crate) fn add(&self, other: &BigUint) -> BigUint {
+        let (long, short) = match self.0.len() >= other.0.len() {
+            true => (&self.0, &other.0),
+            false => (&other.0, &self.0),
+        };
+        let mut out = Vec::with_capacity(long.len() + 1);
+        let mut carry = 0u64;
+        for (i, &limb) in long.iter().enumerate() {
+            let sum = limb as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+            out.push(sum as u32);
+            carry = sum >> 32;
+        }
+        out.push(carry as u32);
+        BigUint::normalized(out)
+    }
+
+    /// Panics if `other` is larger.
+    pub(crate) fn sub(&self, other: &BigUint) -> BigUint {
+        assert!(*self >= *other, "BigUint subtraction underflow");
+        let mut out = Vec::with_capacity(self.0.len());
+        let mut borrow = 0i64;
+        for (i, &limb) in self.0.iter().enumerate() {
+            let diff = limb as i64 - *other.0.get(i).unwrap_or(&0) as i64 - borrow;
+            out.push(diff as u32);
+            borrow = (diff < 0) as i64;
+        }
+        BigUint::normalized(out)
+    }
+
+    pub(crate) fn mul(&self, other: &BigUint) -> BigUint {
+        let mut out = vec![0u32; self.0.len() + other.0.len()];
+        for (i, &a) in self.0.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.0.iter().enumerate() {
+                let t = a as u64 * b as u64 + out[i + j] as u64 + carry;
+                out[i + j] = t as u32;
+                carry = t >> 32;
+            }
+            out[i + other.0.len()] = carry as u32;
+        }
+        BigUint::normalized(out)
+    }
+
+    /// Knuth's algorithm D, keeping only the remainder.
+    pub(crate) fn rem(&self, m: &BigUint) -> BigUint {
+        assert!(!m.is_zero(), "BigUint remainder by zero");
+        if *self < *m {
+            return self.clone();
+        }
+        if let [d] = m.0[..] {
+            let r = self
+                .0
+                .iter()
+                .rev()
+                .fold(0u64, |r, &limb| ((r << 32) | limb as u64) % d as u64);
+            return BigUint::from_u32(r as u32);
+        }
+        let shift = m.0.last().expect("nonzero").leading_zeros();
+        let v = BigUint::normalized(shl(&m.0, shift)).0;
+        let mut u = shl(&self.0, shift);
+        u.resize(self.0.len() + 1, 0);
+        let n = v.len();
+        let (top, next) = (v[n - 1] as u64, v[n - 2] as u64);
+        for j in (0..u.len() - n).rev() {
+            let num = ((u[j + n] as u64) << 32) | u[j + n - 1] as u64;
+            let mut qhat = num / top;
+            let mut rhat = num % top;
+            while qhat >> 32 != 0 || qhat * next > ((rhat << 32) | u[j + n - 2] as u64) {
+                qhat -= 1;
+                rhat += top;
+                if rhat >> 32 != 0 {
+                    break;
+                }
+            }
+            let mut borrow = 0i64;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let p = qhat * v[i] as u64 + carry;
+                carry = p >> 32;
+                let t = u[i + j] as i64 - borrow - (p & 0xffff_ffff) as i64;
+                u[i + j] = t as u32;
+                borrow = (t < 0) as i64;
+            }
+            let t = u[j + n] as i64 - borrow - carry as i64;
+            u[j + n] = t as u32;
+            if t < 0 {
+                // `qhat` was one too large; add the divisor back.
+                let mut carry = 0u64;
+                for i in 0..n {
+                    let sum = u[i + j] as u64 + v[i] as u64 + carry;
+                    u[i + j] = sum as u32;
+                    carry = sum >> 32;
+                }
+                u[j + n] = u[j + n].wrapping_add(carry as u32);
+            }
+        }
+        u.truncate(n);
+        BigUint::normalized(shr(&u, shift))
+    }
+
+    pub(crate) fn mod_add(&self, other: &BigUint, m: &BigUint) -> BigUint {
+        self.add(other).rem(m)
+    }
+
+    /// Both operands must already be reduced.
+    pub(crate) fn mod_sub(&self, other: &BigUint, m: &BigUint) -> BigUint {
+        match *self >= *other {
+            true => self.sub(other),
+            false => self.add(m).sub(other),
+        }
+    }
+
+    pub(crate) fn mod_mul(&self, other: &BigUint, m: &BigUint) -> BigUint {
+        self.mul(other).rem(m)
+    }
+
+    pub(crate) fn mod_pow(&self, exponent: &BigUint, m: &BigUint) -> BigUint {
+        let base = self.rem(m);
+        let mut result = BigUint::from_u32(1).rem(m);
+        for i in (0..exponent.bits()).rev() {
+            result = result.mod_mul(&result, m);
+            if exponent.bit(i) {
+                result = result.mod_mul(&base, m);
+            }
+        }
+        result
+    }
+
+    /// Inverse modulo a prime, by Fermat's little theorem.
+    pub(crate) fn mod_inv_prime(&self, p: &BigUint) -> BigUint {
+        self.mod_pow(&p.sub(&BigUint::from_u32(2)), p)
+    }
+}
+
+fn shl(limbs: &[u32], shift: u32) -> Vec<u32> {
+    if shift == 0 {
+        return limbs.to_vec();
+    }
+    let mut out = Vec::with_capacity(limbs.len() + 1);
+    let mut carry = 0u32;
+    for &limb in limbs {
+        out.push((limb << shift) | carry);
+        carry = limb >> (32 - shift);
+    }
+    out.push(carry);
+    out
+}
+
+fn shr(limbs: &[u32], shift: u32) -> Vec<u32> {
+    if shift == 0 {
+        return limbs.to_vec();
+    }
+    let mut out = vec![0u32; limbs.len()];
+    for i in 0..limbs.len() {
+        let high = limbs.get(i + 1).map_or(0, |next| next << (32 - shift));
+        out[i] = (limbs[i] >> shift) | high;
+    }
+    out
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &BigUint) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &BigUint) -> Ordering {
+        self.0
+            .len()
+            .cmp(&other.0.len())
+            .then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Expected values computed independently with Python's integers.
+    const A: &str =
+        "fedcba9876543210fedcba9876543210fedcba9876543210fedcba98765432100123456789abcdef";
+    const B: &str = "ffffffffffffffffffffffff0000000100000000";
+    const P: &str = "ffffffff00000001000000000000000000000000ffffffffffffffffffffffff";
+
+    fn big(hex: &str) -> BigUint {
+        BigUint::from_hex(hex)
+    }
+
+    #[test]
+    fn byte_conversions() {
+        let n = BigUint::from_be_bytes(&[0, 0, 1, 2, 3, 4, 5]);
+        assert_eq!(n, big("102030405"));
+        assert_eq!(n.to_be_bytes(8), [0, 0, 0, 1, 2, 3, 4, 5]);
+        assert_eq!(n.to_be_bytes(2), [4, 5]);
+        assert_eq!(n.bits(), 33);
+        assert!(n.bit(0) && !n.bit(1) && n.bit(32) && !n.bit(64));
+        assert!(BigUint::from_be_bytes(&[0, 0]).is_zero());
+        assert_eq!(BigUint::from_be_bytes(&[]).bits(), 0);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let (a, b) = (big(A), big(B));
+        assert_eq!(
+            a.add(&b),
+            big("fedcba9876543210fedcba9876543210fedcba9976543210fedcba987654320f0123456889abcdef")
+        );
+        assert_eq!(
+            a.sub(&b),
+            big("fedcba9876543210fedcba9876543210fedcba9776543210fedcba98765432110123456689abcdef")
+        );
+        assert_eq!(
+            a.mul(&b),
+            big(concat!(
+                "fedcba9876543210fedcba97777777798765431fedcba9898765431fedcba988",
+                "89abcdef01234567888888887530eca87777777889abcdef00000000"
+            ))
+        );
+        assert_eq!(a.rem(&b), big("fedcba997530eca7777777778acf135789abcdef"));
+        assert_eq!(a.rem(&big("100000001")), big("6666666a"));
+        assert_eq!(
+            big("ffffffffffffffffffffffff").add(&BigUint::from_u32(1)),
+            big("1000000000000000000000000")
+        );
+        assert!(a.sub(&a).is_zero());
+        assert!(b < a && a.cmp(&b) == Ordering::Greater);
+    }
+
+    #[test]
+    fn modular_arithmetic() {
+        let (a, b, p) = (big(A), big(B), big(P));
+        assert_eq!(
+            a.rem(&p),
+            big("7530eca801234567fedcba977777777789abcdef7654321100000000fedcba98")
+        );
+        assert_eq!(
+            a.mod_pow(&b, &p),
+            big("5353d9b5f515f0aba3aa21aca45de2fb738e7741ce619d42835500d00321fa3d")
+        );
+        let x = big("1234567890abcdef");
+        let inv = x.mod_inv_prime(&p);
+        assert_eq!(
+            inv,
+            big("ee0c667596921ed4c4be911a45005b555a3e6c1e0018ead4f735cdb2b56b01b6")
+        );
+        assert_eq!(x.mod_mul(&inv, &p), BigUint::from_u32(1));
+        let y = p.sub(&BigUint::from_u32(1));
+        assert!(y.mod_add(&BigUint::from_u32(1), &p).is_zero());
+        assert_eq!(BigUint::from_u32(0).mod_sub(&BigUint::from_u32(1), &p), y);
+    }
+}
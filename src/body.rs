@@ -0,0 +1,474 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt, TryStreamExt};
+use http_body_util::{BodyExt, BodyStream};
+use hyper::{
+    HeaderMap,
+    body::{Frame, Incoming, SizeHint},
+    header,
+};
+use tokio::{io::AsyncRead, time::Instant};
+use tokio_util::io::StreamReader;
+
+use crate::{
+    BodyInner, BoxedBodyStream, Error, QueryPairs, Request, body_limit,
+    compression::{Decoder, DecompressionLimit, content_encoding, decode_stream},
+    make_body_from_stream,
+};
+
+/// The `Content-Type` that `collect_json` requires.
+pub const APPLICATION_JSON: &str = "application/json";
+/// The `Content-Type` that `collect_form` requires.
+pub const APPLICATION_FORM: &str = "application/x-www-form-urlencoded";
+
+/// The body of requests and responses alike: what hyper received from the peer, or
+/// one supplied by the application as bytes or a stream of frames. Polled as a
+/// `Stream`, it yields the same frames as through `hyper::body::Body`.
+#[derive(Default)]
+pub enum Body {
+    /// No body at all.
+    #[default]
+    Empty,
+    /// A body held in memory.
+    Full(Bytes),
+    /// A body still being received from the peer.
+    Incoming(Incoming),
+    /// A stream of frames, which may include trailers.
+    Stream(BoxedBodyStream),
+}
+
+impl Body {
+    /// An empty body.
+    pub fn empty() -> Body {
+        Body::Empty
+    }
+
+    /// A body of the given bytes.
+    pub fn from_bytes(bytes: impl Into<Bytes>) -> Body {
+        Body::Full(bytes.into())
+    }
+
+    /// A body of data frames read from `stream`; its errors end the body.
+    pub fn from_stream<S, B, E>(stream: S) -> Body
+    where
+        S: Stream<Item = Result<B, E>> + Send + 'static,
+        B: Into<Bytes>,
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        Body::Stream(Box::new(Box::pin(stream.map(|chunk| {
+            chunk
+                .map(|data| Frame::data(data.into()))
+                .map_err(io::Error::other)
+        }))))
+    }
+
+    /// Reads the rest of the body into memory and leaves it behind as `Full`, so the
+    /// bytes can be inspected here and read again downstream. Trailers are dropped.
+    /// When the body is longer than `max` or fails to read, it is left to replay
+    /// what was read followed by the remainder, and the error is returned.
+    pub async fn buffer(&mut self, max: usize) -> Result<Bytes, Error> {
+        if let Body::Full(bytes) = self
+            && bytes.len() <= max
+        {
+            return Ok(bytes.clone());
+        }
+        let mut rest = std::mem::take(self);
+        let mut buf = BytesMut::new();
+        let (frame, err) = loop {
+            let Some(frame) = rest.next().await else {
+                let bytes = buf.freeze();
+                *self = Body::Full(bytes.clone());
+                return Ok(bytes);
+            };
+            match frame.map(Frame::into_data) {
+                Ok(Ok(data)) if buf.len() + data.len() <= max => buf.extend_from_slice(&data),
+                Ok(Ok(data)) => {
+                    break (Ok(Frame::data(data)), Error::RequestTooLarge { limit: max });
+                }
+                Ok(Err(_trailers)) => {}
+                // Downstream gets a copy of the error, the caller the original.
+                Err(e) => break (Err(io::Error::new(e.kind(), e.to_string())), Error::from(e)),
+            }
+        };
+        let read = futures::stream::iter([Ok(Frame::data(buf.freeze())), frame]);
+        *self = make_body_from_stream(read.chain(rest));
+        Err(err)
+    }
+
+    /// Hands each chunk of data to `sink` as it is read, e.g. to log a payload
+    /// without consuming it.
+    pub fn tee<F>(self, mut sink: F) -> Body
+    where
+        F: FnMut(&Bytes) + Send + 'static,
+    {
+        make_body_from_stream(self.inspect(move |frame| {
+            if let Some(data) = frame.as_ref().ok().and_then(Frame::data_ref) {
+                sink(data);
+            }
+        }))
+    }
+}
+
+impl hyper::body::Body for Body {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+        let this = self.get_mut();
+        match this {
+            Body::Empty => Poll::Ready(None),
+            Body::Full(bytes) => {
+                let bytes = std::mem::take(bytes);
+                *this = Body::Empty;
+                Poll::Ready((!bytes.is_empty()).then(|| Ok(Frame::data(bytes))))
+            }
+            // Kept as the source of the io error, so `Error::from` can recover it.
+            Body::Incoming(body) => Pin::new(body).poll_frame(cx).map_err(io::Error::other),
+            Body::Stream(stream) => stream.poll_next_unpin(cx),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self {
+            Body::Empty => true,
+            Body::Full(bytes) => bytes.is_empty(),
+            Body::Incoming(body) => body.is_end_stream(),
+            Body::Stream(_) => false,
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self {
+            Body::Empty => SizeHint::with_exact(0),
+            Body::Full(bytes) => SizeHint::with_exact(bytes.len() as u64),
+            Body::Incoming(body) => body.size_hint(),
+            Body::Stream(_) => SizeHint::default(),
+        }
+    }
+}
+
+impl Stream for Body {
+    type Item = BodyInner;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<BodyInner>> {
+        hyper::body::Body::poll_frame(self, cx)
+    }
+}
+
+impl std::fmt::Debug for Body {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Body::Empty => f.write_str("Body::Empty"),
+            Body::Full(bytes) => f.debug_tuple("Body::Full").field(bytes).finish(),
+            Body::Incoming(body) => f.debug_tuple("Body::Incoming").field(body).finish(),
+            Body::Stream(_) => f.write_str("Body::Stream(..)"),
+        }
+    }
+}
+
+impl From<Incoming> for Body {
+    fn from(body: Incoming) -> Body {
+        Body::Incoming(body)
+    }
+}
+
+impl From<Bytes> for Body {
+    fn from(bytes: Bytes) -> Body {
+        Body::Full(bytes)
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body::Full(bytes.into())
+    }
+}
+
+impl From<String> for Body {
+    fn from(s: String) -> Body {
+        Body::Full(s.into())
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(s: &'static str) -> Body {
+        Body::Full(Bytes::from_static(s.as_bytes()))
+    }
+}
+
+/// Per-request limits on how long reading the body may take. Stored in the request extensions by the server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BodyTimeouts {
+    read: Option<Duration>,
+    total: Option<Duration>,
+}
+
+impl BodyTimeouts {
+    /// No timeouts.
+    pub fn new() -> BodyTimeouts {
+        BodyTimeouts::default()
+    }
+
+    /// The timeouts the server attached to `req`, or none.
+    pub fn from_request(req: &Request) -> BodyTimeouts {
+        req.extensions().get().copied().unwrap_or_default()
+    }
+
+    /// Fails a read once no frame has arrived for `timeout`.
+    pub fn with_read_timeout(mut self, timeout: Duration) -> BodyTimeouts {
+        self.read = Some(timeout);
+        self
+    }
+
+    /// Fails a read once the whole body has taken longer than `timeout`.
+    pub fn with_total_timeout(mut self, timeout: Duration) -> BodyTimeouts {
+        self.total = Some(timeout);
+        self
+    }
+
+    /// The idle timeout between frames.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read
+    }
+
+    /// The timeout for the whole body.
+    pub fn total_timeout(&self) -> Option<Duration> {
+        self.total
+    }
+}
+
+/// Reads `body` into memory, failing with `RequestTooLarge` past `max_size` bytes.
+pub async fn collect_bytes(body: Body, max_size: usize) -> Result<Bytes, Error> {
+    collect_bytes_with_timeouts(body, max_size, BodyTimeouts::default()).await
+}
+
+/// Reads the request body into memory, decompressing and applying the request's limits and timeouts.
+pub async fn collect_request(req: Request, max_size: usize) -> Result<Bytes, Error> {
+    let timeouts = BodyTimeouts::from_request(&req);
+    let declared = declared_length(&req);
+    collect_stream(request_frames(req), declared, max_size, timeouts).await
+}
+
+/// Like `collect_bytes`, applying `timeouts` as well.
+pub async fn collect_bytes_with_timeouts(
+    body: Body,
+    max_size: usize,
+    timeouts: BodyTimeouts,
+) -> Result<Bytes, Error> {
+    let declared = hyper::body::Body::size_hint(&body).exact();
+    collect_stream(body_frames(body), declared, max_size, timeouts).await
+}
+
+/// The length the collected body will have, when it is known up front. A declared
+/// `Content-Length` says nothing about the size after decompression.
+fn declared_length(req: &Request) -> Option<u64> {
+    let decoded = req.extensions().get::<DecompressionLimit>().is_some()
+        && !matches!(content_encoding(req.headers()), Ok(None));
+    match decoded {
+        true => None,
+        false => hyper::body::Body::size_hint(req.body()).exact(),
+    }
+}
+
+/// A declared length over `max_size` is rejected before anything is read; otherwise
+/// it sizes the buffer, and the limit is still enforced frame by frame.
+async fn collect_stream<S>(
+    mut body: S,
+    declared: Option<u64>,
+    max_size: usize,
+    timeouts: BodyTimeouts,
+) -> Result<Bytes, Error>
+where
+    S: Stream<Item = Result<Bytes, Error>> + Unpin,
+{
+    let capacity = match declared.map(usize::try_from) {
+        Some(Ok(len)) if len <= max_size => len,
+        Some(_) => return Err(Error::RequestTooLarge { limit: max_size }),
+        None => 0,
+    };
+    let deadline = timeouts.total.map(|total| Instant::now() + total);
+    let mut buf = BytesMut::with_capacity(capacity);
+    loop {
+        let wait = match (timeouts.read, deadline) {
+            (Some(read), Some(deadline)) => {
+                Some(read.min(deadline.saturating_duration_since(Instant::now())))
+            }
+            (Some(read), None) => Some(read),
+            (None, Some(deadline)) => Some(deadline.saturating_duration_since(Instant::now())),
+            (None, None) => None,
+        };
+        let frame = match wait {
+            Some(wait) => tokio::time::timeout(wait, body.next())
+                .await
+                .map_err(|_| Error::BodyTimeout)?,
+            None => body.next().await,
+        };
+        let Some(data) = frame else {
+            break;
+        };
+        let data = data?;
+        if buf.len() + data.len() > max_size {
+            return Err(Error::RequestTooLarge { limit: max_size });
+        }
+        buf.extend_from_slice(&data);
+    }
+    Ok(buf.freeze())
+}
+
+/// The data frames of `body`; trailers are skipped.
+pub fn body_frames(body: Body) -> impl Stream<Item = Result<Bytes, Error>> + Send + Unpin {
+    body.into_data_stream().map_err(Error::from)
+}
+
+/// The data frames of a request body, as returned by `request_frames`.
+pub type RequestFrames = Pin<Box<dyn Stream<Item = Result<Bytes, Error>> + Send>>;
+
+/// The data frames of the request body, with the same decompression and limits as `collect_request`.
+pub fn request_frames(req: Request) -> RequestFrames {
+    limited_frames(req, None)
+}
+
+/// Trailers received after the request body, available once its stream has ended.
+#[derive(Clone, Debug, Default)]
+pub struct RequestTrailers(Arc<Mutex<Option<HeaderMap>>>);
+
+impl RequestTrailers {
+    /// The trailers, if the body has ended and the peer sent any. Later calls return `None`.
+    pub fn take(&self) -> Option<HeaderMap> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+}
+
+/// Like `request_frames`, keeping the trailers instead of discarding them.
+pub fn request_frames_with_trailers(req: Request) -> (RequestFrames, RequestTrailers) {
+    let trailers = RequestTrailers::default();
+    (limited_frames(req, Some(trailers.clone())), trailers)
+}
+
+/// Like `collect_request`, also returning the trailers the peer sent.
+pub async fn collect_request_with_trailers(
+    req: Request,
+    max_size: usize,
+) -> Result<(Bytes, Option<HeaderMap>), Error> {
+    let timeouts = BodyTimeouts::from_request(&req);
+    let declared = declared_length(&req);
+    let (frames, trailers) = request_frames_with_trailers(req);
+    let body = collect_stream(frames, declared, max_size, timeouts).await?;
+    Ok((body, trailers.take()))
+}
+
+fn limited_frames(req: Request, trailers: Option<RequestTrailers>) -> RequestFrames {
+    let limit = req.extensions().get::<DecompressionLimit>().copied();
+    let encoding = match limit {
+        Some(_) => content_encoding(req.headers()),
+        None => Ok(None),
+    };
+    let transport = body_limit::state(&req);
+    let frames: RequestFrames = match trailers {
+        Some(RequestTrailers(slot)) => {
+            Box::pin(BodyStream::new(req.into_body()).filter_map(move |frame| {
+                let data = match frame.map(|frame| frame.into_data()) {
+                    Ok(Ok(data)) => Some(Ok(data)),
+                    Ok(Err(frame)) => {
+                        if let Ok(headers) = frame.into_trailers() {
+                            *slot.lock().unwrap_or_else(|e| e.into_inner()) = Some(headers);
+                        }
+                        None
+                    }
+                    Err(e) => Some(Err(Error::from(e))),
+                };
+                futures::future::ready(data)
+            }))
+        }
+        None => Box::pin(body_frames(req.into_body())),
+    };
+    let frames: RequestFrames = match transport {
+        Some(state) => body_limit::limit_frames(frames, state),
+        None => Box::pin(frames),
+    };
+    match (encoding, limit) {
+        (Ok(Some(encoding)), Some(DecompressionLimit(limit))) => {
+            Box::pin(decode_stream(frames, Decoder::new(encoding, limit)))
+        }
+        (Err(()), _) => Box::pin(futures::stream::once(async {
+            Err(Error::UnsupportedMediaType {
+                expected: "gzip or deflate content-encoding",
+            })
+        })),
+        _ => frames,
+    }
+}
+
+/// `body` as an `AsyncRead`.
+pub fn body_reader(body: Body) -> impl AsyncRead + Send + Unpin {
+    StreamReader::new(body_frames(body).map_err(std::io::Error::from))
+}
+
+/// Reads `body` into a `String`, failing on invalid UTF-8.
+pub async fn collect_string(body: Body, max_size: usize) -> Result<String, Error> {
+    let bytes = collect_bytes(body, max_size).await?;
+    Ok(std::str::from_utf8(&bytes)?.to_string())
+}
+
+/// Reads a JSON request body, requiring `Content-Type: application/json`.
+pub async fn collect_json(req: Request, max_size: usize) -> Result<Bytes, Error> {
+    require_content_type(&req, APPLICATION_JSON)?;
+    collect_request(req, max_size).await
+}
+
+/// Reads and decodes an `application/x-www-form-urlencoded` request body.
+pub async fn collect_form(req: Request, max_size: usize) -> Result<Vec<(String, String)>, Error> {
+    require_content_type(&req, APPLICATION_FORM)?;
+    let bytes = collect_request(req, max_size).await?;
+    Ok(QueryPairs::new(std::str::from_utf8(&bytes)?)
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect())
+}
+
+/// Fails with `UnsupportedMediaType` unless the request's media type is `expected`.
+pub fn require_content_type(req: &Request, expected: &'static str) -> Result<(), Error> {
+    let matches = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case(expected));
+    match matches {
+        true => Ok(()),
+        false => Err(Error::UnsupportedMediaType { expected }),
+    }
+}
+
+/// Collects a response body when it fits in `max` bytes and has no trailers; otherwise
+/// the frames read so far are replayed ahead of the rest of the stream.
+pub(crate) async fn buffer_body(mut stream: BoxedBodyStream, max: usize) -> Result<Bytes, Body> {
+    let mut frames: Vec<BodyInner> = vec![];
+    let mut buf = BytesMut::new();
+    loop {
+        let Some(frame) = stream.next().await else {
+            return Ok(buf.freeze());
+        };
+        let data = match &frame {
+            Ok(frame) => frame.data_ref().cloned(),
+            Err(_) => None,
+        };
+        frames.push(frame);
+        match data {
+            Some(data) if buf.len() + data.len() <= max => buf.extend_from_slice(&data),
+            _ => break,
+        }
+    }
+    Err(make_body_from_stream(
+        futures::stream::iter(frames).chain(stream),
+    ))
+}
@@ -0,0 +1,173 @@
+use std::{
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use hyper::header::{self, HeaderValue};
+use tower::{Layer, Service as TowerService};
+
+use crate::{Error, Request, ResponseExt, ServiceBoxFuture, ServiceError, ServiceResponse};
+
+/// A cap on request body size, enforced as the body is read; over it is a 413.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BodyLimit {
+    max: usize,
+    close_connection: bool,
+}
+
+impl BodyLimit {
+    /// Allows bodies of up to `max` bytes.
+    pub fn new(max: usize) -> BodyLimit {
+        BodyLimit {
+            max,
+            close_connection: false,
+        }
+    }
+
+    /// Answer oversized requests with `Connection: close` instead of keeping the
+    /// connection alive, so the rest of the body is never read.
+    pub fn with_close_connection(mut self, close: bool) -> BodyLimit {
+        self.close_connection = close;
+        self
+    }
+
+    /// The maximum body size, in bytes.
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// The limit in force for `req`.
+    pub fn from_request(req: &Request) -> Option<BodyLimit> {
+        req.extensions()
+            .get::<BodyLimitState>()
+            .map(|state| state.limit)
+    }
+
+    /// Installs the limit on the request. The returned flag is raised once the body
+    /// is found to be over.
+    pub(crate) fn apply(self, req: &mut Request) -> Arc<AtomicBool> {
+        let declared = req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        let exceeded = Arc::new(AtomicBool::new(false));
+        req.extensions_mut().insert(BodyLimitState {
+            limit: self,
+            declared,
+            exceeded: exceeded.clone(),
+        });
+        exceeded
+    }
+
+    pub(crate) fn finish(&self, resp: ServiceResponse, exceeded: bool) -> ServiceResponse {
+        match exceeded && self.close_connection {
+            true => resp.with_header(header::CONNECTION, HeaderValue::from_static("close")),
+            false => resp,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct BodyLimitState {
+    limit: BodyLimit,
+    declared: Option<u64>,
+    exceeded: Arc<AtomicBool>,
+}
+
+/// Fails the stream with `RequestTooLarge` on the first frame past the limit, or
+/// without reading anything when the declared length is already over.
+pub(crate) fn limit_frames<S>(
+    frames: S,
+    state: BodyLimitState,
+) -> Pin<Box<dyn Stream<Item = Result<Bytes, Error>> + Send>>
+where
+    S: Stream<Item = Result<Bytes, Error>> + Send + 'static,
+{
+    let max = state.limit.max;
+    if state.declared.is_some_and(|len| len > max as u64) {
+        state.exceeded.store(true, Ordering::Relaxed);
+        return Box::pin(futures::stream::once(async move {
+            Err(Error::RequestTooLarge { limit: max })
+        }));
+    }
+    let mut read = 0;
+    Box::pin(frames.map(move |frame| {
+        let data = frame?;
+        read += data.len();
+        if read > state.limit.max {
+            state.exceeded.store(true, Ordering::Relaxed);
+            return Err(Error::RequestTooLarge {
+                limit: state.limit.max,
+            });
+        }
+        Ok(data)
+    }))
+}
+
+pub(crate) fn state(req: &Request) -> Option<BodyLimitState> {
+    req.extensions().get::<BodyLimitState>().cloned()
+}
+
+/// Overrides the server-wide limit for the services it wraps.
+#[derive(Clone, Copy, Debug)]
+pub struct BodyLimitLayer {
+    limit: BodyLimit,
+}
+
+impl BodyLimitLayer {
+    /// Applies `limit` to the services it wraps.
+    pub fn new(limit: BodyLimit) -> BodyLimitLayer {
+        BodyLimitLayer { limit }
+    }
+}
+
+impl<S> Layer<S> for BodyLimitLayer {
+    type Service = BodyLimitService<S>;
+
+    fn layer(&self, inner: S) -> BodyLimitService<S> {
+        BodyLimitService {
+            inner,
+            limit: self.limit,
+        }
+    }
+}
+
+/// The service produced by `BodyLimitLayer`.
+#[derive(Clone)]
+pub struct BodyLimitService<S> {
+    inner: S,
+    limit: BodyLimit,
+}
+
+impl<S> TowerService<Request> for BodyLimitService<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        let limit = self.limit;
+        let exceeded = limit.apply(&mut req);
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let resp = fut.await?;
+            Ok(limit.finish(resp, exceeded.load(Ordering::Relaxed)))
+        })
+    }
+}
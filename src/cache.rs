@@ -0,0 +1,463 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use hyper::{
+    HeaderMap, Method, StatusCode,
+    header::{self, HeaderName, HeaderValue},
+};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    Request, ResponseExt, ServiceBoxFuture, ServiceError, ServiceResponse, TypedHeader,
+    body::buffer_body, single_frame_body,
+};
+
+const DEFAULT_MAX_ENTRIES: usize = 1024;
+const DEFAULT_MAX_BODY: usize = 1 << 20;
+
+/// The directives of a `Cache-Control` header.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    max_age: Option<Duration>,
+    s_maxage: Option<Duration>,
+    stale_while_revalidate: Option<Duration>,
+    public: bool,
+    private: bool,
+    no_cache: bool,
+    no_store: bool,
+    no_transform: bool,
+    must_revalidate: bool,
+    immutable: bool,
+}
+
+impl CacheControl {
+    /// No directives.
+    pub fn new() -> CacheControl {
+        CacheControl::default()
+    }
+
+    /// Sets `max-age`.
+    pub fn with_max_age(mut self, max_age: Duration) -> CacheControl {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets `s-maxage`, the lifetime for shared caches.
+    pub fn with_s_maxage(mut self, s_maxage: Duration) -> CacheControl {
+        self.s_maxage = Some(s_maxage);
+        self
+    }
+
+    /// Sets `stale-while-revalidate`.
+    pub fn with_stale_while_revalidate(mut self, window: Duration) -> CacheControl {
+        self.stale_while_revalidate = Some(window);
+        self
+    }
+
+    /// Sets `public`.
+    pub fn with_public(mut self) -> CacheControl {
+        self.public = true;
+        self
+    }
+
+    /// Sets `private`.
+    pub fn with_private(mut self) -> CacheControl {
+        self.private = true;
+        self
+    }
+
+    /// Sets `no-cache`.
+    pub fn with_no_cache(mut self) -> CacheControl {
+        self.no_cache = true;
+        self
+    }
+
+    /// Sets `no-store`.
+    pub fn with_no_store(mut self) -> CacheControl {
+        self.no_store = true;
+        self
+    }
+
+    /// Sets `no-transform`.
+    pub fn with_no_transform(mut self) -> CacheControl {
+        self.no_transform = true;
+        self
+    }
+
+    /// Sets `must-revalidate`.
+    pub fn with_must_revalidate(mut self) -> CacheControl {
+        self.must_revalidate = true;
+        self
+    }
+
+    /// Sets `immutable`.
+    pub fn with_immutable(mut self) -> CacheControl {
+        self.immutable = true;
+        self
+    }
+
+    /// The `max-age` directive.
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// The `s-maxage` directive.
+    pub fn s_maxage(&self) -> Option<Duration> {
+        self.s_maxage
+    }
+
+    /// Whether `public` is set.
+    pub fn is_public(&self) -> bool {
+        self.public
+    }
+
+    /// Whether `private` is set.
+    pub fn is_private(&self) -> bool {
+        self.private
+    }
+
+    /// Whether `no-cache` is set.
+    pub fn is_no_cache(&self) -> bool {
+        self.no_cache
+    }
+
+    /// Whether `no-store` is set.
+    pub fn is_no_store(&self) -> bool {
+        self.no_store
+    }
+}
+
+impl fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = [
+            (self.public, "public"),
+            (self.private, "private"),
+            (self.no_cache, "no-cache"),
+            (self.no_store, "no-store"),
+            (self.no_transform, "no-transform"),
+            (self.must_revalidate, "must-revalidate"),
+            (self.immutable, "immutable"),
+        ];
+        let durations = [
+            (self.max_age, "max-age"),
+            (self.s_maxage, "s-maxage"),
+            (self.stale_while_revalidate, "stale-while-revalidate"),
+        ];
+        let mut first = true;
+        let mut sep = |f: &mut fmt::Formatter<'_>| {
+            let s = if first { "" } else { ", " };
+            first = false;
+            f.write_str(s)
+        };
+        for (_, name) in flags.iter().filter(|(set, _)| *set) {
+            sep(f)?;
+            f.write_str(name)?;
+        }
+        for (value, name) in durations {
+            if let Some(value) = value {
+                sep(f)?;
+                write!(f, "{name}={}", value.as_secs())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TypedHeader for CacheControl {
+    fn name() -> HeaderName {
+        header::CACHE_CONTROL
+    }
+
+    /// Unknown directives are ignored.
+    fn decode<'a>(values: impl Iterator<Item = &'a HeaderValue>) -> Option<CacheControl> {
+        let mut cc = CacheControl::default();
+        let directives = values
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim);
+        for directive in directives {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (directive, None),
+            };
+            let seconds = value
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(Duration::from_secs);
+            match name.to_ascii_lowercase().as_str() {
+                "max-age" => cc.max_age = seconds,
+                "s-maxage" => cc.s_maxage = seconds,
+                "stale-while-revalidate" => cc.stale_while_revalidate = seconds,
+                "public" => cc.public = true,
+                "private" => cc.private = true,
+                "no-cache" => cc.no_cache = true,
+                "no-store" => cc.no_store = true,
+                "no-transform" => cc.no_transform = true,
+                "must-revalidate" => cc.must_revalidate = true,
+                "immutable" => cc.immutable = true,
+                _ => {}
+            }
+        }
+        Some(cc)
+    }
+
+    fn encode(&self) -> HeaderValue {
+        HeaderValue::try_from(self.to_string()).unwrap_or_else(|_| HeaderValue::from_static(""))
+    }
+}
+
+struct Entry {
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored: Instant,
+    expires: Instant,
+}
+
+impl Entry {
+    fn matches(&self, req: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| req.get(name) == value.as_ref())
+    }
+
+    fn response(&self) -> ServiceResponse {
+        let mut resp = ServiceResponse::new(single_frame_body(self.body.clone()));
+        *resp.status_mut() = self.status;
+        *resp.headers_mut() = self.headers.clone();
+        resp.with_header(header::AGE, self.stored.elapsed().as_secs().into())
+    }
+}
+
+#[derive(Default)]
+struct Store {
+    entries: HashMap<String, Vec<Entry>>,
+    len: usize,
+}
+
+impl Store {
+    fn get(&mut self, key: &str, req: &HeaderMap) -> Option<ServiceResponse> {
+        let now = Instant::now();
+        let variants = self.entries.get_mut(key)?;
+        let before = variants.len();
+        variants.retain(|entry| entry.expires > now);
+        self.len -= before - variants.len();
+        let resp = variants
+            .iter()
+            .find(|entry| entry.matches(req))
+            .map(Entry::response);
+        if variants.is_empty() {
+            self.entries.remove(key);
+        }
+        resp
+    }
+
+    fn insert(&mut self, key: String, entry: Entry, max_entries: usize) {
+        if max_entries == 0 {
+            return;
+        }
+        let variants = self.entries.entry(key.clone()).or_default();
+        let before = variants.len();
+        variants.retain(|existing| existing.vary != entry.vary);
+        self.len -= before - variants.len();
+        if self.len >= max_entries {
+            self.evict(max_entries - 1);
+        }
+        self.entries.entry(key).or_default().push(entry);
+        self.len += 1;
+    }
+
+    /// Drops expired entries, then the oldest ones until at most `max` remain.
+    fn evict(&mut self, max: usize) {
+        let now = Instant::now();
+        for variants in self.entries.values_mut() {
+            variants.retain(|entry| entry.expires > now);
+        }
+        self.entries.retain(|_, variants| !variants.is_empty());
+        self.len = self.entries.values().map(Vec::len).sum();
+        while self.len > max {
+            let Some((key, i)) = self
+                .entries
+                .iter()
+                .flat_map(|(key, variants)| {
+                    variants
+                        .iter()
+                        .enumerate()
+                        .map(move |(i, entry)| (key, i, entry.stored))
+                })
+                .min_by_key(|(_, _, stored)| *stored)
+                .map(|(key, i, _)| (key.clone(), i))
+            else {
+                break;
+            };
+            let variants = self.entries.get_mut(&key).expect("key was just found");
+            variants.remove(i);
+            if variants.is_empty() {
+                self.entries.remove(&key);
+            }
+            self.len -= 1;
+        }
+    }
+}
+
+/// Memoizes successful GET responses in memory, keyed by path and query plus the
+/// request headers named in the response's `Vary`.
+#[derive(Clone)]
+pub struct CacheLayer {
+    store: Arc<Mutex<Store>>,
+    ttl: Duration,
+    max_entries: usize,
+    max_body: usize,
+}
+
+impl CacheLayer {
+    /// `ttl` applies when the response carries no `max-age` or `s-maxage`.
+    pub fn new(ttl: Duration) -> CacheLayer {
+        CacheLayer {
+            store: Arc::default(),
+            ttl,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            max_body: DEFAULT_MAX_BODY,
+        }
+    }
+
+    /// Caps the number of cached responses, 1024 by default; expired then oldest entries are evicted first.
+    pub fn with_max_entries(mut self, max: usize) -> CacheLayer {
+        self.max_entries = max;
+        self
+    }
+
+    /// Skips caching bodies over `max` bytes, 1 MiB by default.
+    pub fn with_max_body_size(mut self, max: usize) -> CacheLayer {
+        self.max_body = max;
+        self
+    }
+}
+
+impl<S> Layer<S> for CacheLayer {
+    type Service = Cache<S>;
+
+    fn layer(&self, inner: S) -> Cache<S> {
+        Cache {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// The service produced by `CacheLayer`.
+#[derive(Clone)]
+pub struct Cache<S> {
+    inner: S,
+    layer: CacheLayer,
+}
+
+impl<S> TowerService<Request> for Cache<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let request_cc = CacheControl::decode(req.headers().get_all(header::CACHE_CONTROL).iter());
+        let bypass = req.method() != Method::GET
+            || req.headers().contains_key(header::AUTHORIZATION)
+            || request_cc.as_ref().is_some_and(CacheControl::is_no_store);
+        if bypass {
+            return Box::pin(self.inner.call(req));
+        }
+        let key = req
+            .uri()
+            .path_and_query()
+            .map_or("/", |pq| pq.as_str())
+            .to_string();
+        let store = self.layer.store.clone();
+        let revalidate = request_cc.as_ref().is_some_and(CacheControl::is_no_cache);
+        if !revalidate && let Some(resp) = lock(&store).get(&key, req.headers()) {
+            return Box::pin(async move { Ok(resp) });
+        }
+
+        let request_headers = req.headers().clone();
+        let layer = self.layer.clone();
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let resp = fut.await?;
+            let Some(lifetime) = cacheable(&resp, layer.ttl) else {
+                return Ok(resp);
+            };
+            let Some(vary) = vary(&resp, &request_headers) else {
+                return Ok(resp);
+            };
+            let (parts, body) = resp.into_parts();
+            let body = match buffer_body(Box::new(body), layer.max_body).await {
+                Ok(body) => body,
+                Err(body) => return Ok(ServiceResponse::from_parts(parts, body)),
+            };
+            let now = Instant::now();
+            let entry = Entry {
+                vary,
+                status: parts.status,
+                headers: parts.headers.clone(),
+                body: body.clone(),
+                stored: now,
+                expires: now + lifetime,
+            };
+            lock(&store).insert(key, entry, layer.max_entries);
+            Ok(ServiceResponse::from_parts(parts, single_frame_body(body)))
+        })
+    }
+}
+
+fn lock(store: &Mutex<Store>) -> std::sync::MutexGuard<'_, Store> {
+    store.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// How long a response may be served from the cache, if at all.
+fn cacheable(resp: &ServiceResponse, ttl: Duration) -> Option<Duration> {
+    if resp.status() != StatusCode::OK || resp.headers().contains_key(header::SET_COOKIE) {
+        return None;
+    }
+    let cc = CacheControl::decode(resp.headers().get_all(header::CACHE_CONTROL).iter())?;
+    if cc.no_store || cc.private || cc.no_cache {
+        return None;
+    }
+    let lifetime = cc.s_maxage.or(cc.max_age).unwrap_or(ttl);
+    (!lifetime.is_zero()).then_some(lifetime)
+}
+
+/// `Vary: *` makes a response uncacheable.
+fn vary(resp: &ServiceResponse, req: &HeaderMap) -> Option<Vec<(HeaderName, Option<HeaderValue>)>> {
+    let mut vary = vec![];
+    for name in resp
+        .headers()
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        if name == "*" {
+            return None;
+        }
+        let name = HeaderName::try_from(name).ok()?;
+        let value = req.get(&name).cloned();
+        vary.push((name, value));
+    }
+    Some(vary)
+}
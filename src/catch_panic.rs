@@ -0,0 +1,107 @@
+use std::{
+    any::Any,
+    panic::{AssertUnwindSafe, catch_unwind},
+    sync::Arc,
+};
+
+use futures::FutureExt;
+use hyper::StatusCode;
+use tower::{Layer, Service as TowerService};
+
+use crate::{Request, ResponseExt, ServiceBoxFuture, ServiceError, ServiceResponse, trace};
+
+type PanicHandler = Arc<dyn Fn(&str) -> ServiceResponse + Send + Sync>;
+
+/// Turns a panic in the wrapped service into a response, 500 by default.
+#[derive(Clone)]
+pub struct CatchPanicLayer {
+    handler: PanicHandler,
+}
+
+impl Default for CatchPanicLayer {
+    fn default() -> CatchPanicLayer {
+        CatchPanicLayer::new()
+    }
+}
+
+impl CatchPanicLayer {
+    /// Answers panics with a plain 500.
+    pub fn new() -> CatchPanicLayer {
+        CatchPanicLayer {
+            handler: Arc::new(|_| ServiceResponse::from_status(StatusCode::INTERNAL_SERVER_ERROR)),
+        }
+    }
+
+    /// The handler receives the panic message, if the payload was a string.
+    pub fn with_handler<F>(mut self, handler: F) -> CatchPanicLayer
+    where
+        F: Fn(&str) -> ServiceResponse + Send + Sync + 'static,
+    {
+        self.handler = Arc::new(handler);
+        self
+    }
+}
+
+impl<S> Layer<S> for CatchPanicLayer {
+    type Service = CatchPanic<S>;
+
+    fn layer(&self, inner: S) -> CatchPanic<S> {
+        CatchPanic {
+            inner,
+            handler: self.handler.clone(),
+        }
+    }
+}
+
+/// The service produced by `CatchPanicLayer`.
+#[derive(Clone)]
+pub struct CatchPanic<S> {
+    inner: S,
+    handler: PanicHandler,
+}
+
+impl<S> TowerService<Request> for CatchPanic<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let handler = self.handler.clone();
+        let fut = match catch_unwind(AssertUnwindSafe(|| self.inner.call(req))) {
+            Ok(fut) => fut,
+            Err(payload) => {
+                let res = recover(&handler, payload);
+                return Box::pin(async move { Ok(res) });
+            }
+        };
+        Box::pin(async move {
+            match AssertUnwindSafe(fut).catch_unwind().await {
+                Ok(res) => res,
+                Err(payload) => Ok(recover(&handler, payload)),
+            }
+        })
+    }
+}
+
+fn recover(handler: &PanicHandler, payload: Box<dyn Any + Send>) -> ServiceResponse {
+    let message = match payload.downcast_ref::<&'static str>() {
+        Some(s) => s,
+        None => payload
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .unwrap_or("non-string panic payload"),
+    };
+    trace::service_panic(message);
+    handler(message)
+}
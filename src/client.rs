@@ -0,0 +1,224 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use futures::TryStreamExt;
+use http_body_util::BodyStream;
+use hyper::{
+    Method, Uri,
+    body::{Body, Incoming},
+};
+use hyper_util::{
+    client::legacy::{Client as HyperClient, connect::HttpConnector},
+    rt::TokioExecutor,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{Deadline, Error, ServiceResponse, X_REQUEST_TIMEOUT, make_body_from_stream};
+
+type ClientBody = crate::Body;
+type Dispatched = (hyper::Response<Incoming>, Option<OwnedSemaphorePermit>);
+
+struct ClientInner {
+    client: HyperClient<HttpConnector, ClientBody>,
+    timeout: Option<Duration>,
+    max_per_host: Option<usize>,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+/// Pooled HTTP/1.1 client for plain `http://` upstreams, shared by every clone.
+/// Responses come back as `ServiceResponse`, so they can be returned from a service
+/// as they are.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<ClientInner>,
+}
+
+/// Configures a `Client`, created with `Client::builder`.
+#[derive(Clone, Debug)]
+pub struct ClientBuilder {
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    max_idle_per_host: usize,
+    max_per_host: Option<usize>,
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client::new()
+    }
+}
+
+impl Client {
+    /// A client with the default settings.
+    pub fn new() -> Client {
+        Client::builder().build()
+    }
+
+    /// A 30 second response timeout, a 10 second connect timeout, and up to 32 idle connections per host kept for 90 seconds.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder {
+            timeout: Some(Duration::from_secs(30)),
+            connect_timeout: Some(Duration::from_secs(10)),
+            idle_timeout: Some(Duration::from_secs(90)),
+            max_idle_per_host: 32,
+            max_per_host: None,
+        }
+    }
+
+    /// Sends a GET request to `uri`.
+    pub async fn get(&self, uri: &str) -> Result<ServiceResponse, Error> {
+        self.send(Method::GET, uri, Bytes::new()).await
+    }
+
+    /// Sends a POST request with `body` to `uri`.
+    pub async fn post(&self, uri: &str, body: impl Into<Bytes>) -> Result<ServiceResponse, Error> {
+        self.send(Method::POST, uri, body).await
+    }
+
+    /// Sends a request with `method` and `body` to `uri`.
+    pub async fn send(
+        &self,
+        method: Method,
+        uri: &str,
+        body: impl Into<Bytes>,
+    ) -> Result<ServiceResponse, Error> {
+        let uri: Uri = uri.parse()?;
+        let mut req = hyper::Request::new(http_body_util::Full::new(body.into()));
+        *req.method_mut() = method;
+        *req.uri_mut() = uri;
+        self.request(req).await
+    }
+
+    /// The timeout covers waiting for a per-host slot, connecting and receiving the
+    /// response headers; the body then streams without a deadline. A `Deadline` in the
+    /// request's extensions shortens the timeout to what is left of it, and is passed
+    /// on as `X-Request-Timeout`.
+    pub async fn request<B>(&self, mut req: hyper::Request<B>) -> Result<ServiceResponse, Error>
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let mut timeout = self.inner.timeout;
+        if let Some(deadline) = req.extensions().get::<Deadline>().copied() {
+            if deadline.is_expired() {
+                return Err(Error::ClientTimeout);
+            }
+            let remaining = deadline.remaining();
+            timeout = Some(timeout.map_or(remaining, |t| t.min(remaining)));
+            req.headers_mut()
+                .insert(X_REQUEST_TIMEOUT, deadline.header_value());
+        }
+        let fut = self.dispatch(req);
+        let (resp, permit) = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, fut)
+                .await
+                .map_err(|_| Error::ClientTimeout)??,
+            None => fut.await?,
+        };
+        let (parts, body) = resp.into_parts();
+        let body = BodyStream::new(body)
+            .map_err(std::io::Error::other)
+            .map_ok(move |frame| {
+                let _ = &permit;
+                frame
+            });
+        Ok(ServiceResponse::from_parts(
+            parts,
+            make_body_from_stream(body),
+        ))
+    }
+
+    async fn dispatch<B>(&self, req: hyper::Request<B>) -> Result<Dispatched, Error>
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let permit = match self.host_slots(req.uri()) {
+            Some(slots) => Some(
+                slots
+                    .acquire_owned()
+                    .await
+                    .expect("semaphore is never closed"),
+            ),
+            None => None,
+        };
+        let (parts, body) = req.into_parts();
+        let body = BodyStream::new(body).map_err(std::io::Error::other);
+        let req = hyper::Request::from_parts(parts, make_body_from_stream(body));
+        let resp = self
+            .inner
+            .client
+            .request(req)
+            .await
+            .map_err(Error::Client)?;
+        Ok((resp, permit))
+    }
+
+    fn host_slots(&self, uri: &Uri) -> Option<Arc<Semaphore>> {
+        let max = self.inner.max_per_host?;
+        let host = uri.authority()?.as_str().to_ascii_lowercase();
+        let mut hosts = self.inner.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        Some(
+            hosts
+                .entry(host)
+                .or_insert_with(|| Arc::new(Semaphore::new(max)))
+                .clone(),
+        )
+    }
+}
+
+impl ClientBuilder {
+    /// `None` waits indefinitely for the response headers.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> ClientBuilder {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Bounds how long connecting may take; `None` waits indefinitely.
+    pub fn with_connect_timeout(mut self, timeout: Option<Duration>) -> ClientBuilder {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Closes pooled connections idle for longer than `timeout`; `None` keeps them.
+    pub fn with_idle_timeout(mut self, timeout: Option<Duration>) -> ClientBuilder {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Caps the idle connections kept per host.
+    pub fn with_max_idle_per_host(mut self, max: usize) -> ClientBuilder {
+        self.max_idle_per_host = max;
+        self
+    }
+
+    /// Caps requests in flight to each host; further requests wait for a slot. A slot
+    /// is held until the response body has been read or dropped.
+    pub fn with_max_connections_per_host(mut self, max: usize) -> ClientBuilder {
+        self.max_per_host = Some(max.max(1));
+        self
+    }
+
+    /// Builds the client.
+    pub fn build(self) -> Client {
+        let mut connector = HttpConnector::new();
+        connector.set_connect_timeout(self.connect_timeout);
+        let client = HyperClient::builder(TokioExecutor::new())
+            .pool_idle_timeout(self.idle_timeout)
+            .pool_max_idle_per_host(self.max_idle_per_host)
+            .build(connector);
+        Client {
+            inner: Arc::new(ClientInner {
+                client,
+                timeout: self.timeout,
+                max_per_host: self.max_per_host,
+                hosts: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+}
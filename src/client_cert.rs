@@ -0,0 +1,489 @@
+//! Client certificates for mutual TLS. The TLS acceptor verifies the chain during the
+//! handshake and reports it in a `TlsSession`; this module reads the identity out of
+//! the leaf certificate and lets routes require one.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+use hyper::{StatusCode, http::request::Parts};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    ConnectionInfo, Error, FromRequestParts, Request, ResponseExt, ServiceBoxFuture, ServiceError,
+    ServiceResponse,
+};
+
+type CertPredicate = Arc<dyn Fn(&PeerCertificate) -> bool + Send + Sync>;
+
+/// What a TLS acceptor learned during the handshake, returned next to the stream from
+/// acceptors passed to `Server::with_tls_acceptor`.
+#[derive(Clone, Debug, Default)]
+pub struct TlsSession {
+    peer_certificates: Vec<Vec<u8>>,
+}
+
+impl TlsSession {
+    /// A session without a client certificate.
+    pub fn new() -> TlsSession {
+        TlsSession::default()
+    }
+
+    /// The client's certificate chain as DER, leaf first. The acceptor must already
+    /// have verified it against its trusted roots; nothing here checks signatures.
+    pub fn with_peer_certificates(mut self, chain: Vec<Vec<u8>>) -> TlsSession {
+        self.peer_certificates = chain;
+        self
+    }
+
+    /// The identity in the client's certificate, if it sent a well-formed one.
+    pub fn peer_certificate(&self) -> Option<PeerCertificate> {
+        PeerCertificate::from_chain(self.peer_certificates.clone())
+    }
+}
+
+/// A verified client certificate: its chain and the names its leaf certifies.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerCertificate {
+    chain: Vec<Vec<u8>>,
+    common_name: Option<String>,
+    dns_names: Vec<String>,
+    emails: Vec<String>,
+    uris: Vec<String>,
+    ip_addresses: Vec<IpAddr>,
+}
+
+impl PeerCertificate {
+    /// Reads the leaf of `chain`; `None` when the chain is empty or the leaf is not a
+    /// well-formed X.509 certificate.
+    pub fn from_chain(chain: Vec<Vec<u8>>) -> Option<PeerCertificate> {
+        let mut cert = PeerCertificate {
+            chain,
+            common_name: None,
+            dns_names: vec![],
+            emails: vec![],
+            uris: vec![],
+            ip_addresses: vec![],
+        };
+        let leaf = cert.chain.first()?.clone();
+        cert.read_leaf(&leaf)?;
+        Some(cert)
+    }
+
+    /// The client certificate of the connection `req` arrived on.
+    pub fn from_request(req: &Request) -> Option<&PeerCertificate> {
+        ConnectionInfo::from_request(req)?.peer_certificate()
+    }
+
+    /// The chain as DER, leaf first.
+    pub fn chain(&self) -> &[Vec<u8>] {
+        &self.chain
+    }
+
+    /// The leaf certificate as DER.
+    pub fn der(&self) -> &[u8] {
+        &self.chain[0]
+    }
+
+    /// The subject's common name; the last one when there are several.
+    pub fn common_name(&self) -> Option<&str> {
+        self.common_name.as_deref()
+    }
+
+    /// The DNS names among the subject alternative names.
+    pub fn dns_names(&self) -> &[String] {
+        &self.dns_names
+    }
+
+    /// The email addresses among the subject alternative names.
+    pub fn emails(&self) -> &[String] {
+        &self.emails
+    }
+
+    /// The URIs among the subject alternative names, e.g. SPIFFE IDs.
+    pub fn uris(&self) -> &[String] {
+        &self.uris
+    }
+
+    /// The IP addresses among the subject alternative names.
+    pub fn ip_addresses(&self) -> &[IpAddr] {
+        &self.ip_addresses
+    }
+
+    // Certificate ::= SEQUENCE { tbsCertificate, signatureAlgorithm, signature }, and
+    // tbsCertificate ::= SEQUENCE { [0] version, serial, signature, issuer, validity,
+    // subject, subjectPublicKeyInfo, [1] issuerUID, [2] subjectUID, [3] extensions }.
+    fn read_leaf(&mut self, der: &[u8]) -> Option<()> {
+        let cert = Der(der).expect(SEQUENCE)?;
+        let mut tbs = Der(Der(cert).expect(SEQUENCE)?);
+        if tbs.0.first() == Some(&EXPLICIT_0) {
+            tbs.read()?;
+        }
+        tbs.expect(INTEGER)?;
+        tbs.expect(SEQUENCE)?;
+        tbs.expect(SEQUENCE)?;
+        tbs.expect(SEQUENCE)?;
+        let subject = tbs.expect(SEQUENCE)?;
+        tbs.expect(SEQUENCE)?;
+        while !tbs.0.is_empty() {
+            let (tag, value) = tbs.read()?;
+            if tag == EXPLICIT_3 {
+                self.read_extensions(Der(value).expect(SEQUENCE)?)?;
+            }
+        }
+        self.read_subject(subject)
+    }
+
+    // Name ::= SEQUENCE OF SET OF SEQUENCE { type OID, value ANY }.
+    fn read_subject(&mut self, subject: &[u8]) -> Option<()> {
+        let mut rdns = Der(subject);
+        while !rdns.0.is_empty() {
+            let mut attributes = Der(rdns.expect(SET)?);
+            while !attributes.0.is_empty() {
+                let mut attribute = Der(attributes.expect(SEQUENCE)?);
+                let oid = attribute.expect(OID)?;
+                let (tag, value) = attribute.read()?;
+                if oid == OID_COMMON_NAME && STRING_TAGS.contains(&tag) {
+                    self.common_name = Some(String::from_utf8(value.to_vec()).ok()?);
+                }
+            }
+        }
+        Some(())
+    }
+
+    // Extension ::= SEQUENCE { OID, critical BOOLEAN DEFAULT FALSE, value OCTET STRING }.
+    fn read_extensions(&mut self, extensions: &[u8]) -> Option<()> {
+        let mut extensions = Der(extensions);
+        while !extensions.0.is_empty() {
+            let mut extension = Der(extensions.expect(SEQUENCE)?);
+            let oid = extension.expect(OID)?;
+            let (mut tag, mut value) = extension.read()?;
+            if tag == BOOLEAN {
+                (tag, value) = extension.read()?;
+            }
+            if tag != OCTET_STRING {
+                return None;
+            }
+            if oid == OID_SUBJECT_ALT_NAME {
+                self.read_alt_names(Der(value).expect(SEQUENCE)?)?;
+            }
+        }
+        Some(())
+    }
+
+    // GeneralName is a CHOICE of implicitly tagged fields; the others are skipped.
+    fn read_alt_names(&mut self, names: &[u8]) -> Option<()> {
+        let mut names = Der(names);
+        while !names.0.is_empty() {
+            let (tag, value) = names.read()?;
+            let text = || {
+                let text = std::str::from_utf8(value).ok()?;
+                text.is_ascii().then(|| text.to_string())
+            };
+            match tag {
+                RFC822_NAME => self.emails.push(text()?),
+                DNS_NAME => self.dns_names.push(text()?),
+                URI => self.uris.push(text()?),
+                IP_ADDRESS => self.ip_addresses.push(match value.len() {
+                    4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(value).ok()?)),
+                    16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(value).ok()?)),
+                    _ => return None,
+                }),
+                _ => {}
+            }
+        }
+        Some(())
+    }
+}
+
+impl FromRequestParts for PeerCertificate {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<PeerCertificate, Error> {
+        parts
+            .extensions
+            .get::<ConnectionInfo>()
+            .and_then(ConnectionInfo::peer_certificate)
+            .cloned()
+            .ok_or(Error::MissingExtension("PeerCertificate"))
+    }
+}
+
+const BOOLEAN: u8 = 0x01;
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const OID: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const EXPLICIT_0: u8 = 0xa0;
+const EXPLICIT_3: u8 = 0xa3;
+const RFC822_NAME: u8 = 0x81;
+const DNS_NAME: u8 = 0x82;
+const URI: u8 = 0x86;
+const IP_ADDRESS: u8 = 0x87;
+/// UTF8String, PrintableString and IA5String.
+const STRING_TAGS: [u8; 3] = [0x0c, 0x13, 0x16];
+/// 2.5.4.3
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+/// 2.5.29.17
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+/// A DER reader over the remaining input.
+struct Der<'a>(&'a [u8]);
+
+impl<'a> Der<'a> {
+    /// The next tag and value. Certificates only use single-byte tags, and lengths
+    /// beyond four bytes can't be real.
+    fn read(&mut self) -> Option<(u8, &'a [u8])> {
+        let (&tag, rest) = self.0.split_first()?;
+        if tag & 0x1f == 0x1f {
+            return None;
+        }
+        let (&first, mut rest) = rest.split_first()?;
+        let len = if first < 0x80 {
+            first as usize
+        } else {
+            let n = (first & 0x7f) as usize;
+            if n == 0 || n > 4 || rest.len() < n {
+                return None;
+            }
+            let (bytes, tail) = rest.split_at(n);
+            rest = tail;
+            bytes.iter().fold(0, |len, &b| len << 8 | b as usize)
+        };
+        if rest.len() < len {
+            return None;
+        }
+        let (value, tail) = rest.split_at(len);
+        self.0 = tail;
+        Some((tag, value))
+    }
+
+    fn expect(&mut self, tag: u8) -> Option<&'a [u8]> {
+        let (found, value) = self.read()?;
+        (found == tag).then_some(value)
+    }
+}
+
+/// Per-route client certificate policy: `require` answers 403 without a certificate,
+/// `request` lets anonymous clients through. Either way a presented certificate must
+/// pass `with_predicate`, or the answer is 403. Whether clients are asked for a
+/// certificate at all is up to the TLS acceptor.
+#[derive(Clone)]
+pub struct ClientCertLayer {
+    required: bool,
+    predicate: Option<CertPredicate>,
+}
+
+impl ClientCertLayer {
+    /// Only requests on connections with a client certificate get through.
+    pub fn require() -> ClientCertLayer {
+        ClientCertLayer {
+            required: true,
+            predicate: None,
+        }
+    }
+
+    /// Requests without a client certificate get through as well.
+    pub fn request() -> ClientCertLayer {
+        ClientCertLayer {
+            required: false,
+            predicate: None,
+        }
+    }
+
+    /// Only accepts certificates `predicate` returns true for, e.g. ones with a given
+    /// DNS name.
+    pub fn with_predicate<F>(mut self, predicate: F) -> ClientCertLayer
+    where
+        F: Fn(&PeerCertificate) -> bool + Send + Sync + 'static,
+    {
+        self.predicate = Some(Arc::new(predicate));
+        self
+    }
+
+    fn allows(&self, req: &Request) -> bool {
+        match PeerCertificate::from_request(req) {
+            Some(cert) => self.predicate.as_ref().is_none_or(|p| p(cert)),
+            None => !self.required,
+        }
+    }
+}
+
+impl<S> Layer<S> for ClientCertLayer {
+    type Service = ClientCert<S>;
+
+    fn layer(&self, inner: S) -> ClientCert<S> {
+        ClientCert {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// The service produced by `ClientCertLayer`.
+#[derive(Clone)]
+pub struct ClientCert<S> {
+    inner: S,
+    config: ClientCertLayer,
+}
+
+impl<S> TowerService<Request> for ClientCert<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        if !self.config.allows(&req) {
+            return Box::pin(async { Ok(ServiceResponse::from_status(StatusCode::FORBIDDEN)) });
+        }
+        Box::pin(self.inner.call(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Method;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        base64, service_fn,
+        testing::{TestClient, TestRequestExt},
+    };
+
+    /// A self-signed certificate for CN=client.example with the SANs
+    /// DNS:client.example, DNS:*.example.org, email:ops@example.com,
+    /// URI:spiffe://example/svc, IP:10.0.0.1 and IP:::1, made with `openssl req -x509`.
+    const CERT: &str = "\
+        MIICFjCCAbygAwIBAgIUTbqgTx8xMUy3uH4ze0SQN/IDHOMwCgYIKoZIzj0EAwIwKzEQMA4GA1UE\
+        CgwHRXhhbXBsZTEXMBUGA1UEAwwOY2xpZW50LmV4YW1wbGUwHhcNMjYxMDE0MDgwNDQyWhcNMzYx\
+        MDExMDgwNDQyWjArMRAwDgYDVQQKDAdFeGFtcGxlMRcwFQYDVQQDDA5jbGllbnQuZXhhbXBsZTBZ\
+        MBMGByqGSM49AgEGCCqGSM49AwEHA0IABEhYL3+qJN/ipr1Nyy91FnIhk97morgd5K7SLO/Qzimc\
+        LlrSZrxYX7SuDWj0rkSLoyog5NI2QJ6HnLncA3uNSBujgb0wgbowHQYDVR0OBBYEFPDQaSC2120K\
+        H4yYS7aR+0tbxxN5MB8GA1UdIwQYMBaAFPDQaSC2120KH4yYS7aR+0tbxxN5MA8GA1UdEwEB/wQF\
+        MAMBAf8wZwYDVR0RBGAwXoIOY2xpZW50LmV4YW1wbGWCDSouZXhhbXBsZS5vcmeBD29wc0BleGFt\
+        cGxlLmNvbYYUc3BpZmZlOi8vZXhhbXBsZS9zdmOHBAoAAAGHEAAAAAAAAAAAAAAAAAAAAAEwCgYI\
+        KoZIzj0EAwIDSAAwRQIhAM4xqI3/M+2KiYGs7sm8gO0lk4b/q4ZAbxwFWMJKCwkcAiA2d2vFVUit\
+        O6b2qDNVeA2+LG2OkYCLNRuPeXErvAS13w==";
+
+    fn der() -> Vec<u8> {
+        base64::decode(CERT).unwrap()
+    }
+
+    fn with_cert(cert: Option<Vec<u8>>) -> Request {
+        let mut req = Request::test(Method::GET, "/");
+        req.extensions_mut().insert(ConnectionInfo {
+            tls: true,
+            peer_certificate: cert
+                .and_then(|der| PeerCertificate::from_chain(vec![der]))
+                .map(Arc::new),
+            ..ConnectionInfo::default()
+        });
+        req
+    }
+
+    async fn status(layer: ClientCertLayer, req: Request) -> StatusCode {
+        let service = layer.layer(service_fn(|_req: Request| async {
+            Ok::<_, ServiceError>(ServiceResponse::empty())
+        }));
+        service.oneshot(req).await.unwrap().status()
+    }
+
+    #[test]
+    fn reads_the_subject_and_alt_names() {
+        let cert = PeerCertificate::from_chain(vec![der()]).unwrap();
+        assert_eq!(cert.common_name(), Some("client.example"));
+        assert_eq!(cert.dns_names(), ["client.example", "*.example.org"]);
+        assert_eq!(cert.emails(), ["ops@example.com"]);
+        assert_eq!(cert.uris(), ["spiffe://example/svc"]);
+        assert_eq!(
+            cert.ip_addresses(),
+            [
+                "10.0.0.1".parse::<IpAddr>().unwrap(),
+                "::1".parse().unwrap()
+            ]
+        );
+        assert_eq!(cert.der(), der());
+    }
+
+    #[test]
+    fn rejects_malformed_certificates() {
+        assert!(PeerCertificate::from_chain(vec![]).is_none());
+        let der = der();
+        for len in [0, 1, 4, 100, der.len() - 1] {
+            assert!(PeerCertificate::from_chain(vec![der[..len].to_vec()]).is_none());
+        }
+        let mut long = der.clone();
+        long[1] = 0x85;
+        assert!(PeerCertificate::from_chain(vec![long]).is_none());
+    }
+
+    #[tokio::test]
+    async fn policies() {
+        let required = ClientCertLayer::require;
+        assert_eq!(
+            status(required(), with_cert(None)).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(required(), with_cert(Some(der()))).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(required(), Request::test(Method::GET, "/")).await,
+            StatusCode::FORBIDDEN
+        );
+
+        let requested = ClientCertLayer::request;
+        assert_eq!(status(requested(), with_cert(None)).await, StatusCode::OK);
+        assert_eq!(
+            status(requested(), with_cert(Some(der()))).await,
+            StatusCode::OK
+        );
+
+        let other = || requested().with_predicate(|c| c.common_name() == Some("other"));
+        assert_eq!(status(other(), with_cert(None)).await, StatusCode::OK);
+        assert_eq!(
+            status(other(), with_cert(Some(der()))).await,
+            StatusCode::FORBIDDEN
+        );
+        let spiffe =
+            required().with_predicate(|c| c.uris().iter().any(|u| u == "spiffe://example/svc"));
+        assert_eq!(status(spiffe, with_cert(Some(der()))).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn acceptors_expose_the_peer_certificate() {
+        let service = service_fn(|req: Request| async move {
+            let name = PeerCertificate::from_request(&req)
+                .and_then(PeerCertificate::common_name)
+                .unwrap_or("anonymous")
+                .to_string();
+            Ok::<_, ServiceError>(ServiceResponse::from_string(name))
+        });
+        let client = TestClient::with_server(service, |server| {
+            server.with_tls_acceptor(|io| async move {
+                Ok((io, TlsSession::new().with_peer_certificates(vec![der()])))
+            })
+        });
+        client.get("/").send().await.assert_body("client.example");
+
+        let client = TestClient::with_server(service, |server| {
+            server.with_acceptor(|io| async move { Ok(io) })
+        });
+        client.get("/").send().await.assert_body("anonymous");
+    }
+}
@@ -0,0 +1,745 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use hyper::{
+    HeaderMap, Method, StatusCode,
+    body::Frame,
+    header::{self, HeaderValue},
+};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    BodyInner, BoxedBodyStream, Error, Request, ResponseExt, ServiceBoxFuture, ServiceError,
+    ServiceResponse,
+    deflate::{Deflater, InflateError, Inflater, adler32, crc32},
+    make_body_from_stream,
+};
+
+const DEFAULT_MIN_SIZE: u64 = 1024;
+const DEFAULT_CONTENT_TYPES: [&str; 6] = [
+    "text/",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/wasm",
+    "image/svg+xml",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    pub(crate) fn from_header(value: &str) -> Option<Encoding> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    fn negotiate(headers: &HeaderMap) -> Option<Encoding> {
+        let mut best: Option<(Encoding, f32)> = None;
+        for item in headers
+            .get_all(header::ACCEPT_ENCODING)
+            .into_iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+        {
+            let mut params = item.split(';');
+            let coding = params.next().unwrap_or_default().trim();
+            let q = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let encoding = match coding {
+                "*" => Encoding::Gzip,
+                coding => match Encoding::from_header(coding) {
+                    Some(encoding) => encoding,
+                    None => continue,
+                },
+            };
+            // Ties go to gzip, which is listed first in DEFLATE-based preference order.
+            let better = best.is_none_or(|(current, best_q)| {
+                q > best_q || (q == best_q && encoding == Encoding::Gzip && current != encoding)
+            });
+            if q > 0.0 && better {
+                best = Some((encoding, q));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+}
+
+struct Encoder {
+    encoding: Encoding,
+    deflater: Deflater,
+    checksum: u32,
+    size: u32,
+    started: bool,
+}
+
+impl Encoder {
+    fn new(encoding: Encoding) -> Encoder {
+        Encoder {
+            encoding,
+            deflater: Deflater::default(),
+            checksum: match encoding {
+                Encoding::Gzip => 0,
+                Encoding::Deflate => 1,
+            },
+            size: 0,
+            started: false,
+        }
+    }
+
+    fn start(&mut self) -> Vec<u8> {
+        if std::mem::replace(&mut self.started, true) {
+            return Vec::new();
+        }
+        match self.encoding {
+            Encoding::Gzip => vec![0x1f, 0x8b, 0x08, 0, 0, 0, 0, 0, 0, 0xff],
+            Encoding::Deflate => vec![0x78, 0x01],
+        }
+    }
+
+    fn encode(&mut self, data: &[u8]) -> Bytes {
+        self.checksum = match self.encoding {
+            Encoding::Gzip => crc32(self.checksum, data),
+            Encoding::Deflate => adler32(self.checksum, data),
+        };
+        self.size = self.size.wrapping_add(data.len() as u32);
+        let mut out = self.start();
+        out.extend(self.deflater.compress(data));
+        out.into()
+    }
+
+    fn finish(&mut self) -> Bytes {
+        let mut out = self.start();
+        out.extend(self.deflater.finish());
+        match self.encoding {
+            Encoding::Gzip => {
+                out.extend(self.checksum.to_le_bytes());
+                out.extend(self.size.to_le_bytes());
+            }
+            Encoding::Deflate => out.extend(self.checksum.to_be_bytes()),
+        }
+        out.into()
+    }
+}
+
+struct CompressedBody {
+    inner: BoxedBodyStream,
+    encoder: Encoder,
+    trailers: Option<Frame<Bytes>>,
+    done: bool,
+}
+
+impl Stream for CompressedBody {
+    type Item = BodyInner;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<BodyInner>> {
+        if let Some(trailers) = self.trailers.take() {
+            return Poll::Ready(Some(Ok(trailers)));
+        }
+        loop {
+            if self.done {
+                return Poll::Ready(None);
+            }
+            let frame = match futures::ready!(self.inner.poll_next_unpin(cx)) {
+                Some(Ok(frame)) => frame,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => {
+                    self.done = true;
+                    return Poll::Ready(Some(Ok(Frame::data(self.encoder.finish()))));
+                }
+            };
+            match frame.into_data() {
+                Ok(data) if data.is_empty() => continue,
+                Ok(data) => return Poll::Ready(Some(Ok(Frame::data(self.encoder.encode(&data))))),
+                Err(trailers) => {
+                    self.done = true;
+                    self.trailers = Some(trailers);
+                    return Poll::Ready(Some(Ok(Frame::data(self.encoder.finish()))));
+                }
+            }
+        }
+    }
+}
+
+/// Compresses responses with gzip or deflate, as the client's `Accept-Encoding` prefers.
+#[derive(Clone, Debug)]
+pub struct CompressionLayer {
+    min_size: u64,
+    content_types: Arc<[String]>,
+}
+
+impl Default for CompressionLayer {
+    fn default() -> CompressionLayer {
+        CompressionLayer::new()
+    }
+}
+
+impl CompressionLayer {
+    /// Compresses text, JSON, JavaScript, XML, wasm and SVG bodies of at least 1 KiB.
+    pub fn new() -> CompressionLayer {
+        CompressionLayer {
+            min_size: DEFAULT_MIN_SIZE,
+            content_types: DEFAULT_CONTENT_TYPES.map(String::from).into(),
+        }
+    }
+
+    /// Leaves bodies declared smaller than `min_size` bytes uncompressed.
+    pub fn with_min_size(mut self, min_size: u64) -> CompressionLayer {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Sets the media types worth compressing.
+    pub fn with_content_types(
+        mut self,
+        content_types: impl IntoIterator<Item = impl Into<String>>,
+    ) -> CompressionLayer {
+        self.content_types = content_types.into_iter().map(Into::into).collect();
+        self
+    }
+}
+
+impl<S> Layer<S> for CompressionLayer {
+    type Service = Compression<S>;
+
+    fn layer(&self, inner: S) -> Compression<S> {
+        Compression {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// The service produced by `CompressionLayer`.
+#[derive(Clone)]
+pub struct Compression<S> {
+    inner: S,
+    config: CompressionLayer,
+}
+
+impl<S> TowerService<Request> for Compression<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let encoding = match req.method() {
+            &Method::HEAD => None,
+            _ => Encoding::negotiate(req.headers()),
+        };
+        let fut = self.inner.call(req);
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let mut resp = fut.await?;
+            if !content_type_allowed(&config, resp.headers()) {
+                return Ok(resp);
+            }
+            if !header_contains(resp.headers(), header::VARY, "accept-encoding") {
+                resp.headers_mut()
+                    .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+            }
+            let Some(encoding) = encoding else {
+                return Ok(resp);
+            };
+            if !compressible(&config, &resp) {
+                return Ok(resp);
+            }
+
+            let (mut parts, body) = resp.into_parts();
+            parts.headers.remove(header::CONTENT_LENGTH);
+            parts.headers.remove(header::ACCEPT_RANGES);
+            parts.headers.insert(
+                header::CONTENT_ENCODING,
+                HeaderValue::from_static(encoding.as_str()),
+            );
+            let body = CompressedBody {
+                inner: Box::new(body),
+                encoder: Encoder::new(encoding),
+                trailers: None,
+                done: false,
+            };
+            Ok(ServiceResponse::from_parts(
+                parts,
+                make_body_from_stream(body),
+            ))
+        })
+    }
+}
+
+fn compressible(config: &CompressionLayer, resp: &ServiceResponse) -> bool {
+    let status = resp.status();
+    if status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+        || status == StatusCode::PARTIAL_CONTENT
+    {
+        return false;
+    }
+    let headers = resp.headers();
+    if headers.contains_key(header::CONTENT_ENCODING)
+        || headers.contains_key(header::CONTENT_RANGE)
+        || header_contains(headers, header::CACHE_CONTROL, "no-transform")
+    {
+        return false;
+    }
+    let too_small = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<u64>().ok())
+        .is_some_and(|len| len < config.min_size);
+    !too_small && content_type_allowed(config, headers)
+}
+
+fn content_type_allowed(config: &CompressionLayer, headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    config
+        .content_types
+        .iter()
+        .any(|allowed| match allowed.ends_with('/') {
+            true => essence.starts_with(allowed.as_str()),
+            false => essence == *allowed,
+        })
+}
+
+fn header_contains(headers: &HeaderMap, name: header::HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .into_iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim().eq_ignore_ascii_case(token))
+}
+
+const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 16 << 20;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DecompressionLimit(pub(crate) usize);
+
+pub(crate) struct Decoder {
+    encoding: Encoding,
+    inflater: Inflater,
+    header: Option<Vec<u8>>,
+    trailer: Vec<u8>,
+    /// Raw deflate streams (sent by some clients as `deflate`) carry no checksum.
+    raw: bool,
+    checksum: u32,
+    size: usize,
+    limit: usize,
+}
+
+impl Decoder {
+    pub(crate) fn new(encoding: Encoding, limit: usize) -> Decoder {
+        Decoder {
+            encoding,
+            inflater: Inflater::default(),
+            header: Some(Vec::new()),
+            trailer: Vec::new(),
+            raw: false,
+            checksum: match encoding {
+                Encoding::Gzip => 0,
+                Encoding::Deflate => 1,
+            },
+            size: 0,
+            limit,
+        }
+    }
+
+    pub(crate) fn decode(&mut self, data: &[u8]) -> Result<Bytes, Error> {
+        let mut data = data.to_vec();
+        if let Some(mut header) = self.header.take() {
+            header.extend_from_slice(&data);
+            match self.header_len(&header)? {
+                Some(len) => data = header.split_off(len),
+                None => {
+                    self.header = Some(header);
+                    return Ok(Bytes::new());
+                }
+            }
+        }
+
+        if self.inflater.is_done() {
+            self.trailer.extend_from_slice(&data);
+            return Ok(Bytes::new());
+        }
+        let mut out = Vec::new();
+        self.inflater
+            .inflate(&data, &mut out, self.limit - self.size)
+            .map_err(|e| match e {
+                InflateError::TooLarge => Error::DecompressedTooLarge { limit: self.limit },
+                InflateError::Invalid(reason) => Error::Decompression(reason),
+            })?;
+        self.size += out.len();
+        self.checksum = match self.encoding {
+            Encoding::Gzip => crc32(self.checksum, &out),
+            Encoding::Deflate => adler32(self.checksum, &out),
+        };
+        if self.inflater.is_done() {
+            self.trailer = self.inflater.take_remaining();
+        }
+        Ok(out.into())
+    }
+
+    pub(crate) fn finish(&self) -> Result<(), Error> {
+        if !self.inflater.is_done() {
+            return Err(Error::Decompression("truncated compressed body"));
+        }
+        let valid = match (self.encoding, self.raw) {
+            (_, true) => true,
+            (Encoding::Gzip, false) => {
+                self.trailer.len() >= 8
+                    && self.trailer[..4] == self.checksum.to_le_bytes()
+                    && self.trailer[4..8] == (self.size as u32).to_le_bytes()
+            }
+            (Encoding::Deflate, false) => {
+                self.trailer.len() >= 4 && self.trailer[..4] == self.checksum.to_be_bytes()
+            }
+        };
+        match valid {
+            true => Ok(()),
+            false => Err(Error::Decompression("checksum mismatch")),
+        }
+    }
+
+    fn header_len(&mut self, buf: &[u8]) -> Result<Option<usize>, Error> {
+        match self.encoding {
+            Encoding::Gzip => gzip_header_len(buf),
+            Encoding::Deflate => {
+                if buf.len() < 2 {
+                    return Ok(None);
+                }
+                let (cmf, flg) = (buf[0], buf[1]);
+                let zlib = cmf & 0x0f == 8 && (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0;
+                if zlib && flg & 0x20 != 0 {
+                    return Err(Error::Decompression(
+                        "preset dictionaries are not supported",
+                    ));
+                }
+                self.raw = !zlib;
+                Ok(Some(if zlib { 2 } else { 0 }))
+            }
+        }
+    }
+}
+
+fn gzip_header_len(buf: &[u8]) -> Result<Option<usize>, Error> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    if buf.len() < 10 {
+        return Ok(None);
+    }
+    if buf[..3] != [0x1f, 0x8b, 0x08] {
+        return Err(Error::Decompression("invalid gzip header"));
+    }
+    let flags = buf[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let Some(len) = buf.get(pos..pos + 2) else {
+            return Ok(None);
+        };
+        pos += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            match buf
+                .get(pos..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+            {
+                Some(nul) => pos += nul + 1,
+                None => return Ok(None),
+            }
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    Ok((pos <= buf.len()).then_some(pos))
+}
+
+pub(crate) fn decode_stream<S>(
+    stream: S,
+    decoder: Decoder,
+) -> impl Stream<Item = Result<Bytes, Error>>
+where
+    S: Stream<Item = Result<Bytes, Error>> + Unpin,
+{
+    futures::stream::unfold(Some((stream, decoder)), |state| async move {
+        let (mut stream, mut decoder) = state?;
+        loop {
+            let res = match stream.next().await {
+                Some(Ok(data)) => match decoder.decode(&data) {
+                    Ok(out) if out.is_empty() => continue,
+                    res => res,
+                },
+                Some(Err(e)) => Err(e),
+                None => return decoder.finish().err().map(|e| (Err(e), None)),
+            };
+            let next = res.is_ok().then_some((stream, decoder));
+            return Some((res, next));
+        }
+    })
+}
+
+pub(crate) fn content_encoding(headers: &HeaderMap) -> Result<Option<Encoding>, ()> {
+    let Some(value) = headers.get(header::CONTENT_ENCODING) else {
+        return Ok(None);
+    };
+    let value = value.to_str().map_err(|_| ())?.trim();
+    if value.is_empty() || value.eq_ignore_ascii_case("identity") {
+        return Ok(None);
+    }
+    Encoding::from_header(value).map(Some).ok_or(())
+}
+
+/// Decodes gzip and deflate request bodies as they are read through `collect_request` and friends.
+#[derive(Clone, Copy, Debug)]
+pub struct DecompressionLayer {
+    max_size: usize,
+}
+
+impl Default for DecompressionLayer {
+    fn default() -> DecompressionLayer {
+        DecompressionLayer::new()
+    }
+}
+
+impl DecompressionLayer {
+    /// Fails bodies that decode to more than 16 MiB.
+    pub fn new() -> DecompressionLayer {
+        DecompressionLayer {
+            max_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+        }
+    }
+
+    /// Fails bodies that decode to more than `max_size` bytes.
+    pub fn with_max_size(mut self, max_size: usize) -> DecompressionLayer {
+        self.max_size = max_size;
+        self
+    }
+}
+
+impl<S> Layer<S> for DecompressionLayer {
+    type Service = Decompression<S>;
+
+    fn layer(&self, inner: S) -> Decompression<S> {
+        Decompression {
+            inner,
+            max_size: self.max_size,
+        }
+    }
+}
+
+/// The service produced by `DecompressionLayer`.
+#[derive(Clone)]
+pub struct Decompression<S> {
+    inner: S,
+    max_size: usize,
+}
+
+impl<S> TowerService<Request> for Decompression<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        if content_encoding(req.headers()).is_err() {
+            let resp = ServiceResponse::from_status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                .with_header(
+                    header::ACCEPT_ENCODING,
+                    HeaderValue::from_static("gzip, deflate"),
+                );
+            return Box::pin(async move { Ok(resp) });
+        }
+        req.extensions_mut()
+            .insert(DecompressionLimit(self.max_size));
+        Box::pin(self.inner.call(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use super::*;
+    use crate::{
+        collect_request,
+        deflate::tests::{LYRICS_DYNAMIC, lyrics, unhex},
+        service_fn,
+        testing::TestClient,
+    };
+
+    // Made by Python's zlib, with every optional gzip header field.
+    const GZIP: &str = concat!(
+        "1f8b081e0000000000030400616263646e616d652e747874006120636f6d6d656e740077a84bafca2c5028cf",
+        "2cc950482d4b2daa54c8484d4c492d5248cb4ccd4901002ffb11751c000000",
+    );
+    const ZLIB: &str = "789ccb48cdc9c9d751a8cac94c52c8406703009c2a0a77";
+
+    fn decode(encoding: Encoding, data: &[u8], chunk: usize, limit: usize) -> Result<Bytes, Error> {
+        let mut decoder = Decoder::new(encoding, limit);
+        let mut out = BytesMut::new();
+        for piece in data.chunks(chunk) {
+            out.extend_from_slice(&decoder.decode(piece)?);
+        }
+        decoder.finish()?;
+        Ok(out.freeze())
+    }
+
+    #[test]
+    fn decodes_gzip_zlib_and_raw_deflate() {
+        for chunk in [1, 5, 1000] {
+            assert_eq!(
+                decode(Encoding::Gzip, &unhex(GZIP), chunk, 1000).unwrap(),
+                "gzip with every header field"
+            );
+            assert_eq!(
+                decode(Encoding::Deflate, &unhex(ZLIB), chunk, 1000).unwrap(),
+                "hello, zlib hello, zlib hello"
+            );
+            assert_eq!(
+                decode(Encoding::Deflate, &unhex(LYRICS_DYNAMIC), chunk, 1 << 20).unwrap(),
+                lyrics()
+            );
+        }
+    }
+
+    #[test]
+    fn checks_trailers() {
+        let mut gzip = unhex(GZIP);
+        let crc = gzip.len() - 8;
+        gzip[crc] ^= 1;
+        assert!(matches!(
+            decode(Encoding::Gzip, &gzip, 7, 1000),
+            Err(Error::Decompression("checksum mismatch"))
+        ));
+        let gzip = unhex(GZIP);
+        assert!(matches!(
+            decode(Encoding::Gzip, &gzip[..gzip.len() - 1], 7, 1000),
+            Err(Error::Decompression("checksum mismatch"))
+        ));
+        let zlib = unhex(ZLIB);
+        assert!(matches!(
+            decode(Encoding::Deflate, &zlib[..zlib.len() - 6], 7, 1000),
+            Err(Error::Decompression("truncated compressed body"))
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_headers_and_large_output() {
+        assert!(matches!(
+            decode(Encoding::Gzip, b"not gzip at all", 4, 1000),
+            Err(Error::Decompression("invalid gzip header"))
+        ));
+        // A zlib header asking for a preset dictionary.
+        assert!(matches!(
+            decode(Encoding::Deflate, &[0x78, 0xbb, 0, 0], 4, 1000),
+            Err(Error::Decompression(_))
+        ));
+        assert!(matches!(
+            decode(Encoding::Deflate, &unhex(ZLIB), 4, 10),
+            Err(Error::DecompressedTooLarge { limit: 10 })
+        ));
+    }
+
+    fn echo(layer: DecompressionLayer) -> TestClient {
+        TestClient::new(layer.layer(service_fn(|req: Request| async move {
+            let body = collect_request(req, 1 << 20).await?;
+            Ok::<_, ServiceError>(body)
+        })))
+    }
+
+    #[tokio::test]
+    async fn decompresses_request_bodies() {
+        let client = echo(DecompressionLayer::new());
+        client
+            .post("/")
+            .header("content-encoding", "gzip")
+            .body(unhex(GZIP))
+            .send()
+            .await
+            .assert_status(StatusCode::OK)
+            .assert_body("gzip with every header field");
+        client
+            .post("/")
+            .body("plain")
+            .send()
+            .await
+            .assert_body("plain");
+        client
+            .post("/")
+            .header("content-encoding", "br")
+            .body("?")
+            .send()
+            .await
+            .assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+            .assert_header("accept-encoding", "gzip, deflate");
+        client
+            .post("/")
+            .header("content-encoding", "gzip")
+            .body("not gzip")
+            .send()
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn limits_the_decompressed_size() {
+        let client = echo(DecompressionLayer::new().with_max_size(100));
+        client
+            .post("/")
+            .header("content-encoding", "deflate")
+            .body(unhex(LYRICS_DYNAMIC))
+            .send()
+            .await
+            .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,179 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::StreamExt;
+use hyper::StatusCode;
+use tokio::sync::Semaphore;
+use tower::{Layer, Service as TowerService};
+
+use crate::{Request, ResponseExt, ServiceBoxFuture, ServiceError, ServiceResponse};
+
+/// Each layer owns one semaphore, shared by every service it wraps: apply it to the
+/// whole `Service` for a global cap, or build one per route.
+#[derive(Clone, Debug)]
+pub struct ConcurrencyLimitLayer {
+    permits: Arc<Semaphore>,
+    queue_timeout: Option<Duration>,
+}
+
+impl ConcurrencyLimitLayer {
+    /// Rejects requests with 503 as soon as `max` are in flight.
+    pub fn new(max: usize) -> ConcurrencyLimitLayer {
+        ConcurrencyLimitLayer {
+            permits: Arc::new(Semaphore::new(max)),
+            queue_timeout: None,
+        }
+    }
+
+    /// Waits up to `timeout` for a slot before rejecting.
+    pub fn with_queue_timeout(mut self, timeout: Duration) -> ConcurrencyLimitLayer {
+        self.queue_timeout = Some(timeout);
+        self
+    }
+
+    /// The slots currently free.
+    pub fn available(&self) -> usize {
+        self.permits.available_permits()
+    }
+}
+
+impl<S> Layer<S> for ConcurrencyLimitLayer {
+    type Service = ConcurrencyLimit<S>;
+
+    fn layer(&self, inner: S) -> ConcurrencyLimit<S> {
+        ConcurrencyLimit {
+            inner,
+            permits: self.permits.clone(),
+            queue_timeout: self.queue_timeout,
+        }
+    }
+}
+
+/// The service produced by `ConcurrencyLimitLayer`.
+#[derive(Clone)]
+pub struct ConcurrencyLimit<S> {
+    inner: S,
+    permits: Arc<Semaphore>,
+    queue_timeout: Option<Duration>,
+}
+
+impl<S> TowerService<Request> for ConcurrencyLimit<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let permits = self.permits.clone();
+        let queue_timeout = self.queue_timeout;
+        Box::pin(async move {
+            let permit = match queue_timeout {
+                None => permits.try_acquire_owned().ok(),
+                Some(timeout) => tokio::time::timeout(timeout, permits.acquire_owned())
+                    .await
+                    .ok()
+                    .and_then(Result::ok),
+            };
+            let Some(permit) = permit else {
+                return Ok(ServiceResponse::from_status(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                ));
+            };
+            let resp = inner.call(req).await?;
+            // A streaming response is still in flight until its body ends.
+            Ok(resp.map_body(move |body| {
+                body.inspect(move |_| {
+                    let _ = &permit;
+                })
+            }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Method;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{service_fn, testing::TestRequestExt};
+
+    #[tokio::test]
+    async fn permit_is_held_until_the_body_ends() {
+        let layer = ConcurrencyLimitLayer::new(1);
+        let (resp, mut writer) = ServiceResponse::writer();
+        let resp = Arc::new(std::sync::Mutex::new(Some(resp)));
+        let service = layer.layer(service_fn(move |_: Request| {
+            let resp = resp.lock().unwrap().take();
+            async move {
+                Ok::<_, ServiceError>(resp.unwrap_or_else(|| ServiceResponse::from_string("done")))
+            }
+        }));
+
+        let streaming = service
+            .clone()
+            .oneshot(Request::test(Method::GET, "/"))
+            .await
+            .unwrap();
+        assert_eq!(layer.available(), 0);
+        let rejected = service
+            .clone()
+            .oneshot(Request::test(Method::GET, "/"))
+            .await
+            .unwrap();
+        assert_eq!(rejected.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        writer.send("chunk".into()).await.unwrap();
+        tokio::io::AsyncWriteExt::shutdown(&mut writer)
+            .await
+            .unwrap();
+        assert_eq!(streaming.into_bytes().await.unwrap(), "chunk");
+        assert_eq!(layer.available(), 1);
+
+        let resp = service
+            .oneshot(Request::test(Method::GET, "/"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        // A body dropped unread gives the permit back too.
+        drop(resp);
+        assert_eq!(layer.available(), 1);
+    }
+
+    #[tokio::test]
+    async fn queues_up_to_the_timeout() {
+        let layer = ConcurrencyLimitLayer::new(1).with_queue_timeout(Duration::from_millis(20));
+        let service = layer.layer(service_fn(|_: Request| async {
+            Ok::<_, ServiceError>(ServiceResponse::empty())
+        }));
+        let held = service
+            .clone()
+            .oneshot(Request::test(Method::GET, "/"))
+            .await
+            .unwrap();
+        let rejected = service
+            .clone()
+            .oneshot(Request::test(Method::GET, "/"))
+            .await
+            .unwrap();
+        assert_eq!(rejected.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let waiting = tokio::spawn(service.oneshot(Request::test(Method::GET, "/")));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        drop(held);
+        assert_eq!(waiting.await.unwrap().unwrap().status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    fmt::Write as _,
+    fs::Metadata,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hyper::{
+    HeaderMap, Method, StatusCode,
+    header::{self, HeaderValue},
+};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    EntityTag, IfNoneMatch, Request, RequestExt, ResponseExt, ServiceBoxFuture, ServiceError,
+    ServiceResponse, TypedHeader, body::buffer_body, date::DateTime, sha256::sha256,
+    single_frame_body,
+};
+
+const DEFAULT_MAX_BUFFER: usize = 1 << 20;
+
+/// Headers a 304 must repeat from the response it stands in for.
+const NOT_MODIFIED_HEADERS: [header::HeaderName; 6] = [
+    header::CACHE_CONTROL,
+    header::CONTENT_LOCATION,
+    header::DATE,
+    header::ETAG,
+    header::EXPIRES,
+    header::VARY,
+];
+
+/// `time` formatted as an IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn http_date(time: SystemTime) -> String {
+    DateTime::from_system(time).http()
+}
+
+/// Parses an HTTP date in any of the three formats RFC 9110 allows.
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    DateTime::parse_http(value)
+}
+
+/// A strong ETag derived from the SHA-256 of `body`.
+pub fn etag_for_bytes(body: &[u8]) -> EntityTag {
+    let mut tag = String::with_capacity(32);
+    for b in &sha256(body)[..16] {
+        let _ = write!(tag, "{b:02x}");
+    }
+    EntityTag::strong(tag)
+}
+
+/// Derived from size and modification time, so only weakly identifies the content.
+pub fn etag_for_metadata(meta: &Metadata) -> EntityTag {
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    EntityTag::weak(format!(
+        "{:x}-{:x}.{:x}",
+        meta.len(),
+        modified.as_secs(),
+        modified.subsec_nanos()
+    ))
+}
+
+/// Whether the client's cached copy is still current. `If-None-Match` takes precedence
+/// over `If-Modified-Since`, and only GET and HEAD requests are considered.
+pub fn is_fresh(
+    req: &Request,
+    etag: Option<&EntityTag>,
+    last_modified: Option<SystemTime>,
+) -> bool {
+    let if_modified_since = req
+        .headers()
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_http_date);
+    matches!(*req.method(), Method::GET | Method::HEAD)
+        && evaluate(
+            req.if_none_match().as_ref(),
+            if_modified_since,
+            etag,
+            last_modified,
+        )
+}
+
+fn evaluate(
+    if_none_match: Option<&IfNoneMatch>,
+    if_modified_since: Option<SystemTime>,
+    etag: Option<&EntityTag>,
+    last_modified: Option<SystemTime>,
+) -> bool {
+    match (if_none_match, etag) {
+        (Some(IfNoneMatch::Any), _) => true,
+        (Some(condition), Some(etag)) => condition.matches(etag),
+        (Some(_), None) => false,
+        (None, _) => match (if_modified_since, last_modified) {
+            // HTTP dates have second resolution.
+            (Some(since), Some(modified)) => seconds(modified) <= seconds(since),
+            _ => false,
+        },
+    }
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// A `304 Not Modified` response carrying the validators it was checked against.
+pub fn not_modified(
+    etag: Option<&EntityTag>,
+    last_modified: Option<SystemTime>,
+) -> ServiceResponse {
+    let mut resp = ServiceResponse::empty().with_status(StatusCode::NOT_MODIFIED);
+    if let Some(etag) = etag {
+        resp = resp.with_typed_header(etag);
+    }
+    if let Some(modified) = last_modified {
+        resp = resp.with_header(header::LAST_MODIFIED, last_modified_value(modified));
+    }
+    resp
+}
+
+pub(crate) fn last_modified_value(time: SystemTime) -> HeaderValue {
+    HeaderValue::try_from(http_date(time)).unwrap_or_else(|_| HeaderValue::from_static(""))
+}
+
+/// Adds strong ETags to successful GET and HEAD responses that lack one, buffering
+/// bodies up to `max_buffer` bytes, and answers fresh conditional requests with 304.
+#[derive(Clone, Copy, Debug)]
+pub struct ConditionalLayer {
+    max_buffer: usize,
+}
+
+impl Default for ConditionalLayer {
+    fn default() -> ConditionalLayer {
+        ConditionalLayer::new()
+    }
+}
+
+impl ConditionalLayer {
+    /// Buffers bodies of up to 1 MiB to compute ETags.
+    pub fn new() -> ConditionalLayer {
+        ConditionalLayer {
+            max_buffer: DEFAULT_MAX_BUFFER,
+        }
+    }
+
+    /// Buffers bodies of up to `max` bytes to compute ETags; larger ones pass through untagged.
+    pub fn with_max_buffer(mut self, max: usize) -> ConditionalLayer {
+        self.max_buffer = max;
+        self
+    }
+}
+
+impl<S> Layer<S> for ConditionalLayer {
+    type Service = Conditional<S>;
+
+    fn layer(&self, inner: S) -> Conditional<S> {
+        Conditional {
+            inner,
+            max_buffer: self.max_buffer,
+        }
+    }
+}
+
+/// The service produced by `ConditionalLayer`.
+#[derive(Clone)]
+pub struct Conditional<S> {
+    inner: S,
+    max_buffer: usize,
+}
+
+impl<S> TowerService<Request> for Conditional<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        if !matches!(*req.method(), Method::GET | Method::HEAD) {
+            return Box::pin(self.inner.call(req));
+        }
+        let if_none_match = req.if_none_match();
+        let if_modified_since = req
+            .headers()
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_http_date);
+        let max_buffer = self.max_buffer;
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let mut resp = fut.await?;
+            if resp.status() != StatusCode::OK {
+                return Ok(resp);
+            }
+            let mut etag = EntityTag::decode(resp.headers().get_all(header::ETAG).iter());
+            if etag.is_none() && !resp.headers().contains_key(header::ETAG) {
+                let (parts, body) = resp.into_parts();
+                resp = match buffer_body(Box::new(body), max_buffer).await {
+                    Ok(body) => {
+                        let computed = etag_for_bytes(&body);
+                        etag = Some(computed.clone());
+                        ServiceResponse::from_parts(parts, single_frame_body(body))
+                            .with_typed_header(&computed)
+                    }
+                    Err(body) => ServiceResponse::from_parts(parts, body),
+                };
+            }
+            let last_modified = resp
+                .headers()
+                .get(header::LAST_MODIFIED)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_http_date);
+            match evaluate(
+                if_none_match.as_ref(),
+                if_modified_since,
+                etag.as_ref(),
+                last_modified,
+            ) {
+                true => Ok(not_modified_from(resp.headers())),
+                false => Ok(resp),
+            }
+        })
+    }
+}
+
+fn not_modified_from(headers: &HeaderMap) -> ServiceResponse {
+    let mut resp = ServiceResponse::empty().with_status(StatusCode::NOT_MODIFIED);
+    for name in NOT_MODIFIED_HEADERS.iter().chain([&header::LAST_MODIFIED]) {
+        for value in headers.get_all(name) {
+            resp.headers_mut().append(name, value.clone());
+        }
+    }
+    resp
+}
@@ -0,0 +1,303 @@
+use std::time::Duration;
+
+use hyper_util::{
+    rt::{TokioExecutor, TokioTimer},
+    server::conn::auto,
+};
+
+/// HTTP/1 connection settings, applied through `Server::with_http1`.
+#[derive(Clone, Debug)]
+pub struct Http1Config {
+    pub(crate) keep_alive: bool,
+    pub(crate) keep_alive_timeout: Option<Duration>,
+    pub(crate) max_requests: Option<usize>,
+    title_case_headers: bool,
+    preserve_header_case: bool,
+}
+
+impl Default for Http1Config {
+    fn default() -> Http1Config {
+        Http1Config {
+            keep_alive: true,
+            keep_alive_timeout: None,
+            max_requests: None,
+            title_case_headers: false,
+            preserve_header_case: false,
+        }
+    }
+}
+
+impl Http1Config {
+    /// Keep-alive on, with no idle timeout or request cap.
+    pub fn new() -> Http1Config {
+        Http1Config::default()
+    }
+
+    /// With keep-alive off every connection closes after its first response.
+    pub fn with_keep_alive(mut self, enabled: bool) -> Http1Config {
+        self.keep_alive = enabled;
+        self
+    }
+
+    /// Closes connections left idle between requests for this long. The header read
+    /// timeout also runs while idle, so the shorter of the two applies.
+    pub fn with_keep_alive_timeout(mut self, timeout: Duration) -> Http1Config {
+        self.keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    /// Sends `Connection: close` with the response to the `max`th request.
+    pub fn with_max_requests(mut self, max: usize) -> Http1Config {
+        self.max_requests = Some(max.max(1));
+        self
+    }
+
+    /// Writes response header names in Title-Case instead of lowercase.
+    pub fn with_title_case_headers(mut self, enabled: bool) -> Http1Config {
+        self.title_case_headers = enabled;
+        self
+    }
+
+    /// Writes response headers with the casing of the request they answer, for clients
+    /// that wrongly treat header names as case-sensitive.
+    pub fn with_preserve_header_case(mut self, enabled: bool) -> Http1Config {
+        self.preserve_header_case = enabled;
+        self
+    }
+
+    pub(crate) fn apply(&self, builder: &mut auto::Builder<TokioExecutor>) {
+        builder
+            .http1()
+            .keep_alive(self.keep_alive)
+            .title_case_headers(self.title_case_headers)
+            .preserve_header_case(self.preserve_header_case);
+    }
+}
+
+/// HTTP/2 connection settings, applied through `Server::with_http2`. Unset values keep hyper's defaults.
+#[derive(Clone, Debug, Default)]
+pub struct Http2Config {
+    max_concurrent_streams: Option<u32>,
+    initial_stream_window_size: Option<u32>,
+    initial_connection_window_size: Option<u32>,
+    adaptive_window: bool,
+    keep_alive_interval: Option<Duration>,
+    keep_alive_timeout: Option<Duration>,
+}
+
+impl Http2Config {
+    /// hyper's HTTP/2 defaults.
+    pub fn new() -> Http2Config {
+        Http2Config::default()
+    }
+
+    /// Caps the streams a client may have open on one connection.
+    pub fn with_max_concurrent_streams(mut self, max: u32) -> Http2Config {
+        self.max_concurrent_streams = Some(max);
+        self
+    }
+
+    /// Sets the initial flow-control window of each stream, in bytes.
+    pub fn with_initial_stream_window_size(mut self, size: u32) -> Http2Config {
+        self.initial_stream_window_size = Some(size);
+        self
+    }
+
+    /// Sets the initial flow-control window of the connection, in bytes.
+    pub fn with_initial_connection_window_size(mut self, size: u32) -> Http2Config {
+        self.initial_connection_window_size = Some(size);
+        self
+    }
+
+    /// Sizes flow-control windows from measured bandwidth-delay product, overriding the initial sizes.
+    pub fn with_adaptive_window(mut self, enabled: bool) -> Http2Config {
+        self.adaptive_window = enabled;
+        self
+    }
+
+    /// Sends a PING after the connection has been idle for `interval`.
+    pub fn with_keep_alive_interval(mut self, interval: Duration) -> Http2Config {
+        self.keep_alive_interval = Some(interval);
+        self
+    }
+
+    /// Closes the connection when a keep-alive PING isn't answered within `timeout`.
+    pub fn with_keep_alive_timeout(mut self, timeout: Duration) -> Http2Config {
+        self.keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    pub(crate) fn apply(&self, builder: &mut auto::Builder<TokioExecutor>) {
+        let mut http2 = builder.http2();
+        http2
+            .timer(TokioTimer::new())
+            .max_concurrent_streams(self.max_concurrent_streams)
+            .initial_stream_window_size(self.initial_stream_window_size)
+            .initial_connection_window_size(self.initial_connection_window_size)
+            .adaptive_window(self.adaptive_window)
+            .keep_alive_interval(self.keep_alive_interval);
+        if let Some(timeout) = self.keep_alive_timeout {
+            http2.keep_alive_timeout(timeout);
+        }
+    }
+}
+
+/// What to do with connections beyond a `ConnectionLimits` cap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Stop accepting until a slot frees, leaving the excess in the kernel backlog.
+    #[default]
+    Queue,
+    /// Accept and immediately close the excess connections.
+    Reject,
+}
+
+/// Caps on concurrent connections, in total and per client address.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConnectionLimits {
+    pub(crate) max_connections: Option<usize>,
+    pub(crate) max_per_ip: Option<usize>,
+    pub(crate) overflow: Overflow,
+}
+
+impl ConnectionLimits {
+    /// No limits.
+    pub fn new() -> ConnectionLimits {
+        ConnectionLimits::default()
+    }
+
+    /// Caps the connections open at once across all clients.
+    pub fn with_max_connections(mut self, max: usize) -> ConnectionLimits {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Caps the connections open at once from a single IP address.
+    pub fn with_max_per_ip(mut self, max: usize) -> ConnectionLimits {
+        self.max_per_ip = Some(max);
+        self
+    }
+
+    /// Sets what happens to connections over a cap.
+    pub fn with_overflow(mut self, overflow: Overflow) -> ConnectionLimits {
+        self.overflow = overflow;
+        self
+    }
+}
+
+/// Socket options for `Server::bind_with`. The defaults match `Server::bind`.
+#[derive(Clone, Copy, Debug)]
+pub struct ListenerConfig {
+    pub(crate) reuse_port: bool,
+    pub(crate) acceptors: usize,
+    pub(crate) nodelay: bool,
+    pub(crate) keepalive: Option<Duration>,
+    pub(crate) backlog: u32,
+    pub(crate) recv_buffer_size: Option<usize>,
+    pub(crate) send_buffer_size: Option<usize>,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> ListenerConfig {
+        ListenerConfig {
+            reuse_port: false,
+            acceptors: 1,
+            nodelay: false,
+            keepalive: None,
+            backlog: 1024,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+        }
+    }
+}
+
+impl ListenerConfig {
+    /// One acceptor, `TCP_NODELAY` off and a backlog of 1024.
+    pub fn new() -> ListenerConfig {
+        ListenerConfig::default()
+    }
+
+    /// Binds `acceptors` sockets to the same address with `SO_REUSEPORT` and accepts on
+    /// each from its own task, letting the kernel spread connections between them.
+    /// Usually set to the number of runtime workers; with one acceptor, other
+    /// processes can still bind the same port.
+    #[cfg(unix)]
+    pub fn with_reuse_port(mut self, acceptors: usize) -> ListenerConfig {
+        self.reuse_port = true;
+        self.acceptors = acceptors.max(1);
+        self
+    }
+
+    /// Sets `TCP_NODELAY` on accepted sockets.
+    pub fn with_nodelay(mut self, enabled: bool) -> ListenerConfig {
+        self.nodelay = enabled;
+        self
+    }
+
+    /// Idle time before TCP keepalive probes are sent on accepted connections.
+    pub fn with_keepalive(mut self, idle: Duration) -> ListenerConfig {
+        self.keepalive = Some(idle);
+        self
+    }
+
+    /// Sets the listen backlog.
+    pub fn with_backlog(mut self, backlog: u32) -> ListenerConfig {
+        self.backlog = backlog;
+        self
+    }
+
+    /// Sets `SO_RCVBUF` on accepted sockets.
+    pub fn with_recv_buffer_size(mut self, size: usize) -> ListenerConfig {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets `SO_SNDBUF` on accepted sockets.
+    pub fn with_send_buffer_size(mut self, size: usize) -> ListenerConfig {
+        self.send_buffer_size = Some(size);
+        self
+    }
+}
+
+/// Runtimes built by `Server::run`. By default that is one multi-threaded runtime
+/// with tokio's defaults: a worker per core and up to 512 blocking threads.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RuntimeConfig {
+    pub(crate) worker_threads: Option<usize>,
+    pub(crate) max_blocking_threads: Option<usize>,
+    pub(crate) per_worker: bool,
+}
+
+impl RuntimeConfig {
+    /// tokio's defaults.
+    pub fn new() -> RuntimeConfig {
+        RuntimeConfig::default()
+    }
+
+    /// Sets the number of worker threads; at least one.
+    pub fn with_worker_threads(mut self, threads: usize) -> RuntimeConfig {
+        self.worker_threads = Some(threads.max(1));
+        self
+    }
+
+    /// Threads for `spawn_blocking` and `blocking_service_fn`, per runtime.
+    pub fn with_max_blocking_threads(mut self, threads: usize) -> RuntimeConfig {
+        self.max_blocking_threads = Some(threads.max(1));
+        self
+    }
+
+    /// Gives each worker a single-threaded runtime of its own that accepts on its own
+    /// listener and serves those connections without ever moving them to another
+    /// thread. Listeners are added with `SO_REUSEPORT` to have one per worker, so the
+    /// server must have been bound with `ListenerConfig::with_reuse_port`.
+    #[cfg(unix)]
+    pub fn with_runtime_per_worker(mut self, enabled: bool) -> RuntimeConfig {
+        self.per_worker = enabled;
+        self
+    }
+
+    pub(crate) fn workers(&self) -> usize {
+        self.worker_threads
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+}
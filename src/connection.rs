@@ -0,0 +1,72 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::{PeerCertificate, Request};
+
+/// Details of the connection a request arrived on, added to every request's extensions.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionInfo {
+    pub(crate) remote_addr: Option<SocketAddr>,
+    pub(crate) local_addr: Option<SocketAddr>,
+    pub(crate) unix_path: Option<Arc<Path>>,
+    pub(crate) tls: bool,
+    pub(crate) peer_certificate: Option<Arc<PeerCertificate>>,
+    pub(crate) client_ip: Option<IpAddr>,
+    pub(crate) scheme: Option<Arc<str>>,
+    pub(crate) host: Option<Arc<str>>,
+}
+
+impl ConnectionInfo {
+    /// The connection info of `req`.
+    pub fn from_request(req: &Request) -> Option<&ConnectionInfo> {
+        req.extensions().get()
+    }
+
+    /// The peer address of a TCP connection.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// The local address of a TCP connection.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// The socket path of a Unix domain connection.
+    pub fn unix_path(&self) -> Option<&Path> {
+        self.unix_path.as_deref()
+    }
+
+    /// Whether the connection went through a TLS acceptor.
+    pub fn is_tls(&self) -> bool {
+        self.tls
+    }
+
+    /// The client certificate the TLS acceptor verified, if the client sent one.
+    pub fn peer_certificate(&self) -> Option<&PeerCertificate> {
+        self.peer_certificate.as_deref()
+    }
+
+    /// The client address: the one forwarded by a trusted proxy, else the peer's.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+            .or_else(|| self.remote_addr.map(|addr| addr.ip()))
+    }
+
+    /// The scheme the client used: a trusted forwarded one, else `https` over TLS and `http` otherwise.
+    pub fn scheme(&self) -> &str {
+        match &self.scheme {
+            Some(scheme) => scheme,
+            None if self.tls => "https",
+            None => "http",
+        }
+    }
+
+    /// The host forwarded by a trusted proxy.
+    pub fn forwarded_host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+}
@@ -0,0 +1,501 @@
+//! Reading cookies from requests and setting them on responses, optionally signed
+//! so clients can't alter them.
+
+use std::{fmt, time::Duration};
+
+use hyper::header::HeaderValue;
+
+use crate::{
+    Request, RequestExt, base64,
+    sha256::{constant_time_eq, hmac_sha256},
+};
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SameSite {
+    /// Sent only on same-site requests.
+    Strict,
+    /// Also sent on top-level navigations from other sites.
+    Lax,
+    /// Sent on cross-site requests too; browsers require `Secure` with it.
+    None,
+}
+
+/// A cookie to send in `Set-Cookie`, with its attributes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    same_site: Option<SameSite>,
+    secure: bool,
+    http_only: bool,
+}
+
+impl Cookie {
+    /// A session cookie with no attributes.
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Cookie {
+        Cookie {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            same_site: None,
+            secure: false,
+            http_only: false,
+        }
+    }
+
+    /// A cookie that tells the client to delete `name`.
+    pub fn removal(name: impl Into<String>) -> Cookie {
+        Cookie::new(name, "")
+            .with_path("/")
+            .with_max_age(Duration::ZERO)
+    }
+
+    /// The cookie name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The cookie value.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Replaces the value, keeping the attributes.
+    pub fn with_value(mut self, value: impl Into<String>) -> Cookie {
+        self.value = value.into();
+        self
+    }
+
+    /// Sets the `Path` attribute.
+    pub fn with_path(mut self, path: impl Into<String>) -> Cookie {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Sets the `Domain` attribute.
+    pub fn with_domain(mut self, domain: impl Into<String>) -> Cookie {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Sets `Max-Age`; a zero duration deletes the cookie.
+    pub fn with_max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets the `SameSite` attribute.
+    pub fn with_same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Sets or clears the `Secure` attribute.
+    pub fn with_secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    /// Sets or clears the `HttpOnly` attribute.
+    pub fn with_http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    /// `None` if the name or value isn't allowed by RFC 6265, or an attribute
+    /// contains a `;`, since either could smuggle in attributes of its own.
+    pub fn to_header_value(&self) -> Option<HeaderValue> {
+        let attributes = [&self.path, &self.domain];
+        if !is_token(&self.name)
+            || !is_cookie_value(&self.value)
+            || attributes.into_iter().flatten().any(|a| a.contains(';'))
+        {
+            return None;
+        }
+        HeaderValue::from_str(&self.to_string()).ok()
+    }
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?={}".contains(&b))
+}
+
+fn is_cookie_value(s: &str) -> bool {
+    let s = match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(inner) => inner,
+        None => s,
+    };
+    s.bytes()
+        .all(|b| b.is_ascii_graphic() && !matches!(b, b'"' | b',' | b';' | b'\\'))
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => f.write_str("; SameSite=Strict")?,
+            Some(SameSite::Lax) => f.write_str("; SameSite=Lax")?,
+            Some(SameSite::None) => f.write_str("; SameSite=None")?,
+            None => {}
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        Ok(())
+    }
+}
+
+/// The secret used to sign cookies with HMAC-SHA256.
+#[derive(Clone)]
+pub struct Key(Vec<u8>);
+
+impl Key {
+    /// A key from `secret`, which should be at least 32 random bytes.
+    pub fn new(secret: impl Into<Vec<u8>>) -> Key {
+        Key(secret.into())
+    }
+
+    fn sign(&self, name: &str, value: &str) -> String {
+        let mut msg = Vec::with_capacity(name.len() + value.len() + 1);
+        msg.extend_from_slice(name.as_bytes());
+        msg.push(b'=');
+        msg.extend_from_slice(value.as_bytes());
+        base64::encode(&hmac_sha256(&self.0, &msg))
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+/// The encoded length of a SHA-256 MAC, which prefixes every signed value.
+const SIGNATURE_LEN: usize = 44;
+
+/// The cookies a request sent, plus the changes to answer with.
+#[derive(Clone, Debug, Default)]
+pub struct CookieJar {
+    original: Vec<(String, String)>,
+    delta: Vec<Cookie>,
+}
+
+impl CookieJar {
+    /// An empty jar.
+    pub fn new() -> CookieJar {
+        CookieJar::default()
+    }
+
+    /// The cookies in the request's `Cookie` headers.
+    pub fn from_request(req: &Request) -> CookieJar {
+        req.cookies()
+    }
+
+    /// Parses `Cookie` header values; malformed pairs are skipped.
+    pub fn parse<'a>(headers: impl IntoIterator<Item = &'a str>) -> CookieJar {
+        let original = headers
+            .into_iter()
+            .flat_map(|h| h.split(';'))
+            .filter_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                let name = name.trim();
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                (!name.is_empty()).then(|| (name.to_string(), value.to_string()))
+            })
+            .collect();
+        CookieJar {
+            original,
+            delta: Vec::new(),
+        }
+    }
+
+    /// Values added to the jar shadow those the client sent.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        if let Some(cookie) = self.delta.iter().rev().find(|c| c.name == name) {
+            return match cookie.max_age == Some(Duration::ZERO) {
+                true => None,
+                false => Some(&cookie.value),
+            };
+        }
+        self.original
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The current cookies: those received, overlaid with additions and without removals.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.original
+            .iter()
+            .map(|(n, v)| (n.as_str(), v.as_str()))
+            .filter(|(n, _)| !self.delta.iter().any(|c| c.name == *n))
+            .chain(
+                self.delta
+                    .iter()
+                    .filter(|c| c.max_age != Some(Duration::ZERO))
+                    .map(|c| (c.name.as_str(), c.value.as_str())),
+            )
+    }
+
+    /// Adds or replaces a cookie, to be sent in `Set-Cookie`.
+    pub fn add(&mut self, cookie: Cookie) {
+        self.delta.retain(|c| c.name != cookie.name);
+        self.delta.push(cookie);
+    }
+
+    /// Removes a cookie, telling the client to delete it.
+    pub fn remove(&mut self, name: impl Into<String>) {
+        self.add(Cookie::removal(name));
+    }
+
+    /// Cookies that were added or removed, i.e. the ones to send as `Set-Cookie`.
+    pub fn delta(&self) -> impl Iterator<Item = &Cookie> {
+        self.delta.iter()
+    }
+
+    /// A view of the jar that signs added values and checks them on read.
+    pub fn signed<'a>(&'a mut self, key: &'a Key) -> SignedJar<'a> {
+        SignedJar { jar: self, key }
+    }
+}
+
+/// A `CookieJar` whose values carry an HMAC signature, returned by `CookieJar::signed`.
+pub struct SignedJar<'a> {
+    jar: &'a mut CookieJar,
+    key: &'a Key,
+}
+
+impl SignedJar<'_> {
+    /// Returns the value only if its signature verifies.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let raw = self.jar.get(name)?;
+        if raw.len() < SIGNATURE_LEN || !raw.is_char_boundary(SIGNATURE_LEN) {
+            return None;
+        }
+        let (signature, value) = raw.split_at(SIGNATURE_LEN);
+        let expected = self.key.sign(name, value);
+        constant_time_eq(signature.as_bytes(), expected.as_bytes()).then_some(value)
+    }
+
+    /// Adds a cookie with its value signed under the jar's key.
+    pub fn add(&mut self, mut cookie: Cookie) {
+        cookie.value = format!(
+            "{}{}",
+            self.key.sign(&cookie.name, &cookie.value),
+            cookie.value
+        );
+        self.jar.add(cookie);
+    }
+
+    /// Removes a cookie, as `CookieJar::remove`.
+    pub fn remove(&mut self, name: impl Into<String>) {
+        self.jar.remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Method, StatusCode, header};
+
+    use super::*;
+    use crate::{
+        ResponseExt, ServiceError, ServiceResponse, service_fn, testing::TestClient,
+        testing::TestRequestExt,
+    };
+
+    fn jar(header: &str) -> CookieJar {
+        CookieJar::parse([header])
+    }
+
+    #[test]
+    fn parse_pairs() {
+        let jar = CookieJar::parse(["a=1; b = 2 ;c=\"3\"", "d=x=y; =skipped; novalue; e="]);
+        let pairs: Vec<_> = jar.iter().collect();
+        assert_eq!(
+            pairs,
+            [("a", "1"), ("b", "2"), ("c", "3"), ("d", "x=y"), ("e", "")]
+        );
+        assert_eq!(jar.get("d"), Some("x=y"));
+        assert_eq!(jar.get("novalue"), None);
+        assert_eq!(jar.get(""), None);
+        // A lone quote isn't a quoted value.
+        assert_eq!(self::jar("a=\"1").get("a"), Some("\"1"));
+        // The first of several same-named cookies wins.
+        assert_eq!(self::jar("a=1; a=2").get("a"), Some("1"));
+    }
+
+    #[test]
+    fn from_request_reads_every_header() {
+        let mut req = Request::test(Method::GET, "/");
+        req.headers_mut()
+            .append(header::COOKIE, HeaderValue::from_static("a=1"));
+        req.headers_mut()
+            .append(header::COOKIE, HeaderValue::from_static("b=2"));
+        let jar = CookieJar::from_request(&req);
+        assert_eq!(jar.get("a"), Some("1"));
+        assert_eq!(jar.get("b"), Some("2"));
+    }
+
+    #[test]
+    fn added_and_removed_cookies_shadow_the_request() {
+        let mut jar = jar("a=1; b=2; c=3");
+        jar.add(Cookie::new("a", "old"));
+        jar.add(Cookie::new("a", "new"));
+        jar.remove("b");
+        jar.add(Cookie::new("d", "4"));
+        assert_eq!(jar.get("a"), Some("new"));
+        assert_eq!(jar.get("b"), None);
+        assert_eq!(jar.get("c"), Some("3"));
+        let pairs: Vec<_> = jar.iter().collect();
+        assert_eq!(pairs, [("c", "3"), ("a", "new"), ("d", "4")]);
+        let delta: Vec<_> = jar.delta().map(|c| c.to_string()).collect();
+        assert_eq!(delta, ["a=new", "b=; Path=/; Max-Age=0", "d=4"]);
+    }
+
+    #[test]
+    fn display_all_attributes() {
+        let cookie = Cookie::new("id", "v")
+            .with_path("/app")
+            .with_domain("example.com")
+            .with_max_age(Duration::from_millis(3_600_999))
+            .with_same_site(SameSite::Lax)
+            .with_secure(true)
+            .with_http_only(true);
+        assert_eq!(
+            cookie.to_string(),
+            "id=v; Path=/app; Domain=example.com; Max-Age=3600; SameSite=Lax; Secure; HttpOnly"
+        );
+        let cookie = cookie.with_same_site(SameSite::None).with_value("w");
+        assert!(cookie.to_string().contains("; SameSite=None;"));
+        assert_eq!(cookie.value(), "w");
+        assert_eq!(cookie.name(), "id");
+    }
+
+    #[test]
+    fn header_values_reject_injection() {
+        assert!(Cookie::new("a", "\"quoted\"").to_header_value().is_some());
+        assert!(Cookie::new("a", "b=c/+").to_header_value().is_some());
+        assert!(Cookie::new("a", "").to_header_value().is_some());
+        for cookie in [
+            Cookie::new("a", "x; Domain=evil.example"),
+            Cookie::new("a", "has space"),
+            Cookie::new("a", "comma,"),
+            Cookie::new("a", "back\\slash"),
+            Cookie::new("a", "\"unbalanced"),
+            Cookie::new("a", "new\nline"),
+            Cookie::new("a", "é"),
+            Cookie::new("", "v"),
+            Cookie::new("a=b", "v"),
+            Cookie::new("a b", "v"),
+            Cookie::new("a", "v").with_path("/; Secure"),
+            Cookie::new("a", "v").with_domain("x; HttpOnly"),
+        ] {
+            assert!(cookie.to_header_value().is_none(), "{cookie}");
+        }
+    }
+
+    #[test]
+    fn signed_round_trip() {
+        let key = Key::new("secret");
+        let mut out = CookieJar::new();
+        out.signed(&key).add(Cookie::new("user", "alice"));
+        let sent = out.delta().next().unwrap().clone();
+        assert!(sent.to_header_value().is_some());
+        assert_eq!(sent.value().len(), SIGNATURE_LEN + 5);
+        assert!(sent.value().ends_with("alice"));
+
+        let mut jar = jar(&sent.to_string());
+        assert_eq!(jar.signed(&key).get("user"), Some("alice"));
+        // The raw value carries the signature.
+        assert_eq!(jar.get("user"), Some(sent.value()));
+        jar.signed(&key).remove("user");
+        assert_eq!(jar.signed(&key).get("user"), None);
+    }
+
+    #[test]
+    fn signed_rejects_forgeries() {
+        let key = Key::new("secret");
+        let mut out = CookieJar::new();
+        out.signed(&key).add(Cookie::new("user", "alice"));
+        let signed = out.get("user").unwrap().to_string();
+        let signature = &signed[..SIGNATURE_LEN];
+
+        let check = |header: String| jar(&header).signed(&key).get("user").is_none();
+        // Wrong key.
+        let mut other = jar(&format!("user={signed}"));
+        assert_eq!(other.signed(&Key::new("other")).get("user"), None);
+        // Changed value, or a valid signature moved to another name.
+        assert!(check(format!("user={signature}mallory")));
+        assert!(
+            jar(&format!("admin={signed}"))
+                .signed(&key)
+                .get("admin")
+                .is_none()
+        );
+        // Flipped signature byte.
+        let mut flipped = signed.clone().into_bytes();
+        flipped[0] = if flipped[0] == b'A' { b'B' } else { b'A' };
+        assert!(check(format!(
+            "user={}",
+            String::from_utf8(flipped).unwrap()
+        )));
+        // Unsigned, short, and multibyte values straddling the signature.
+        assert!(check("user=alice".to_string()));
+        assert!(check(format!("user={}", &signature[..SIGNATURE_LEN - 1])));
+        assert!(check(format!("user={}é", &signature[..SIGNATURE_LEN - 1])));
+    }
+
+    #[test]
+    fn key_debug_hides_the_secret() {
+        assert_eq!(format!("{:?}", Key::new("secret")), "Key(..)");
+    }
+
+    #[tokio::test]
+    async fn responses_carry_the_delta() {
+        let client = TestClient::new(service_fn(|req: Request| async move {
+            let mut jar = req.cookies();
+            let visits: u32 = jar.get("visits").and_then(|v| v.parse().ok()).unwrap_or(0);
+            jar.add(Cookie::new("visits", (visits + 1).to_string()).with_http_only(true));
+            jar.remove("old");
+            jar.add(Cookie::new("bad", "a;b"));
+            Ok::<_, ServiceError>(ServiceResponse::empty().with_cookies(&jar))
+        }));
+        let resp = client
+            .get("/")
+            .header("cookie", "visits=41; old=1")
+            .send()
+            .await;
+        resp.assert_status(StatusCode::OK);
+        let set: Vec<_> = resp
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .map(|v| v.to_str().unwrap())
+            .collect();
+        assert_eq!(set, ["visits=42; HttpOnly", "old=; Path=/; Max-Age=0"]);
+    }
+}
@@ -0,0 +1,412 @@
+use std::{sync::Arc, time::Duration};
+
+use hyper::{
+    HeaderMap, Method, StatusCode,
+    header::{self, HeaderName, HeaderValue},
+};
+use tower::{Layer, Service as TowerService};
+
+use crate::{Request, ResponseExt, ServiceBoxFuture, ServiceError, ServiceResponse};
+
+type OriginPredicate = Arc<dyn Fn(&HeaderValue) -> bool + Send + Sync>;
+
+#[derive(Clone)]
+enum AllowOrigin {
+    Any,
+    List(Arc<[HeaderValue]>),
+    Predicate(OriginPredicate),
+}
+
+#[derive(Clone)]
+enum AllowHeaders {
+    None,
+    Mirror,
+    List(HeaderValue),
+}
+
+/// Answers CORS preflights and adds CORS headers to responses for allowed origins.
+#[derive(Clone)]
+pub struct CorsLayer {
+    origin: AllowOrigin,
+    methods: HeaderValue,
+    headers: AllowHeaders,
+    expose_headers: Option<HeaderValue>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Default for CorsLayer {
+    fn default() -> CorsLayer {
+        CorsLayer::new()
+    }
+}
+
+impl CorsLayer {
+    /// Allows no origins and the `GET`, `HEAD` and `POST` methods.
+    pub fn new() -> CorsLayer {
+        CorsLayer {
+            origin: AllowOrigin::List(Arc::new([])),
+            methods: HeaderValue::from_static("GET, HEAD, POST"),
+            headers: AllowHeaders::None,
+            expose_headers: None,
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// Allows every origin. Panics combined with `with_credentials(true)`.
+    pub fn with_any_origin(mut self) -> CorsLayer {
+        self.origin = AllowOrigin::Any;
+        self.checked()
+    }
+
+    /// `*` in the list allows any origin, as `with_any_origin` does.
+    pub fn with_origins<I>(mut self, origins: I) -> CorsLayer
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let origins: Vec<HeaderValue> = origins
+            .into_iter()
+            .filter_map(|o| HeaderValue::from_str(o.as_ref()).ok())
+            .collect();
+        self.origin = match origins.iter().any(|o| o == "*") {
+            true => AllowOrigin::Any,
+            false => AllowOrigin::List(origins.into()),
+        };
+        self.checked()
+    }
+
+    /// Allows the origins for which `predicate` returns true.
+    pub fn with_origin_predicate<F>(mut self, predicate: F) -> CorsLayer
+    where
+        F: Fn(&HeaderValue) -> bool + Send + Sync + 'static,
+    {
+        self.origin = AllowOrigin::Predicate(Arc::new(predicate));
+        self
+    }
+
+    /// Sets the methods preflights allow.
+    pub fn with_methods(mut self, methods: impl IntoIterator<Item = Method>) -> CorsLayer {
+        self.methods = join(methods.into_iter().map(|m| m.to_string()));
+        self
+    }
+
+    /// Sets the request headers preflights allow.
+    pub fn with_headers(mut self, headers: impl IntoIterator<Item = HeaderName>) -> CorsLayer {
+        self.headers = AllowHeaders::List(join(headers.into_iter().map(|h| h.to_string())));
+        self
+    }
+
+    /// Allows whatever headers the preflight asks for.
+    pub fn with_any_headers(mut self) -> CorsLayer {
+        self.headers = AllowHeaders::Mirror;
+        self
+    }
+
+    /// Sets `Access-Control-Expose-Headers`, the response headers scripts may read.
+    pub fn with_expose_headers(
+        mut self,
+        headers: impl IntoIterator<Item = HeaderName>,
+    ) -> CorsLayer {
+        self.expose_headers = Some(join(headers.into_iter().map(|h| h.to_string())));
+        self
+    }
+
+    /// Sets `Access-Control-Allow-Credentials`, letting requests carry cookies. Needs an
+    /// origin list or predicate: panics combined with `with_any_origin`.
+    pub fn with_credentials(mut self, credentials: bool) -> CorsLayer {
+        self.credentials = credentials;
+        self.checked()
+    }
+
+    /// Sets how long browsers may cache a preflight answer.
+    pub fn with_max_age(mut self, max_age: Duration) -> CorsLayer {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Browsers refuse `*` with credentials, and echoing every origin instead would let
+    /// any site, `null` included, make credentialed requests.
+    fn checked(self) -> CorsLayer {
+        assert!(
+            !(self.credentials && matches!(self.origin, AllowOrigin::Any)),
+            "CORS credentials need an explicit origin list or predicate, not any origin"
+        );
+        self
+    }
+
+    fn allow_origin(&self, origin: Option<&HeaderValue>) -> Option<HeaderValue> {
+        match (&self.origin, origin) {
+            (AllowOrigin::Any, _) => Some(HeaderValue::from_static("*")),
+            (AllowOrigin::List(list), Some(origin)) => {
+                list.contains(origin).then(|| origin.clone())
+            }
+            (AllowOrigin::Predicate(f), Some(origin)) => f(origin).then(|| origin.clone()),
+            (_, None) => None,
+        }
+    }
+
+    fn apply(&self, headers: &mut HeaderMap, origin: Option<&HeaderValue>) -> bool {
+        if !matches!(self.origin, AllowOrigin::Any) {
+            headers.append(header::VARY, HeaderValue::from_static("origin"));
+        }
+        let Some(allow_origin) = self.allow_origin(origin) else {
+            return false;
+        };
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if self.credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        true
+    }
+
+    fn preflight(&self, req: &Request) -> ServiceResponse {
+        let mut resp = ServiceResponse::empty().with_status(StatusCode::NO_CONTENT);
+        let headers = resp.headers_mut();
+        headers.append(
+            header::VARY,
+            HeaderValue::from_static(
+                "access-control-request-method, access-control-request-headers",
+            ),
+        );
+        if !self.apply(headers, req.headers().get(header::ORIGIN)) {
+            *resp.status_mut() = StatusCode::FORBIDDEN;
+            return resp;
+        }
+
+        headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, self.methods.clone());
+        let allow_headers = match &self.headers {
+            AllowHeaders::Mirror => req
+                .headers()
+                .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+                .cloned(),
+            AllowHeaders::List(list) => Some(list.clone()),
+            AllowHeaders::None => None,
+        };
+        if let Some(allow_headers) = allow_headers {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+        }
+        if let Some(max_age) = self.max_age {
+            headers.insert(
+                header::ACCESS_CONTROL_MAX_AGE,
+                HeaderValue::from(max_age.as_secs()),
+            );
+        }
+        resp
+    }
+}
+
+fn join(items: impl Iterator<Item = String>) -> HeaderValue {
+    let joined = items.collect::<Vec<_>>().join(", ");
+    HeaderValue::from_str(&joined).expect("methods and header names are valid header values")
+}
+
+impl<S> Layer<S> for CorsLayer {
+    type Service = Cors<S>;
+
+    fn layer(&self, inner: S) -> Cors<S> {
+        Cors {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// The service produced by `CorsLayer`.
+#[derive(Clone)]
+pub struct Cors<S> {
+    inner: S,
+    config: CorsLayer,
+}
+
+impl<S> TowerService<Request> for Cors<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let is_preflight = req.method() == Method::OPTIONS
+            && req.headers().contains_key(header::ORIGIN)
+            && req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+        if is_preflight {
+            let resp = self.config.preflight(&req);
+            return Box::pin(async move { Ok(resp) });
+        }
+
+        let origin = req.headers().get(header::ORIGIN).cloned();
+        let fut = self.inner.call(req);
+        let config = self.config.clone();
+        Box::pin(async move {
+            let mut resp = fut.await?;
+            let headers = resp.headers_mut();
+            if config.apply(headers, origin.as_ref())
+                && let Some(expose) = &config.expose_headers
+            {
+                headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, expose.clone());
+            }
+            Ok(resp)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{service_fn, testing::TestRequestExt};
+
+    async fn send(layer: &CorsLayer, method: Method, headers: &[(&str, &str)]) -> ServiceResponse {
+        let service = layer.layer(service_fn(|_: Request| async {
+            Ok::<_, ServiceError>(ServiceResponse::empty())
+        }));
+        let mut req = Request::test(method, "/");
+        for (name, value) in headers {
+            req.headers_mut().append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        service.oneshot(req).await.unwrap()
+    }
+
+    fn preflight_from(origin: &str) -> [(&str, &str); 3] {
+        [
+            ("origin", origin),
+            ("access-control-request-method", "PUT"),
+            ("access-control-request-headers", "x-token"),
+        ]
+    }
+
+    fn values(resp: &ServiceResponse, name: HeaderName) -> Vec<&str> {
+        resp.headers()
+            .get_all(name)
+            .iter()
+            .map(|v| v.to_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn answers_preflights_for_allowed_origins() {
+        let layer = CorsLayer::new()
+            .with_origins(["https://app.example"])
+            .with_methods([Method::GET, Method::PUT])
+            .with_any_headers()
+            .with_max_age(Duration::from_secs(600));
+        let resp = send(
+            &layer,
+            Method::OPTIONS,
+            &preflight_from("https://app.example"),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let headers = resp.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "GET, PUT");
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_HEADERS], "x-token");
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS));
+
+        let resp = send(
+            &layer,
+            Method::OPTIONS,
+            &preflight_from("https://evil.example"),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(
+            !resp
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+
+    #[tokio::test]
+    async fn any_origin_uses_a_wildcard() {
+        let layer = CorsLayer::new().with_any_origin();
+        let resp = send(&layer, Method::GET, &[("origin", "null")]).await;
+        assert_eq!(resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(values(&resp, header::VARY).is_empty());
+    }
+
+    #[tokio::test]
+    async fn credentials_echo_listed_origins_only() {
+        let layer = CorsLayer::new()
+            .with_origins(["https://app.example"])
+            .with_credentials(true)
+            .with_expose_headers([HeaderName::from_static("x-total")]);
+        let resp = send(&layer, Method::GET, &[("origin", "https://app.example")]).await;
+        let headers = resp.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[header::ACCESS_CONTROL_EXPOSE_HEADERS], "x-total");
+        assert_eq!(values(&resp, header::VARY), ["origin"]);
+
+        for origin in ["null", "https://evil.example"] {
+            let resp = send(&layer, Method::GET, &[("origin", origin)]).await;
+            assert!(
+                !resp
+                    .headers()
+                    .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            );
+            assert!(
+                !resp
+                    .headers()
+                    .contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+            );
+            assert_eq!(values(&resp, header::VARY), ["origin"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn preflights_vary_on_origin() {
+        let layer = CorsLayer::new().with_origin_predicate(|o| o.as_bytes().ends_with(b".example"));
+        let resp = send(
+            &layer,
+            Method::OPTIONS,
+            &preflight_from("https://a.example"),
+        )
+        .await;
+        let vary = values(&resp, header::VARY);
+        assert!(vary.contains(&"origin"), "{vary:?}");
+        assert!(
+            vary.iter()
+                .any(|v| v.contains("access-control-request-method"))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "explicit origin list")]
+    fn any_origin_with_credentials_is_refused() {
+        CorsLayer::new().with_any_origin().with_credentials(true);
+    }
+
+    #[test]
+    #[should_panic(expected = "explicit origin list")]
+    fn credentials_with_wildcard_list_is_refused() {
+        CorsLayer::new().with_credentials(true).with_origins(["*"]);
+    }
+}
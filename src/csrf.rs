@@ -0,0 +1,357 @@
+use std::{fmt, sync::Arc};
+
+use hyper::{Method, header::HeaderName, http::request::Parts};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    Error, FromRequestParts, Request, RequestExt, ResponseExt, ServiceBoxFuture, ServiceError,
+    ServiceResponse, Session,
+    cookies::{Cookie, SameSite},
+    error::error_response,
+    random,
+    sha256::constant_time_eq,
+};
+
+/// The header `CsrfLayer` checks by default.
+pub const X_CSRF_TOKEN: HeaderName = HeaderName::from_static("x-csrf-token");
+
+const TOKEN_BYTES: usize = 32;
+const SESSION_KEY: &str = "csrf_token";
+
+/// Why `CsrfLayer` rejected a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsrfError {
+    /// The request carried no token, or there was no valid one to compare it with.
+    MissingToken,
+    /// The token was malformed or didn't match the current one.
+    InvalidToken,
+}
+
+impl fmt::Display for CsrfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CsrfError::MissingToken => f.write_str("missing CSRF token"),
+            CsrfError::InvalidToken => f.write_str("CSRF token does not match"),
+        }
+    }
+}
+
+impl std::error::Error for CsrfError {}
+
+/// A fresh random token, hex-encoded.
+pub fn csrf_token() -> String {
+    random::hex_token(TOKEN_BYTES)
+}
+
+/// Whether `token` has the shape `csrf_token` produces: lowercase hex of the right length.
+fn is_well_formed(token: &str) -> bool {
+    token.len() == TOKEN_BYTES * 2
+        && token
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// The token the next unsafe request must echo back, for embedding in pages.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    /// The token `CsrfLayer` attached to `req`.
+    pub fn from_request(req: &Request) -> Option<&CsrfToken> {
+        req.extensions().get()
+    }
+
+    /// The token as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// For handlers that take the token from a form field, which the layer can't see
+    /// without consuming the body.
+    pub fn matches(&self, candidate: &str) -> bool {
+        constant_time_eq(self.0.as_bytes(), candidate.as_bytes())
+    }
+}
+
+impl fmt::Display for CsrfToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromRequestParts for CsrfToken {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<CsrfToken, Error> {
+        parts
+            .extensions
+            .get()
+            .cloned()
+            .ok_or(Error::MissingExtension("CsrfToken"))
+    }
+}
+
+#[derive(Clone)]
+enum Storage {
+    Cookie(Cookie),
+    Session,
+}
+
+/// Requests with unsafe methods must repeat the current token in the `X-CSRF-Token`
+/// header, or are rejected with `Error::Csrf` (403 by default). The token lives in a
+/// cookie readable by scripts (double submit), or in the `Session` for the
+/// synchronizer pattern, which needs a `SessionLayer` outside this one.
+#[derive(Clone)]
+pub struct CsrfLayer {
+    storage: Storage,
+    header: HeaderName,
+    exempt: Arc<[String]>,
+}
+
+impl CsrfLayer {
+    /// Keeps the token in a `SameSite=Strict` cookie named `csrf_token`, which scripts can read.
+    pub fn double_submit() -> CsrfLayer {
+        CsrfLayer {
+            storage: Storage::Cookie(
+                Cookie::new("csrf_token", "")
+                    .with_path("/")
+                    .with_same_site(SameSite::Strict),
+            ),
+            header: X_CSRF_TOKEN,
+            exempt: Arc::from([]),
+        }
+    }
+
+    /// Keeps the token in the `Session`.
+    pub fn synchronizer() -> CsrfLayer {
+        CsrfLayer {
+            storage: Storage::Session,
+            ..CsrfLayer::double_submit()
+        }
+    }
+
+    /// The template's name and attributes are used for the token cookie; its value is
+    /// ignored. Only applies to `double_submit`.
+    pub fn with_cookie(mut self, cookie: Cookie) -> CsrfLayer {
+        if let Storage::Cookie(_) = self.storage {
+            self.storage = Storage::Cookie(cookie.with_http_only(false));
+        }
+        self
+    }
+
+    /// Reads the token from `header` instead of `X-CSRF-Token`.
+    pub fn with_header(mut self, header: HeaderName) -> CsrfLayer {
+        self.header = header;
+        self
+    }
+
+    /// Skips verification for `path` and everything below it, e.g. for webhooks
+    /// authenticated by other means.
+    pub fn with_exempt(mut self, path: &str) -> CsrfLayer {
+        let mut exempt = self.exempt.to_vec();
+        exempt.push(path.trim_end_matches('/').to_string());
+        self.exempt = exempt.into();
+        self
+    }
+
+    fn is_exempt(&self, path: &str) -> bool {
+        self.exempt.iter().any(|prefix| {
+            path.strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+impl<S> Layer<S> for CsrfLayer {
+    type Service = Csrf<S>;
+
+    fn layer(&self, inner: S) -> Csrf<S> {
+        Csrf {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// The service produced by `CsrfLayer`.
+#[derive(Clone)]
+pub struct Csrf<S> {
+    inner: S,
+    config: CsrfLayer,
+}
+
+impl<S> TowerService<Request> for Csrf<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        let (stored, session) = match &self.config.storage {
+            Storage::Cookie(cookie) => (req.cookies().get(cookie.name()).map(str::to_string), None),
+            Storage::Session => {
+                let Some(session) = Session::from_request(&req) else {
+                    let resp = error_response(&req, Error::MissingExtension("Session"));
+                    return Box::pin(async { Ok(resp) });
+                };
+                (session.get(SESSION_KEY), Some(session))
+            }
+        };
+        // A tampered or truncated token is treated as missing, so a fresh one is issued.
+        let current = stored.filter(|token| is_well_formed(token));
+
+        if !is_safe(req.method()) && !self.config.is_exempt(req.uri().path()) {
+            let submitted = req
+                .headers()
+                .get(&self.config.header)
+                .and_then(|v| v.to_str().ok());
+            let rejection = match (&current, submitted) {
+                (_, None | Some("")) | (None, _) => Some(CsrfError::MissingToken),
+                (_, Some(submitted)) if !is_well_formed(submitted) => Some(CsrfError::InvalidToken),
+                (Some(current), Some(submitted)) => {
+                    (!constant_time_eq(current.as_bytes(), submitted.as_bytes()))
+                        .then_some(CsrfError::InvalidToken)
+                }
+            };
+            if let Some(e) = rejection {
+                let resp = error_response(&req, Error::Csrf(e));
+                return Box::pin(async { Ok(resp) });
+            }
+        }
+
+        let issued = match current {
+            Some(token) => {
+                req.extensions_mut().insert(CsrfToken(token));
+                None
+            }
+            None => {
+                let token = csrf_token();
+                if let Some(session) = &session {
+                    session.insert(SESSION_KEY, token.clone());
+                }
+                req.extensions_mut().insert(CsrfToken(token.clone()));
+                Some(token)
+            }
+        };
+        let cookie = match (&self.config.storage, issued) {
+            (Storage::Cookie(cookie), Some(token)) => Some(cookie.clone().with_value(token)),
+            _ => None,
+        };
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let resp = fut.await?;
+            Ok(match cookie {
+                Some(cookie) => resp.with_cookie(&cookie),
+                None => resp,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{
+        StatusCode,
+        header::{self, HeaderValue},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{service_fn, testing::TestRequestExt};
+
+    async fn send(
+        cookie: Option<&str>,
+        submitted: Option<&str>,
+        method: Method,
+    ) -> ServiceResponse {
+        let service = CsrfLayer::double_submit().layer(service_fn(|req: Request| async move {
+            let token = CsrfToken::from_request(&req).unwrap();
+            assert!(is_well_formed(token.as_str()));
+            Ok::<_, ServiceError>(ServiceResponse::empty())
+        }));
+        let mut req = Request::test(method, "/form");
+        if let Some(cookie) = cookie {
+            let value = HeaderValue::from_str(&format!("csrf_token={cookie}")).unwrap();
+            req.headers_mut().insert(header::COOKIE, value);
+        }
+        if let Some(submitted) = submitted {
+            let value = HeaderValue::from_str(submitted).unwrap();
+            req.headers_mut().insert(X_CSRF_TOKEN, value);
+        }
+        service.oneshot(req).await.unwrap()
+    }
+
+    fn issued(resp: &ServiceResponse) -> Option<String> {
+        let cookie = resp.headers().get(header::SET_COOKIE)?.to_str().unwrap();
+        let value = cookie.strip_prefix("csrf_token=")?.split(';').next()?;
+        Some(value.to_string())
+    }
+
+    #[test]
+    fn tokens_are_well_formed() {
+        assert!(is_well_formed(&csrf_token()));
+        assert!(!is_well_formed(""));
+        assert!(!is_well_formed(&csrf_token()[1..]));
+        assert!(!is_well_formed(&csrf_token().to_uppercase()));
+        assert!(!is_well_formed(&"z".repeat(TOKEN_BYTES * 2)));
+    }
+
+    #[tokio::test]
+    async fn matching_tokens_pass() {
+        let token = csrf_token();
+        let resp = send(Some(&token), Some(&token), Method::POST).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(issued(&resp), None);
+    }
+
+    #[tokio::test]
+    async fn bad_tokens_are_rejected() {
+        let token = csrf_token();
+        let other = csrf_token();
+        for (cookie, submitted) in [
+            (Some(token.as_str()), None),
+            (Some(token.as_str()), Some("")),
+            (Some(token.as_str()), Some(other.as_str())),
+            (Some(token.as_str()), Some(&token[..10])),
+            (Some(""), Some("")),
+            (Some("short"), Some("short")),
+            (None, Some(token.as_str())),
+        ] {
+            let resp = send(cookie, submitted, Method::POST).await;
+            assert_eq!(
+                resp.status(),
+                StatusCode::FORBIDDEN,
+                "{cookie:?} {submitted:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_cookies_get_a_fresh_token() {
+        for cookie in [None, Some(""), Some("not-a-token")] {
+            let resp = send(cookie, None, Method::GET).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let token = issued(&resp).unwrap();
+            assert!(is_well_formed(&token), "{token}");
+        }
+        let token = csrf_token();
+        assert_eq!(issued(&send(Some(&token), None, Method::GET).await), None);
+    }
+}
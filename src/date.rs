@@ -0,0 +1,283 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub(crate) const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+const LONG_WEEKDAYS: [&str; 7] = [
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+];
+
+pub(crate) struct DateTime {
+    pub(crate) year: i64,
+    pub(crate) month: u32,
+    pub(crate) day: u32,
+    pub(crate) hour: u32,
+    pub(crate) minute: u32,
+    pub(crate) second: u32,
+    pub(crate) millis: u32,
+}
+
+impl DateTime {
+    pub(crate) fn from_system(time: SystemTime) -> DateTime {
+        let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since.as_secs() as i64;
+        let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400) as u32);
+
+        // Howard Hinnant's days-to-civil conversion.
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        DateTime {
+            year,
+            month,
+            day,
+            hour: rem / 3600,
+            minute: rem % 3600 / 60,
+            second: rem % 60,
+            millis: since.subsec_millis(),
+        }
+    }
+
+    /// 10/Oct/2000:13:55:36 +0000
+    pub(crate) fn clf(&self) -> String {
+        format!(
+            "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+            self.day,
+            MONTHS[self.month as usize - 1],
+            self.year,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+
+    /// 2000-10-10T13:55:36.000Z
+    pub(crate) fn rfc3339(&self) -> String {
+        format!(
+            "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.millis
+        )
+    }
+
+    /// Sun, 06 Nov 1994 08:49:37 GMT
+    pub(crate) fn http(&self) -> String {
+        let weekday = days_from_civil(self.year, self.month, self.day).rem_euclid(7);
+        format!(
+            "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+            WEEKDAYS[weekday as usize],
+            self.day,
+            MONTHS[self.month as usize - 1],
+            self.year,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+
+    /// Accepts IMF-fixdate and the obsolete RFC 850 and asctime forms. Years outside
+    /// 1970..=9999 and out-of-range fields give `None`.
+    pub(crate) fn parse_http(value: &str) -> Option<SystemTime> {
+        let value = value.trim();
+        parse_imf_fixdate(value)
+            .or_else(|| parse_rfc850(value))
+            .or_else(|| parse_asctime(value))
+    }
+}
+
+/// Sun, 06 Nov 1994 08:49:37 GMT
+fn parse_imf_fixdate(value: &str) -> Option<SystemTime> {
+    let (weekday, rest) = value.split_once(", ")?;
+    if !WEEKDAYS.contains(&weekday) {
+        return None;
+    }
+    let mut parts = rest.split(' ');
+    let day = parse_digits(parts.next()?, 2)?;
+    let month = parse_month(parts.next()?)?;
+    let year = parse_digits(parts.next()?, 4)?;
+    let time = parts.next()?;
+    if parts.next() != Some("GMT") || parts.next().is_some() {
+        return None;
+    }
+    to_system(i64::from(year), month, day, time)
+}
+
+/// Sunday, 06-Nov-94 08:49:37 GMT
+fn parse_rfc850(value: &str) -> Option<SystemTime> {
+    let (weekday, rest) = value.split_once(", ")?;
+    if !LONG_WEEKDAYS.contains(&weekday) {
+        return None;
+    }
+    let mut parts = rest.split(' ');
+    let mut date = parts.next()?.split('-');
+    let day = parse_digits(date.next()?, 2)?;
+    let month = parse_month(date.next()?)?;
+    let year = parse_digits(date.next()?, 2)?;
+    let time = parts.next()?;
+    if date.next().is_some() || parts.next() != Some("GMT") || parts.next().is_some() {
+        return None;
+    }
+    // RFC 9110: a two-digit year more than 50 years ahead is in the previous century.
+    let current = DateTime::from_system(SystemTime::now()).year;
+    let mut year = current - current % 100 + i64::from(year);
+    if year > current + 50 {
+        year -= 100;
+    }
+    to_system(year, month, day, time)
+}
+
+/// Sun Nov  6 08:49:37 1994
+fn parse_asctime(value: &str) -> Option<SystemTime> {
+    let weekday = value.get(..4)?;
+    if !WEEKDAYS
+        .iter()
+        .any(|w| weekday.strip_suffix(' ') == Some(*w))
+    {
+        return None;
+    }
+    let mut parts = value[4..].split(' ').filter(|part| !part.is_empty());
+    let month = parse_month(parts.next()?)?;
+    let day = parse_digits(parts.next()?, 2)?;
+    let time = parts.next()?;
+    let year = parse_digits(parts.next()?, 4)?;
+    if parts.next().is_some() {
+        return None;
+    }
+    to_system(i64::from(year), month, day, time)
+}
+
+/// Up to `max` ASCII digits; `u32::from_str` alone would also take a `+` sign.
+fn parse_digits(value: &str, max: usize) -> Option<u32> {
+    if value.is_empty() || value.len() > max || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+fn parse_month(value: &str) -> Option<u32> {
+    MONTHS
+        .iter()
+        .position(|m| *m == value)
+        .map(|i| i as u32 + 1)
+}
+
+/// `time` is `HH:MM:SS`; a leap second is read as the end of the minute.
+fn to_system(year: i64, month: u32, day: u32, time: &str) -> Option<SystemTime> {
+    let mut time = time.split(':').map(|part| parse_digits(part, 2));
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if time.next().is_some()
+        || !(1970..=9999).contains(&year)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    let secs = days
+        .checked_mul(86400)?
+        .checked_add(u64::from(hour * 3600 + minute * 60 + second))?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+/// Howard Hinnant's civil-to-days conversion.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: u64 = 784111777;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn round_trips() {
+        for secs in [0, SAMPLE, 951782400, 253402300799] {
+            let formatted = DateTime::from_system(at(secs)).http();
+            assert_eq!(
+                DateTime::parse_http(&formatted),
+                Some(at(secs)),
+                "{formatted}"
+            );
+        }
+        assert_eq!(
+            DateTime::from_system(at(SAMPLE)).http(),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+    }
+
+    #[test]
+    fn parses_the_obsolete_forms() {
+        assert_eq!(
+            DateTime::parse_http("Sunday, 06-Nov-94 08:49:37 GMT"),
+            Some(at(SAMPLE))
+        );
+        assert_eq!(
+            DateTime::parse_http("Sun Nov  6 08:49:37 1994"),
+            Some(at(SAMPLE))
+        );
+        assert_eq!(
+            DateTime::parse_http("Thu Jan  1 00:00:00 1970"),
+            Some(UNIX_EPOCH)
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_fields() {
+        for value in [
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:49:37 GMT",
+            "Sun, 06 Nov 1994 08:60:37 GMT",
+            "Sun, 06 Nov 1994 08:49:61 GMT",
+            "Sun, 06 Nox 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Nov 1969 08:49:37 GMT",
+            "Fun, 06 Nov 1994 08:49:37 GMT",
+            "Sun, +6 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37",
+            "Sun Nov  6 08:49:37",
+            "",
+        ] {
+            assert_eq!(DateTime::parse_http(value), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn huge_years_do_not_overflow() {
+        for value in [
+            "Sun, 06 Nov 10000 08:49:37 GMT",
+            "Sun, 06 Nov 99999999999999999999 08:49:37 GMT",
+            "Sun, 06 Nov 9223372036854775807 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 9223372036854775807",
+        ] {
+            assert_eq!(DateTime::parse_http(value), None, "{value}");
+        }
+    }
+}
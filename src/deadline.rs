@@ -0,0 +1,238 @@
+use std::time::{Duration, Instant};
+
+use hyper::{
+    StatusCode,
+    header::{HeaderName, HeaderValue},
+    http::request::Parts,
+};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    Error, FromRequestParts, Request, ResponseExt, ServiceBoxFuture, ServiceError, ServiceResponse,
+};
+
+/// The time budget left to the caller, in (possibly fractional) seconds.
+pub const X_REQUEST_TIMEOUT: HeaderName = HeaderName::from_static("x-request-timeout");
+
+const FAR_FUTURE: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// When the response to a request is due. `Client::request` honours a deadline found
+/// in the outgoing request's extensions, so proxied and forwarded calls inherit it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    /// A deadline at `instant`.
+    pub fn at(instant: Instant) -> Deadline {
+        Deadline(instant)
+    }
+
+    /// A deadline `budget` from now. Budgets past what `Instant` can represent are
+    /// cut to a century.
+    pub fn after(budget: Duration) -> Deadline {
+        let now = Instant::now();
+        Deadline(now.checked_add(budget).unwrap_or_else(|| now + FAR_FUTURE))
+    }
+
+    /// When the deadline passes.
+    pub fn instant(&self) -> Instant {
+        self.0
+    }
+
+    /// Zero once the deadline has passed.
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    /// Whether the deadline has passed.
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
+
+    /// The deadline attached to `req`.
+    pub fn from_request(req: &Request) -> Option<&Deadline> {
+        req.extensions().get()
+    }
+
+    /// The budget a client asked for, never more than `budget`. Negative, NaN and
+    /// infinite values are ignored.
+    pub(crate) fn from_header(value: &HeaderValue, budget: Duration) -> Option<Deadline> {
+        let secs: f64 = value.to_str().ok()?.trim().parse().ok()?;
+        let requested = Duration::try_from_secs_f64(secs).ok()?;
+        Some(Deadline::after(requested.min(budget)))
+    }
+
+    pub(crate) fn header_value(&self) -> HeaderValue {
+        let remaining = self.remaining();
+        HeaderValue::from_str(&format!(
+            "{}.{:03}",
+            remaining.as_secs(),
+            remaining.subsec_millis()
+        ))
+        .expect("valid header value")
+    }
+}
+
+impl FromRequestParts for Deadline {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Deadline, Error> {
+        parts
+            .extensions
+            .get()
+            .copied()
+            .ok_or(Error::MissingExtension("Deadline"))
+    }
+}
+
+/// Gives every request a `Deadline` and answers 504 once it passes. A client can ask
+/// for less time with `X-Request-Timeout` but not for more, and a deadline set by an
+/// outer layer is only ever shortened.
+#[derive(Clone, Debug)]
+pub struct DeadlineLayer {
+    budget: Duration,
+    header: Option<HeaderName>,
+    status: StatusCode,
+}
+
+impl DeadlineLayer {
+    /// Gives each request `budget` to answer in, and lets clients ask for less with `X-Request-Timeout`.
+    pub fn new(budget: Duration) -> DeadlineLayer {
+        DeadlineLayer {
+            budget,
+            header: Some(X_REQUEST_TIMEOUT),
+            status: StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// Reads client budgets from `header` instead.
+    pub fn with_header(mut self, header: HeaderName) -> DeadlineLayer {
+        self.header = Some(header);
+        self
+    }
+
+    /// Ignores budgets requested by clients.
+    pub fn without_header(mut self) -> DeadlineLayer {
+        self.header = None;
+        self
+    }
+
+    /// Sets the status sent once a deadline passes; 504 by default.
+    pub fn with_status(mut self, status: StatusCode) -> DeadlineLayer {
+        self.status = status;
+        self
+    }
+
+    fn deadline(&self, req: &Request) -> Deadline {
+        let requested = self
+            .header
+            .as_ref()
+            .and_then(|name| req.headers().get(name))
+            .and_then(|value| Deadline::from_header(value, self.budget));
+        [requested, Deadline::from_request(req).copied()]
+            .into_iter()
+            .flatten()
+            .fold(Deadline::after(self.budget), Deadline::min)
+    }
+}
+
+impl<S> Layer<S> for DeadlineLayer {
+    type Service = DeadlineService<S>;
+
+    fn layer(&self, inner: S) -> DeadlineService<S> {
+        DeadlineService {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// The service produced by `DeadlineLayer`.
+#[derive(Clone)]
+pub struct DeadlineService<S> {
+    inner: S,
+    config: DeadlineLayer,
+}
+
+impl<S> TowerService<Request> for DeadlineService<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        let deadline = self.config.deadline(&req);
+        let status = self.config.status;
+        if deadline.is_expired() {
+            return Box::pin(async move { Ok(ServiceResponse::from_status(status)) });
+        }
+        req.extensions_mut().insert(deadline);
+        let fut = tokio::time::timeout_at(deadline.instant().into(), self.inner.call(req));
+        Box::pin(async move {
+            match fut.await {
+                Ok(res) => res,
+                Err(_) => Ok(ServiceResponse::from_status(status)),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Method;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{service_fn, testing::TestRequestExt};
+
+    const BUDGET: Duration = Duration::from_secs(5);
+
+    fn requested(value: &str) -> Option<Duration> {
+        Deadline::from_header(&HeaderValue::from_str(value).unwrap(), BUDGET)
+            .map(|deadline| deadline.remaining())
+    }
+
+    #[test]
+    fn client_budgets_are_clamped() {
+        assert!(requested("0.5").unwrap() <= Duration::from_millis(500));
+        assert!(requested("1.5e19").unwrap() <= BUDGET);
+        assert!(requested("1e300").is_none());
+        assert!(requested("-1").is_none());
+        assert!(requested("NaN").is_none());
+        assert!(requested("inf").is_none());
+        assert!(requested("soon").is_none());
+    }
+
+    #[test]
+    fn huge_budgets_do_not_overflow() {
+        let deadline = Deadline::after(Duration::MAX);
+        assert!(deadline.remaining() > Duration::from_secs(365 * 24 * 60 * 60));
+        assert!(!deadline.is_expired());
+    }
+
+    #[tokio::test]
+    async fn huge_header_falls_back_to_the_budget() {
+        let service = DeadlineLayer::new(BUDGET).layer(service_fn(|req: Request| async move {
+            let remaining = Deadline::from_request(&req).unwrap().remaining();
+            assert!(remaining <= BUDGET);
+            Ok::<_, ServiceError>(ServiceResponse::empty())
+        }));
+        for value in ["1.5e19", "-3", "NaN", "inf"] {
+            let mut req = Request::test(Method::GET, "/");
+            req.headers_mut()
+                .insert(X_REQUEST_TIMEOUT, HeaderValue::from_static(value));
+            let resp = service.clone().oneshot(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+    }
+}
@@ -0,0 +1,865 @@
+//! A small DEFLATE (RFC 1951) encoder using fixed Huffman codes, plus the checksums needed
+//! for the gzip and zlib wrappers.
+
+const WINDOW: usize = 32 << 10;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 32;
+const HASH_BITS: u32 = 15;
+
+pub(crate) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+pub(crate) const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+pub(crate) const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub(crate) const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// The match finder's state lives as long as the stream, so each frame only hashes
+/// its own bytes. History grows to twice the window before the oldest half is
+/// dropped, which keeps the cost of sliding the chains off the per-frame path.
+#[derive(Default)]
+pub(crate) struct Deflater {
+    history: Vec<u8>,
+    chains: Chains,
+    out: Vec<u8>,
+    bits: u64,
+    nbits: u32,
+}
+
+impl Deflater {
+    /// Emits `data` as one fixed-Huffman block followed by an empty stored block, so
+    /// everything written so far can be decoded without waiting for more input.
+    pub(crate) fn compress(&mut self, data: &[u8]) -> Vec<u8> {
+        self.put_bits(0b010, 3);
+
+        let mut pos = self.history.len();
+        let mut buf = std::mem::take(&mut self.history);
+        buf.extend_from_slice(data);
+        let mut chains = std::mem::take(&mut self.chains);
+        // The last bytes of the previous frame couldn't be hashed until now.
+        chains.insert_until(&buf, pos);
+
+        while pos < buf.len() {
+            let (len, dist) = chains.longest_match(&buf, pos);
+            if len >= MIN_MATCH {
+                self.put_length(len);
+                self.put_distance(dist);
+                pos += len;
+            } else {
+                self.put_symbol(buf[pos] as u16);
+                pos += 1;
+            }
+            chains.insert_until(&buf, pos);
+        }
+        self.put_symbol(256);
+
+        self.put_bits(0, 3);
+        self.align();
+        self.out.extend_from_slice(&[0x00, 0x00, 0xff, 0xff]);
+
+        if buf.len() > 2 * WINDOW {
+            let keep = buf.len() - WINDOW;
+            buf.drain(..keep);
+            chains.slide(keep);
+        }
+        self.history = buf;
+        self.chains = chains;
+        std::mem::take(&mut self.out)
+    }
+
+    pub(crate) fn finish(&mut self) -> Vec<u8> {
+        self.put_bits(0b011, 3);
+        self.put_symbol(256);
+        self.align();
+        std::mem::take(&mut self.out)
+    }
+
+    fn put_bits(&mut self, value: u32, n: u32) {
+        self.bits |= (value as u64) << self.nbits;
+        self.nbits += n;
+        while self.nbits >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    fn put_code(&mut self, code: u32, len: u32) {
+        self.put_bits(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn align(&mut self) {
+        if self.nbits > 0 {
+            self.put_bits(0, 8 - self.nbits);
+        }
+    }
+
+    fn put_symbol(&mut self, sym: u16) {
+        let sym = sym as u32;
+        match sym {
+            0..=143 => self.put_code(0x30 + sym, 8),
+            144..=255 => self.put_code(0x190 + sym - 144, 9),
+            256..=279 => self.put_code(sym - 256, 7),
+            _ => self.put_code(0xc0 + sym - 280, 8),
+        }
+    }
+
+    fn put_length(&mut self, len: usize) {
+        let i = LENGTH_BASE.partition_point(|&base| base as usize <= len) - 1;
+        self.put_symbol(257 + i as u16);
+        self.put_bits(
+            (len - LENGTH_BASE[i] as usize) as u32,
+            LENGTH_EXTRA[i] as u32,
+        );
+    }
+
+    fn put_distance(&mut self, dist: usize) {
+        let i = DIST_BASE.partition_point(|&base| base as usize <= dist) - 1;
+        self.put_code(i as u32, 5);
+        self.put_bits((dist - DIST_BASE[i] as usize) as u32, DIST_EXTRA[i] as u32);
+    }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let v = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+    (v.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Hash chains over the history buffer: `head` holds the latest position for each
+/// hash and `prev` the one before it, as offsets into the buffer.
+struct Chains {
+    head: Vec<u32>,
+    prev: Vec<u32>,
+    /// Positions before this one have been inserted.
+    next: usize,
+}
+
+const NONE: u32 = u32::MAX;
+
+impl Default for Chains {
+    fn default() -> Chains {
+        Chains {
+            head: vec![NONE; 1 << HASH_BITS],
+            prev: Vec::new(),
+            next: 0,
+        }
+    }
+}
+
+impl Chains {
+    /// Inserts every position before `end` that has enough bytes after it to hash.
+    fn insert_until(&mut self, buf: &[u8], end: usize) {
+        let end = end.min((buf.len() + 1).saturating_sub(MIN_MATCH));
+        if self.prev.len() < end {
+            self.prev.resize(end, NONE);
+        }
+        for pos in self.next..end {
+            let h = hash(&buf[pos..]);
+            self.prev[pos] = self.head[h];
+            self.head[h] = pos as u32;
+        }
+        self.next = self.next.max(end);
+    }
+
+    /// Accounts for the first `n` bytes of history being dropped.
+    fn slide(&mut self, n: usize) {
+        let n = n as u32;
+        self.prev.drain(..(n as usize).min(self.prev.len()));
+        for p in self.head.iter_mut().chain(self.prev.iter_mut()) {
+            *p = match *p {
+                NONE => NONE,
+                p if p < n => NONE,
+                p => p - n,
+            };
+        }
+        self.next -= n as usize;
+    }
+
+    fn longest_match(&self, buf: &[u8], pos: usize) -> (usize, usize) {
+        if pos + MIN_MATCH > buf.len() {
+            return (0, 0);
+        }
+        let max = (buf.len() - pos).min(MAX_MATCH);
+        let (mut best_len, mut best_dist) = (0, 0);
+        let mut candidate = self.head[hash(&buf[pos..])];
+        for _ in 0..MAX_CHAIN {
+            if candidate == NONE || pos - candidate as usize > WINDOW {
+                break;
+            }
+            let candidate_pos = candidate as usize;
+            let len = buf[candidate_pos..]
+                .iter()
+                .zip(&buf[pos..pos + max])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > best_len {
+                best_len = len;
+                best_dist = pos - candidate_pos;
+                if len == max {
+                    break;
+                }
+            }
+            candidate = self.prev[candidate_pos];
+        }
+        (best_len, best_dist)
+    }
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+            k += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub(crate) fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc = CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+pub(crate) fn adler32(adler: u32, data: &[u8]) -> u32 {
+    let (mut a, mut b) = (adler & 0xffff, adler >> 16);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum InflateError {
+    Invalid(&'static str),
+    TooLarge,
+}
+
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, InflateError> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(InflateError::Invalid("over-subscribed huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (sym, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = sym as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn fixed() -> (Huffman, Huffman) {
+        let mut lengths = [0u8; 288];
+        lengths[..144].fill(8);
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        lengths[280..].fill(8);
+        let lit = Huffman::new(&lengths).expect("fixed literal code is complete");
+        let dist = Huffman::new(&[5; 30]).expect("fixed distance code is valid");
+        (lit, dist)
+    }
+}
+
+enum Symbol {
+    Literal(u8),
+    Copy { len: usize, distance: usize },
+    End,
+}
+
+enum InflateState {
+    Header,
+    Stored(usize),
+    Codes(Box<(Huffman, Huffman)>),
+    Done,
+}
+
+/// A resumable DEFLATE decoder. Input may arrive in arbitrary pieces; whenever a symbol is
+/// incomplete the decoder rewinds to its start and waits for more input.
+pub(crate) struct Inflater {
+    input: Vec<u8>,
+    bitpos: usize,
+    state: InflateState,
+    last: bool,
+    window: Vec<u8>,
+}
+
+impl Default for Inflater {
+    fn default() -> Inflater {
+        Inflater {
+            input: Vec::new(),
+            bitpos: 0,
+            state: InflateState::Header,
+            last: false,
+            window: Vec::new(),
+        }
+    }
+}
+
+impl Inflater {
+    pub(crate) fn is_done(&self) -> bool {
+        matches!(self.state, InflateState::Done)
+    }
+
+    /// Bytes that followed the end of the deflate stream, such as a gzip trailer.
+    pub(crate) fn take_remaining(&mut self) -> Vec<u8> {
+        let start = self.bitpos.div_ceil(8);
+        let rest = self.input.split_off(start.min(self.input.len()));
+        self.input.clear();
+        self.bitpos = 0;
+        rest
+    }
+
+    pub(crate) fn inflate(
+        &mut self,
+        data: &[u8],
+        out: &mut Vec<u8>,
+        max_out: usize,
+    ) -> Result<(), InflateError> {
+        self.input.extend_from_slice(data);
+        let res = self.run(out, max_out);
+
+        if !self.is_done() {
+            let consumed = self.bitpos / 8;
+            self.input.drain(..consumed);
+            self.bitpos -= consumed * 8;
+        }
+        let keep = self.window.len().saturating_sub(WINDOW);
+        if keep > WINDOW {
+            self.window.drain(..keep);
+        }
+        res
+    }
+
+    fn run(&mut self, out: &mut Vec<u8>, max_out: usize) -> Result<(), InflateError> {
+        loop {
+            match &self.state {
+                InflateState::Done => return Ok(()),
+                InflateState::Header => {
+                    let start = self.bitpos;
+                    match self.block_header()? {
+                        Some(state) => self.state = state,
+                        None => {
+                            self.bitpos = start;
+                            return Ok(());
+                        }
+                    }
+                }
+                InflateState::Stored(remaining) => {
+                    let remaining = *remaining;
+                    let available = self.input.len() - self.bitpos / 8;
+                    let n = remaining.min(available);
+                    let start = self.bitpos / 8;
+                    self.emit_slice(start, n, out, max_out)?;
+                    self.bitpos += n * 8;
+                    match remaining - n {
+                        0 => self.end_block(),
+                        rest => {
+                            self.state = InflateState::Stored(rest);
+                            return Ok(());
+                        }
+                    }
+                }
+                InflateState::Codes(_) => {
+                    let InflateState::Codes(tables) =
+                        std::mem::replace(&mut self.state, InflateState::Header)
+                    else {
+                        unreachable!()
+                    };
+                    let res = self.codes(&tables, out, max_out);
+                    match res {
+                        Ok(true) => self.end_block(),
+                        Ok(false) => {
+                            self.state = InflateState::Codes(tables);
+                            return Ok(());
+                        }
+                        Err(e) => {
+                            self.state = InflateState::Codes(tables);
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn end_block(&mut self) {
+        self.state = match self.last {
+            true => InflateState::Done,
+            false => InflateState::Header,
+        };
+    }
+
+    fn block_header(&mut self) -> Result<Option<InflateState>, InflateError> {
+        let Some(header) = self.bits(3) else {
+            return Ok(None);
+        };
+        self.last = header & 1 == 1;
+        match header >> 1 {
+            0 => {
+                self.bitpos = self.bitpos.div_ceil(8) * 8;
+                let (Some(len), Some(nlen)) = (self.bits(16), self.bits(16)) else {
+                    return Ok(None);
+                };
+                if len != !nlen & 0xffff {
+                    return Err(InflateError::Invalid("stored block length mismatch"));
+                }
+                Ok(Some(InflateState::Stored(len as usize)))
+            }
+            1 => Ok(Some(InflateState::Codes(Box::new(Huffman::fixed())))),
+            2 => Ok(self
+                .dynamic_tables()?
+                .map(|tables| InflateState::Codes(Box::new(tables)))),
+            _ => Err(InflateError::Invalid("invalid block type")),
+        }
+    }
+
+    fn dynamic_tables(&mut self) -> Result<Option<(Huffman, Huffman)>, InflateError> {
+        const ORDER: [usize; 19] = [
+            16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+        ];
+
+        let (Some(nlen), Some(ndist), Some(ncode)) = (self.bits(5), self.bits(5), self.bits(4))
+        else {
+            return Ok(None);
+        };
+        let (nlen, ndist, ncode) = (nlen as usize + 257, ndist as usize + 1, ncode as usize + 4);
+        if nlen > 286 || ndist > 30 {
+            return Err(InflateError::Invalid("too many length or distance codes"));
+        }
+
+        let mut code_lengths = [0u8; 19];
+        for &i in &ORDER[..ncode] {
+            let Some(len) = self.bits(3) else {
+                return Ok(None);
+            };
+            code_lengths[i] = len as u8;
+        }
+        let code = Huffman::new(&code_lengths)?;
+
+        let mut lengths = vec![0u8; nlen + ndist];
+        let mut i = 0;
+        while i < lengths.len() {
+            let Some(sym) = self.decode(&code)? else {
+                return Ok(None);
+            };
+            let (value, repeat) = match sym {
+                0..=15 => (sym as u8, 1),
+                16 => {
+                    let Some(extra) = self.bits(2) else {
+                        return Ok(None);
+                    };
+                    let prev = match i {
+                        0 => return Err(InflateError::Invalid("repeat with no first length")),
+                        i => lengths[i - 1],
+                    };
+                    (prev, 3 + extra as usize)
+                }
+                17 => match self.bits(3) {
+                    Some(extra) => (0, 3 + extra as usize),
+                    None => return Ok(None),
+                },
+                _ => match self.bits(7) {
+                    Some(extra) => (0, 11 + extra as usize),
+                    None => return Ok(None),
+                },
+            };
+            if i + repeat > lengths.len() {
+                return Err(InflateError::Invalid("too many code lengths"));
+            }
+            lengths[i..i + repeat].fill(value);
+            i += repeat;
+        }
+        if lengths[256] == 0 {
+            return Err(InflateError::Invalid("missing end-of-block code"));
+        }
+
+        let lit = Huffman::new(&lengths[..nlen])?;
+        let dist = Huffman::new(&lengths[nlen..])?;
+        Ok(Some((lit, dist)))
+    }
+
+    /// Returns Ok(true) once the end-of-block symbol is reached.
+    fn codes(
+        &mut self,
+        (lit, dist): &(Huffman, Huffman),
+        out: &mut Vec<u8>,
+        max_out: usize,
+    ) -> Result<bool, InflateError> {
+        loop {
+            let start = self.bitpos;
+            let Some(step) = self.symbol(lit, dist)? else {
+                self.bitpos = start;
+                return Ok(false);
+            };
+            match step {
+                Symbol::End => return Ok(true),
+                Symbol::Literal(byte) => self.emit_literal(byte, out, max_out)?,
+                Symbol::Copy { len, distance } => {
+                    if distance > self.window.len() {
+                        return Err(InflateError::Invalid("distance too far back"));
+                    }
+                    if out.len() + len > max_out {
+                        return Err(InflateError::TooLarge);
+                    }
+                    for _ in 0..len {
+                        let byte = self.window[self.window.len() - distance];
+                        self.window.push(byte);
+                        out.push(byte);
+                    }
+                }
+            }
+        }
+    }
+
+    fn symbol(&mut self, lit: &Huffman, dist: &Huffman) -> Result<Option<Symbol>, InflateError> {
+        let Some(sym) = self.decode(lit)? else {
+            return Ok(None);
+        };
+        let sym = sym as usize;
+        if sym < 256 {
+            return Ok(Some(Symbol::Literal(sym as u8)));
+        }
+        if sym == 256 {
+            return Ok(Some(Symbol::End));
+        }
+        let i = sym - 257;
+        if i >= LENGTH_BASE.len() {
+            return Err(InflateError::Invalid("invalid length symbol"));
+        }
+        let Some(extra) = self.bits(LENGTH_EXTRA[i] as u32) else {
+            return Ok(None);
+        };
+        let len = LENGTH_BASE[i] as usize + extra as usize;
+
+        let Some(d) = self.decode(dist)? else {
+            return Ok(None);
+        };
+        let d = d as usize;
+        if d >= DIST_BASE.len() {
+            return Err(InflateError::Invalid("invalid distance symbol"));
+        }
+        let Some(extra) = self.bits(DIST_EXTRA[d] as u32) else {
+            return Ok(None);
+        };
+        Ok(Some(Symbol::Copy {
+            len,
+            distance: DIST_BASE[d] as usize + extra as usize,
+        }))
+    }
+
+    fn emit_literal(
+        &mut self,
+        byte: u8,
+        out: &mut Vec<u8>,
+        max_out: usize,
+    ) -> Result<(), InflateError> {
+        if out.len() >= max_out {
+            return Err(InflateError::TooLarge);
+        }
+        self.window.push(byte);
+        out.push(byte);
+        Ok(())
+    }
+
+    fn emit_slice(
+        &mut self,
+        start: usize,
+        n: usize,
+        out: &mut Vec<u8>,
+        max_out: usize,
+    ) -> Result<(), InflateError> {
+        if out.len() + n > max_out {
+            return Err(InflateError::TooLarge);
+        }
+        let bytes = &self.input[start..start + n];
+        self.window.extend_from_slice(bytes);
+        out.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn bits(&mut self, n: u32) -> Option<u32> {
+        if self.bitpos + n as usize > self.input.len() * 8 {
+            return None;
+        }
+        let mut value = 0;
+        for i in 0..n {
+            let pos = self.bitpos + i as usize;
+            let bit = (self.input[pos / 8] >> (pos % 8)) & 1;
+            value |= (bit as u32) << i;
+        }
+        self.bitpos += n as usize;
+        Some(value)
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> Result<Option<u16>, InflateError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            let Some(bit) = self.bits(1) else {
+                return Ok(None);
+            };
+            code |= bit as i32;
+            let count = huffman.counts[len] as i32;
+            if code - first < count {
+                return Ok(Some(huffman.symbols[(index + code - first) as usize]));
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(InflateError::Invalid("invalid huffman code"))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // The lyrics for 99 down to 61, compressed by zlib into one dynamic block.
+    pub(crate) fn lyrics() -> Vec<u8> {
+        (61..=99)
+            .rev()
+            .flat_map(|i| {
+                format!("{i} bottles of beer on the wall, {i} bottles of beer.\n").into_bytes()
+            })
+            .collect()
+    }
+
+    pub(crate) const LYRICS_DYNAMIC: &str = concat!(
+        "85d2cb69c3600004e1bbabf80b30c1cfdd553916c8e4202c700469df057861cec3dcbe691af3b6efebf237b6e798",
+        "97e53db6d7d87f97f1ff58d7e398befacf610a3c298fe17179048fca7387e75e9e1b3cb7f25ce1b996e702cfa53c",
+        "6778cee539c173fa7e020e521c041ca43808384871107090e220e020c541c0418a83808314070107290e020e521c",
+        "041ca438303870716070e0e2c0e0c0c581c1818b038303170706072e0e0c0e5c1c181cb838303870716070e0e240",
+        "e040c581c0818a038103150702072a0e040e541c081ca838103850712070a0e240e040c5c107",
+    );
+    const STORED: &str = "010c00f3ff73746f726564206279746573";
+    // The body of `zlib.compress(b"hello, zlib hello, zlib hello")`: one fixed block.
+    const FIXED: &str = "cb48cdc9c9d751a8cac94c52c8406703009c";
+
+    fn inflate_in_chunks(data: &[u8], size: usize) -> Result<Vec<u8>, InflateError> {
+        let mut inflater = Inflater::default();
+        let mut out = vec![];
+        for chunk in data.chunks(size) {
+            inflater.inflate(chunk, &mut out, usize::MAX)?;
+        }
+        assert!(inflater.is_done());
+        Ok(out)
+    }
+
+    #[test]
+    fn inflates_every_block_type_at_any_chunking() {
+        let dynamic = unhex(LYRICS_DYNAMIC);
+        for size in [1, 2, 3, 17, 64, dynamic.len()] {
+            assert_eq!(inflate_in_chunks(&dynamic, size).unwrap(), lyrics());
+            assert_eq!(
+                inflate_in_chunks(&unhex(STORED), size).unwrap(),
+                b"stored bytes"
+            );
+            assert_eq!(
+                inflate_in_chunks(&unhex(FIXED), size).unwrap(),
+                b"hello, zlib hello, zlib hello"
+            );
+        }
+    }
+
+    #[test]
+    fn hands_back_what_follows_the_stream() {
+        let mut data = unhex(STORED);
+        data.extend_from_slice(b"trailer");
+        let mut inflater = Inflater::default();
+        let mut out = vec![];
+        inflater.inflate(&data, &mut out, usize::MAX).unwrap();
+        assert!(inflater.is_done());
+        assert_eq!(inflater.take_remaining(), b"trailer");
+    }
+
+    #[test]
+    fn waits_for_truncated_input() {
+        let dynamic = unhex(LYRICS_DYNAMIC);
+        let mut inflater = Inflater::default();
+        let mut out = vec![];
+        inflater
+            .inflate(&dynamic[..dynamic.len() - 1], &mut out, usize::MAX)
+            .unwrap();
+        assert!(!inflater.is_done());
+        assert!(lyrics().starts_with(&out));
+    }
+
+    #[test]
+    fn enforces_the_output_limit() {
+        let lyrics = lyrics();
+        let limit = lyrics.len() - 1;
+        let mut out = vec![];
+        let res = Inflater::default().inflate(&unhex(LYRICS_DYNAMIC), &mut out, limit);
+        assert_eq!(res, Err(InflateError::TooLarge));
+        assert!(out.len() <= limit);
+        let mut out = vec![];
+        let res = Inflater::default().inflate(&unhex(STORED), &mut out, 11);
+        assert_eq!(res, Err(InflateError::TooLarge));
+    }
+
+    #[test]
+    fn rejects_invalid_streams() {
+        // A copy before any output, built from the encoder's own primitives.
+        let mut too_far = Deflater::default();
+        too_far.put_bits(0b011, 3);
+        too_far.put_length(3);
+        too_far.put_distance(1);
+        too_far.put_symbol(256);
+        too_far.align();
+        // A dynamic block whose code-length code gives every symbol length 1.
+        let mut oversubscribed = Deflater::default();
+        oversubscribed.put_bits(0b101, 3);
+        oversubscribed.put_bits(0, 5 + 5);
+        oversubscribed.put_bits(15, 4);
+        for _ in 0..19 {
+            oversubscribed.put_bits(1, 3);
+        }
+        oversubscribed.align();
+        let cases: [(&[u8], &str); 4] = [
+            (&[0x07], "invalid block type"),
+            (
+                &[0x01, 0x0c, 0x00, 0x00, 0x00],
+                "stored block length mismatch",
+            ),
+            (&too_far.out, "distance too far back"),
+            (&oversubscribed.out, "over-subscribed huffman code"),
+        ];
+        for (data, reason) in cases {
+            let res = Inflater::default().inflate(data, &mut vec![], usize::MAX);
+            assert_eq!(res, Err(InflateError::Invalid(reason)));
+        }
+    }
+
+    // The standard check values for CRC-32 and Adler-32.
+    // Words drawn from a small vocabulary by an LCG: compressible, but not trivially.
+    fn text(len: usize, mut seed: u32) -> Vec<u8> {
+        const WORDS: [&str; 8] = [
+            "alpha ", "beta ", "gamma ", "delta ", "42 ", "\n", "é ", "zz ",
+        ];
+        let mut out = Vec::with_capacity(len + 8);
+        while out.len() < len {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            out.extend_from_slice(WORDS[(seed >> 16) as usize % WORDS.len()].as_bytes());
+        }
+        out.truncate(len);
+        out
+    }
+
+    #[test]
+    fn round_trips_across_frames() {
+        // Enough in total to slide the history more than once.
+        let sizes = [0, 1, 2, 1, 5, 1000, 40_000, 3, 70_000, 100, 33_000, 2];
+        let frames: Vec<_> = sizes
+            .iter()
+            .enumerate()
+            .map(|(i, &len)| text(len, i as u32))
+            .collect();
+        let mut deflater = Deflater::default();
+        let mut compressed = vec![];
+        let mut inflater = Inflater::default();
+        let mut out = vec![];
+        for frame in &frames {
+            let block = deflater.compress(frame);
+            // Every frame is decodable on its own, without the next.
+            let before = out.len();
+            inflater.inflate(&block, &mut out, usize::MAX).unwrap();
+            assert_eq!(&out[before..], &frame[..]);
+            compressed.extend(block);
+        }
+        compressed.extend(deflater.finish());
+        let all = frames.concat();
+        assert_eq!(inflate_in_chunks(&compressed, 4096).unwrap(), all);
+        assert!(compressed.len() < all.len() / 2);
+    }
+
+    #[test]
+    fn matches_reach_into_earlier_frames() {
+        // Bytes with no repeats of their own, so the only matches are in the copy.
+        let mut seed = 7u32;
+        let frame: Vec<u8> = (0..2000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        let mut deflater = Deflater::default();
+        let first = deflater.compress(&frame);
+        let second = deflater.compress(&frame);
+        assert!(first.len() > 2000);
+        // The repeat is a handful of maximum-length matches.
+        assert!(second.len() < 40, "{}", second.len());
+        let mut stream = [first, second, deflater.finish()].concat();
+        // Unaligned frame boundaries: the tail of one frame starts a match.
+        let mut deflater = Deflater::default();
+        for chunk in [&b"abcab"[..], b"c", b"abcabc"] {
+            stream.extend(deflater.compress(chunk));
+        }
+        stream.extend(deflater.finish());
+        let mut inflater = Inflater::default();
+        let mut out = vec![];
+        inflater.inflate(&stream, &mut out, usize::MAX).unwrap();
+        assert!(inflater.is_done());
+        assert_eq!(out, [&frame[..], &frame].concat());
+        let rest = inflater.take_remaining();
+        assert_eq!(inflate_in_chunks(&rest, 1).unwrap(), b"abcabcabcabc");
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(0, b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xCBF4_3926);
+        assert_eq!(adler32(1, b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(adler32(1, b"Wiki"), b"pedia"), 0x11E6_0398);
+    }
+}
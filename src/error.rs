@@ -0,0 +1,375 @@
+use std::{fmt, sync::Arc};
+
+use hyper::{HeaderMap, Method, StatusCode, Uri, Version};
+
+use crate::{ConnectionInfo, CsrfError, Request, ResponseExt, ServiceError, ServiceResponse};
+
+/// Turns an error from a route into the response sent for it.
+pub type ErrorMapper = Arc<dyn Fn(Error) -> ServiceResponse + Send + Sync>;
+/// `Server::on_error`'s hook: like an `ErrorMapper`, with the request's metadata.
+pub type ErrorHandler = Arc<dyn Fn(Error, &ErrorContext) -> ServiceResponse + Send + Sync>;
+/// A route's recovery hook; see `Recovery`.
+pub type ErrorRecovery = Arc<dyn Fn(&Error, &ErrorContext) -> Recovery + Send + Sync>;
+
+/// What a recovery hook makes of an error from a route's service.
+pub enum Recovery {
+    /// Send this response instead.
+    Respond(ServiceResponse),
+    /// Dispatch the request again, to the routes after the one that failed and then
+    /// the fallback. Only requests with no body or one held in memory can be sent
+    /// again; for the others this is `Propagate`.
+    Next,
+    /// Leave the error to the error mapper or `Server::on_error`.
+    Propagate,
+}
+
+/// Request metadata handed to `Server::on_error` alongside the error.
+#[derive(Clone, Debug)]
+pub struct ErrorContext {
+    /// The request method.
+    pub method: Method,
+    /// The request URI, after path normalization.
+    pub uri: Uri,
+    /// The HTTP version.
+    pub version: Version,
+    /// The request headers.
+    pub headers: HeaderMap,
+    /// The connection the request came in on.
+    pub connection: ConnectionInfo,
+}
+
+impl ErrorContext {
+    /// Captures `req`'s metadata.
+    pub fn from_request(req: &Request) -> ErrorContext {
+        ErrorContext {
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            version: req.version(),
+            headers: req.headers().clone(),
+            connection: ConnectionInfo::from_request(req)
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ServerErrorHandler(pub(crate) ErrorHandler);
+
+/// For requests rejected by middleware, before any route's error mapper applies.
+pub(crate) fn error_response(req: &Request, e: Error) -> ServiceResponse {
+    match req.extensions().get::<ServerErrorHandler>() {
+        Some(handler) => (handler.0)(e, &ErrorContext::from_request(req)),
+        None => e.into_response(),
+    }
+}
+
+/// The errors the crate's services, extractors and middleware fail with. Each maps
+/// to a status code; see `Error::status`.
+#[derive(Debug)]
+pub enum Error {
+    /// Hyper failed to read the request.
+    Hyper(hyper::Error),
+    /// Reading the request body failed.
+    Io(std::io::Error),
+    /// The request body exceeded a size limit.
+    RequestTooLarge {
+        /// The limit, in bytes.
+        limit: usize,
+    },
+    /// A body that had to be UTF-8 wasn't.
+    Encoding(std::str::Utf8Error),
+    /// The request didn't have the content type a handler needs.
+    UnsupportedMediaType {
+        /// The content type that was needed.
+        expected: &'static str,
+    },
+    /// The request asked for a WebSocket upgrade but wasn't a valid handshake.
+    WebSocketHandshake(&'static str),
+    /// The multipart body was malformed or too large.
+    Multipart(&'static str),
+    /// The client took too long to send the request body.
+    BodyTimeout,
+    /// The decompressed request body exceeded its limit.
+    DecompressedTooLarge {
+        /// The limit, in bytes.
+        limit: usize,
+    },
+    /// The compressed request body couldn't be decoded.
+    Decompression(&'static str),
+    /// A handler needed a request extension that no layer or router had added.
+    MissingExtension(&'static str),
+    /// A URI built by the server, e.g. for an upstream, was invalid.
+    InvalidUri(hyper::http::uri::InvalidUri),
+    /// An upstream request failed.
+    Client(hyper_util::client::legacy::Error),
+    /// An upstream took too long to respond.
+    ClientTimeout,
+    /// CSRF validation failed.
+    Csrf(CsrfError),
+    /// The request URI exceeded `HeaderLimit`'s length.
+    UriTooLong {
+        /// The limit, in bytes.
+        limit: usize,
+    },
+    /// The request had more header fields than `HeaderLimit` allows.
+    TooManyHeaders {
+        /// The limit on the number of fields.
+        limit: usize,
+    },
+    /// The request headers exceeded `HeaderLimit`'s total size.
+    HeadersTooLarge {
+        /// The limit, in bytes.
+        limit: usize,
+    },
+    /// The request path couldn't be normalized.
+    InvalidPath(&'static str),
+    /// The query string was rejected by the UTF-8 policy.
+    InvalidQuery(&'static str),
+    /// A service failed with an error that isn't one of ours.
+    Service(ServiceError),
+}
+
+impl Error {
+    /// The status code this error answers with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::Hyper(_) => StatusCode::BAD_REQUEST,
+            Error::Io(_) => StatusCode::BAD_REQUEST,
+            Error::RequestTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::Encoding(_) => StatusCode::BAD_REQUEST,
+            Error::UnsupportedMediaType { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::WebSocketHandshake(_) => StatusCode::BAD_REQUEST,
+            Error::Multipart(_) => StatusCode::BAD_REQUEST,
+            Error::BodyTimeout => StatusCode::REQUEST_TIMEOUT,
+            Error::DecompressedTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::Decompression(_) => StatusCode::BAD_REQUEST,
+            Error::MissingExtension(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::InvalidUri(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Client(_) => StatusCode::BAD_GATEWAY,
+            Error::ClientTimeout => StatusCode::GATEWAY_TIMEOUT,
+            Error::Csrf(_) => StatusCode::FORBIDDEN,
+            Error::UriTooLong { .. } => StatusCode::URI_TOO_LONG,
+            Error::TooManyHeaders { .. } | Error::HeadersTooLarge { .. } => {
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+            }
+            Error::InvalidPath(_) | Error::InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            Error::Service(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Recovers our error from a service's, wrapping anything else as `Service`.
+    pub fn from_service(e: ServiceError) -> Error {
+        match e.downcast::<Error>() {
+            Ok(e) => *e,
+            Err(e) => Error::Service(e),
+        }
+    }
+
+    /// A plain-text response with `status()` and its reason phrase.
+    pub fn into_response(self) -> ServiceResponse {
+        ServiceResponse::from_status(self.status())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Hyper(e) => write!(f, "failed to read request: {e}"),
+            Error::Io(e) => write!(f, "failed to read request body: {e}"),
+            Error::RequestTooLarge { limit } => {
+                write!(f, "request body exceeds the limit of {limit} bytes")
+            }
+            Error::Encoding(e) => write!(f, "request body is not valid utf-8: {e}"),
+            Error::UnsupportedMediaType { expected } => {
+                write!(f, "expected a request with content type `{expected}`")
+            }
+            Error::WebSocketHandshake(reason) => write!(f, "invalid websocket handshake: {reason}"),
+            Error::Multipart(reason) => write!(f, "invalid multipart body: {reason}"),
+            Error::BodyTimeout => write!(f, "timed out reading the request body"),
+            Error::DecompressedTooLarge { limit } => {
+                write!(
+                    f,
+                    "decompressed request body exceeds the limit of {limit} bytes"
+                )
+            }
+            Error::Decompression(reason) => write!(f, "invalid compressed body: {reason}"),
+            Error::MissingExtension(name) => write!(f, "request extension `{name}` is missing"),
+            Error::InvalidUri(e) => write!(f, "invalid uri: {e}"),
+            Error::Client(e) => write!(f, "upstream request failed: {e}"),
+            Error::ClientTimeout => write!(f, "timed out waiting for the upstream response"),
+            Error::Csrf(e) => write!(f, "request rejected: {e}"),
+            Error::UriTooLong { limit } => {
+                write!(f, "request uri exceeds the limit of {limit} bytes")
+            }
+            Error::TooManyHeaders { limit } => {
+                write!(f, "request has more than {limit} header fields")
+            }
+            Error::HeadersTooLarge { limit } => {
+                write!(f, "request headers exceed the limit of {limit} bytes")
+            }
+            Error::InvalidPath(reason) => write!(f, "invalid request path: {reason}"),
+            Error::InvalidQuery(reason) => write!(f, "invalid query string: {reason}"),
+            Error::Service(e) => write!(f, "service failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Hyper(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Encoding(e) => Some(e),
+            Error::InvalidUri(e) => Some(e),
+            Error::Client(e) => Some(e),
+            Error::Csrf(e) => Some(e),
+            Error::Service(e) => Some(&**e),
+            Error::RequestTooLarge { .. }
+            | Error::UnsupportedMediaType { .. }
+            | Error::WebSocketHandshake(_)
+            | Error::Multipart(_)
+            | Error::BodyTimeout
+            | Error::DecompressedTooLarge { .. }
+            | Error::Decompression(_)
+            | Error::MissingExtension(_)
+            | Error::ClientTimeout
+            | Error::UriTooLong { .. }
+            | Error::TooManyHeaders { .. }
+            | Error::HeadersTooLarge { .. }
+            | Error::InvalidPath(_)
+            | Error::InvalidQuery(_) => None,
+        }
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(e: Error) -> std::io::Error {
+        std::io::Error::other(e)
+    }
+}
+
+/// Undoes the wrapping done by `Body`: errors that started out as ours or hyper's
+/// come back as such, and anything else is a failed read.
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        if e.get_ref()
+            .is_some_and(|inner| inner.is::<Error>() || inner.is::<hyper::Error>())
+        {
+            let inner = e.into_inner().expect("checked above");
+            return match inner.downcast::<Error>() {
+                Ok(e) => *e,
+                Err(inner) => Error::Hyper(*inner.downcast().expect("checked above")),
+            };
+        }
+        Error::Io(e)
+    }
+}
+
+impl From<hyper::Error> for Error {
+    fn from(e: hyper::Error) -> Error {
+        Error::Hyper(e)
+    }
+}
+
+impl From<hyper::http::uri::InvalidUri> for Error {
+    fn from(e: hyper::http::uri::InvalidUri) -> Error {
+        Error::InvalidUri(e)
+    }
+}
+
+impl From<std::str::Utf8Error> for Error {
+    fn from(e: std::str::Utf8Error) -> Error {
+        Error::Encoding(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use hyper::Method;
+
+    use super::*;
+    use crate::{
+        DynRoute, PathEqRouter, Route, ServiceBuilder, service_fn,
+        testing::{TestClient, TestRequestExt},
+    };
+
+    fn failing() -> DynRoute {
+        Route::from_parts(
+            PathEqRouter::new("/fail"),
+            service_fn(|_req: Request| async {
+                Err::<ServiceResponse, ServiceError>(io::Error::other("disk on fire").into())
+            }),
+        )
+        .make_dyn()
+    }
+
+    #[test]
+    fn from_service_unwraps_our_errors() {
+        let e = Error::from_service(Box::new(Error::BodyTimeout));
+        assert!(matches!(e, Error::BodyTimeout));
+        let e = Error::from_service(io::Error::other("boom").into());
+        assert!(matches!(e, Error::Service(_)));
+        assert_eq!(e.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn foreign_errors_reach_the_mapper() {
+        let service = ServiceBuilder::new()
+            .with_dyn_route(failing())
+            .with_error_mapper(|e| {
+                assert!(matches!(e, Error::Service(_)));
+                ServiceResponse::from_status(StatusCode::SERVICE_UNAVAILABLE)
+            })
+            .build();
+        let client = TestClient::new(service);
+        client
+            .get("/fail")
+            .send()
+            .await
+            .assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn foreign_errors_reach_on_error() {
+        let service = ServiceBuilder::new().with_dyn_route(failing()).build();
+        let client = TestClient::with_server(service, |server| {
+            server.on_error(|e, _| match e {
+                Error::Service(_) => ServiceResponse::from_status(StatusCode::IM_A_TEAPOT),
+                e => e.into_response(),
+            })
+        });
+        client
+            .get("/fail")
+            .send()
+            .await
+            .assert_status(StatusCode::IM_A_TEAPOT);
+    }
+
+    #[tokio::test]
+    async fn foreign_errors_without_a_service_are_500() {
+        let client = TestClient::new(service_fn(|_req: Request| async {
+            Err::<ServiceResponse, ServiceError>(io::Error::other("boom").into())
+        }));
+        client
+            .get("/")
+            .send()
+            .await
+            .assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn route_call_recovers_foreign_errors() {
+        let route = failing().with_error_recovery(|e, _| match e {
+            Error::Service(_) => Recovery::Respond(ServiceResponse::from_status(StatusCode::GONE)),
+            _ => Recovery::Propagate,
+        });
+        let resp = match route.call(Request::test(Method::GET, "/fail")) {
+            Ok(fut) => fut.await.unwrap(),
+            Err(_) => panic!("route passed"),
+        };
+        assert_eq!(resp.status(), StatusCode::GONE);
+    }
+}
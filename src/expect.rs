@@ -0,0 +1,100 @@
+use std::sync::Arc;
+
+use hyper::{StatusCode, header};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    ContentLength, Request, RequestExt, ResponseExt, ServiceBoxFuture, ServiceError,
+    ServiceResponse,
+};
+
+type ContinueCheck = Arc<dyn Fn(&Request) -> Result<(), StatusCode> + Send + Sync>;
+
+/// Vets requests sent with `Expect: 100-continue` before their body is transferred.
+/// hyper sends the `100 Continue` the first time the body is read, so a request that
+/// fails a check is answered with its final status while the client is still waiting,
+/// and never uploads the body. Any other expectation gets a 417.
+#[derive(Clone, Default)]
+pub struct ExpectContinueLayer {
+    checks: Arc<[ContinueCheck]>,
+}
+
+impl ExpectContinueLayer {
+    /// No checks: every `100-continue` request is let through.
+    pub fn new() -> ExpectContinueLayer {
+        ExpectContinueLayer::default()
+    }
+
+    /// Checks run in order; the first error is the response's status.
+    pub fn with_check<F>(mut self, check: F) -> ExpectContinueLayer
+    where
+        F: Fn(&Request) -> Result<(), StatusCode> + Send + Sync + 'static,
+    {
+        let mut checks = self.checks.to_vec();
+        checks.push(Arc::new(check));
+        self.checks = checks.into();
+        self
+    }
+
+    /// Answers 413 for bodies declared longer than `max` bytes, and 411 when no
+    /// length is declared at all.
+    pub fn with_max_length(self, max: u64) -> ExpectContinueLayer {
+        self.with_check(move |req| match req.typed_header::<ContentLength>() {
+            Some(ContentLength(len)) if len > max => Err(StatusCode::PAYLOAD_TOO_LARGE),
+            Some(_) => Ok(()),
+            None => Err(StatusCode::LENGTH_REQUIRED),
+        })
+    }
+
+    fn check(&self, req: &Request) -> Result<(), StatusCode> {
+        let Some(expect) = req.headers().get(header::EXPECT) else {
+            return Ok(());
+        };
+        if !expect.as_bytes().eq_ignore_ascii_case(b"100-continue") {
+            return Err(StatusCode::EXPECTATION_FAILED);
+        }
+        self.checks.iter().try_for_each(|check| check(req))
+    }
+}
+
+impl<S> Layer<S> for ExpectContinueLayer {
+    type Service = ExpectContinue<S>;
+
+    fn layer(&self, inner: S) -> ExpectContinue<S> {
+        ExpectContinue {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// The service produced by `ExpectContinueLayer`.
+#[derive(Clone)]
+pub struct ExpectContinue<S> {
+    inner: S,
+    config: ExpectContinueLayer,
+}
+
+impl<S> TowerService<Request> for ExpectContinue<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        match self.config.check(&req) {
+            Ok(()) => Box::pin(self.inner.call(req)),
+            Err(status) => Box::pin(async move { Ok(ServiceResponse::from_status(status)) }),
+        }
+    }
+}
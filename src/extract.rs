@@ -0,0 +1,334 @@
+use std::{convert::Infallible, future::Future, marker::PhantomData};
+
+use bytes::Bytes;
+use hyper::{
+    HeaderMap, Method, Uri, Version,
+    http::{Extensions, request::Parts},
+};
+use tower::Service as TowerService;
+
+use crate::{
+    BodyLimit, ConnectionInfo, Error, IntoResponse, PathParams, QueryPairs, Request,
+    ServiceBoxFuture, ServiceError, ServiceResponse, Session, collect_json, collect_request,
+    cookies::CookieJar,
+};
+
+const DEFAULT_BODY_LIMIT: usize = 2 << 20;
+
+pub(crate) fn body_limit(req: &Request) -> usize {
+    BodyLimit::from_request(req).map_or(DEFAULT_BODY_LIMIT, |limit| limit.max())
+}
+
+/// Extracts a value from the request head, leaving the body for other extractors.
+pub trait FromRequestParts: Sized {
+    /// The response sent when extraction fails.
+    type Rejection: IntoResponse + Send;
+
+    /// Extracts the value.
+    fn from_request_parts(
+        parts: &mut Parts,
+    ) -> impl Future<Output = Result<Self, Self::Rejection>> + Send;
+}
+
+/// Markers selecting the `FromRequest` impl an extractor goes through.
+pub mod marker {
+    /// Extracted from the request head via `FromRequestParts`.
+    pub enum ViaParts {}
+    /// Extracted from the whole request, body included.
+    pub enum ViaRequest {}
+}
+
+/// `M` only disambiguates the blanket impl for parts extractors from body extractors.
+pub trait FromRequest<M = marker::ViaRequest>: Sized {
+    /// The response sent when extraction fails.
+    type Rejection: IntoResponse + Send;
+
+    /// Extracts the value, consuming the request.
+    fn from_request(req: Request) -> impl Future<Output = Result<Self, Self::Rejection>> + Send;
+}
+
+impl<T: FromRequestParts + Send> FromRequest<marker::ViaParts> for T {
+    type Rejection = T::Rejection;
+
+    async fn from_request(req: Request) -> Result<T, T::Rejection> {
+        let (mut parts, _) = req.into_parts();
+        T::from_request_parts(&mut parts).await
+    }
+}
+
+impl FromRequestParts for Method {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Method, Infallible> {
+        Ok(parts.method.clone())
+    }
+}
+
+impl FromRequestParts for Uri {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Uri, Infallible> {
+        Ok(parts.uri.clone())
+    }
+}
+
+impl FromRequestParts for Version {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Version, Infallible> {
+        Ok(parts.version)
+    }
+}
+
+impl FromRequestParts for HeaderMap {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<HeaderMap, Infallible> {
+        Ok(parts.headers.clone())
+    }
+}
+
+impl FromRequestParts for Extensions {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Extensions, Infallible> {
+        Ok(parts.extensions.clone())
+    }
+}
+
+impl FromRequestParts for PathParams {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<PathParams, Infallible> {
+        Ok(parts.extensions.get().cloned().unwrap_or_default())
+    }
+}
+
+impl FromRequestParts for ConnectionInfo {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<ConnectionInfo, Error> {
+        parts
+            .extensions
+            .get()
+            .cloned()
+            .ok_or(Error::MissingExtension("ConnectionInfo"))
+    }
+}
+
+impl FromRequestParts for Session {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Session, Error> {
+        parts
+            .extensions
+            .get()
+            .cloned()
+            .ok_or(Error::MissingExtension("Session"))
+    }
+}
+
+impl FromRequestParts for CookieJar {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<CookieJar, Infallible> {
+        Ok(CookieJar::parse(
+            parts
+                .headers
+                .get_all(hyper::header::COOKIE)
+                .into_iter()
+                .filter_map(|v| v.to_str().ok()),
+        ))
+    }
+}
+
+impl<T: FromRequestParts + Send> FromRequestParts for Option<T> {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Option<T>, Infallible> {
+        Ok(T::from_request_parts(parts).await.ok())
+    }
+}
+
+/// The decoded query string pairs, in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query(pub Vec<(String, String)>);
+
+impl Query {
+    /// The first value for `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+impl FromRequestParts for Query {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Query, Infallible> {
+        let pairs = QueryPairs::new(parts.uri.query().unwrap_or(""));
+        Ok(Query(
+            pairs
+                .map(|(k, v)| (k.into_owned(), v.into_owned()))
+                .collect(),
+        ))
+    }
+}
+
+/// A clone of a value from the request extensions; missing values are a 500.
+#[derive(Clone, Debug)]
+pub struct Extension<T>(pub T);
+
+impl<T: Clone + Send + Sync + 'static> FromRequestParts for Extension<T> {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<Extension<T>, Error> {
+        parts
+            .extensions
+            .get()
+            .cloned()
+            .map(Extension)
+            .ok_or(Error::MissingExtension(std::any::type_name::<T>()))
+    }
+}
+
+/// JSON bodies are passed through as validated raw bytes; decode them with the serializer of
+/// your choice.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Json<T>(pub T);
+
+impl FromRequest for Json<Bytes> {
+    type Rejection = Error;
+
+    async fn from_request(req: Request) -> Result<Json<Bytes>, Error> {
+        let limit = body_limit(&req);
+        collect_json(req, limit).await.map(Json)
+    }
+}
+
+impl FromRequest for Request {
+    type Rejection = Infallible;
+
+    async fn from_request(req: Request) -> Result<Request, Infallible> {
+        Ok(req)
+    }
+}
+
+impl FromRequest for Bytes {
+    type Rejection = Error;
+
+    async fn from_request(req: Request) -> Result<Bytes, Error> {
+        let limit = body_limit(&req);
+        collect_request(req, limit).await
+    }
+}
+
+impl FromRequest for String {
+    type Rejection = Error;
+
+    async fn from_request(req: Request) -> Result<String, Error> {
+        let limit = body_limit(&req);
+        let bytes = collect_request(req, limit).await?;
+        Ok(std::str::from_utf8(&bytes)?.to_string())
+    }
+}
+
+/// An async function usable as a service: its arguments are extractors and its output an `IntoResponse`.
+pub trait Handler<T>: Clone + Send + Sync + 'static {
+    /// Extracts the arguments from `req` and runs the handler.
+    fn call(self, req: Request) -> ServiceBoxFuture;
+}
+
+impl<F, Fut, R> Handler<()> for F
+where
+    F: FnOnce() -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = R> + Send,
+    R: IntoResponse,
+{
+    fn call(self, _req: Request) -> ServiceBoxFuture {
+        Box::pin(async move { Ok(self().await.into_response()) })
+    }
+}
+
+macro_rules! impl_handler {
+    ($($ty:ident),*; $last:ident) => {
+        impl<F, Fut, R, M, $($ty,)* $last> Handler<(M, $($ty,)* $last,)> for F
+        where
+            F: FnOnce($($ty,)* $last) -> Fut + Clone + Send + Sync + 'static,
+            Fut: Future<Output = R> + Send,
+            R: IntoResponse,
+            $($ty: FromRequestParts + Send,)*
+            $last: FromRequest<M> + Send,
+        {
+            #[allow(non_snake_case, unused_mut)]
+            fn call(self, req: Request) -> ServiceBoxFuture {
+                Box::pin(async move {
+                    let (mut parts, body) = req.into_parts();
+                    $(
+                        let $ty = match $ty::from_request_parts(&mut parts).await {
+                            Ok(value) => value,
+                            Err(rejection) => return Ok(rejection.into_response()),
+                        };
+                    )*
+                    let req = Request::from_parts(parts, body);
+                    let $last = match $last::from_request(req).await {
+                        Ok(value) => value,
+                        Err(rejection) => return Ok(rejection.into_response()),
+                    };
+                    Ok(self($($ty,)* $last).await.into_response())
+                })
+            }
+        }
+    };
+}
+
+impl_handler!(; T1);
+impl_handler!(T1; T2);
+impl_handler!(T1, T2; T3);
+impl_handler!(T1, T2, T3; T4);
+impl_handler!(T1, T2, T3, T4; T5);
+impl_handler!(T1, T2, T3, T4, T5; T6);
+impl_handler!(T1, T2, T3, T4, T5, T6; T7);
+impl_handler!(T1, T2, T3, T4, T5, T6, T7; T8);
+
+/// A `Handler` wrapped as a service, returned by `handler_fn`.
+pub struct HandlerService<H, T> {
+    handler: H,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<H: Clone, T> Clone for HandlerService<H, T> {
+    fn clone(&self) -> HandlerService<H, T> {
+        HandlerService {
+            handler: self.handler.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Wraps `handler` as a service.
+pub fn handler_fn<H: Handler<T>, T>(handler: H) -> HandlerService<H, T> {
+    HandlerService {
+        handler,
+        _marker: PhantomData,
+    }
+}
+
+impl<H: Handler<T>, T> TowerService<Request> for HandlerService<H, T> {
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        self.handler.clone().call(req)
+    }
+}
@@ -0,0 +1,431 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv6Addr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use hyper::header::{self, HeaderMap, HeaderName};
+use tower::{Layer, Service as TowerService};
+
+use crate::{ConnectionInfo, Request, ServiceError, ServiceResponse};
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+/// An IP network, such as `10.0.0.0/8`; parsed from `addr` or `addr/prefix`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// The network `addr/prefix`, or `None` if the prefix is too long for the address family.
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Cidr> {
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        (prefix <= max).then_some(Cidr { addr, prefix })
+    }
+
+    /// Whether `ip` is in the network; IPv4-mapped IPv6 addresses match IPv4 networks.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_matches(u32::from(net).into(), u32::from(ip).into(), 32, self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(u128::from(net), u128::from(ip), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(net: u128, ip: u128, bits: u8, prefix: u8) -> bool {
+    let shift = bits - prefix;
+    shift >= bits || (net >> shift) == (ip >> shift)
+}
+
+/// The error for a malformed `Cidr`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidCidr;
+
+impl fmt::Display for InvalidCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid CIDR block")
+    }
+}
+
+impl std::error::Error for InvalidCidr {}
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    fn from_str(s: &str) -> Result<Cidr, InvalidCidr> {
+        let (addr, prefix) = s.split_once('/').unwrap_or((s, ""));
+        let addr: IpAddr = addr.parse().map_err(|_| InvalidCidr)?;
+        let prefix = match prefix {
+            "" if !s.contains('/') => match addr {
+                IpAddr::V4(_) => 32,
+                IpAddr::V6(_) => 128,
+            },
+            prefix => prefix.parse().map_err(|_| InvalidCidr)?,
+        };
+        Cidr::new(addr, prefix).ok_or(InvalidCidr)
+    }
+}
+
+/// Trusts `Forwarded` and `X-Forwarded-*` headers from the given proxies, exposing the client they name through `ConnectionInfo`.
+#[derive(Clone, Debug, Default)]
+pub struct ForwardedLayer {
+    trusted: Arc<[Cidr]>,
+}
+
+impl ForwardedLayer {
+    /// Trusts proxies within `trusted`.
+    pub fn new(trusted: impl IntoIterator<Item = Cidr>) -> ForwardedLayer {
+        ForwardedLayer {
+            trusted: trusted.into_iter().collect(),
+        }
+    }
+
+    /// Also trusts proxies within `cidr`.
+    pub fn with_trusted_proxy(self, cidr: Cidr) -> ForwardedLayer {
+        let mut trusted = self.trusted.to_vec();
+        trusted.push(cidr);
+        ForwardedLayer {
+            trusted: trusted.into(),
+        }
+    }
+}
+
+impl<S> Layer<S> for ForwardedLayer {
+    type Service = Forwarded<S>;
+
+    fn layer(&self, inner: S) -> Forwarded<S> {
+        Forwarded {
+            inner,
+            trusted: self.trusted.clone(),
+        }
+    }
+}
+
+/// The service produced by `ForwardedLayer`.
+#[derive(Clone)]
+pub struct Forwarded<S> {
+    inner: S,
+    trusted: Arc<[Cidr]>,
+}
+
+impl<S> TowerService<Request> for Forwarded<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = S::Future;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        let is_trusted = |ip: IpAddr| self.trusted.iter().any(|cidr| cidr.contains(ip));
+        let hops = match req.extensions().get::<ConnectionInfo>() {
+            Some(info) if info.remote_addr().is_some_and(|addr| is_trusted(addr.ip())) => {
+                forwarded_hops(req.headers())
+            }
+            _ => Vec::new(),
+        };
+
+        // Walk from the nearest proxy outwards; the first untrusted hop is the client.
+        let client = hops
+            .iter()
+            .rposition(|hop| !hop.addr.is_some_and(is_trusted))
+            .or((!hops.is_empty()).then_some(0));
+        if let (Some(i), Some(info)) = (client, req.extensions_mut().get_mut::<ConnectionInfo>()) {
+            let hop = &hops[i];
+            if let Some(addr) = hop.addr {
+                info.client_ip = Some(addr);
+            }
+            if let Some(proto) = &hop.proto {
+                info.scheme = Some(Arc::from(proto.to_ascii_lowercase()));
+            }
+            if let Some(host) = &hop.host {
+                info.host = Some(Arc::from(host.as_str()));
+            }
+        }
+
+        self.inner.call(req)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Hop {
+    addr: Option<IpAddr>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Hop> {
+    if headers.contains_key(header::FORWARDED) {
+        return header_list(headers, &header::FORWARDED)
+            .map(|element| {
+                let mut hop = Hop::default();
+                for pair in split_quoted(element, ';') {
+                    let Some((key, value)) = pair.split_once('=') else {
+                        continue;
+                    };
+                    let value = unquote(value.trim());
+                    match key.trim().to_ascii_lowercase().as_str() {
+                        "for" => hop.addr = parse_node(&value),
+                        "proto" => hop.proto = Some(value),
+                        "host" => hop.host = Some(value),
+                        _ => {}
+                    }
+                }
+                hop
+            })
+            .collect();
+    }
+
+    let mut hops: Vec<Hop> = header_list(headers, &X_FORWARDED_FOR)
+        .map(|node| Hop {
+            addr: parse_node(node),
+            ..Hop::default()
+        })
+        .collect();
+    let protos: Vec<&str> = header_list(headers, &X_FORWARDED_PROTO).collect();
+    let hosts: Vec<&str> = header_list(headers, &X_FORWARDED_HOST).collect();
+    let len = hops.len();
+    for (i, hop) in hops.iter_mut().enumerate() {
+        hop.proto = pick(&protos, i, len);
+        hop.host = pick(&hosts, i, len);
+    }
+    hops
+}
+
+/// Proxies that append one value per hop line up with the address list; otherwise the
+/// outermost value describes the original request.
+fn pick(values: &[&str], i: usize, len: usize) -> Option<String> {
+    match values.len() == len {
+        true => values.get(i),
+        false => values.first(),
+    }
+    .map(|v| v.to_string())
+}
+
+fn header_list<'a>(headers: &'a HeaderMap, name: &HeaderName) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .into_iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| split_quoted(v, ','))
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+}
+
+fn split_quoted(s: &str, sep: char) -> impl Iterator<Item = &str> {
+    let (mut quoted, mut escaped) = (false, false);
+    s.split(move |c| {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ => {}
+        }
+        c == sep && !quoted
+    })
+}
+
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(inner) => inner.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => value.to_string(),
+    }
+}
+
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim();
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest
+            .split_once(']')?
+            .0
+            .parse::<Ipv6Addr>()
+            .ok()
+            .map(IpAddr::V6);
+    }
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    node.rsplit_once(':')?.0.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Method;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{service_fn, testing::TestRequestExt};
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    // What the inner service sees as client ip, scheme and host, for a request from
+    // `peer` behind proxies in 10.0.0.0/8 and 2001:db8::/32.
+    async fn resolve(peer: &str, headers: &[(&str, &str)]) -> (String, String, String) {
+        let layer = ForwardedLayer::new(["10.0.0.0/8".parse().unwrap()])
+            .with_trusted_proxy("2001:db8::/32".parse().unwrap());
+        let service = layer.layer(service_fn(|req: Request| async move {
+            let info = ConnectionInfo::from_request(&req).unwrap();
+            let ip = info
+                .client_ip()
+                .map(|ip| ip.to_string())
+                .unwrap_or_default();
+            let host = info.forwarded_host().unwrap_or_default().to_string();
+            Ok::<_, ServiceError>(format!("{ip}|{}|{host}", info.scheme()))
+        }));
+        let mut req = Request::test(Method::GET, "/");
+        for (name, value) in headers {
+            req.headers_mut().append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        req.extensions_mut()
+            .get_mut::<ConnectionInfo>()
+            .unwrap()
+            .remote_addr = Some(peer.parse().unwrap());
+        let resp = service.oneshot(req).await.unwrap();
+        let body = crate::collect_bytes(resp.into_body(), 1024).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let mut parts = body.split('|').map(str::to_string);
+        (
+            parts.next().unwrap(),
+            parts.next().unwrap(),
+            parts.next().unwrap(),
+        )
+    }
+
+    fn resolved(ip: &str, scheme: &str, host: &str) -> (String, String, String) {
+        (ip.into(), scheme.into(), host.into())
+    }
+
+    #[test]
+    fn cidr_parsing_and_matching() {
+        let net: Cidr = "192.168.0.0/16".parse().unwrap();
+        assert!(net.contains(ip("192.168.3.4")));
+        assert!(!net.contains(ip("192.169.0.1")));
+        assert!(net.contains(ip("::ffff:192.168.1.1")));
+        assert!(!net.contains(ip("2001:db8::1")));
+        let host: Cidr = "10.1.2.3".parse().unwrap();
+        assert!(host.contains(ip("10.1.2.3")) && !host.contains(ip("10.1.2.4")));
+        let all: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains(ip("255.255.255.255")));
+        let v6: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains(ip("2001:db8:ffff::1")) && !v6.contains(ip("2001:db9::1")));
+        for invalid in [
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/",
+            "10.0.0/8",
+            "x/8",
+            "10.0.0.0/-1",
+        ] {
+            assert_eq!(invalid.parse::<Cidr>(), Err(InvalidCidr), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn node_parsing() {
+        assert_eq!(parse_node("192.0.2.1"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("192.0.2.1:8080"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("[2001:db8::1]:443"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("[2001:db8::1]"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("2001:db8::1"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+        assert_eq!(parse_node("[192.0.2.1]"), None);
+    }
+
+    #[tokio::test]
+    async fn headers_from_untrusted_peers_are_ignored() {
+        let spoofed = [
+            ("x-forwarded-for", "1.1.1.1"),
+            ("x-forwarded-proto", "https"),
+        ];
+        assert_eq!(
+            resolve("203.0.113.9:5000", &spoofed).await,
+            resolved("203.0.113.9", "http", "")
+        );
+    }
+
+    #[tokio::test]
+    async fn x_forwarded_headers() {
+        let headers = [
+            ("x-forwarded-for", "1.1.1.1, 2.2.2.2"),
+            ("x-forwarded-for", "10.0.0.2"),
+            ("x-forwarded-proto", "HTTPS"),
+            ("x-forwarded-host", "example.com"),
+        ];
+        // The client is the nearest hop not in a trusted block: 2.2.2.2 may have
+        // written "1.1.1.1" itself.
+        assert_eq!(
+            resolve("10.0.0.1:80", &headers).await,
+            resolved("2.2.2.2", "https", "example.com")
+        );
+        let per_hop = [
+            ("x-forwarded-for", "1.1.1.1, 10.0.0.2"),
+            ("x-forwarded-proto", "https, http"),
+        ];
+        assert_eq!(
+            resolve("10.0.0.1:80", &per_hop).await,
+            resolved("1.1.1.1", "https", "")
+        );
+        // With every hop trusted, the outermost one is the best we know.
+        let all_trusted = [("x-forwarded-for", "10.9.9.9, 10.0.0.2")];
+        assert_eq!(
+            resolve("10.0.0.1:80", &all_trusted).await,
+            resolved("10.9.9.9", "http", "")
+        );
+    }
+
+    #[tokio::test]
+    async fn forwarded_header_takes_precedence() {
+        let headers = [
+            (
+                "forwarded",
+                r#"for="[2001:db8:cafe::17]:4711";proto=https;host="a.example", for=10.0.0.5"#,
+            ),
+            ("x-forwarded-for", "9.9.9.9"),
+        ];
+        assert_eq!(
+            resolve("[2001:db8::1]:443", &headers).await,
+            resolved("2001:db8:cafe::17", "https", "a.example")
+        );
+        let quoted = [("forwarded", r#"For="198.51.100.17";Host="x\"y, z""#)];
+        assert_eq!(
+            resolve("10.0.0.1:80", &quoted).await,
+            resolved("198.51.100.17", "http", "x\"y, z")
+        );
+        // An obfuscated client leaves the peer's address in place.
+        let hidden = [("forwarded", "for=_hidden;proto=https")];
+        assert_eq!(
+            resolve("10.0.0.1:80", &hidden).await,
+            resolved("10.0.0.1", "https", "")
+        );
+    }
+}
@@ -0,0 +1,387 @@
+//! Unary and streaming gRPC over HTTP/2: message framing, status trailers and a
+//! service adapter for handlers that work on encoded message bytes.
+
+use std::{
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use hyper::{
+    HeaderMap, Method, StatusCode,
+    body::Frame,
+    header::{self, HeaderValue},
+};
+use tower::Service as TowerService;
+
+use crate::{
+    Request, RequestFrames, ResponseExt, ServiceBoxFuture, ServiceError, ServiceResponse,
+    request_frames,
+};
+
+/// The content type gRPC requests and responses carry.
+pub const APPLICATION_GRPC: &str = "application/grpc";
+/// The largest message `Grpc` accepts unless configured otherwise: 4 MiB.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 << 20;
+
+/// A gRPC status code, sent as `grpc-status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Code {
+    /// Not an error.
+    Ok = 0,
+    /// The operation was cancelled, typically by the caller.
+    Cancelled = 1,
+    /// An error that fits no other code.
+    Unknown = 2,
+    /// The client sent an invalid argument.
+    InvalidArgument = 3,
+    /// The deadline passed before the operation finished.
+    DeadlineExceeded = 4,
+    /// Something requested was not found.
+    NotFound = 5,
+    /// Something the client tried to create already exists.
+    AlreadyExists = 6,
+    /// The caller may not perform the operation.
+    PermissionDenied = 7,
+    /// A resource, such as a quota, has run out.
+    ResourceExhausted = 8,
+    /// The system is not in a state the operation requires.
+    FailedPrecondition = 9,
+    /// The operation was aborted, usually by a concurrency conflict.
+    Aborted = 10,
+    /// The operation went past the valid range.
+    OutOfRange = 11,
+    /// The operation isn't implemented or supported.
+    Unimplemented = 12,
+    /// An invariant the server relies on was broken.
+    Internal = 13,
+    /// The service is unavailable for now; retrying may help.
+    Unavailable = 14,
+    /// Data was lost or corrupted beyond recovery.
+    DataLoss = 15,
+    /// The request lacks valid authentication credentials.
+    Unauthenticated = 16,
+}
+
+/// The outcome of a call: a code and a message for the client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Status {
+    code: Code,
+    message: String,
+}
+
+impl Status {
+    /// A status with `code` and a human-readable `message`.
+    pub fn new(code: Code, message: impl Into<String>) -> Status {
+        Status {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Success, with no message.
+    pub fn ok() -> Status {
+        Status::new(Code::Ok, "")
+    }
+
+    /// `InvalidArgument` with `message`.
+    pub fn invalid_argument(message: impl Into<String>) -> Status {
+        Status::new(Code::InvalidArgument, message)
+    }
+
+    /// `NotFound` with `message`.
+    pub fn not_found(message: impl Into<String>) -> Status {
+        Status::new(Code::NotFound, message)
+    }
+
+    /// `Unimplemented` with `message`.
+    pub fn unimplemented(message: impl Into<String>) -> Status {
+        Status::new(Code::Unimplemented, message)
+    }
+
+    /// `Internal` with `message`.
+    pub fn internal(message: impl Into<String>) -> Status {
+        Status::new(Code::Internal, message)
+    }
+
+    /// The status code.
+    pub fn code(&self) -> Code {
+        self.code
+    }
+
+    /// The message, empty if there is none.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    fn to_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("grpc-status", HeaderValue::from(self.code as i32));
+        if !self.message.is_empty()
+            && let Ok(message) = HeaderValue::from_str(&percent_encode(&self.message))
+        {
+            headers.insert("grpc-message", message);
+        }
+        headers
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for Status {}
+
+/// grpc-message is percent-encoded UTF-8, leaving printable ASCII other than `%` as is.
+fn percent_encode(message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    for b in message.bytes() {
+        match b {
+            b' '..=b'~' if b != b'%' => out.push(b as char),
+            b => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+/// A call as `GrpcService` sees it.
+pub struct GrpcRequest {
+    /// `/package.Service/Method`.
+    pub path: String,
+    /// The request headers, including any custom metadata.
+    pub headers: HeaderMap,
+    /// The request messages.
+    pub messages: Streaming,
+}
+
+/// Response messages, still encoded, ending early with an error status if need be.
+pub type MessageStream = Pin<Box<dyn Stream<Item = Result<Bytes, Status>> + Send>>;
+
+/// Length-prefixed messages read from a request body, still encoded.
+pub struct Streaming {
+    frames: RequestFrames,
+    buf: BytesMut,
+    max_message_size: usize,
+    done: bool,
+}
+
+impl Streaming {
+    fn new(frames: RequestFrames, max_message_size: usize) -> Streaming {
+        Streaming {
+            frames,
+            buf: BytesMut::new(),
+            max_message_size,
+            done: false,
+        }
+    }
+
+    /// The only message of a unary call.
+    pub async fn message(mut self) -> Result<Bytes, Status> {
+        let message = self
+            .next()
+            .await
+            .unwrap_or_else(|| Err(Status::internal("missing request message")))?;
+        match self.next().await {
+            None => Ok(message),
+            Some(Err(status)) => Err(status),
+            Some(Ok(_)) => Err(Status::unimplemented("expected a single request message")),
+        }
+    }
+
+    fn decode(&mut self) -> Option<Result<Bytes, Status>> {
+        if self.buf.len() < 5 {
+            return None;
+        }
+        let compressed = self.buf[0];
+        let len = u32::from_be_bytes([self.buf[1], self.buf[2], self.buf[3], self.buf[4]]) as usize;
+        if compressed != 0 {
+            return Some(Err(Status::unimplemented(
+                "compressed messages are not supported",
+            )));
+        }
+        if len > self.max_message_size {
+            return Some(Err(Status::new(
+                Code::ResourceExhausted,
+                format!("message larger than {} bytes", self.max_message_size),
+            )));
+        }
+        if self.buf.len() < 5 + len {
+            return None;
+        }
+        self.buf.advance(5);
+        Some(Ok(self.buf.split_to(len).freeze()))
+    }
+}
+
+impl Stream for Streaming {
+    type Item = Result<Bytes, Status>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if self.done {
+                return Poll::Ready(None);
+            }
+            if let Some(message) = self.decode() {
+                self.done = message.is_err();
+                return Poll::Ready(Some(message));
+            }
+            match self.frames.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(data))) => self.buf.extend_from_slice(&data),
+                Poll::Ready(Some(Err(e))) => {
+                    self.done = true;
+                    return Poll::Ready(Some(Err(Status::internal(e.to_string()))));
+                }
+                Poll::Ready(None) => {
+                    self.done = true;
+                    return match self.buf.is_empty() {
+                        true => Poll::Ready(None),
+                        false => Poll::Ready(Some(Err(Status::internal("truncated message")))),
+                    };
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// A response stream of exactly one message.
+pub fn unary(message: impl Into<Bytes>) -> MessageStream {
+    Box::pin(futures::stream::iter([Ok(message.into())]))
+}
+
+fn encode(message: Bytes) -> Bytes {
+    let mut buf = BytesMut::with_capacity(5 + message.len());
+    buf.put_u8(0);
+    buf.put_u32(message.len() as u32);
+    buf.extend_from_slice(&message);
+    buf.freeze()
+}
+
+/// Framing is handled by `Grpc`; implementations decode and encode message payloads
+/// with the codec of their choice, and route on `GrpcRequest::path`.
+pub trait GrpcService: Clone + Send + Sync + 'static {
+    /// Handles one call. An `Err` ends the call with that status and no messages.
+    fn call(
+        &self,
+        request: GrpcRequest,
+    ) -> impl Future<Output = Result<MessageStream, Status>> + Send;
+}
+
+/// Serves a `GrpcService`: checks the method and content type, and frames the
+/// messages and trailers.
+#[derive(Clone)]
+pub struct Grpc<G> {
+    service: G,
+    max_message_size: usize,
+}
+
+impl<G: GrpcService> Grpc<G> {
+    /// Wraps `service`, accepting messages up to `DEFAULT_MAX_MESSAGE_SIZE`.
+    pub fn new(service: G) -> Grpc<G> {
+        Grpc {
+            service,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Rejects request messages larger than `max` bytes with `ResourceExhausted`.
+    pub fn with_max_message_size(mut self, max: usize) -> Grpc<G> {
+        self.max_message_size = max;
+        self
+    }
+}
+
+fn is_grpc(req: &Request) -> bool {
+    req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| {
+            ct.strip_prefix(APPLICATION_GRPC).is_some_and(|rest| {
+                rest.is_empty() || rest.starts_with('+') || rest.starts_with(';')
+            })
+        })
+}
+
+impl<G: GrpcService> TowerService<Request> for Grpc<G> {
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        if req.method() != Method::POST {
+            return Box::pin(async { Ok(crate::method_not_allowed(&[Method::POST])) });
+        }
+        if !is_grpc(&req) {
+            return Box::pin(async {
+                Ok(ServiceResponse::from_status(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                ))
+            });
+        }
+        let service = self.service.clone();
+        let max_message_size = self.max_message_size;
+        Box::pin(async move {
+            let path = req.uri().path().to_string();
+            let headers = req.headers().clone();
+            let messages = Streaming::new(request_frames(req), max_message_size);
+            let request = GrpcRequest {
+                path,
+                headers,
+                messages,
+            };
+            let messages = match service.call(request).await {
+                Ok(messages) => messages,
+                // Trailers-only response: the status travels in the headers.
+                Err(status) => {
+                    let mut resp = ServiceResponse::empty().with_header(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static(APPLICATION_GRPC),
+                    );
+                    resp.headers_mut().extend(status.to_headers());
+                    return Ok(resp);
+                }
+            };
+
+            let status = Arc::new(Mutex::new(None));
+            let slot = status.clone();
+            let frames = messages
+                .map(move |message| match message {
+                    Ok(message) => Some(Ok::<_, io::Error>(Frame::data(encode(message)))),
+                    Err(e) => {
+                        *slot.lock().unwrap_or_else(|e| e.into_inner()) = Some(e);
+                        None
+                    }
+                })
+                .take_while(|frame| futures::future::ready(frame.is_some()))
+                .filter_map(futures::future::ready);
+            Ok(ServiceResponse::from_stream(frames)
+                .with_header(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(APPLICATION_GRPC),
+                )
+                .with_header(
+                    header::TRAILER,
+                    HeaderValue::from_static("grpc-status, grpc-message"),
+                )
+                .with_trailers(async move {
+                    status
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .take()
+                        .unwrap_or_else(Status::ok)
+                        .to_headers()
+                }))
+        })
+    }
+}
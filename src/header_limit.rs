@@ -0,0 +1,145 @@
+use hyper_util::{rt::TokioExecutor, server::conn::auto};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    Error, Request, ServiceBoxFuture, ServiceError, ServiceResponse, error::error_response,
+};
+
+/// hyper refuses HTTP/1 read buffers smaller than this.
+const MIN_BUF_SIZE: usize = 8192;
+
+/// Limits on the request line and headers. Set on the server, the header limits are
+/// also handed to hyper, which answers HTTP/1 requests over them with 431 or 414
+/// before they are parsed in full; everything else is checked once the request head
+/// has arrived.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeaderLimit {
+    max_headers: Option<usize>,
+    max_header_bytes: Option<usize>,
+    max_uri_length: Option<usize>,
+}
+
+impl HeaderLimit {
+    /// No limits.
+    pub fn new() -> HeaderLimit {
+        HeaderLimit::default()
+    }
+
+    /// Caps the number of header fields; over it is a 431.
+    pub fn with_max_headers(mut self, max: usize) -> HeaderLimit {
+        self.max_headers = Some(max);
+        self
+    }
+
+    /// Counts names and values plus four bytes of framing per field. hyper's HTTP/1
+    /// buffer, which also holds the request line, is never smaller than 8 KiB.
+    pub fn with_max_header_bytes(mut self, max: usize) -> HeaderLimit {
+        self.max_header_bytes = Some(max);
+        self
+    }
+
+    /// Caps the length of the request target; over it is a 414.
+    pub fn with_max_uri_length(mut self, max: usize) -> HeaderLimit {
+        self.max_uri_length = Some(max);
+        self
+    }
+
+    /// Checks the request head against every limit.
+    pub fn check(&self, req: &Request) -> Result<(), Error> {
+        if let Some(limit) = self.max_uri_length {
+            let uri = req.uri();
+            let len = uri.path_and_query().map_or(0, |p| p.as_str().len())
+                + uri.authority().map_or(0, |a| a.as_str().len());
+            if len > limit {
+                return Err(Error::UriTooLong { limit });
+            }
+        }
+        if let Some(limit) = self.max_headers
+            && req.headers().len() > limit
+        {
+            return Err(Error::TooManyHeaders { limit });
+        }
+        if let Some(limit) = self.max_header_bytes {
+            let len: usize = req
+                .headers()
+                .iter()
+                .map(|(name, value)| name.as_str().len() + value.len() + 4)
+                .sum();
+            if len > limit {
+                return Err(Error::HeadersTooLarge { limit });
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn apply(&self, builder: &mut auto::Builder<TokioExecutor>) {
+        if let Some(max) = self.max_headers {
+            builder.http1().max_headers(max);
+        }
+        if let Some(max) = self.max_header_bytes {
+            builder.http1().max_buf_size(max.max(MIN_BUF_SIZE));
+            builder
+                .http2()
+                .max_header_list_size(max.try_into().unwrap_or(u32::MAX));
+        }
+    }
+}
+
+/// Tightens the server-wide limits for the services it wraps. Requests over the
+/// server's own limits never get this far.
+#[derive(Clone, Copy, Debug)]
+pub struct HeaderLimitLayer {
+    limit: HeaderLimit,
+}
+
+impl HeaderLimitLayer {
+    /// Applies `limit` to the services it wraps.
+    pub fn new(limit: HeaderLimit) -> HeaderLimitLayer {
+        HeaderLimitLayer { limit }
+    }
+}
+
+impl<S> Layer<S> for HeaderLimitLayer {
+    type Service = HeaderLimitService<S>;
+
+    fn layer(&self, inner: S) -> HeaderLimitService<S> {
+        HeaderLimitService {
+            inner,
+            limit: self.limit,
+        }
+    }
+}
+
+/// The service produced by `HeaderLimitLayer`.
+#[derive(Clone)]
+pub struct HeaderLimitService<S> {
+    inner: S,
+    limit: HeaderLimit,
+}
+
+impl<S> TowerService<Request> for HeaderLimitService<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        match self.limit.check(&req) {
+            Ok(()) => Box::pin(self.inner.call(req)),
+            Err(e) => {
+                let resp = error_response(&req, e);
+                Box::pin(async move { Ok(resp) })
+            }
+        }
+    }
+}
@@ -0,0 +1,603 @@
+use std::{fmt, str::FromStr};
+
+use hyper::header::{self, HeaderName, HeaderValue};
+
+use crate::base64;
+
+/// A header with a typed representation, read and written through `Request::typed_header` and friends.
+pub trait TypedHeader: Sized {
+    /// The header this type represents.
+    fn name() -> HeaderName;
+
+    /// Receives every value of the header, in order.
+    fn decode<'a>(values: impl Iterator<Item = &'a HeaderValue>) -> Option<Self>;
+
+    /// Renders the header as a single value.
+    fn encode(&self) -> HeaderValue;
+}
+
+fn lists<'a>(values: impl Iterator<Item = &'a HeaderValue>) -> impl Iterator<Item = &'a str> {
+    values
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| split_unquoted(v, ','))
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+/// Splits on `sep` outside quoted strings, so `"a,b"` stays one item.
+fn split_unquoted(value: &str, sep: char) -> Vec<&str> {
+    let mut items = vec![];
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == sep && !quoted => {
+                items.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&value[start..]);
+    items
+}
+
+/// The contents of a quoted string with its escapes removed, or `value` as is.
+fn unquote(value: &str) -> String {
+    match value
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    {
+        Some(inner) => {
+            let mut out = String::with_capacity(inner.len());
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => out.extend(chars.next()),
+                    c => out.push(c),
+                }
+            }
+            out
+        }
+        None => value.to_string(),
+    }
+}
+
+fn encoded(value: String) -> HeaderValue {
+    HeaderValue::try_from(value).unwrap_or_else(|_| HeaderValue::from_static(""))
+}
+
+/// A parsed `Content-Type`: the media type and its parameters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContentType {
+    mime: String,
+    params: Vec<(String, String)>,
+}
+
+impl ContentType {
+    /// A content type with no parameters; `mime` is lowercased.
+    pub fn new(mime: impl Into<String>) -> ContentType {
+        ContentType {
+            mime: mime.into().to_ascii_lowercase(),
+            params: vec![],
+        }
+    }
+
+    /// Appends a parameter such as `charset=utf-8`.
+    pub fn with_param(mut self, name: impl Into<String>, value: impl Into<String>) -> ContentType {
+        self.params
+            .push((name.into().to_ascii_lowercase(), value.into()));
+        self
+    }
+
+    /// The lowercased `type/subtype`, without parameters.
+    pub fn mime(&self) -> &str {
+        &self.mime
+    }
+
+    /// The value of a parameter, matched case-insensitively by name.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The `charset` parameter, if present.
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+}
+
+impl TypedHeader for ContentType {
+    fn name() -> HeaderName {
+        header::CONTENT_TYPE
+    }
+
+    fn decode<'a>(mut values: impl Iterator<Item = &'a HeaderValue>) -> Option<ContentType> {
+        let value = values.next()?.to_str().ok()?;
+        let mut parts = split_unquoted(value, ';').into_iter();
+        let mime = parts.next()?.trim();
+        if !mime.contains('/') {
+            return None;
+        }
+        let params = parts
+            .filter_map(|p| p.split_once('='))
+            .map(|(k, v)| (k.trim().to_ascii_lowercase(), unquote(v.trim())))
+            .collect();
+        Some(ContentType {
+            mime: mime.to_ascii_lowercase(),
+            params,
+        })
+    }
+
+    fn encode(&self) -> HeaderValue {
+        let mut value = self.mime.clone();
+        for (k, v) in &self.params {
+            value.push_str(&format!("; {k}={v}"));
+        }
+        encoded(value)
+    }
+}
+
+/// The body length in bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentLength(pub u64);
+
+impl TypedHeader for ContentLength {
+    fn name() -> HeaderName {
+        header::CONTENT_LENGTH
+    }
+
+    fn decode<'a>(mut values: impl Iterator<Item = &'a HeaderValue>) -> Option<ContentLength> {
+        values
+            .next()?
+            .to_str()
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+            .map(ContentLength)
+    }
+
+    fn encode(&self) -> HeaderValue {
+        self.0.into()
+    }
+}
+
+/// The options of a `Connection` header, lowercased.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Connection(Vec<String>);
+
+impl Connection {
+    /// `Connection: close`.
+    pub fn close() -> Connection {
+        Connection(vec!["close".to_string()])
+    }
+
+    /// `Connection: keep-alive`.
+    pub fn keep_alive() -> Connection {
+        Connection(vec!["keep-alive".to_string()])
+    }
+
+    /// Whether the header lists `option`, ignoring case.
+    pub fn contains(&self, option: &str) -> bool {
+        self.0.iter().any(|o| o.eq_ignore_ascii_case(option))
+    }
+
+    /// Whether the peer asked to close the connection.
+    pub fn is_close(&self) -> bool {
+        self.contains("close")
+    }
+}
+
+impl TypedHeader for Connection {
+    fn name() -> HeaderName {
+        header::CONNECTION
+    }
+
+    fn decode<'a>(values: impl Iterator<Item = &'a HeaderValue>) -> Option<Connection> {
+        let options: Vec<String> = lists(values).map(str::to_ascii_lowercase).collect();
+        (!options.is_empty()).then_some(Connection(options))
+    }
+
+    fn encode(&self) -> HeaderValue {
+        encoded(self.0.join(", "))
+    }
+}
+
+/// An entry of a quality-weighted list such as `Accept` or `Accept-Encoding`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QualityItem {
+    /// The entry itself, e.g. `text/html` or `gzip`.
+    pub value: String,
+    /// The `q` weight, clamped to `0.0..=1.0`; `1.0` when absent.
+    pub q: f32,
+}
+
+impl QualityItem {
+    fn parse(item: &str) -> Option<QualityItem> {
+        let mut parts = split_unquoted(item, ';').into_iter();
+        let value = parts.next()?.trim();
+        let mut q = 1.0;
+        for param in parts {
+            if let Some((k, v)) = param.split_once('=')
+                && k.trim().eq_ignore_ascii_case("q")
+            {
+                q = v
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|q| q.is_finite())?
+                    .clamp(0.0, 1.0);
+            }
+        }
+        Some(QualityItem {
+            value: value.to_string(),
+            q,
+        })
+    }
+}
+
+impl fmt::Display for QualityItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.q {
+            1.0 => f.write_str(&self.value),
+            q => write!(f, "{};q={q}", self.value),
+        }
+    }
+}
+
+/// Malformed entries are skipped rather than failing the whole header.
+pub(crate) fn quality_list<'a>(values: impl Iterator<Item = &'a HeaderValue>) -> Vec<QualityItem> {
+    lists(values).filter_map(QualityItem::parse).collect()
+}
+
+fn encode_quality_list(items: &[QualityItem]) -> HeaderValue {
+    let items: Vec<String> = items.iter().map(QualityItem::to_string).collect();
+    encoded(items.join(", "))
+}
+
+/// The entries of an `Accept` header, in the order sent.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Accept(pub Vec<QualityItem>);
+
+impl TypedHeader for Accept {
+    fn name() -> HeaderName {
+        header::ACCEPT
+    }
+
+    fn decode<'a>(values: impl Iterator<Item = &'a HeaderValue>) -> Option<Accept> {
+        Some(Accept(quality_list(values)))
+    }
+
+    fn encode(&self) -> HeaderValue {
+        encode_quality_list(&self.0)
+    }
+}
+
+/// An `Authorization` header split into scheme and credentials.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Authorization {
+    scheme: String,
+    credentials: String,
+}
+
+impl Authorization {
+    /// A header with an arbitrary scheme.
+    pub fn new(scheme: impl Into<String>, credentials: impl Into<String>) -> Authorization {
+        Authorization {
+            scheme: scheme.into(),
+            credentials: credentials.into(),
+        }
+    }
+
+    /// A `Bearer` header carrying `token`.
+    pub fn bearer(token: impl Into<String>) -> Authorization {
+        Authorization::new("Bearer", token)
+    }
+
+    /// A `Basic` header with base64-encoded `user:password` credentials.
+    pub fn basic(user: &str, password: &str) -> Authorization {
+        Authorization::new(
+            "Basic",
+            base64::encode(format!("{user}:{password}").as_bytes()),
+        )
+    }
+
+    /// The scheme as sent, e.g. `Bearer`.
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /// The credentials following the scheme, undecoded.
+    pub fn credentials(&self) -> &str {
+        &self.credentials
+    }
+
+    /// The token of a `Bearer` header; the scheme is matched case-insensitively.
+    pub fn bearer_token(&self) -> Option<&str> {
+        self.scheme
+            .eq_ignore_ascii_case("bearer")
+            .then_some(self.credentials.as_str())
+    }
+
+    /// The decoded user and password of a `Basic` header.
+    pub fn basic_credentials(&self) -> Option<(String, String)> {
+        if !self.scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+        let decoded = String::from_utf8(base64::decode(&self.credentials)?).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+        Some((user.to_string(), password.to_string()))
+    }
+}
+
+/// Keeps credentials out of logs.
+impl fmt::Debug for Authorization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Authorization")
+            .field("scheme", &self.scheme)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TypedHeader for Authorization {
+    fn name() -> HeaderName {
+        header::AUTHORIZATION
+    }
+
+    fn decode<'a>(mut values: impl Iterator<Item = &'a HeaderValue>) -> Option<Authorization> {
+        let (scheme, credentials) = values.next()?.to_str().ok()?.trim().split_once(' ')?;
+        Some(Authorization::new(scheme, credentials.trim()))
+    }
+
+    fn encode(&self) -> HeaderValue {
+        let mut value = encoded(format!("{} {}", self.scheme, self.credentials));
+        value.set_sensitive(true);
+        value
+    }
+}
+
+/// An entity tag, as used by `ETag`, `If-Match` and `If-None-Match`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EntityTag {
+    weak: bool,
+    tag: String,
+}
+
+impl EntityTag {
+    /// A strong tag; `tag` excludes the quotes.
+    pub fn strong(tag: impl Into<String>) -> EntityTag {
+        EntityTag {
+            weak: false,
+            tag: tag.into(),
+        }
+    }
+
+    /// A weak (`W/`) tag; `tag` excludes the quotes.
+    pub fn weak(tag: impl Into<String>) -> EntityTag {
+        EntityTag {
+            weak: true,
+            tag: tag.into(),
+        }
+    }
+
+    /// Whether the tag is weak.
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// The opaque tag, without quotes or the `W/` prefix.
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Strong comparison: both tags strong and equal.
+    pub fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Weak comparison: equal tags, regardless of weakness.
+    pub fn weak_eq(&self, other: &EntityTag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl FromStr for EntityTag {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<EntityTag, ()> {
+        let s = s.trim();
+        let (weak, quoted) = match s.strip_prefix("W/") {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let tag = quoted
+            .strip_prefix('"')
+            .and_then(|rest| rest.strip_suffix('"'))
+            .ok_or(())?;
+        match tag.contains('"') {
+            true => Err(()),
+            false => Ok(EntityTag {
+                weak,
+                tag: tag.to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for EntityTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.weak {
+            true => write!(f, "W/\"{}\"", self.tag),
+            false => write!(f, "\"{}\"", self.tag),
+        }
+    }
+}
+
+impl TypedHeader for EntityTag {
+    fn name() -> HeaderName {
+        header::ETAG
+    }
+
+    fn decode<'a>(mut values: impl Iterator<Item = &'a HeaderValue>) -> Option<EntityTag> {
+        values.next()?.to_str().ok()?.parse().ok()
+    }
+
+    fn encode(&self) -> HeaderValue {
+        encoded(self.to_string())
+    }
+}
+
+/// A parsed `If-None-Match` header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IfNoneMatch {
+    /// `*`, matching any current representation.
+    Any,
+    /// A list of entity tags.
+    Tags(Vec<EntityTag>),
+}
+
+impl IfNoneMatch {
+    /// `If-None-Match` uses weak comparison.
+    pub fn matches(&self, etag: &EntityTag) -> bool {
+        match self {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::Tags(tags) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        }
+    }
+}
+
+impl TypedHeader for IfNoneMatch {
+    fn name() -> HeaderName {
+        header::IF_NONE_MATCH
+    }
+
+    fn decode<'a>(values: impl Iterator<Item = &'a HeaderValue>) -> Option<IfNoneMatch> {
+        let items: Vec<&str> = lists(values).collect();
+        if items.contains(&"*") {
+            return Some(IfNoneMatch::Any);
+        }
+        let tags: Vec<EntityTag> = items.iter().filter_map(|s| s.parse().ok()).collect();
+        (!tags.is_empty()).then_some(IfNoneMatch::Tags(tags))
+    }
+
+    fn encode(&self) -> HeaderValue {
+        match self {
+            IfNoneMatch::Any => HeaderValue::from_static("*"),
+            IfNoneMatch::Tags(tags) => {
+                let tags: Vec<String> = tags.iter().map(EntityTag::to_string).collect();
+                encoded(tags.join(", "))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode<T: TypedHeader>(values: &[&str]) -> Option<T> {
+        let values: Vec<HeaderValue> = values
+            .iter()
+            .map(|v| HeaderValue::from_str(v).unwrap())
+            .collect();
+        T::decode(values.iter())
+    }
+
+    fn qualities(values: &[&str]) -> Vec<(String, f32)> {
+        let accept: Accept = decode(values).unwrap();
+        accept
+            .0
+            .into_iter()
+            .map(|item| (item.value, item.q))
+            .collect()
+    }
+
+    #[test]
+    fn quality_lists() {
+        assert_eq!(
+            qualities(&["text/html, application/json;q=0.5", "*/*; Q=0.1"]),
+            [
+                ("text/html".to_string(), 1.0),
+                ("application/json".to_string(), 0.5),
+                ("*/*".to_string(), 0.1)
+            ]
+        );
+        assert_eq!(
+            qualities(&["a;q=2", "b;q=-1"]),
+            [("a".to_string(), 1.0), ("b".to_string(), 0.0)]
+        );
+        // Entries with unreadable weights are dropped, not the whole header.
+        assert_eq!(
+            qualities(&["a;q=high, b;q=NaN, c;q=inf, d;q=0.3"]),
+            [("d".to_string(), 0.3)]
+        );
+        let accept = Accept(vec![
+            QualityItem {
+                value: "a".into(),
+                q: 1.0,
+            },
+            QualityItem {
+                value: "b".into(),
+                q: 0.25,
+            },
+        ]);
+        assert_eq!(accept.encode(), "a, b;q=0.25");
+    }
+
+    #[test]
+    fn lists_split_on_commas() {
+        let connection: Connection = decode(&["keep-alive, Upgrade", ", ,close,"]).unwrap();
+        assert!(connection.contains("upgrade") && connection.is_close());
+        assert_eq!(connection.encode(), "keep-alive, upgrade, close");
+        assert_eq!(decode::<Connection>(&[" , "]), None);
+    }
+
+    #[test]
+    fn quoted_strings_are_kept_whole() {
+        let tags: IfNoneMatch = decode(&[r#""a,b", W/"c""#]).unwrap();
+        assert_eq!(
+            tags,
+            IfNoneMatch::Tags(vec![EntityTag::strong("a,b"), EntityTag::weak("c")])
+        );
+        assert_eq!(
+            decode::<IfNoneMatch>(&[r#""x", *"#]),
+            Some(IfNoneMatch::Any)
+        );
+
+        let content_type: ContentType =
+            decode(&[r#"Multipart/Form-Data; Boundary="a;b=c"; name="say \"hi\"""#]).unwrap();
+        assert_eq!(content_type.mime(), "multipart/form-data");
+        assert_eq!(content_type.param("boundary"), Some("a;b=c"));
+        assert_eq!(content_type.param("NAME"), Some(r#"say "hi""#));
+
+        assert_eq!(
+            qualities(&[r#"text/html;level="1,2";q=0.5, text/plain"#]),
+            [
+                (r#"text/html"#.to_string(), 0.5),
+                ("text/plain".to_string(), 1.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_values() {
+        assert_eq!(decode::<ContentType>(&["text"]), None);
+        assert_eq!(decode::<ContentType>(&[]), None);
+        assert_eq!(decode::<ContentLength>(&["12"]), Some(ContentLength(12)));
+        for length in ["-1", "1.5", "", "12 13", "0x10"] {
+            assert_eq!(decode::<ContentLength>(&[length]), None, "{length}");
+        }
+        for tag in ["abc", "\"abc", "W/abc", r#""a"b""#] {
+            assert_eq!(decode::<EntityTag>(&[tag]), None, "{tag}");
+        }
+        assert_eq!(decode::<IfNoneMatch>(&["abc, W/"]), None);
+        assert_eq!(decode::<Authorization>(&["Bearer"]), None);
+        let non_ascii = HeaderValue::from_bytes(b"text/\xff").unwrap();
+        assert_eq!(ContentType::decode(std::iter::once(&non_ascii)), None);
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use hyper::StatusCode;
+use tower::Service as TowerService;
+
+use crate::{Request, ResponseExt, ServiceBoxFuture, ServiceError, ServiceResponse, json};
+
+const DEFAULT_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+type CheckFuture = Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
+type Check = Arc<dyn Fn() -> CheckFuture + Send + Sync>;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Probe {
+    Liveness,
+    Readiness,
+}
+
+#[derive(Default)]
+struct Inner {
+    liveness: Vec<(String, Check)>,
+    readiness: Vec<(String, Check)>,
+    draining: AtomicBool,
+    not_ready: AtomicBool,
+}
+
+/// Liveness and readiness checks, served as JSON by the `liveness` and `readiness` handlers. Readiness fails while the server drains.
+#[derive(Clone)]
+pub struct Health {
+    inner: Arc<Inner>,
+    timeout: Duration,
+}
+
+impl Default for Health {
+    fn default() -> Health {
+        Health::new()
+    }
+}
+
+impl Health {
+    /// No checks, with a 5 second timeout per check.
+    pub fn new() -> Health {
+        Health {
+            inner: Arc::default(),
+            timeout: DEFAULT_CHECK_TIMEOUT,
+        }
+    }
+
+    /// Fails checks that take longer than `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Health {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Adds a named check to the liveness probe. Panics once the handle has been cloned.
+    pub fn with_liveness_check<F, Fut>(self, name: impl Into<String>, check: F) -> Health
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        self.with_check(Probe::Liveness, name.into(), check)
+    }
+
+    /// Adds a named check to the readiness probe. Panics once the handle has been cloned.
+    pub fn with_readiness_check<F, Fut>(self, name: impl Into<String>, check: F) -> Health
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        self.with_check(Probe::Readiness, name.into(), check)
+    }
+
+    fn with_check<F, Fut>(mut self, probe: Probe, name: String, check: F) -> Health
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let check: Check = Arc::new(move || Box::pin(check()));
+        let inner = Arc::get_mut(&mut self.inner)
+            .expect("checks must be registered before the Health handle is shared");
+        match probe {
+            Probe::Liveness => inner.liveness.push((name, check)),
+            Probe::Readiness => inner.readiness.push((name, check)),
+        }
+        self
+    }
+
+    /// Marks the service ready or not; readiness fails while not ready.
+    pub fn set_ready(&self, ready: bool) {
+        self.inner.not_ready.store(!ready, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_draining(&self) {
+        self.inner.draining.store(true, Ordering::Relaxed);
+    }
+
+    /// A service answering the liveness probe: 200 when every check passes, 503 otherwise.
+    pub fn liveness(&self) -> HealthHandler {
+        HealthHandler {
+            health: self.clone(),
+            probe: Probe::Liveness,
+        }
+    }
+
+    /// A service answering the readiness probe: 200 when ready and every check passes, 503 otherwise.
+    pub fn readiness(&self) -> HealthHandler {
+        HealthHandler {
+            health: self.clone(),
+            probe: Probe::Readiness,
+        }
+    }
+
+    async fn report(&self, probe: Probe) -> ServiceResponse {
+        let checks = match probe {
+            Probe::Liveness => &self.inner.liveness,
+            Probe::Readiness => &self.inner.readiness,
+        };
+        let results = futures::future::join_all(checks.iter().map(|(name, check)| async move {
+            let res = match tokio::time::timeout(self.timeout, check()).await {
+                Ok(res) => res,
+                Err(_) => Err("timed out".to_string()),
+            };
+            (name, res)
+        }))
+        .await;
+
+        let status = match probe {
+            Probe::Readiness if self.inner.draining.load(Ordering::Relaxed) => "draining",
+            Probe::Readiness if self.inner.not_ready.load(Ordering::Relaxed) => "not_ready",
+            _ if results.iter().any(|(_, res)| res.is_err()) => "error",
+            _ => "ok",
+        };
+
+        let mut body = format!("{{\"status\":\"{status}\",\"checks\":{{");
+        for (i, (name, res)) in results.iter().enumerate() {
+            if i > 0 {
+                body.push(',');
+            }
+            json::push_string(&mut body, name);
+            match res {
+                Ok(()) => body.push_str(":{\"status\":\"ok\"}"),
+                Err(e) => {
+                    body.push_str(":{\"status\":\"error\",\"error\":");
+                    json::push_string(&mut body, e);
+                    body.push('}');
+                }
+            }
+        }
+        body.push_str("}}");
+
+        let code = match status {
+            "ok" => StatusCode::OK,
+            _ => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        ServiceResponse::json(body).with_status(code)
+    }
+}
+
+/// A probe endpoint returned by `Health::liveness` or `Health::readiness`.
+#[derive(Clone)]
+pub struct HealthHandler {
+    health: Health,
+    probe: Probe,
+}
+
+impl TowerService<Request> for HealthHandler {
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: Request) -> Self::Future {
+        let health = self.health.clone();
+        let probe = self.probe;
+        Box::pin(async move { Ok(health.report(probe).await) })
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures::{Stream, StreamExt};
+use hyper::{Method, StatusCode, Uri, http::Extensions};
+
+use crate::{
+    BodyInner, BoxedBodyStream, ConnectionInfo, Request, ServiceResponse, make_body_from_stream,
+};
+
+pub(crate) type ConnectHook = Arc<dyn Fn(&ConnectionInfo, &mut Extensions) + Send + Sync>;
+pub(crate) type DisconnectHook = Arc<dyn Fn(&ConnectionInfo) + Send + Sync>;
+pub(crate) type RequestStartHook = Arc<dyn Fn(&Request) + Send + Sync>;
+pub(crate) type RequestEndHook = Arc<dyn Fn(&RequestEnd) + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    pub(crate) on_connect: Option<ConnectHook>,
+    pub(crate) on_disconnect: Option<DisconnectHook>,
+    pub(crate) on_request_start: Option<RequestStartHook>,
+    pub(crate) on_request_end: Option<RequestEndHook>,
+}
+
+/// Handed to `Server::on_request_end` once the response body has been sent, or dropped
+/// by a departing client.
+#[derive(Clone, Debug)]
+pub struct RequestEnd {
+    /// The request method.
+    pub method: Method,
+    /// The request URI.
+    pub uri: Uri,
+    /// `None` when the service failed without producing a response.
+    pub status: Option<StatusCode>,
+    /// The connection the request arrived on.
+    pub connection: ConnectionInfo,
+    /// The response body bytes sent.
+    pub bytes_out: u64,
+    /// Time from the request arriving to the response body finishing.
+    pub latency: Duration,
+}
+
+impl RequestEnd {
+    pub(crate) fn new(req: &Request) -> RequestEnd {
+        RequestEnd {
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            status: None,
+            connection: ConnectionInfo::from_request(req)
+                .cloned()
+                .unwrap_or_default(),
+            bytes_out: 0,
+            latency: Duration::ZERO,
+        }
+    }
+}
+
+pub(crate) struct PendingEnd {
+    hook: RequestEndHook,
+    end: RequestEnd,
+    start: Instant,
+}
+
+impl PendingEnd {
+    pub(crate) fn new(hook: RequestEndHook, req: &Request) -> PendingEnd {
+        PendingEnd {
+            hook,
+            end: RequestEnd::new(req),
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn report(mut self) {
+        self.end.latency = self.start.elapsed();
+        (self.hook)(&self.end);
+    }
+
+    pub(crate) fn attach(mut self, resp: ServiceResponse) -> ServiceResponse {
+        self.end.status = Some(resp.status());
+        let (parts, body) = resp.into_parts();
+        let body = ReportedBody {
+            inner: Box::new(body),
+            pending: Some(self),
+        };
+        ServiceResponse::from_parts(parts, make_body_from_stream(body))
+    }
+}
+
+struct ReportedBody {
+    inner: BoxedBodyStream,
+    pending: Option<PendingEnd>,
+}
+
+impl Stream for ReportedBody {
+    type Item = BodyInner;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<BodyInner>> {
+        let item = futures::ready!(self.inner.poll_next_unpin(cx));
+        match &item {
+            Some(Ok(frame)) => {
+                if let (Some(data), Some(pending)) = (frame.data_ref(), &mut self.pending) {
+                    pending.end.bytes_out += data.len() as u64;
+                }
+            }
+            Some(Err(_)) | None => self.finish(),
+        }
+        Poll::Ready(item)
+    }
+}
+
+impl ReportedBody {
+    fn finish(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            pending.report();
+        }
+    }
+}
+
+impl Drop for ReportedBody {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Runs the disconnect hook when the connection task ends, including when it is
+/// aborted after the drain timeout.
+pub(crate) struct DisconnectGuard(pub(crate) Option<(DisconnectHook, ConnectionInfo)>);
+
+impl Drop for DisconnectGuard {
+    fn drop(&mut self) {
+        if let Some((hook, info)) = self.0.take() {
+            hook(&info);
+        }
+    }
+}
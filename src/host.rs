@@ -0,0 +1,61 @@
+use std::sync::Arc;
+
+use crate::{Request, RequestExt, Router};
+
+/// Matches on the request's host, ignoring case, any port and a trailing dot.
+/// `*.example.com` matches any subdomain of `example.com` but not the domain itself;
+/// `*` matches every host.
+#[derive(Clone, Debug)]
+pub struct HostRouter {
+    pattern: Arc<str>,
+}
+
+impl HostRouter {
+    /// A router for hosts matching `pattern`.
+    pub fn new(pattern: impl AsRef<str>) -> HostRouter {
+        let pattern = pattern.as_ref().trim_end_matches('.').to_ascii_lowercase();
+        HostRouter {
+            pattern: Arc::from(pattern),
+        }
+    }
+
+    /// The lowercased pattern.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Whether `host` matches the pattern.
+    pub fn matches_host(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.');
+        match self.pattern.strip_prefix('*') {
+            Some("") => true,
+            Some(suffix) => {
+                host.len() > suffix.len()
+                    && host.is_char_boundary(host.len() - suffix.len())
+                    && host[host.len() - suffix.len()..].eq_ignore_ascii_case(suffix)
+            }
+            None => host.eq_ignore_ascii_case(&self.pattern),
+        }
+    }
+}
+
+impl Router for HostRouter {
+    fn matches(&self, req: &Request) -> bool {
+        req.authority()
+            .is_some_and(|authority| self.matches_host(host_name(authority)))
+    }
+}
+
+fn host_name(authority: &str) -> &str {
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    if authority.starts_with('[') {
+        return authority
+            .split_once(']')
+            .map_or(authority, |(host, _)| &host[1..]);
+    }
+    authority
+        .split_once(':')
+        .map_or(authority, |(host, _)| host)
+}
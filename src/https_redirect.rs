@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use hyper::{Method, StatusCode};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    MountPath, Request, RequestExt, ResponseExt, ServiceBoxFuture, ServiceError, ServiceResponse,
+};
+
+/// Redirects plain HTTP requests to the same URL over HTTPS, going by
+/// `RequestExt::scheme`, so a trusted proxy's forwarded proto counts. GET and HEAD
+/// get a 301, anything else a 308 so clients repeat the method and body. ACME HTTP-01
+/// challenges are exempt by default.
+#[derive(Clone)]
+pub struct HttpsRedirectLayer {
+    port: Option<u16>,
+    exempt: Arc<[String]>,
+}
+
+impl Default for HttpsRedirectLayer {
+    fn default() -> HttpsRedirectLayer {
+        HttpsRedirectLayer::new()
+    }
+}
+
+impl HttpsRedirectLayer {
+    /// Redirects to port 443, exempting `/.well-known/acme-challenge`.
+    pub fn new() -> HttpsRedirectLayer {
+        HttpsRedirectLayer {
+            port: None,
+            exempt: Arc::from(["/.well-known/acme-challenge".to_string()]),
+        }
+    }
+
+    /// For HTTPS served on a port other than 443.
+    pub fn with_port(mut self, port: u16) -> HttpsRedirectLayer {
+        self.port = Some(port).filter(|&port| port != 443);
+        self
+    }
+
+    /// Serves `path` and everything below it over plain HTTP as well. Paths are
+    /// matched before any mount prefix is stripped.
+    pub fn with_exempt(mut self, path: &str) -> HttpsRedirectLayer {
+        let mut exempt = self.exempt.to_vec();
+        exempt.push(path.trim_end_matches('/').to_string());
+        self.exempt = exempt.into();
+        self
+    }
+
+    /// Redirects every path, ACME challenges included.
+    pub fn without_exempt_paths(mut self) -> HttpsRedirectLayer {
+        self.exempt = Arc::from([]);
+        self
+    }
+
+    fn is_exempt(&self, path: &str) -> bool {
+        self.exempt.iter().any(|prefix| {
+            path.strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+
+    fn location(&self, host: &str, path_and_query: &str) -> String {
+        match self.port {
+            Some(port) => format!("https://{host}:{port}{path_and_query}"),
+            None => format!("https://{host}{path_and_query}"),
+        }
+    }
+}
+
+/// The host without its port, keeping IPv6 literals bracketed.
+fn strip_port(authority: &str) -> &str {
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    match authority.find(']') {
+        Some(end) if authority.starts_with('[') => &authority[..=end],
+        _ => authority
+            .split_once(':')
+            .map_or(authority, |(host, _)| host),
+    }
+}
+
+impl<S> Layer<S> for HttpsRedirectLayer {
+    type Service = HttpsRedirect<S>;
+
+    fn layer(&self, inner: S) -> HttpsRedirect<S> {
+        HttpsRedirect {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// The service produced by `HttpsRedirectLayer`.
+#[derive(Clone)]
+pub struct HttpsRedirect<S> {
+    inner: S,
+    config: HttpsRedirectLayer,
+}
+
+impl<S> TowerService<Request> for HttpsRedirect<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        if req.scheme() != "http" {
+            return Box::pin(self.inner.call(req));
+        }
+        let mount = MountPath::from_request(&req).map_or("", MountPath::as_str);
+        let full_path = format!("{mount}{}", req.path_and_query());
+        let path = full_path.split('?').next().unwrap_or("/");
+        if self.config.is_exempt(path) {
+            return Box::pin(self.inner.call(req));
+        }
+        let resp = match req.authority() {
+            Some(authority) => {
+                let location = self.config.location(strip_port(authority), &full_path);
+                match *req.method() {
+                    Method::GET | Method::HEAD => ServiceResponse::moved_permanently(&location),
+                    _ => ServiceResponse::permanent_redirect(&location),
+                }
+            }
+            None => ServiceResponse::from_status(StatusCode::BAD_REQUEST),
+        };
+        Box::pin(async { Ok(resp) })
+    }
+}
@@ -0,0 +1,452 @@
+use std::fmt::Write as _;
+
+pub(crate) fn push_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// A parsed JSON document. Objects keep their keys in document order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    /// `null`.
+    Null,
+    /// `true` or `false`.
+    Bool(bool),
+    /// A number, as a double.
+    Number(f64),
+    /// A string, with escapes decoded.
+    String(String),
+    /// An array.
+    Array(Vec<JsonValue>),
+    /// An object's members, in document order.
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    /// Parses a complete document; `None` on any syntax error, trailing input or nesting deeper than 128 levels.
+    pub fn parse(input: &str) -> Option<JsonValue> {
+        let mut parser = Parser {
+            input: input.as_bytes(),
+            pos: 0,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        (parser.pos == parser.input.len()).then_some(value)
+    }
+
+    /// The member `key` of an object; later duplicates win, as in most parsers.
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(members) => {
+                members.iter().rev().find(|(k, _)| k == key).map(|(_, v)| v)
+            }
+            _ => None,
+        }
+    }
+
+    /// The value if it is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The value if it is a number.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            JsonValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The value if it is a bool.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            JsonValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// The items if the value is an array.
+    pub fn as_array(&self) -> Option<&[JsonValue]> {
+        match self {
+            JsonValue::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Whether the value is `null`.
+    pub fn is_null(&self) -> bool {
+        matches!(self, JsonValue::Null)
+    }
+}
+
+impl std::fmt::Display for JsonValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonValue::Null => f.write_str("null"),
+            JsonValue::Bool(b) => write!(f, "{b}"),
+            JsonValue::Number(n) if n.is_finite() => write!(f, "{n}"),
+            JsonValue::Number(_) => f.write_str("null"),
+            JsonValue::String(s) => {
+                let mut out = String::new();
+                push_string(&mut out, s);
+                f.write_str(&out)
+            }
+            JsonValue::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                f.write_str("]")
+            }
+            JsonValue::Object(members) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    let mut out = String::new();
+                    push_string(&mut out, key);
+                    write!(f, "{out}:{value}")?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+/// Deeply nested input is rejected rather than risking the stack.
+const MAX_DEPTH: usize = 128;
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.input.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, literal: &str) -> bool {
+        let matched = self.input[self.pos..].starts_with(literal.as_bytes());
+        if matched {
+            self.pos += literal.len();
+        }
+        matched
+    }
+
+    fn value(&mut self) -> Option<JsonValue> {
+        self.skip_whitespace();
+        match *self.input.get(self.pos)? {
+            b'n' => self.eat("null").then_some(JsonValue::Null),
+            b't' => self.eat("true").then_some(JsonValue::Bool(true)),
+            b'f' => self.eat("false").then_some(JsonValue::Bool(false)),
+            b'"' => self.string().map(JsonValue::String),
+            b'[' => self.nested(Parser::array),
+            b'{' => self.nested(Parser::object),
+            _ => self.number(),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Option<JsonValue>) -> Option<JsonValue> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return None;
+        }
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn array(&mut self) -> Option<JsonValue> {
+        self.pos += 1;
+        let mut items = vec![];
+        self.skip_whitespace();
+        if self.eat("]") {
+            return Some(JsonValue::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            if self.eat("]") {
+                return Some(JsonValue::Array(items));
+            }
+            if !self.eat(",") {
+                return None;
+            }
+        }
+    }
+
+    fn object(&mut self) -> Option<JsonValue> {
+        self.pos += 1;
+        let mut members = vec![];
+        self.skip_whitespace();
+        if self.eat("}") {
+            return Some(JsonValue::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.input.get(self.pos) != Some(&b'"') {
+                return None;
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if !self.eat(":") {
+                return None;
+            }
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            if self.eat("}") {
+                return Some(JsonValue::Object(members));
+            }
+            if !self.eat(",") {
+                return None;
+            }
+        }
+    }
+
+    fn number(&mut self) -> Option<JsonValue> {
+        let start = self.pos;
+        while let Some(b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E') = self.input.get(self.pos) {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.input[start..self.pos]).ok()?;
+        // Rust's float syntax is looser than JSON's in a few places.
+        let digits = text.strip_prefix('-').unwrap_or(text);
+        if !digits.starts_with(|c: char| c.is_ascii_digit())
+            || (digits.starts_with('0') && digits[1..].starts_with(|c: char| c.is_ascii_digit()))
+            || digits.contains(".e")
+            || digits.contains(".E")
+            || digits.ends_with('.')
+        {
+            return None;
+        }
+        text.parse().ok().map(JsonValue::Number)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let start = self.pos;
+            while let Some(&b) = self.input.get(self.pos) {
+                if b == b'"' || b == b'\\' || b < 0x20 {
+                    break;
+                }
+                self.pos += 1;
+            }
+            out.push_str(std::str::from_utf8(&self.input[start..self.pos]).ok()?);
+            match *self.input.get(self.pos)? {
+                b'"' => {
+                    self.pos += 1;
+                    return Some(out);
+                }
+                b'\\' => {
+                    self.pos += 1;
+                    let escape = *self.input.get(self.pos)?;
+                    self.pos += 1;
+                    match escape {
+                        b'"' => out.push('"'),
+                        b'\\' => out.push('\\'),
+                        b'/' => out.push('/'),
+                        b'b' => out.push('\u{8}'),
+                        b'f' => out.push('\u{c}'),
+                        b'n' => out.push('\n'),
+                        b'r' => out.push('\r'),
+                        b't' => out.push('\t'),
+                        b'u' => out.push(self.unicode_escape()?),
+                        _ => return None,
+                    }
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let hex = self.input.get(self.pos..self.pos + 4)?;
+        if !hex.iter().all(u8::is_ascii_hexdigit) {
+            return None;
+        }
+        self.pos += 4;
+        u32::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()
+    }
+
+    fn unicode_escape(&mut self) -> Option<char> {
+        let high = self.hex4()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high);
+        }
+        if !self.eat("\\u") {
+            return None;
+        }
+        let low = self.hex4()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return None;
+        }
+        char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(input: &str) -> Option<String> {
+        match JsonValue::parse(input)? {
+            JsonValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(
+            string(r#""a\"b\\c\/d\b\f\n\r\t""#).unwrap(),
+            "a\"b\\c/d\u{8}\u{c}\n\r\t"
+        );
+        assert_eq!(string(r#""\u00e9\u20AC""#).unwrap(), "é€");
+        assert_eq!(string(r#""caf\u00e9 ok""#).unwrap(), "café ok");
+        for input in [
+            r#""\x""#,
+            r#""\u12""#,
+            r#""\u12g4""#,
+            "\"tab\there\"",
+            "\"a\nb\"",
+        ] {
+            assert_eq!(string(input), None, "{input}");
+        }
+    }
+
+    #[test]
+    fn decodes_surrogate_pairs() {
+        assert_eq!(string(r#""\ud83d\ude00""#).unwrap(), "😀");
+        for input in [
+            r#""\ud83d""#,
+            r#""\ud83dx""#,
+            r#""\ud83d\u0041""#,
+            r#""\ude00""#,
+            r#""\ude00\ud83d""#,
+        ] {
+            assert_eq!(string(input), None, "{input}");
+        }
+    }
+
+    #[test]
+    fn escapes_round_trip() {
+        let value = JsonValue::String("quote \" slash \\ nl \n bell \u{7} 😀".into());
+        let text = value.to_string();
+        assert_eq!(text, r#""quote \" slash \\ nl \n bell \u0007 😀""#);
+        assert_eq!(JsonValue::parse(&text), Some(value));
+    }
+
+    #[test]
+    fn numbers_follow_json_syntax() {
+        for (input, expected) in [
+            ("0", 0.0),
+            ("-0", -0.0),
+            ("12", 12.0),
+            ("-3.25", -3.25),
+            ("1e3", 1000.0),
+            ("1E+2", 100.0),
+            ("2.5e-1", 0.25),
+            ("0.5", 0.5),
+        ] {
+            assert_eq!(
+                JsonValue::parse(input),
+                Some(JsonValue::Number(expected)),
+                "{input}"
+            );
+        }
+        for input in [
+            "01",
+            "-01",
+            "+1",
+            ".5",
+            "1.",
+            "1.e3",
+            "-",
+            "1e",
+            "1e+",
+            "0x10",
+            "1.2.3",
+            "--1",
+            "NaN",
+            "Infinity",
+            "-Infinity",
+        ] {
+            assert_eq!(JsonValue::parse(input), None, "{input}");
+        }
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(JsonValue::parse(&nested(MAX_DEPTH)).is_some());
+        assert!(JsonValue::parse(&nested(MAX_DEPTH + 1)).is_none());
+        let objects = "{\"a\":".repeat(MAX_DEPTH + 1) + "1" + &"}".repeat(MAX_DEPTH + 1);
+        assert!(JsonValue::parse(&objects).is_none());
+        // Depth is about nesting, not the number of siblings.
+        let wide = format!("[{}]", vec![nested(MAX_DEPTH - 1); 4].join(","));
+        assert!(JsonValue::parse(&wide).is_some());
+    }
+
+    #[test]
+    fn rejects_trailing_input() {
+        assert!(JsonValue::parse(" {\"a\": [1, true, null]} \n").is_some());
+        for input in ["{} {}", "[1] x", "1 2", "null,", "\"a\"\"b\"", "truex"] {
+            assert_eq!(JsonValue::parse(input), None, "{input}");
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let full = r#"{"name": "alice", "tags": ["a", "b"], "ok": true, "n": -1.5e3}"#;
+        assert!(JsonValue::parse(full).is_some());
+        for end in 0..full.len() {
+            assert_eq!(JsonValue::parse(&full[..end]), None, "{}", &full[..end]);
+        }
+        for input in [
+            "[1,]",
+            "{\"a\":1,}",
+            "{\"a\"}",
+            "{1:2}",
+            "[",
+            "\"",
+            "tru",
+            "nul",
+        ] {
+            assert_eq!(JsonValue::parse(input), None, "{input}");
+        }
+    }
+
+    #[test]
+    fn later_duplicate_keys_win() {
+        let value = JsonValue::parse(r#"{"a": 1, "b": 2, "a": 3}"#).unwrap();
+        assert_eq!(value.get("a").and_then(JsonValue::as_f64), Some(3.0));
+        assert_eq!(value.to_string(), r#"{"a":1,"b":2,"a":3}"#);
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Notify;
+
+use crate::Http1Config;
+
+/// Per-connection bookkeeping for `Http1Config`'s request limit and idle timeout.
+pub(crate) struct KeepAlive {
+    max_requests: Option<usize>,
+    timeout: Option<Duration>,
+    requests: AtomicUsize,
+    in_flight: AtomicUsize,
+    last_active: Mutex<Instant>,
+    changed: Notify,
+    http2: AtomicBool,
+}
+
+/// Marks a request as in flight until dropped along with its response body.
+pub(crate) struct Active(Arc<KeepAlive>);
+
+impl KeepAlive {
+    pub(crate) fn new(config: &Http1Config) -> Option<Arc<KeepAlive>> {
+        if config.max_requests.is_none() && config.keep_alive_timeout.is_none() {
+            return None;
+        }
+        Some(Arc::new(KeepAlive {
+            max_requests: config.max_requests,
+            timeout: config.keep_alive_timeout,
+            requests: AtomicUsize::new(0),
+            in_flight: AtomicUsize::new(0),
+            last_active: Mutex::new(Instant::now()),
+            changed: Notify::new(),
+            http2: AtomicBool::new(false),
+        }))
+    }
+
+    /// The settings only concern HTTP/1, so an HTTP/2 connection is left alone.
+    pub(crate) fn disable(&self) {
+        if !self.http2.swap(true, Ordering::Relaxed) {
+            self.changed.notify_waiters();
+        }
+    }
+
+    /// Returns whether the connection should close after this request's response.
+    pub(crate) fn start(self: &Arc<KeepAlive>) -> (bool, Option<Active>) {
+        let n = self.requests.fetch_add(1, Ordering::Relaxed) + 1;
+        let close = self.max_requests.is_some_and(|max| n >= max);
+        let active = self.timeout.map(|_| {
+            self.in_flight.fetch_add(1, Ordering::Relaxed);
+            self.changed.notify_waiters();
+            Active(self.clone())
+        });
+        (close, active)
+    }
+
+    /// Resolves once no request has been in flight for the idle timeout; never
+    /// without one.
+    pub(crate) async fn idle(&self) {
+        let Some(timeout) = self.timeout else {
+            return std::future::pending().await;
+        };
+        loop {
+            let changed = self.changed.notified();
+            if self.http2.load(Ordering::Relaxed) {
+                return std::future::pending().await;
+            }
+            if self.in_flight.load(Ordering::Relaxed) > 0 {
+                changed.await;
+                continue;
+            }
+            let deadline = *self.last_active.lock().unwrap_or_else(|e| e.into_inner()) + timeout;
+            if deadline <= Instant::now() {
+                return;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline.into()) => {}
+                _ = changed => {}
+            }
+        }
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        *self.0.last_active.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+        self.0.changed.notify_waiters();
+    }
+}
@@ -1,36 +1,297 @@
+//! A thin HTTP server library over hyper and tower.
+//!
+//! Routers decide which route takes a request and tower services answer it;
+//! `ServiceBuilder` collects routes into a `Service`, and `Server` accepts
+//! connections and drives them with hyper.
+//!
+//! Not supported:
+//! - 103 Early Hints: hyper 1.6 and h2 0.4 give servers no way to send an
+//!   informational response ahead of the final one, so `Link` preloads have to go
+//!   on the final response.
+//! - HTTP/3: serving QUIC needs quinn, h3 and rustls, none of which the crate
+//!   depends on, and an `Alt-Svc` header without a QUIC listener behind it would
+//!   only send clients to a dead port.
+
+#![warn(missing_docs)]
+
 use std::{future::Future, io, path::PathBuf, pin::Pin, sync::Arc};
 
 use bytes::Bytes;
 use futures::{Stream, TryFutureExt};
-use http_body_util::StreamBody;
 use hyper::{
-    Response, StatusCode,
+    Method, Response, StatusCode, Uri,
     body::{Frame, Incoming},
-    server::conn::http1,
+    header::{self, HeaderValue},
+    service::Service as HyperService,
 };
-use hyper_util::{rt::TokioIo, service::TowerToHyperService};
 use tokio::net::TcpListener;
-use tower::{Service as TowerService, util::BoxCloneSyncService};
+use tower::{Layer, Service as TowerService, ServiceExt, util::BoxCloneSyncService};
+
+mod access_log;
+#[cfg(feature = "acme")]
+mod acme;
+mod api_key;
+mod auth;
+mod base64;
+#[cfg(feature = "jwt")]
+mod bigint;
+mod body;
+mod body_limit;
+mod cache;
+mod catch_panic;
+mod client;
+mod client_cert;
+mod compression;
+mod concurrency;
+mod conditional;
+mod config;
+mod connection;
+mod cors;
+mod csrf;
+mod date;
+mod deadline;
+mod deflate;
+mod error;
+mod expect;
+mod extract;
+mod forwarded;
+mod header_limit;
+mod headers;
+mod health;
+mod hooks;
+mod host;
+mod https_redirect;
+mod json;
+#[cfg(feature = "jwt")]
+mod jwt;
+mod keep_alive;
+mod limits;
+mod listener;
+mod mirror;
+mod mount;
+mod normalize;
+mod not_handled;
+#[cfg(feature = "jwt")]
+mod p256;
+mod path;
+mod pattern;
+mod problem;
+mod proxy;
+mod query;
+mod random;
+mod range;
+mod rate_limit;
+mod reload;
+mod request;
+mod request_id;
+mod response;
+#[cfg(feature = "jwt")]
+mod rsa;
+mod security_headers;
+mod server;
+mod service_fn;
+mod session;
+mod sha1;
+mod sha256;
+mod state;
+mod static_files;
+mod timeout;
+mod trace;
+mod tree;
+mod upstream;
+mod writer;
+
+pub mod cookies;
+pub mod grpc;
+pub mod metrics;
+pub mod multipart;
+pub mod negotiate;
+pub mod predicate;
+pub mod sse;
+pub mod testing;
+pub mod ws;
+
+pub use access_log::{
+    AccessLog, AccessLogEntry, AccessLogLayer, FileSink, LogFormat, LogSink, Stdout, WriterSink,
+};
+#[cfg(feature = "acme")]
+pub use acme::{ACME_CHALLENGE_PATH, AccountKey, Acme, AcmeChallenges, AcmeLayer};
+pub use api_key::{
+    ApiKey, ApiKeyInfo, ApiKeyLayer, FileKeyStore, KeyStore, MemoryKeyStore, X_API_KEY,
+};
+pub use auth::{Auth, AuthLayer, Principal, secure_eq};
+pub use body::{
+    APPLICATION_FORM, APPLICATION_JSON, Body, BodyTimeouts, RequestFrames, RequestTrailers,
+    body_frames, body_reader, collect_bytes, collect_bytes_with_timeouts, collect_form,
+    collect_json, collect_request, collect_request_with_trailers, collect_string, request_frames,
+    request_frames_with_trailers, require_content_type,
+};
+pub use body_limit::{BodyLimit, BodyLimitLayer, BodyLimitService};
+pub use cache::{Cache, CacheControl, CacheLayer};
+pub use catch_panic::{CatchPanic, CatchPanicLayer};
+pub use client::{Client, ClientBuilder};
+pub use client_cert::{ClientCert, ClientCertLayer, PeerCertificate, TlsSession};
+pub use compression::{Compression, CompressionLayer, Decompression, DecompressionLayer};
+pub use concurrency::{ConcurrencyLimit, ConcurrencyLimitLayer};
+pub use conditional::{
+    Conditional, ConditionalLayer, etag_for_bytes, etag_for_metadata, http_date, is_fresh,
+    not_modified, parse_http_date,
+};
+pub use config::{
+    ConnectionLimits, Http1Config, Http2Config, ListenerConfig, Overflow, RuntimeConfig,
+};
+pub use connection::ConnectionInfo;
+pub use cors::{Cors, CorsLayer};
+pub use csrf::{Csrf, CsrfError, CsrfLayer, CsrfToken, X_CSRF_TOKEN, csrf_token};
+pub use deadline::{Deadline, DeadlineLayer, DeadlineService, X_REQUEST_TIMEOUT};
+pub use error::{Error, ErrorContext, ErrorHandler, ErrorMapper, ErrorRecovery, Recovery};
+pub use expect::{ExpectContinue, ExpectContinueLayer};
+pub use extract::{
+    Extension, FromRequest, FromRequestParts, Handler, HandlerService, Json, Query, handler_fn,
+    marker,
+};
+pub use forwarded::{Cidr, Forwarded, ForwardedLayer, InvalidCidr};
+pub use header_limit::{HeaderLimit, HeaderLimitLayer, HeaderLimitService};
+pub use headers::{
+    Accept, Authorization, Connection, ContentLength, ContentType, EntityTag, IfNoneMatch,
+    QualityItem, TypedHeader,
+};
+pub use health::{Health, HealthHandler};
+pub use hooks::RequestEnd;
+pub use host::HostRouter;
+pub use https_redirect::{HttpsRedirect, HttpsRedirectLayer};
+pub use hyper::http::{Extensions, request::Parts};
+pub use json::JsonValue;
+#[cfg(feature = "jwt")]
+pub use jwt::{Claims, Jwk, Jwt, JwtLayer};
+pub use mirror::{Mirror, MirrorLayer};
+pub use mount::{MountPath, MountRouter};
+pub use normalize::{RawPath, Utf8Policy};
+pub use not_handled::NotHandled;
+pub use path::{MatchedPath, PathParams, PathRouter};
+pub use problem::{APPLICATION_PROBLEM_JSON, Problem};
+pub use proxy::{InvalidUpstream, Proxy};
+pub use query::{QueryPairs, decode_component, decode_component_bytes};
+pub use range::{ByteRange, ranged_bytes};
+pub use rate_limit::{
+    MemoryRateStore, RateAlgorithm, RateDecision, RateLimit, RateLimitLayer, RatePolicy, RateStore,
+};
+pub use reload::ReloadableAcceptor;
+pub use request::RequestExt;
+pub use request_id::{RequestId, RequestIdLayer, RequestIdService, X_REQUEST_ID, uuid_v4};
+pub use response::{BuilderExt, IntoResponse, ResponseExt};
+pub use security_headers::{FrameOptions, SecurityHeaders, SecurityHeadersLayer};
+pub use server::{AcceptFuture, Acceptor, BoxedIo, Io, Server, acceptor_fn, tls_acceptor_fn};
+pub use service_fn::{
+    BlockingServiceFn, ContextFn, GuardFn, RouterFn, ServiceFn, blocking_service_fn, context_fn,
+    guard_fn, router_fn, service_fn,
+};
+pub use session::{
+    FileStore, MemoryStore, Session, SessionData, SessionLayer, SessionService, SessionStore,
+    StoreFuture,
+};
+pub use state::{State, StateLayer, StateService};
+pub use static_files::{StaticFiles, mime_type};
+pub use timeout::{Timeout, TimeoutLayer};
+pub use tree::{RouteConflict, RouteTree};
+pub use upstream::{Balance, Upstream, UpstreamPool, UpstreamPoolBuilder};
+pub use writer::BodyWriter;
+
+#[cfg(feature = "macros")]
+pub use libserver_macros::{handler, route};
 
-pub type Request = hyper::Request<Incoming>;
+/// Paths the macros expand to, so callers need no direct dependency on hyper or tower.
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub mod __private {
+    pub use hyper::Method;
+    pub use tower::util::BoxCloneSyncService;
+}
 
+/// Collects functions annotated with `#[route]` into a `ServiceBuilder`.
+#[cfg(feature = "macros")]
+#[macro_export]
+macro_rules! routes {
+    ($($route:path),* $(,)?) => {
+        $crate::ServiceBuilder::new()$(.with_dyn_route($route()))*
+    };
+}
+
+/// A request as services see it, with the crate's `Body`.
+pub type Request = hyper::Request<Body>;
+
+/// One frame of a body stream, or the I/O error that ended it.
 pub type BodyInner = io::Result<Frame<Bytes>>;
+/// A type-erased body stream.
 pub type BoxedBodyStream = Box<dyn Stream<Item = BodyInner> + Send + Unpin + 'static>;
 
-pub type ServiceResponse = Response<StreamBody<BoxedBodyStream>>;
+/// A response as services produce it.
+pub type ServiceResponse = Response<Body>;
+/// The error type every service returns.
 pub type ServiceError = Box<dyn std::error::Error + Send + Sync>;
+/// What a service call resolves to.
 pub type ServiceResult = Result<ServiceResponse, ServiceError>;
+/// The boxed future most services in this crate return.
 pub type ServiceBoxFuture = Pin<Box<dyn Future<Output = ServiceResult> + Send + 'static>>;
 
+/// A type-erased router, shared between clones of a `Service`.
 pub type DynRouter = Arc<dyn Router>;
+/// A type-erased service that can be cloned and shared across threads.
 pub type DynService = BoxCloneSyncService<Request, ServiceResponse, ServiceError>;
+/// A route with both halves type-erased, as `ServiceBuilder` stores them.
 pub type DynRoute = Route<DynRouter, DynService>;
 
+/// The default fallback: a plain-text 404.
 pub const NOT_FOUND: StaticService<&str> =
     StaticService::new("404 Not Found", StatusCode::NOT_FOUND);
 
+/// What a router made of a request.
+pub enum Routing {
+    /// The router doesn't take the request; the next route is tried.
+    Pass,
+    /// The router takes the request; its service is called.
+    Matched,
+    /// Sent instead of calling the route's service.
+    Respond(ServiceResponse),
+}
+
+/// Decides whether a route takes a request. Closures `Fn(&Request) -> bool` are routers.
 pub trait Router: Send + Sync + 'static {
+    /// Whether this router takes `req`, without changing it.
     fn matches(&self, req: &Request) -> bool;
+
+    /// Like `matches`, but the router may record what it matched, such as path
+    /// parameters, in the request's extensions.
+    fn route(&self, req: &mut Request) -> bool {
+        self.matches(req)
+    }
+
+    /// Like `route`, but the router may also answer the request itself, with a
+    /// redirect or a 401 challenge for instance. This is what the dispatcher asks;
+    /// wrappers forward it.
+    fn route_or_respond(&self, req: &mut Request) -> Routing {
+        match self.route(req) {
+            true => Routing::Matched,
+            false => Routing::Pass,
+        }
+    }
+
+    /// The methods allowed for `req` when the router would take it under another
+    /// method, so the dispatcher can answer 405 with an `Allow` header.
+    fn allowed_methods(&self, _req: &Request) -> Option<&[Method]> {
+        None
+    }
+
+    /// The methods this router is restricted to, if any, for `OPTIONS *`.
+    fn methods(&self) -> Option<&[Method]> {
+        None
+    }
+
+    /// Why this router passed on `req`; wrappers forward their inner router's reason.
+    fn not_handled(&self, req: &Request) -> Option<NotHandled> {
+        self.allowed_methods(req)
+            .map(|methods| NotHandled::MethodNotAllowed(methods.to_vec()))
+    }
 }
 
 impl<T> Router for T
@@ -42,11 +303,39 @@ where
     }
 }
 
+impl Router for DynRouter {
+    fn matches(&self, req: &Request) -> bool {
+        (**self).matches(req)
+    }
+
+    fn route(&self, req: &mut Request) -> bool {
+        (**self).route(req)
+    }
+
+    fn route_or_respond(&self, req: &mut Request) -> Routing {
+        (**self).route_or_respond(req)
+    }
+
+    fn allowed_methods(&self, req: &Request) -> Option<&[Method]> {
+        (**self).allowed_methods(req)
+    }
+
+    fn methods(&self) -> Option<&[Method]> {
+        (**self).methods()
+    }
+
+    fn not_handled(&self, req: &Request) -> Option<NotHandled> {
+        (**self).not_handled(req)
+    }
+}
+
+/// Matches paths naming a file under a directory, or a directory with an `index.html`.
 pub struct StaticDirRouter {
     dir: PathBuf,
 }
 
 impl StaticDirRouter {
+    /// Matches files under `dir`.
     pub fn new(dir: impl Into<PathBuf>) -> StaticDirRouter {
         StaticDirRouter { dir: dir.into() }
     }
@@ -62,11 +351,13 @@ impl Router for StaticDirRouter {
     }
 }
 
+/// Matches one exact path.
 pub struct PathEqRouter {
     path: String,
 }
 
 impl PathEqRouter {
+    /// Matches requests whose path is exactly `path`.
     pub fn new(path: impl Into<String>) -> PathEqRouter {
         PathEqRouter { path: path.into() }
     }
@@ -78,11 +369,14 @@ impl Router for PathEqRouter {
     }
 }
 
+/// Matches every path that starts with a prefix.
 pub struct PathPrefixRouter {
     prefix: String,
 }
 
 impl PathPrefixRouter {
+    /// Matches requests whose path starts with `prefix`, with no regard for segment
+    /// boundaries.
     pub fn new(prefix: impl Into<String>) -> PathPrefixRouter {
         PathPrefixRouter {
             prefix: prefix.into(),
@@ -96,23 +390,254 @@ impl Router for PathPrefixRouter {
     }
 }
 
+/// Restricts another router to a set of methods; other methods get a 405 when
+/// nothing else takes the request.
+pub struct MethodRouter<R> {
+    router: R,
+    methods: Vec<Method>,
+}
+
+impl<R> MethodRouter<R> {
+    /// Only lets `router` match requests with one of `methods`.
+    pub fn new(router: R, methods: impl IntoIterator<Item = Method>) -> MethodRouter<R> {
+        MethodRouter {
+            router,
+            methods: methods.into_iter().collect(),
+        }
+    }
+}
+
+impl<R: Router> Router for MethodRouter<R> {
+    fn matches(&self, req: &Request) -> bool {
+        self.methods.contains(req.method()) && self.router.matches(req)
+    }
+
+    fn route(&self, req: &mut Request) -> bool {
+        self.methods.contains(req.method()) && self.router.route(req)
+    }
+
+    fn route_or_respond(&self, req: &mut Request) -> Routing {
+        match self.methods.contains(req.method()) {
+            true => self.router.route_or_respond(req),
+            false => Routing::Pass,
+        }
+    }
+
+    fn allowed_methods(&self, req: &Request) -> Option<&[Method]> {
+        (!self.methods.contains(req.method()) && self.router.matches(req))
+            .then_some(self.methods.as_slice())
+    }
+
+    fn methods(&self) -> Option<&[Method]> {
+        Some(&self.methods)
+    }
+
+    fn not_handled(&self, req: &Request) -> Option<NotHandled> {
+        match self.methods.contains(req.method()) {
+            true => self.router.not_handled(req),
+            false => self
+                .allowed_methods(req)
+                .map(|methods| NotHandled::MethodNotAllowed(methods.to_vec())),
+        }
+    }
+}
+
+/// Matches when the client accepts one of the media types the route produces; when
+/// the route would otherwise match, the dispatcher answers 406 instead.
+pub struct MediaTypeRouter<R> {
+    router: R,
+    types: Vec<String>,
+}
+
+impl<R> MediaTypeRouter<R> {
+    /// Wraps `router` so it only matches clients accepting one of `types`.
+    pub fn new<T: Into<String>>(
+        router: R,
+        types: impl IntoIterator<Item = T>,
+    ) -> MediaTypeRouter<R> {
+        MediaTypeRouter {
+            router,
+            types: types.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    fn acceptable(&self, req: &Request) -> bool {
+        let types: Vec<&str> = self.types.iter().map(String::as_str).collect();
+        negotiate::media_type(req.headers(), &types).is_some()
+    }
+}
+
+impl<R: Router> Router for MediaTypeRouter<R> {
+    fn matches(&self, req: &Request) -> bool {
+        self.acceptable(req) && self.router.matches(req)
+    }
+
+    fn route(&self, req: &mut Request) -> bool {
+        self.acceptable(req) && self.router.route(req)
+    }
+
+    fn route_or_respond(&self, req: &mut Request) -> Routing {
+        match self.acceptable(req) {
+            true => self.router.route_or_respond(req),
+            false => Routing::Pass,
+        }
+    }
+
+    fn allowed_methods(&self, req: &Request) -> Option<&[Method]> {
+        self.router.allowed_methods(req)
+    }
+
+    fn methods(&self) -> Option<&[Method]> {
+        self.router.methods()
+    }
+
+    fn not_handled(&self, req: &Request) -> Option<NotHandled> {
+        match !self.acceptable(req) && self.router.matches(req) {
+            true => Some(NotHandled::NotAcceptable(self.types.clone())),
+            false => self.router.not_handled(req),
+        }
+    }
+}
+
+/// Runs two routers in turn; the second sees whatever the first added to the
+/// request's extensions, so path parameters and the claims they're checked against
+/// can come from separate routers and reach the service side by side. When the
+/// second declines, the first's additions are rolled back. `matches` can't see those
+/// additions, so it only asks each router on its own.
+pub struct Chain<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> Chain<A, B> {
+    /// Runs `first`, then `second`.
+    pub fn new(first: A, second: B) -> Chain<A, B> {
+        Chain { first, second }
+    }
+}
+
+impl<A: Router, B: Router> Router for Chain<A, B> {
+    fn matches(&self, req: &Request) -> bool {
+        self.first.matches(req) && self.second.matches(req)
+    }
+
+    fn route(&self, req: &mut Request) -> bool {
+        let saved = req.extensions().clone();
+        if self.first.route(req) && self.second.route(req) {
+            return true;
+        }
+        *req.extensions_mut() = saved;
+        false
+    }
+
+    fn route_or_respond(&self, req: &mut Request) -> Routing {
+        let saved = req.extensions().clone();
+        let routing = match self.first.route_or_respond(req) {
+            Routing::Matched => self.second.route_or_respond(req),
+            routing => routing,
+        };
+        if let Routing::Pass = routing {
+            *req.extensions_mut() = saved;
+        }
+        routing
+    }
+
+    fn allowed_methods(&self, req: &Request) -> Option<&[Method]> {
+        match self.first.matches(req) {
+            true => self.second.allowed_methods(req),
+            false => self.first.allowed_methods(req),
+        }
+    }
+
+    fn methods(&self) -> Option<&[Method]> {
+        self.first.methods().or_else(|| self.second.methods())
+    }
+
+    fn not_handled(&self, req: &Request) -> Option<NotHandled> {
+        match self.first.matches(req) {
+            true => self.second.not_handled(req),
+            false => self.first.not_handled(req),
+        }
+    }
+}
+
+/// A router paired with the service that handles what it matches.
 #[derive(Clone)]
 pub struct Route<R, S> {
     router: R,
     service: S,
+    recovery: Option<ErrorRecovery>,
 }
 
 impl<R, S> Route<R, S> {
+    /// Pairs `router` with `service`.
     pub fn from_parts(router: R, service: S) -> Route<R, S> {
-        Route { router, service }
+        Route {
+            router,
+            service,
+            recovery: None,
+        }
     }
 
+    /// Replaces the router with whatever `f` makes of it.
     pub fn map_router<N>(self, f: impl FnOnce(R) -> N) -> Route<N, S> {
-        Route::from_parts(f(self.router), self.service)
+        Route {
+            router: f(self.router),
+            service: self.service,
+            recovery: self.recovery,
+        }
     }
 
+    /// Replaces the service with whatever `f` makes of it.
     pub fn map_service<U>(self, f: impl FnOnce(S) -> U) -> Route<R, U> {
-        Route::from_parts(self.router, f(self.service))
+        Route {
+            router: self.router,
+            service: f(self.service),
+            recovery: self.recovery,
+        }
+    }
+
+    /// Consulted before the error mapper when this route's service fails; takes the
+    /// place of the one set with `ServiceBuilder::with_error_recovery`.
+    pub fn with_error_recovery(
+        mut self,
+        recovery: impl Fn(&Error, &ErrorContext) -> Recovery + Send + Sync + 'static,
+    ) -> Route<R, S> {
+        self.recovery = Some(Arc::new(recovery));
+        self
+    }
+
+    /// Wraps this route's service in a tower `Layer`.
+    pub fn layer<L: Layer<S>>(self, layer: L) -> Route<R, L::Service> {
+        self.map_service(|s| layer.layer(s))
+    }
+
+    /// Only match requests with one of `methods`.
+    pub fn with_methods(
+        self,
+        methods: impl IntoIterator<Item = Method>,
+    ) -> Route<MethodRouter<R>, S> {
+        self.map_router(|r| MethodRouter::new(r, methods))
+    }
+
+    /// Only match clients accepting one of `types`; others get a 406 when nothing
+    /// else takes the request.
+    pub fn with_media_types<T: Into<String>>(
+        self,
+        types: impl IntoIterator<Item = T>,
+    ) -> Route<MediaTypeRouter<R>, S> {
+        self.map_router(|r| MediaTypeRouter::new(r, types))
+    }
+
+    /// Only match when `router` also routes the request, after this route's own
+    /// router has; see `Chain`.
+    pub fn chain<N: Router>(self, router: N) -> Route<Chain<R, N>, S> {
+        self.map_router(|r| Chain::new(r, router))
+    }
+
+    /// Only match when `predicate` also holds, e.g. `predicate::content_type("application/json")`.
+    pub fn when<P: Router>(self, predicate: P) -> Route<predicate::When<R, P>, S> {
+        self.map_router(|r| predicate::When::new(r, predicate))
     }
 }
 
@@ -127,14 +652,60 @@ impl<
 where
     S::Future: Send + 'static,
 {
+    /// Erases the router and service types so routes of different types can be stored
+    /// together.
     pub fn make_dyn(self) -> DynRoute {
         self.map_router(|r| Arc::new(r) as DynRouter)
             .map_service(|s| BoxCloneSyncService::new(s))
     }
+
+    /// Routes and calls in one step, for dispatchers other than `Service`. Hands the
+    /// request back when the router passes on it. This route's recovery hook sees
+    /// errors first, but `Recovery::Next` can only propagate the error here.
+    pub fn call(&self, mut req: Request) -> Result<ServiceBoxFuture, Box<Request>> {
+        match self.router.route_or_respond(&mut req) {
+            Routing::Pass => return Err(Box::new(req)),
+            Routing::Respond(resp) => return Ok(Box::pin(async move { Ok(resp) })),
+            Routing::Matched => {}
+        }
+        let recovery = self
+            .recovery
+            .clone()
+            .map(|hook| (hook, ErrorContext::from_request(&req)));
+        let fut = self.service.clone().oneshot(req);
+        Ok(Box::pin(async move {
+            match (fut.await, recovery) {
+                (Err(e), Some((hook, cx))) => {
+                    let e = Error::from_service(e);
+                    match hook(&e, &cx) {
+                        Recovery::Respond(resp) => Ok(resp),
+                        Recovery::Next | Recovery::Propagate => Err(e.into()),
+                    }
+                }
+                (result, _) => result,
+            }
+        }))
+    }
 }
 
+/// What to do with request paths that have a trailing slash or repeated slashes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SlashPolicy {
+    /// Route paths as the client sent them.
+    #[default]
+    Keep,
+    /// Route as if the path had been written canonically.
+    Normalize,
+    /// Answer with a 308 to the canonical path.
+    Redirect,
+}
+
+/// Collects routes and settings, then builds a `Service` that dispatches to them.
 pub struct ServiceBuilder {
     routes: Vec<DynRoute>,
+    error_mapper: Option<ErrorMapper>,
+    recovery: Option<ErrorRecovery>,
+    slash_policy: SlashPolicy,
 }
 
 impl Default for ServiceBuilder {
@@ -144,10 +715,17 @@ impl Default for ServiceBuilder {
 }
 
 impl ServiceBuilder {
+    /// An empty builder: no routes, no error mapper, slashes kept as sent.
     pub fn new() -> ServiceBuilder {
-        ServiceBuilder { routes: vec![] }
+        ServiceBuilder {
+            routes: vec![],
+            error_mapper: None,
+            recovery: None,
+            slash_policy: SlashPolicy::Keep,
+        }
     }
 
+    /// Adds a route. Routes are tried in the order they were added.
     pub fn with_route<R, S>(self, route: Route<R, S>) -> ServiceBuilder
     where
         R: Router,
@@ -161,11 +739,41 @@ impl ServiceBuilder {
         self.with_dyn_route(route.make_dyn())
     }
 
+    /// Sends every request under `prefix` to `service`, with the prefix stripped from
+    /// the path; see `MountRouter`.
+    pub fn with_mount<S>(self, prefix: impl AsRef<str>, service: S) -> ServiceBuilder
+    where
+        S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.with_route(Route::from_parts(MountRouter::new(prefix), service))
+    }
+
+    /// Sends requests for a host pattern such as `api.example.com` or `*.example.com`
+    /// to `service`, typically a `Service` with its own routes.
+    pub fn with_host<S>(self, pattern: impl AsRef<str>, service: S) -> ServiceBuilder
+    where
+        S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.with_route(Route::from_parts(HostRouter::new(pattern), service))
+    }
+
+    /// Adds a route that is already type-erased.
     pub fn with_dyn_route(mut self, route: DynRoute) -> ServiceBuilder {
         self.routes.push(route);
         self
     }
 
+    /// Finishes the builder, with `fallback` answering requests no route takes.
     pub fn with_fallback<S>(self, fallback: S) -> Service
     where
         S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
@@ -178,9 +786,49 @@ impl ServiceBuilder {
         Service {
             routes: self.routes,
             fallback: BoxCloneSyncService::new(fallback),
+            error_mapper: self.error_mapper,
+            recovery: self.recovery,
+            slash_policy: self.slash_policy,
         }
     }
 
+    /// Turns errors from routes into responses. Without one, each error answers with
+    /// its status code and reason phrase as plain text.
+    pub fn with_error_mapper(
+        mut self,
+        mapper: impl Fn(Error) -> ServiceResponse + Send + Sync + 'static,
+    ) -> ServiceBuilder {
+        self.error_mapper = Some(Arc::new(mapper));
+        self
+    }
+
+    /// Consulted before the error mapper when a route's service fails, for routes
+    /// without a hook of their own.
+    pub fn with_error_recovery(
+        mut self,
+        recovery: impl Fn(&Error, &ErrorContext) -> Recovery + Send + Sync + 'static,
+    ) -> ServiceBuilder {
+        self.recovery = Some(Arc::new(recovery));
+        self
+    }
+
+    /// How to treat trailing and repeated slashes before routing.
+    pub fn with_slash_policy(mut self, policy: SlashPolicy) -> ServiceBuilder {
+        self.slash_policy = policy;
+        self
+    }
+
+    /// Render errors as `application/problem+json` instead of bare status codes.
+    pub fn with_problem_errors(self) -> ServiceBuilder {
+        self.with_error_mapper(|e| Problem::from(e).into_response())
+    }
+
+    /// Finishes the builder with a 404 fallback.
+    pub fn build(self) -> Service {
+        self.with_fallback(NOT_FOUND)
+    }
+
+    /// Sends requests for files that exist under `dir` to `service`; see `StaticDirRouter`.
     pub fn with_static_dir<S>(self, dir: impl Into<PathBuf>, service: S) -> Self
     where
         S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
@@ -195,35 +843,220 @@ impl ServiceBuilder {
             service,
         ))
     }
+
+    /// Serves the files under `dir` at `prefix`, with `StaticFiles`' defaults.
+    pub fn with_static_files(
+        self,
+        prefix: impl Into<String>,
+        dir: impl Into<PathBuf>,
+    ) -> ServiceBuilder {
+        let prefix = prefix.into();
+        let files = StaticFiles::new(dir).with_prefix(&prefix);
+        self.with_route(Route::from_parts(PathPrefixRouter::new(prefix), files))
+    }
 }
 
+/// Dispatches each request to the first route that takes it, falling back to a
+/// default service. Built with `ServiceBuilder`.
 #[derive(Clone)]
 pub struct Service {
     routes: Vec<DynRoute>,
     fallback: DynService,
+    error_mapper: Option<ErrorMapper>,
+    recovery: Option<ErrorRecovery>,
+    slash_policy: SlashPolicy,
 }
 
 impl Service {
+    /// Serves this service on `listener` with a default `Server`.
     pub async fn serve(self, listener: TcpListener) -> Result<(), std::io::Error> {
-        let adapter = TowerToHyperService::new(self);
-        let service = Arc::new(adapter);
-
-        loop {
-            let service = service.clone();
-            let io = TokioIo::new(listener.accept().await?.0);
-
-            tokio::spawn(async move {
-                http1::Builder::new()
-                    .serve_connection(io, service)
-                    .with_upgrades()
-                    .await
-                    .inspect_err(|e| {
-                        dbg!(e);
-                    })
-                    .ok();
-            });
+        Server::from_listener(listener).serve(self).await
+    }
+
+    /// Wraps the whole service in a tower `Layer`.
+    pub fn layer<L: Layer<Service>>(self, layer: L) -> L::Service {
+        layer.layer(self)
+    }
+
+    /// Adds a route after the ones already there.
+    pub fn push<R, S>(&mut self, route: Route<R, S>)
+    where
+        R: Router,
+        S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.push_dyn(route.make_dyn());
+    }
+
+    /// Like `ServiceBuilder::with_mount`.
+    pub fn mount<S>(&mut self, prefix: impl AsRef<str>, service: S)
+    where
+        S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.push(Route::from_parts(MountRouter::new(prefix), service));
+    }
+
+    /// Like `ServiceBuilder::with_host`.
+    pub fn host<S>(&mut self, pattern: impl AsRef<str>, service: S)
+    where
+        S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.push(Route::from_parts(HostRouter::new(pattern), service));
+    }
+
+    /// Adds a route that is already type-erased.
+    pub fn push_dyn(&mut self, route: DynRoute) {
+        self.routes.push(route);
+    }
+
+    /// Replaces the service that answers requests no route takes.
+    pub fn set_fallback<S>(&mut self, fallback: S)
+    where
+        S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.fallback = BoxCloneSyncService::new(fallback);
+    }
+
+    /// Like `ServiceBuilder::with_error_mapper`.
+    pub fn set_error_mapper(
+        &mut self,
+        mapper: impl Fn(Error) -> ServiceResponse + Send + Sync + 'static,
+    ) {
+        self.error_mapper = Some(Arc::new(mapper));
+    }
+
+    /// Routes before `start` are skipped.
+    fn dispatch(&self, req: &mut Request, start: usize) -> Dispatch {
+        if self.slash_policy != SlashPolicy::Keep
+            && let Some(path) = canonical_path(req.uri().path())
+        {
+            let path_and_query = match req.uri().query() {
+                Some(query) => format!("{path}?{query}"),
+                None => path,
+            };
+            if self.slash_policy == SlashPolicy::Redirect {
+                return Dispatch::Redirect(path_and_query);
+            }
+            let mut parts = req.uri().clone().into_parts();
+            parts.path_and_query = path_and_query.parse().ok();
+            if let Ok(uri) = Uri::from_parts(parts) {
+                *req.uri_mut() = uri;
+            }
+        }
+        match self.find_route(req, start) {
+            Some(Ok(idx)) => return Dispatch::Route(idx),
+            Some(Err(resp)) => return Dispatch::Respond(resp),
+            None => {}
+        }
+        if req.method() == Method::HEAD {
+            // Served by the GET route, which still sees the request as HEAD.
+            *req.method_mut() = Method::GET;
+            let found = self.find_route(req, start);
+            *req.method_mut() = Method::HEAD;
+            match found {
+                Some(Ok(idx)) => return Dispatch::Head(idx),
+                Some(Err(resp)) => return Dispatch::Respond(without_body(resp)),
+                None => {}
+            }
+        }
+        if req.method() == Method::OPTIONS && req.uri().path() == "*" {
+            let mut allowed = vec![];
+            for method in self
+                .routes
+                .iter()
+                .filter_map(|r| r.router.methods())
+                .flatten()
+            {
+                if !allowed.contains(method) {
+                    allowed.push(method.clone());
+                }
+            }
+            return Dispatch::Options(with_implied_methods(allowed));
+        }
+        match self.not_handled(req) {
+            NotHandled::NoMatch => Dispatch::Fallback,
+            NotHandled::MethodNotAllowed(allowed) if req.method() == Method::OPTIONS => {
+                Dispatch::Options(allowed)
+            }
+            reason => Dispatch::NotHandled(reason),
+        }
+    }
+
+    /// The first route whose router takes the request, or the response a router
+    /// answered it with.
+    fn find_route(
+        &self,
+        req: &mut Request,
+        start: usize,
+    ) -> Option<Result<usize, ServiceResponse>> {
+        for (idx, route) in self.routes.iter().enumerate().skip(start) {
+            match route.router.route_or_respond(req) {
+                Routing::Pass => {}
+                Routing::Matched => return Some(Ok(idx)),
+                Routing::Respond(resp) => return Some(Err(resp)),
+            }
         }
+        None
     }
+
+    fn not_handled(&self, req: &Request) -> NotHandled {
+        let reason = self
+            .routes
+            .iter()
+            .filter_map(|r| r.router.not_handled(req))
+            .fold(NotHandled::NoMatch, NotHandled::merge);
+        match reason {
+            NotHandled::MethodNotAllowed(allowed) => {
+                NotHandled::MethodNotAllowed(with_implied_methods(allowed))
+            }
+            reason => reason,
+        }
+    }
+}
+
+enum Dispatch {
+    Route(usize),
+    Head(usize),
+    Fallback,
+    NotHandled(NotHandled),
+    Options(Vec<Method>),
+    Redirect(String),
+    Respond(ServiceResponse),
+}
+
+/// Collapses repeated slashes and drops a trailing one, or `None` if already canonical.
+fn canonical_path(path: &str) -> Option<String> {
+    if !path.contains("//") && (path == "/" || !path.ends_with('/')) {
+        return None;
+    }
+    let mut canonical = String::with_capacity(path.len());
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        canonical.push('/');
+        canonical.push_str(segment);
+    }
+    if canonical.is_empty() {
+        canonical.push('/');
+    }
+    Some(canonical)
 }
 
 impl TowerService<Request> for Service {
@@ -239,37 +1072,200 @@ impl TowerService<Request> for Service {
     }
 
     fn call(&mut self, req: Request) -> Self::Future {
-        Box::pin(
-            self.routes
-                .iter_mut()
-                .find(|r| r.router.matches(&req))
-                .map(|r| &mut r.service)
-                .unwrap_or(&mut self.fallback)
-                .call(req)
-                .inspect_err(|e| println!("{e}")),
-        )
+        let span = trace::request_span(&req);
+        trace::in_span(span, move || self.call_routed(req, 0))
+    }
+}
+
+impl HyperService<hyper::Request<Incoming>> for Service {
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn call(&self, req: hyper::Request<Incoming>) -> Self::Future {
+        let req = req.map(Body::Incoming);
+        let span = trace::request_span(&req);
+        trace::in_span(span, move || self.call_routed(req, 0))
+    }
+}
+
+impl Service {
+    fn call_routed(&self, mut req: Request, start: usize) -> ServiceBoxFuture {
+        let replay =
+            match self.recovery.is_some() || self.routes.iter().any(|r| r.recovery.is_some()) {
+                true => replay(&req),
+                false => None,
+            };
+        let (service, idx, head) = match self.dispatch(&mut req, start) {
+            Dispatch::Route(idx) => (self.routes[idx].service.clone(), Some(idx), false),
+            Dispatch::Head(idx) => (self.routes[idx].service.clone(), Some(idx), true),
+            Dispatch::Fallback => (self.fallback.clone(), None, false),
+            Dispatch::Options(allowed) => {
+                return Box::pin(async move { Ok(options_response(&allowed)) });
+            }
+            Dispatch::NotHandled(reason) => {
+                return Box::pin(async move { Ok(reason.into_response()) });
+            }
+            Dispatch::Redirect(location) => {
+                return Box::pin(async move { Ok(ServiceResponse::permanent_redirect(&location)) });
+            }
+            Dispatch::Respond(resp) => return Box::pin(async move { Ok(resp) }),
+        };
+        let fallthrough = idx.and_then(|idx| {
+            let hook = self.routes[idx]
+                .recovery
+                .clone()
+                .or_else(|| self.recovery.clone())?;
+            Some(Fallthrough {
+                hook,
+                cx: ErrorContext::from_request(&req),
+                replay,
+                service: self.clone(),
+                next: idx + 1,
+            })
+        });
+        let fut = call_with_mapper(service, req, self.error_mapper.clone(), fallthrough);
+        match head {
+            true => Box::pin(async move { Ok(without_body(fut.await?)) }),
+            false => fut,
+        }
+    }
+}
+
+/// A copy of the request to dispatch again if a recovery hook asks for it, taken
+/// before routing adds anything to it.
+fn replay(req: &Request) -> Option<Request> {
+    let body = match req.body() {
+        Body::Full(bytes) => Body::Full(bytes.clone()),
+        body if hyper::body::Body::is_end_stream(body) => Body::Empty,
+        _ => return None,
+    };
+    let mut copy = Request::new(body);
+    *copy.method_mut() = req.method().clone();
+    *copy.uri_mut() = req.uri().clone();
+    *copy.version_mut() = req.version();
+    *copy.headers_mut() = req.headers().clone();
+    *copy.extensions_mut() = req.extensions().clone();
+    Some(copy)
+}
+
+struct Fallthrough {
+    hook: ErrorRecovery,
+    cx: ErrorContext,
+    replay: Option<Request>,
+    service: Service,
+    next: usize,
+}
+
+/// Without a mapper of its own, the service defers to the handler installed by
+/// `Server::on_error`, and then to the plain status response.
+fn call_with_mapper(
+    service: DynService,
+    req: Request,
+    error_mapper: Option<ErrorMapper>,
+    fallthrough: Option<Fallthrough>,
+) -> ServiceBoxFuture {
+    let matched = MatchedPath::from_request(&req).cloned();
+    let handler = match error_mapper {
+        Some(_) => None,
+        None => req
+            .extensions()
+            .get::<error::ServerErrorHandler>()
+            .map(|handler| (handler.0.clone(), ErrorContext::from_request(&req))),
+    };
+    let fut = service.oneshot(req).inspect_err(trace::service_error);
+    Box::pin(async move {
+        let mut resp = match fut.await {
+            Ok(resp) => resp,
+            Err(e) => {
+                let e = Error::from_service(e);
+                match fallthrough.map(|f| ((f.hook)(&e, &f.cx), f)) {
+                    Some((Recovery::Respond(resp), _)) => resp,
+                    Some((
+                        Recovery::Next,
+                        Fallthrough {
+                            replay: Some(req),
+                            service,
+                            next,
+                            ..
+                        },
+                    )) => return service.call_routed(req, next).await,
+                    _ => match (error_mapper, handler) {
+                        (Some(mapper), _) => mapper(e),
+                        (None, Some((handler, cx))) => handler(e, &cx),
+                        (None, None) => e.into_response(),
+                    },
+                }
+            }
+        };
+        if let Some(matched) = matched {
+            resp.extensions_mut().insert(matched);
+        }
+        Ok(resp)
+    })
+}
+
+pub(crate) fn method_not_allowed(allowed: &[Method]) -> ServiceResponse {
+    ServiceResponse::from_bytes("405 Method Not Allowed")
+        .with_status(StatusCode::METHOD_NOT_ALLOWED)
+        .with_header(header::ALLOW, allow_header(allowed))
+}
+
+pub(crate) fn options_response(allowed: &[Method]) -> ServiceResponse {
+    ServiceResponse::empty()
+        .with_status(StatusCode::NO_CONTENT)
+        .with_header(header::ALLOW, allow_header(allowed))
+}
+
+fn allow_header(allowed: &[Method]) -> HeaderValue {
+    let allow = allowed
+        .iter()
+        .map(Method::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+    HeaderValue::from_str(&allow).expect("method names are valid header values")
+}
+
+/// The dispatchers answer HEAD wherever GET is routed, and OPTIONS everywhere.
+pub(crate) fn with_implied_methods(mut allowed: Vec<Method>) -> Vec<Method> {
+    if allowed.contains(&Method::GET) && !allowed.contains(&Method::HEAD) {
+        allowed.push(Method::HEAD);
+    }
+    if !allowed.contains(&Method::OPTIONS) {
+        allowed.push(Method::OPTIONS);
     }
+    allowed
+}
+
+/// Keeps the GET response's headers, `Content-Length` included, for a HEAD request.
+pub(crate) fn without_body(resp: ServiceResponse) -> ServiceResponse {
+    resp.map(|_| make_body_from_stream(futures::stream::empty()))
 }
 
-pub fn single_frame_body(body: impl Into<Bytes> + Send + 'static) -> StreamBody<BoxedBodyStream> {
-    make_body_from_stream(single_frame_stream(body))
+/// A body that is a single chunk of bytes.
+pub fn single_frame_body(body: impl Into<Bytes> + Send + 'static) -> Body {
+    Body::Full(body.into())
 }
 
+/// A data frame holding `frame`.
 pub fn make_frame(frame: impl Into<Bytes>) -> BodyInner {
     Ok(Frame::data(frame.into()))
 }
 
+/// A stream yielding `body` as its one frame.
 pub fn single_frame_stream(body: impl Into<Bytes>) -> impl Stream<Item = BodyInner> {
-    futures::stream::once(async { make_frame(body) })
+    futures::stream::iter([make_frame(body)])
 }
 
-pub fn make_body_from_stream<S>(stream: S) -> StreamBody<BoxedBodyStream>
+/// A body that streams the frames `stream` yields.
+pub fn make_body_from_stream<S>(stream: S) -> Body
 where
     S: Stream<Item = BodyInner> + Send + 'static,
 {
-    StreamBody::new(Box::new(Box::pin(stream)))
+    Body::Stream(Box::new(Box::pin(stream)))
 }
 
+/// A service that answers every request with `body` and a 200.
 pub fn static_service(
     body: impl Into<Bytes> + Send + 'static,
 ) -> impl TowerService<
@@ -283,11 +1279,12 @@ pub fn static_service(
     let body = body.into();
     tower::service_fn(move |_| {
         let body = body.clone();
-        let fut = async move { Ok(Response::new(single_frame_body(body))) };
+        let fut = async move { Ok(ServiceResponse::from_bytes(body)) };
         Box::pin(fut) as ServiceBoxFuture
     })
 }
 
+/// A service that answers every request with the same body and status.
 #[derive(Clone)]
 pub struct StaticService<T> {
     body: T,
@@ -295,6 +1292,7 @@ pub struct StaticService<T> {
 }
 
 impl<T> StaticService<T> {
+    /// Answers with `body` and `status`; `const`, so it can back a `const` or `static`.
     pub const fn new(body: T, status: StatusCode) -> Self {
         Self { body, status }
     }
@@ -313,8 +1311,7 @@ impl<T: Into<Bytes> + Clone + Send + 'static> TowerService<Request> for StaticSe
     }
 
     fn call(&mut self, _req: Request) -> Self::Future {
-        let mut resp = Response::new(single_frame_body(self.body.clone()));
-        *resp.status_mut() = self.status;
+        let resp = ServiceResponse::from_bytes(self.body.clone()).with_status(self.status);
         Box::pin(async { Ok(resp) })
     }
 }
@@ -0,0 +1,143 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+use crate::{ConnectionLimits, Overflow};
+
+pub(crate) struct ConnectionLimiter {
+    overflow: Overflow,
+    global: Option<Arc<Semaphore>>,
+    per_ip: Option<Arc<IpLimiter>>,
+}
+
+pub(crate) struct ConnectionPermit {
+    _global: Option<OwnedSemaphorePermit>,
+    _per_ip: Option<IpPermit>,
+}
+
+impl ConnectionLimiter {
+    pub(crate) fn new(limits: ConnectionLimits) -> ConnectionLimiter {
+        ConnectionLimiter {
+            overflow: limits.overflow,
+            global: limits
+                .max_connections
+                .map(|max| Arc::new(Semaphore::new(max))),
+            per_ip: limits.max_per_ip.map(|max| {
+                Arc::new(IpLimiter {
+                    max,
+                    counts: Mutex::new(HashMap::new()),
+                    released: Notify::new(),
+                })
+            }),
+        }
+    }
+
+    /// With `Overflow::Queue` this waits before accepting so the kernel backlog holds
+    /// the excess connections; with `Overflow::Reject` the permit is taken afterwards.
+    pub(crate) async fn reserve(&self) -> Option<OwnedSemaphorePermit> {
+        match (&self.global, self.overflow) {
+            (Some(global), Overflow::Queue) => global.clone().acquire_owned().await.ok(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn admit(
+        &self,
+        reserved: Option<OwnedSemaphorePermit>,
+        ip: Option<IpAddr>,
+    ) -> Option<PendingPermit> {
+        let global = match (&self.global, reserved) {
+            (_, Some(permit)) => Some(permit),
+            (Some(global), None) => Some(global.clone().try_acquire_owned().ok()?),
+            (None, None) => None,
+        };
+        let per_ip = match (&self.per_ip, ip) {
+            (Some(limiter), Some(ip)) => match self.overflow {
+                Overflow::Queue => PerIp::Wait(limiter.clone(), ip),
+                Overflow::Reject => PerIp::Held(Some(limiter.try_acquire(ip)?)),
+            },
+            _ => PerIp::Held(None),
+        };
+        Some(PendingPermit { global, per_ip })
+    }
+}
+
+enum PerIp {
+    Held(Option<IpPermit>),
+    Wait(Arc<IpLimiter>, IpAddr),
+}
+
+pub(crate) struct PendingPermit {
+    global: Option<OwnedSemaphorePermit>,
+    per_ip: PerIp,
+}
+
+impl PendingPermit {
+    pub(crate) async fn ready(self) -> ConnectionPermit {
+        let per_ip = match self.per_ip {
+            PerIp::Held(permit) => permit,
+            PerIp::Wait(limiter, ip) => Some(limiter.acquire(ip).await),
+        };
+        ConnectionPermit {
+            _global: self.global,
+            _per_ip: per_ip,
+        }
+    }
+}
+
+struct IpLimiter {
+    max: usize,
+    counts: Mutex<HashMap<IpAddr, usize>>,
+    released: Notify,
+}
+
+impl IpLimiter {
+    fn try_acquire(self: &Arc<IpLimiter>, ip: IpAddr) -> Option<IpPermit> {
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(ip).or_default();
+        if *count >= self.max {
+            if *count == 0 {
+                counts.remove(&ip);
+            }
+            return None;
+        }
+        *count += 1;
+        Some(IpPermit {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+
+    async fn acquire(self: &Arc<IpLimiter>, ip: IpAddr) -> IpPermit {
+        loop {
+            let released = self.released.notified();
+            if let Some(permit) = self.try_acquire(ip) {
+                return permit;
+            }
+            released.await;
+        }
+    }
+}
+
+struct IpPermit {
+    limiter: Arc<IpLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for IpPermit {
+    fn drop(&mut self) {
+        let mut counts = self.limiter.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.ip);
+            }
+        }
+        drop(counts);
+        self.limiter.released.notify_waiters();
+    }
+}
@@ -0,0 +1,240 @@
+use std::{io, net::SocketAddr};
+
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+
+#[cfg(unix)]
+use std::{path::Path, sync::Arc};
+
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::{
+    net::TcpListener,
+    sync::{Mutex, mpsc},
+};
+
+use crate::{BoxedIo, ConnectionInfo, ListenerConfig, trace};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AcceptErrorKind {
+    /// The peer went away before the connection was accepted; safe to retry at once.
+    Connection,
+    /// Out of file descriptors or socket buffers.
+    Resources,
+    Other,
+}
+
+impl AcceptErrorKind {
+    pub(crate) fn of(e: &io::Error) -> AcceptErrorKind {
+        match e.kind() {
+            io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock => return AcceptErrorKind::Connection,
+            io::ErrorKind::OutOfMemory => return AcceptErrorKind::Resources,
+            _ => {}
+        }
+        #[cfg(unix)]
+        if let Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM) = e.raw_os_error() {
+            return AcceptErrorKind::Resources;
+        }
+        AcceptErrorKind::Other
+    }
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            AcceptErrorKind::Connection => "connection",
+            AcceptErrorKind::Resources => "resources",
+            AcceptErrorKind::Other => "other",
+        }
+    }
+}
+
+pub(crate) enum Listener {
+    Tcp(TcpListener, ListenerConfig),
+    /// Bound without a runtime, or taken off one for `Server::run`; registered with
+    /// whichever runtime its acceptor runs on.
+    Detached(std::net::TcpListener, ListenerConfig),
+    #[cfg(unix)]
+    Unix(UnixSocket),
+    /// Connections handed over in-process by `testing::TestClient`.
+    Memory(Mutex<mpsc::Receiver<BoxedIo>>),
+}
+
+/// One listener per acceptor, all bound to the same address.
+pub(crate) fn bind_tcp(addr: SocketAddr, config: &ListenerConfig) -> io::Result<Vec<Listener>> {
+    bind_detached(addr, config)?
+        .into_iter()
+        .map(|listener| listener.register())
+        .collect()
+}
+
+pub(crate) fn bind_detached(
+    addr: SocketAddr,
+    config: &ListenerConfig,
+) -> io::Result<Vec<Listener>> {
+    let mut listeners = Vec::with_capacity(config.acceptors);
+    let mut addr = addr;
+    for _ in 0..config.acceptors {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        #[cfg(unix)]
+        {
+            socket.set_reuse_address(true)?;
+            if config.reuse_port {
+                socket.set_reuse_port(true)?;
+            }
+        }
+        if let Some(size) = config.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = config.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(config.backlog.try_into().unwrap_or(i32::MAX))?;
+        let listener = std::net::TcpListener::from(socket);
+        // Later sockets must share the port the kernel picked for the first.
+        addr = listener.local_addr()?;
+        listeners.push(Listener::Detached(listener, *config));
+    }
+    Ok(listeners)
+}
+
+fn configure_stream(stream: &tokio::net::TcpStream, config: &ListenerConfig) -> io::Result<()> {
+    if config.nodelay {
+        stream.set_nodelay(true)?;
+    }
+    if let Some(idle) = config.keepalive {
+        SockRef::from(stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?;
+    }
+    Ok(())
+}
+
+impl Listener {
+    /// Must be called from within the runtime that will accept on the listener.
+    pub(crate) fn register(self) -> io::Result<Listener> {
+        match self {
+            Listener::Detached(listener, config) => {
+                Ok(Listener::Tcp(TcpListener::from_std(listener)?, config))
+            }
+            listener => Ok(listener),
+        }
+    }
+
+    /// Moves a TCP listener off the runtime it was registered with. Other kinds of
+    /// listener can't be moved.
+    pub(crate) fn detach(self) -> io::Result<Listener> {
+        match self {
+            Listener::Tcp(listener, config) => {
+                let socket = SockRef::from(&listener).try_clone()?;
+                Ok(Listener::Detached(socket.into(), config))
+            }
+            Listener::Detached(..) => Ok(self),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only TCP listeners can move to another runtime",
+            )),
+        }
+    }
+
+    pub(crate) async fn accept(&self) -> io::Result<(BoxedIo, ConnectionInfo)> {
+        match self {
+            Listener::Tcp(listener, config) => {
+                let (stream, peer) = listener.accept().await?;
+                if let Err(e) = configure_stream(&stream, config) {
+                    trace::warn("configuring accepted socket failed", &e);
+                }
+                let info = ConnectionInfo {
+                    remote_addr: Some(peer),
+                    local_addr: stream.local_addr().ok(),
+                    ..ConnectionInfo::default()
+                };
+                Ok((Box::new(stream), info))
+            }
+            #[cfg(unix)]
+            Listener::Unix(socket) => {
+                let (stream, _) = socket.listener.accept().await?;
+                let info = ConnectionInfo {
+                    unix_path: Some(socket.path.clone()),
+                    ..ConnectionInfo::default()
+                };
+                Ok((Box::new(stream), info))
+            }
+            Listener::Detached(..) => Err(io::Error::other(
+                "listener is not registered with a runtime",
+            )),
+            Listener::Memory(rx) => match rx.lock().await.recv().await {
+                Some(stream) => Ok((stream, ConnectionInfo::default())),
+                // The client is gone; nothing else will ever connect.
+                None => futures::future::pending().await,
+            },
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Listener::Tcp(listener, _) => listener.local_addr(),
+            Listener::Detached(listener, _) => listener.local_addr(),
+            #[cfg(unix)]
+            Listener::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unix sockets do not have a socket address",
+            )),
+            Listener::Memory(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "in-memory listeners do not have a socket address",
+            )),
+        }
+    }
+}
+
+#[cfg(unix)]
+pub(crate) struct UnixSocket {
+    listener: UnixListener,
+    path: Arc<Path>,
+}
+
+#[cfg(unix)]
+impl UnixSocket {
+    pub(crate) async fn bind(path: &Path) -> io::Result<UnixSocket> {
+        remove_stale_socket(path).await?;
+        Ok(UnixSocket {
+            listener: UnixListener::bind(path)?,
+            path: Arc::from(path),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+#[cfg(unix)]
+async fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let Ok(meta) = tokio::fs::symlink_metadata(path).await else {
+        return Ok(());
+    };
+    if !meta.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+    match tokio::net::UnixStream::connect(path).await {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is in use by another process", path.display()),
+        )),
+        Err(_) => tokio::fs::remove_file(path).await,
+    }
+}
@@ -0,0 +1,340 @@
+//! Request counters and latency histograms, rendered in the Prometheus text format.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicI64, Ordering},
+    },
+    time::Instant,
+};
+
+use hyper::{
+    Method, StatusCode,
+    header::{self, HeaderValue},
+};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    MatchedPath, Request, ResponseExt, ServiceBoxFuture, ServiceError, ServiceResponse,
+    listener::AcceptErrorKind,
+};
+
+const DEFAULT_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+const UNMATCHED: &str = "unmatched";
+
+struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+struct Registry {
+    requests: BTreeMap<(String, String, u16), u64>,
+    latency: BTreeMap<(String, String), Histogram>,
+    accept_errors: BTreeMap<&'static str, u64>,
+}
+
+struct Inner {
+    buckets: Vec<f64>,
+    registry: Mutex<Registry>,
+    in_flight: AtomicI64,
+}
+
+/// Request counters and latency histograms by method, route and status; clones share them.
+#[derive(Clone)]
+pub struct Metrics(Arc<Inner>);
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    /// Metrics with the default latency buckets, from 5 ms to 10 s.
+    pub fn new() -> Metrics {
+        Metrics::with_buckets(DEFAULT_BUCKETS)
+    }
+
+    /// Metrics with the given latency bucket bounds, in seconds.
+    pub fn with_buckets(buckets: impl IntoIterator<Item = f64>) -> Metrics {
+        let mut buckets: Vec<f64> = buckets.into_iter().collect();
+        buckets.sort_by(f64::total_cmp);
+        Metrics(Arc::new(Inner {
+            buckets,
+            registry: Mutex::default(),
+            in_flight: AtomicI64::new(0),
+        }))
+    }
+
+    /// The layer recording requests into these metrics.
+    pub fn layer(&self) -> MetricsLayer {
+        MetricsLayer(self.clone())
+    }
+
+    /// A service answering with the Prometheus text exposition format, for mounting at
+    /// `/metrics`.
+    pub fn handler(&self) -> MetricsHandler {
+        MetricsHandler(self.clone())
+    }
+
+    fn observe(&self, route: &str, method: &str, status: StatusCode, seconds: f64) {
+        let mut registry = self.0.registry.lock().unwrap_or_else(|e| e.into_inner());
+        *registry
+            .requests
+            .entry((route.to_string(), method.to_string(), status.as_u16()))
+            .or_default() += 1;
+
+        let histogram = registry
+            .latency
+            .entry((route.to_string(), method.to_string()))
+            .or_insert_with(|| Histogram {
+                counts: vec![0; self.0.buckets.len()],
+                sum: 0.0,
+                count: 0,
+            });
+        for (count, bound) in histogram.counts.iter_mut().zip(&self.0.buckets) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    /// Counts a failed `accept()`, e.g. from `Server::on_accept_error`.
+    pub fn record_accept_error(&self, e: &io::Error) {
+        let mut registry = self.0.registry.lock().unwrap_or_else(|e| e.into_inner());
+        *registry
+            .accept_errors
+            .entry(AcceptErrorKind::of(e).as_str())
+            .or_default() += 1;
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let registry = self.0.registry.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total Total number of HTTP requests.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((route, method, status), count) in &registry.requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{route=\"{}\",method=\"{}\",status=\"{status}\"}} {count}",
+                escape(route),
+                escape(method),
+            );
+        }
+
+        out.push_str(
+            "# HELP http_request_duration_seconds Time until response headers are sent.\n",
+        );
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for ((route, method), histogram) in &registry.latency {
+            let labels = format!("route=\"{}\",method=\"{}\"", escape(route), escape(method));
+            for (count, bound) in histogram.counts.iter().zip(&self.0.buckets) {
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {count}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{labels}}} {}",
+                histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{{labels}}} {}",
+                histogram.count
+            );
+        }
+
+        out.push_str("# HELP http_requests_in_flight Requests currently being handled.\n");
+        out.push_str("# TYPE http_requests_in_flight gauge\n");
+        let _ = writeln!(
+            out,
+            "http_requests_in_flight {}",
+            self.0.in_flight.load(Ordering::Relaxed)
+        );
+
+        if !registry.accept_errors.is_empty() {
+            out.push_str(
+                "# HELP http_accept_errors_total Failed attempts to accept a connection.\n",
+            );
+            out.push_str("# TYPE http_accept_errors_total counter\n");
+            for (kind, count) in &registry.accept_errors {
+                let _ = writeln!(out, "http_accept_errors_total{{kind=\"{kind}\"}} {count}");
+            }
+        }
+        out
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+struct InFlight(Metrics);
+
+impl InFlight {
+    fn new(metrics: Metrics) -> InFlight {
+        metrics.0.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(metrics)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Records requests into `Metrics`.
+#[derive(Clone)]
+pub struct MetricsLayer(Metrics);
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> MetricsService<S> {
+        MetricsService {
+            inner,
+            metrics: self.0.clone(),
+        }
+    }
+}
+
+/// Clients choose the method, so extension methods share one label rather than
+/// adding a series each.
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::CONNECT => "CONNECT",
+        Method::OPTIONS => "OPTIONS",
+        Method::TRACE => "TRACE",
+        Method::PATCH => "PATCH",
+        _ => "OTHER",
+    }
+}
+
+/// The service produced by `MetricsLayer`.
+#[derive(Clone)]
+pub struct MetricsService<S> {
+    inner: S,
+    metrics: Metrics,
+}
+
+impl<S> TowerService<Request> for MetricsService<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let method = method_label(req.method());
+        let in_flight = InFlight::new(self.metrics.clone());
+        let start = Instant::now();
+        let fut = self.inner.call(req);
+
+        Box::pin(async move {
+            let res = fut.await;
+            let metrics = &in_flight.0;
+            let elapsed = start.elapsed().as_secs_f64();
+            match &res {
+                Ok(resp) => {
+                    let route = resp
+                        .extensions()
+                        .get::<MatchedPath>()
+                        .map_or(UNMATCHED, MatchedPath::as_str);
+                    metrics.observe(route, method, resp.status(), elapsed);
+                }
+                Err(_) => metrics.observe(
+                    UNMATCHED,
+                    method,
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    elapsed,
+                ),
+            }
+            res
+        })
+    }
+}
+
+/// The service returned by `Metrics::handler`.
+#[derive(Clone)]
+pub struct MetricsHandler(Metrics);
+
+impl TowerService<Request> for MetricsHandler {
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = futures::future::Ready<Result<ServiceResponse, ServiceError>>;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: Request) -> Self::Future {
+        let resp = ServiceResponse::from_string(self.0.render()).with_header(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; version=0.0.4"),
+        );
+        futures::future::ready(Ok(resp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{service_fn, testing::TestRequestExt};
+
+    #[tokio::test]
+    async fn extension_methods_share_a_label() {
+        let metrics = Metrics::new();
+        let service = metrics.layer().layer(service_fn(|_: Request| async {
+            Ok::<_, ServiceError>(ServiceResponse::empty())
+        }));
+        for method in ["GET", "PURGE", "X-RANDOM-1", "X-RANDOM-2"] {
+            let req = Request::test(Method::from_bytes(method.as_bytes()).unwrap(), "/");
+            service.clone().oneshot(req).await.unwrap();
+        }
+        let rendered = metrics.render();
+        assert!(rendered.contains(r#"method="GET""#), "{rendered}");
+        assert!(rendered.contains(r#"method="OTHER""#), "{rendered}");
+        assert!(!rendered.contains("PURGE") && !rendered.contains("X-RANDOM"));
+    }
+}
@@ -0,0 +1,204 @@
+use std::{sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    Version,
+    body::Body,
+    header::{self, HeaderValue},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    Client, InvalidUpstream, Request, RequestExt, ServiceBoxFuture, ServiceError, ServiceResponse,
+    Upstream, proxy, random, trace,
+};
+
+/// Copies a sample of requests to a secondary upstream, for dark-launching a new
+/// deployment against live traffic. Copies are sent in the background; their
+/// responses are discarded and never alter the primary's. Requests with a body are
+/// only mirrored when `with_max_body` allows it.
+#[derive(Clone)]
+pub struct MirrorLayer {
+    upstream: Upstream,
+    client: Client,
+    /// Share of requests copied, in hundredths of a percent.
+    sample: u32,
+    timeout: Duration,
+    in_flight: Arc<Semaphore>,
+    max_body: usize,
+}
+
+impl MirrorLayer {
+    /// Mirrors every bodiless request to `upstream`, with up to 64 copies in flight and a 5 second timeout.
+    pub fn new(upstream: &str) -> Result<MirrorLayer, InvalidUpstream> {
+        Ok(MirrorLayer {
+            upstream: Upstream::new(upstream)?,
+            client: Client::new(),
+            sample: 10_000,
+            timeout: Duration::from_secs(5),
+            in_flight: Arc::new(Semaphore::new(64)),
+            max_body: 0,
+        })
+    }
+
+    /// Clamped to 0..=100.
+    pub fn with_percentage(mut self, percent: f64) -> MirrorLayer {
+        self.sample = (percent.clamp(0.0, 100.0) * 100.0).round() as u32;
+        self
+    }
+
+    /// Sets the client the copies are sent with.
+    pub fn with_client(mut self, client: Client) -> MirrorLayer {
+        self.client = client;
+        self
+    }
+
+    /// Time allowed for a copy, body included, before it is abandoned.
+    pub fn with_timeout(mut self, timeout: Duration) -> MirrorLayer {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Copies beyond `max` in flight are skipped, so a slow secondary can't pile up
+    /// work.
+    pub fn with_max_in_flight(mut self, max: usize) -> MirrorLayer {
+        self.in_flight = Arc::new(Semaphore::new(max));
+        self
+    }
+
+    /// Buffers bodies of up to `max` bytes so they can be sent twice. The primary
+    /// only sees the request once its body has been read; longer bodies are passed
+    /// on as they are, without a copy.
+    pub fn with_max_body(mut self, max: usize) -> MirrorLayer {
+        self.max_body = max;
+        self
+    }
+
+    fn sampled(&self) -> bool {
+        if self.sample >= 10_000 {
+            return true;
+        }
+        random::fast_u64() % 10_000 < u64::from(self.sample)
+    }
+
+    fn reserve(&self) -> Option<OwnedSemaphorePermit> {
+        if !self.sampled() {
+            return None;
+        }
+        self.in_flight.clone().try_acquire_owned().ok()
+    }
+
+    fn copy(&self, req: &Request, body: Bytes) -> Option<hyper::Request<Full<Bytes>>> {
+        let query = req
+            .uri()
+            .query()
+            .map(|q| format!("?{q}"))
+            .unwrap_or_default();
+        let uri = format!(
+            "http://{}{}{}{query}",
+            self.upstream.authority(),
+            self.upstream.base_path(),
+            req.uri().path()
+        )
+        .parse()
+        .ok()?;
+
+        let mut copy = hyper::Request::new(Full::new(body));
+        *copy.method_mut() = req.method().clone();
+        *copy.uri_mut() = uri;
+        *copy.version_mut() = Version::HTTP_11;
+        *copy.headers_mut() = req.headers().clone();
+        let headers = copy.headers_mut();
+        proxy::strip_hop_by_hop(headers);
+        headers.insert(
+            header::HOST,
+            HeaderValue::from_str(self.upstream.authority()).ok()?,
+        );
+        Some(copy)
+    }
+
+    fn send(&self, copy: hyper::Request<Full<Bytes>>, permit: OwnedSemaphorePermit) {
+        let client = self.client.clone();
+        let timeout = self.timeout;
+        tokio::spawn(async move {
+            let _permit = permit;
+            let sent = async {
+                let resp = client.request(copy).await?;
+                resp.into_body().collect().await?;
+                Ok::<_, ServiceError>(())
+            };
+            if let Ok(Err(e)) = tokio::time::timeout(timeout, sent).await {
+                trace::warn("mirrored request failed", &e);
+            }
+        });
+    }
+}
+
+impl<S> Layer<S> for MirrorLayer {
+    type Service = Mirror<S>;
+
+    fn layer(&self, inner: S) -> Mirror<S> {
+        Mirror {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// The service produced by `MirrorLayer`.
+#[derive(Clone)]
+pub struct Mirror<S> {
+    inner: S,
+    config: MirrorLayer,
+}
+
+impl<S> TowerService<Request> for Mirror<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        let Some(permit) = self.config.reserve() else {
+            return Box::pin(self.inner.call(req));
+        };
+        if req.body().is_end_stream() {
+            if let Some(copy) = self.config.copy(&req, Bytes::new()) {
+                self.config.send(copy, permit);
+            }
+            return Box::pin(self.inner.call(req));
+        }
+        let declared = req.content_length().unwrap_or(0);
+        if self.config.max_body == 0 || declared > self.config.max_body as u64 {
+            return Box::pin(self.inner.call(req));
+        }
+
+        let config = self.config.clone();
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            // A body that turns out too long, or fails, is replayed to the primary.
+            if let Ok(body) = req.body_mut().buffer(config.max_body).await
+                && let Some(copy) = config.copy(&req, body)
+            {
+                config.send(copy, permit);
+            }
+            inner.call(req).await
+        })
+    }
+}
@@ -0,0 +1,90 @@
+use std::{fmt, sync::Arc};
+
+use hyper::Uri;
+
+use crate::{Request, Router};
+
+/// Matches requests under `prefix` and strips it before the mounted service sees them.
+#[derive(Clone, Debug)]
+pub struct MountRouter {
+    prefix: Arc<str>,
+}
+
+impl MountRouter {
+    /// Mounts at `prefix`; a missing leading slash is added and a trailing one dropped.
+    pub fn new(prefix: impl AsRef<str>) -> MountRouter {
+        let prefix = prefix.as_ref().trim_end_matches('/');
+        let prefix = match prefix.starts_with('/') || prefix.is_empty() {
+            true => prefix.to_string(),
+            false => format!("/{prefix}"),
+        };
+        MountRouter {
+            prefix: Arc::from(prefix),
+        }
+    }
+
+    /// The normalized prefix.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    fn strip<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let rest = path.strip_prefix(&*self.prefix)?;
+        match rest {
+            "" => Some("/"),
+            rest if rest.starts_with('/') => Some(rest),
+            _ => None,
+        }
+    }
+}
+
+impl Router for MountRouter {
+    fn matches(&self, req: &Request) -> bool {
+        self.strip(req.uri().path()).is_some()
+    }
+
+    fn route(&self, req: &mut Request) -> bool {
+        let Some(rest) = self.strip(req.uri().path()) else {
+            return false;
+        };
+        let path_and_query = match req.uri().query() {
+            Some(query) => format!("{rest}?{query}"),
+            None => rest.to_string(),
+        };
+        let mut parts = req.uri().clone().into_parts();
+        parts.path_and_query = path_and_query.parse().ok();
+        let Ok(uri) = Uri::from_parts(parts) else {
+            return false;
+        };
+        *req.uri_mut() = uri;
+
+        let mount = match MountPath::from_request(req) {
+            Some(outer) => format!("{}{}", outer.0, self.prefix),
+            None => self.prefix.to_string(),
+        };
+        req.extensions_mut().insert(MountPath(Arc::from(mount)));
+        true
+    }
+}
+
+/// The prefixes stripped by enclosing mounts, outermost first.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MountPath(Arc<str>);
+
+impl MountPath {
+    /// The mount prefixes for `req`.
+    pub fn from_request(req: &Request) -> Option<&MountPath> {
+        req.extensions().get()
+    }
+
+    /// The prefixes as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for MountPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
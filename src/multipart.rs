@@ -0,0 +1,508 @@
+//! Streaming `multipart/form-data` parsing for request bodies.
+
+use std::pin::Pin;
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use hyper::header;
+
+use crate::{Error, Request, request_frames};
+
+const DEFAULT_MAX_PART_SIZE: usize = 16 << 20;
+const DEFAULT_MAX_TOTAL_SIZE: usize = 64 << 20;
+const MAX_HEADER_SIZE: usize = 8 << 10;
+
+type DataStream = Pin<Box<dyn Stream<Item = Result<Bytes, Error>> + Send>>;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Data,
+    AfterDelimiter,
+    Done,
+}
+
+/// A streaming `multipart/form-data` reader, yielding one `Field` at a time.
+pub struct Multipart {
+    stream: DataStream,
+    buf: BytesMut,
+    delimiter: Bytes,
+    state: State,
+    eof: bool,
+    total: usize,
+    part: usize,
+    max_total_size: usize,
+    max_part_size: usize,
+}
+
+impl Multipart {
+    /// Reads the request body, taking the boundary from its `Content-Type`.
+    pub fn from_request(req: Request) -> Result<Multipart, Error> {
+        let boundary = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_boundary)
+            .ok_or(Error::UnsupportedMediaType {
+                expected: "multipart/form-data",
+            })?;
+        Ok(Multipart::new(request_frames(req), &boundary))
+    }
+
+    /// Reads `stream` as a multipart body delimited by `boundary`.
+    pub fn new<S>(stream: S, boundary: &str) -> Multipart
+    where
+        S: Stream<Item = Result<Bytes, Error>> + Send + 'static,
+    {
+        Multipart {
+            stream: Box::pin(stream),
+            // The leading CRLF lets the first delimiter match like every later one.
+            buf: BytesMut::from(&b"\r\n"[..]),
+            delimiter: Bytes::from(format!("\r\n--{boundary}")),
+            state: State::Data,
+            eof: false,
+            total: 0,
+            part: 0,
+            max_total_size: DEFAULT_MAX_TOTAL_SIZE,
+            max_part_size: DEFAULT_MAX_PART_SIZE,
+        }
+    }
+
+    /// Fails fields larger than `max_part_size` bytes; 16 MiB by default.
+    pub fn with_max_part_size(mut self, max_part_size: usize) -> Multipart {
+        self.max_part_size = max_part_size;
+        self
+    }
+
+    /// Fails once the whole body exceeds `max_total_size` bytes; 64 MiB by default.
+    pub fn with_max_total_size(mut self, max_total_size: usize) -> Multipart {
+        self.max_total_size = max_total_size;
+        self
+    }
+
+    /// The next field, skipping what is left of the previous one; `None` after the last.
+    pub async fn next_field(&mut self) -> Result<Option<Field<'_>>, Error> {
+        while self.state == State::Data {
+            self.next_chunk(false).await?;
+        }
+        if self.state == State::Done {
+            return Ok(None);
+        }
+
+        self.fill(2).await?;
+        if self.buf.starts_with(b"--") {
+            self.state = State::Done;
+            return Ok(None);
+        }
+        let Some(line_end) = self.find_buffered(b"\r\n", MAX_HEADER_SIZE).await? else {
+            return Err(Error::Multipart("malformed boundary line"));
+        };
+        if !self.buf[..line_end]
+            .iter()
+            .all(|b| matches!(b, b' ' | b'\t'))
+        {
+            return Err(Error::Multipart("malformed boundary line"));
+        }
+        self.buf.advance(line_end + 2);
+
+        self.fill(2).await?;
+        let headers = match self.buf.starts_with(b"\r\n") {
+            true => {
+                self.buf.advance(2);
+                String::new()
+            }
+            false => match self.find_buffered(b"\r\n\r\n", MAX_HEADER_SIZE).await? {
+                Some(end) => {
+                    let headers = self.buf.split_to(end + 4);
+                    std::str::from_utf8(&headers[..end])
+                        .map_err(|_| Error::Multipart("part headers are not valid utf-8"))?
+                        .to_string()
+                }
+                None => return Err(Error::Multipart("malformed part headers")),
+            },
+        };
+
+        let mut field = FieldHeaders::default();
+        for line in headers.split("\r\n") {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-disposition" => {
+                    field.name = disposition_param(value, "name");
+                    field.file_name = disposition_param(value, "filename");
+                }
+                "content-type" => field.content_type = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        self.state = State::Data;
+        self.part = 0;
+        Ok(Some(Field {
+            multipart: self,
+            headers: field,
+        }))
+    }
+
+    async fn next_chunk(&mut self, track: bool) -> Result<Option<Bytes>, Error> {
+        loop {
+            if self.state != State::Data {
+                return Ok(None);
+            }
+
+            let delim_len = self.delimiter.len();
+            let data = match find(&self.buf, &self.delimiter) {
+                Some(pos) => {
+                    let data = self.buf.split_to(pos).freeze();
+                    self.buf.advance(delim_len);
+                    self.state = State::AfterDelimiter;
+                    data
+                }
+                None if self.buf.len() > delim_len => {
+                    let keep = delim_len - 1;
+                    self.buf.split_to(self.buf.len() - keep).freeze()
+                }
+                None => {
+                    if !self.read_more().await? {
+                        return Err(Error::Multipart("unexpected end of multipart body"));
+                    }
+                    continue;
+                }
+            };
+
+            if track {
+                self.part += data.len();
+                if self.part > self.max_part_size {
+                    return Err(Error::RequestTooLarge {
+                        limit: self.max_part_size,
+                    });
+                }
+            }
+            if !data.is_empty() {
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    async fn find_buffered(&mut self, needle: &[u8], max: usize) -> Result<Option<usize>, Error> {
+        loop {
+            if let Some(pos) = find(&self.buf, needle) {
+                return Ok(Some(pos));
+            }
+            if self.buf.len() > max || !self.read_more().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    async fn fill(&mut self, len: usize) -> Result<(), Error> {
+        while self.buf.len() < len {
+            if !self.read_more().await? {
+                return Err(Error::Multipart("unexpected end of multipart body"));
+            }
+        }
+        Ok(())
+    }
+
+    async fn read_more(&mut self) -> Result<bool, Error> {
+        if self.eof {
+            return Ok(false);
+        }
+        match self.stream.next().await.transpose()? {
+            Some(data) => {
+                self.total += data.len();
+                if self.total > self.max_total_size {
+                    return Err(Error::RequestTooLarge {
+                        limit: self.max_total_size,
+                    });
+                }
+                self.buf.extend_from_slice(&data);
+                Ok(true)
+            }
+            None => {
+                self.eof = true;
+                Ok(false)
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct FieldHeaders {
+    name: Option<String>,
+    file_name: Option<String>,
+    content_type: Option<String>,
+}
+
+/// One part of a multipart body, read before asking for the next field.
+pub struct Field<'a> {
+    multipart: &'a mut Multipart,
+    headers: FieldHeaders,
+}
+
+impl Field<'_> {
+    /// The `name` from `Content-Disposition`.
+    pub fn name(&self) -> Option<&str> {
+        self.headers.name.as_deref()
+    }
+
+    /// The `filename` from `Content-Disposition`.
+    pub fn file_name(&self) -> Option<&str> {
+        self.headers.file_name.as_deref()
+    }
+
+    /// The part's `Content-Type`.
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers.content_type.as_deref()
+    }
+
+    /// The next chunk of the part's data, `None` at its end.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        self.multipart.next_chunk(true).await
+    }
+
+    /// The rest of the part's data, read into memory.
+    pub async fn bytes(mut self) -> Result<Bytes, Error> {
+        let mut buf = BytesMut::new();
+        while let Some(chunk) = self.chunk().await? {
+            buf.extend_from_slice(&chunk);
+        }
+        Ok(buf.freeze())
+    }
+
+    /// The rest of the part's data as a UTF-8 string.
+    pub async fn text(self) -> Result<String, Error> {
+        let bytes = self.bytes().await?;
+        Ok(std::str::from_utf8(&bytes)?.to_string())
+    }
+}
+
+fn parse_boundary(content_type: &str) -> Option<String> {
+    let mut parts = content_type.split(';');
+    let mime = parts.next()?.trim();
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    parts
+        .filter_map(|p| p.split_once('='))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, v)| v.trim().trim_matches('"').to_string())
+        .filter(|b| !b.is_empty())
+}
+
+fn disposition_param(value: &str, name: &str) -> Option<String> {
+    let mut rest = value.split_once(';')?.1;
+    loop {
+        rest = rest.trim_start();
+        let (key, after) = rest.split_once('=')?;
+        let (val, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut out = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => out.extend(chars.next().map(|(_, c)| c)),
+                        '"' => {
+                            end = i + 1;
+                            break;
+                        }
+                        c => out.push(c),
+                    }
+                }
+                (out, &quoted[end..])
+            }
+            None => {
+                let end = after.find(';').unwrap_or(after.len());
+                (after[..end].trim().to_string(), &after[end..])
+            }
+        };
+        if key.trim().eq_ignore_ascii_case(name) {
+            return Some(val);
+        }
+        rest = remaining.trim_start().strip_prefix(';')?;
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Method, StatusCode};
+
+    use super::*;
+    use crate::{ServiceError, service_fn, testing::TestClient, testing::TestRequestExt};
+
+    const BODY: &str = concat!(
+        "preamble to ignore\r\n",
+        "--XyZ\r\n",
+        "Content-Disposition: form-data; name=\"title\"\r\n",
+        "\r\n",
+        "hello\r\n",
+        "--XyZ  \r\n",
+        "content-disposition: form-data; filename=\"a \\\"b\\\".txt\"; name=file\r\n",
+        "Content-Type: text/plain\r\n",
+        "\r\n",
+        "line one\r\n-XyZ\r\n--Xy\r\n",
+        "--XyZ\r\n",
+        "\r\n",
+        "\r\n",
+        "--XyZ--\r\n",
+        "epilogue to ignore",
+    );
+
+    // Splits `body` into chunks of `size` bytes, so delimiters straddle reads.
+    fn chunked(body: &'static str, size: usize) -> Multipart {
+        let chunks: Vec<Result<Bytes, Error>> = body
+            .as_bytes()
+            .chunks(size)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        Multipart::new(futures::stream::iter(chunks), "XyZ")
+    }
+
+    async fn fields(mut multipart: Multipart) -> Result<Vec<(Option<String>, Bytes)>, Error> {
+        let mut out = vec![];
+        while let Some(field) = multipart.next_field().await? {
+            let name = field.name().map(str::to_string);
+            out.push((name, field.bytes().await?));
+        }
+        Ok(out)
+    }
+
+    #[tokio::test]
+    async fn parses_fields_at_any_chunking() {
+        for size in [1, 2, 3, 7, 64, BODY.len()] {
+            let fields = fields(chunked(BODY, size)).await.unwrap();
+            assert_eq!(
+                fields,
+                [
+                    (Some("title".into()), Bytes::from("hello")),
+                    (Some("file".into()), Bytes::from("line one\r\n-XyZ\r\n--Xy")),
+                    (None, Bytes::new()),
+                ],
+                "chunks of {size}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_part_headers() {
+        let mut multipart = chunked(BODY, 5);
+        multipart.next_field().await.unwrap().unwrap();
+        let field = multipart.next_field().await.unwrap().unwrap();
+        assert_eq!(field.name(), Some("file"));
+        assert_eq!(field.file_name(), Some("a \"b\".txt"));
+        assert_eq!(field.content_type(), Some("text/plain"));
+    }
+
+    #[tokio::test]
+    async fn unread_fields_are_skipped() {
+        let mut multipart = chunked(BODY, 4);
+        multipart.next_field().await.unwrap().unwrap();
+        let field = multipart.next_field().await.unwrap().unwrap();
+        assert_eq!(field.name(), Some("file"));
+        assert!(multipart.next_field().await.unwrap().is_some());
+        assert!(multipart.next_field().await.unwrap().is_none());
+        assert!(multipart.next_field().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_and_malformed_bodies() {
+        let cases = [
+            "--XyZ\r\nname: x\r\n\r\nno closing delimiter",
+            "--XyZ\r\nContent-Disposition: form-data; name=a",
+            "--XyZjunk\r\n\r\nx\r\n--XyZ--",
+            "no delimiter at all",
+            "",
+        ];
+        for body in cases {
+            assert!(
+                matches!(fields(chunked(body, 3)).await, Err(Error::Multipart(_))),
+                "{body:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn enforces_size_limits() {
+        let part = chunked(BODY, 8).with_max_part_size(10);
+        assert!(matches!(
+            fields(part).await,
+            Err(Error::RequestTooLarge { limit: 10 })
+        ));
+        let total = chunked(BODY, 8).with_max_total_size(40);
+        assert!(matches!(
+            fields(total).await,
+            Err(Error::RequestTooLarge { limit: 40 })
+        ));
+        assert!(
+            fields(chunked(BODY, 8).with_max_part_size(30))
+                .await
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn boundary_and_disposition_params() {
+        assert_eq!(
+            parse_boundary("multipart/form-data; boundary=\"a b\"").as_deref(),
+            Some("a b")
+        );
+        assert_eq!(
+            parse_boundary("Multipart/Form-Data;charset=utf-8; BOUNDARY=x").as_deref(),
+            Some("x")
+        );
+        assert_eq!(parse_boundary("multipart/form-data; boundary="), None);
+        assert_eq!(parse_boundary("multipart/mixed; boundary=x"), None);
+        assert_eq!(parse_boundary("text/plain"), None);
+        let value = "form-data; filename=\"x;y.txt\"; name=\"f\"";
+        assert_eq!(disposition_param(value, "name").as_deref(), Some("f"));
+        assert_eq!(
+            disposition_param(value, "filename").as_deref(),
+            Some("x;y.txt")
+        );
+        assert_eq!(disposition_param("form-data", "name"), None);
+    }
+
+    #[tokio::test]
+    async fn from_request_checks_the_content_type() {
+        let req = Request::test(Method::POST, "/").with_body(BODY);
+        assert!(matches!(
+            Multipart::from_request(req),
+            Err(Error::UnsupportedMediaType { .. })
+        ));
+        let mut req = Request::test(Method::POST, "/").with_body(BODY);
+        req.headers_mut().insert(
+            header::CONTENT_TYPE,
+            "multipart/form-data; boundary=XyZ".parse().unwrap(),
+        );
+        let fields = fields(Multipart::from_request(req).unwrap()).await.unwrap();
+        assert_eq!(fields.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn errors_through_a_server_are_400() {
+        let client = TestClient::new(service_fn(|req: Request| async move {
+            let fields = fields(Multipart::from_request(req)?).await?;
+            Ok::<_, ServiceError>(format!("{}", fields.len()))
+        }));
+        let send = |body: &'static str| {
+            client
+                .post("/")
+                .header("content-type", "multipart/form-data; boundary=XyZ")
+                .body(body)
+                .send()
+        };
+        send(BODY)
+            .await
+            .assert_status(StatusCode::OK)
+            .assert_body("3");
+        send("--XyZ\r\n\r\ntruncated")
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+    }
+}
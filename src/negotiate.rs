@@ -0,0 +1,213 @@
+//! Each function returns the server's preferred candidate among those the client
+//! rates highest, or `None` when the client accepts none of them. Without the
+//! header every candidate is acceptable and the first one wins.
+
+use hyper::{
+    HeaderMap,
+    header::{self, HeaderName},
+};
+
+use crate::headers::{QualityItem, quality_list};
+
+/// The best media type for the `Accept` header; `type/*` and `*/*` ranges match.
+pub fn media_type<'a>(headers: &HeaderMap, available: &[&'a str]) -> Option<&'a str> {
+    best(headers, header::ACCEPT, available, |range, candidate| {
+        let (range_type, range_sub) = range.split_once('/')?;
+        let (cand_type, cand_sub) = candidate.split_once('/')?;
+        match (range_type, range_sub) {
+            ("*", "*") => Some(0),
+            (t, "*") if t.eq_ignore_ascii_case(cand_type) => Some(1),
+            (t, s) if t.eq_ignore_ascii_case(cand_type) && s.eq_ignore_ascii_case(cand_sub) => {
+                Some(2)
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Ranges match candidates by prefix, so `en` accepts `en-GB`.
+pub fn language<'a>(headers: &HeaderMap, available: &[&'a str]) -> Option<&'a str> {
+    best(
+        headers,
+        header::ACCEPT_LANGUAGE,
+        available,
+        |range, candidate| {
+            if range == "*" {
+                return Some(0);
+            }
+            let prefix = candidate.get(..range.len())?;
+            let boundary =
+                candidate[range.len()..].is_empty() || candidate[range.len()..].starts_with('-');
+            (prefix.eq_ignore_ascii_case(range) && boundary).then_some(range.len())
+        },
+    )
+}
+
+/// `identity` stays acceptable unless the client explicitly refuses it.
+pub fn encoding<'a>(headers: &HeaderMap, available: &[&'a str]) -> Option<&'a str> {
+    if !headers.contains_key(header::ACCEPT_ENCODING) {
+        return available.first().copied();
+    }
+    let items = quality_list(headers.get_all(header::ACCEPT_ENCODING).iter());
+    select(&items, available, |range, candidate| match range {
+        "*" => Some(0),
+        range if range.eq_ignore_ascii_case(candidate) => Some(1),
+        _ => None,
+    })
+    .or_else(|| {
+        let identity = available
+            .iter()
+            .find(|c| c.eq_ignore_ascii_case("identity"))?;
+        let refused = items.iter().any(|item| {
+            item.q == 0.0 && (item.value == "*" || item.value.eq_ignore_ascii_case("identity"))
+        });
+        (!refused).then_some(*identity)
+    })
+}
+
+fn best<'a>(
+    headers: &HeaderMap,
+    name: HeaderName,
+    available: &[&'a str],
+    specificity: impl Fn(&str, &str) -> Option<usize>,
+) -> Option<&'a str> {
+    if !headers.contains_key(&name) {
+        return available.first().copied();
+    }
+    let items = quality_list(headers.get_all(name).iter());
+    select(&items, available, specificity)
+}
+
+/// The most specific matching range decides each candidate's quality.
+fn select<'a>(
+    items: &[QualityItem],
+    available: &[&'a str],
+    specificity: impl Fn(&str, &str) -> Option<usize>,
+) -> Option<&'a str> {
+    let mut chosen: Option<(&'a str, f32)> = None;
+    for &candidate in available {
+        let q = items
+            .iter()
+            .filter_map(|item| Some((specificity(&item.value, candidate)?, item.q)))
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, q)| q);
+        if let Some(q) = q
+            && q > 0.0
+            && chosen.is_none_or(|(_, best)| q > best)
+        {
+            chosen = Some((candidate, q));
+        }
+    }
+    chosen.map(|(candidate, _)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::HeaderValue;
+
+    use super::*;
+
+    fn headers(name: HeaderName, values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(&name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn accept(values: &[&str]) -> HeaderMap {
+        headers(header::ACCEPT, values)
+    }
+
+    const TYPES: [&str; 3] = ["application/json", "text/html", "text/plain"];
+
+    #[test]
+    fn highest_quality_wins() {
+        let h = accept(&["text/html;q=0.5, application/json;q=0.9"]);
+        assert_eq!(media_type(&h, &TYPES), Some("application/json"));
+        let h = accept(&["text/plain", "application/json;q=0.2"]);
+        assert_eq!(media_type(&h, &TYPES), Some("text/plain"));
+    }
+
+    #[test]
+    fn wildcards_match_by_specificity() {
+        assert_eq!(
+            media_type(&accept(&["*/*"]), &TYPES),
+            Some("application/json")
+        );
+        assert_eq!(media_type(&accept(&["text/*"]), &TYPES), Some("text/html"));
+        assert_eq!(
+            media_type(&accept(&["TEXT/PLAIN"]), &TYPES),
+            Some("text/plain")
+        );
+        // The exact range outranks the wildcards, whatever their order.
+        let h = accept(&["text/html;q=0.1, */*;q=0.8, text/*;q=0.5"]);
+        assert_eq!(media_type(&h, &TYPES), Some("application/json"));
+        assert_eq!(
+            media_type(&h, &["text/html", "text/plain"]),
+            Some("text/plain")
+        );
+    }
+
+    #[test]
+    fn zero_quality_refuses() {
+        let h = accept(&["*/*, application/json;q=0"]);
+        assert_eq!(media_type(&h, &TYPES), Some("text/html"));
+        assert_eq!(media_type(&h, &["application/json"]), None);
+        assert_eq!(media_type(&accept(&["*/*;q=0"]), &TYPES), None);
+    }
+
+    #[test]
+    fn ties_go_to_the_server_order() {
+        let h = accept(&["text/plain, text/html"]);
+        assert_eq!(media_type(&h, &TYPES), Some("text/html"));
+        assert_eq!(
+            media_type(&h, &["text/plain", "text/html"]),
+            Some("text/plain")
+        );
+        let h = headers(header::ACCEPT_LANGUAGE, &["fr;q=0.5, de;q=0.5"]);
+        assert_eq!(language(&h, &["de", "fr"]), Some("de"));
+    }
+
+    #[test]
+    fn missing_or_malformed_accept() {
+        assert_eq!(
+            media_type(&HeaderMap::new(), &TYPES),
+            Some("application/json")
+        );
+        assert_eq!(media_type(&HeaderMap::new(), &[]), None);
+        // Unparseable ranges and weights are skipped; nothing left means nothing fits.
+        for value in ["text", "text/html;q=x", "/", ""] {
+            assert_eq!(media_type(&accept(&[value]), &TYPES), None, "{value}");
+        }
+        let h = accept(&["bogus, text/html;q=x, text/plain;q=0.4,"]);
+        assert_eq!(media_type(&h, &TYPES), Some("text/plain"));
+    }
+
+    #[test]
+    fn languages_match_by_prefix() {
+        let h = headers(header::ACCEPT_LANGUAGE, &["en;q=0.8, en-GB"]);
+        assert_eq!(language(&h, &["en-US", "en-GB"]), Some("en-GB"));
+        assert_eq!(language(&h, &["en-US", "fr"]), Some("en-US"));
+        assert_eq!(language(&h, &["eng", "fr"]), None);
+        let h = headers(header::ACCEPT_LANGUAGE, &["*;q=0.1, fr"]);
+        assert_eq!(language(&h, &["de", "fr"]), Some("fr"));
+        assert_eq!(language(&h, &["de", "é"]), Some("de"));
+    }
+
+    #[test]
+    fn identity_unless_refused() {
+        let h = headers(header::ACCEPT_ENCODING, &["gzip;q=0.5, br"]);
+        assert_eq!(encoding(&h, &["gzip", "br", "identity"]), Some("br"));
+        let h = headers(header::ACCEPT_ENCODING, &["br"]);
+        assert_eq!(encoding(&h, &["gzip", "identity"]), Some("identity"));
+        let h = headers(header::ACCEPT_ENCODING, &["br, identity;q=0"]);
+        assert_eq!(encoding(&h, &["gzip", "identity"]), None);
+        let h = headers(header::ACCEPT_ENCODING, &["*;q=0"]);
+        assert_eq!(encoding(&h, &["gzip", "identity"]), None);
+        assert_eq!(
+            encoding(&HeaderMap::new(), &["gzip", "identity"]),
+            Some("gzip")
+        );
+    }
+}
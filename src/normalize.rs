@@ -0,0 +1,415 @@
+use std::{fmt, sync::Arc};
+
+use hyper::Uri;
+
+use crate::{
+    Error, Request,
+    query::{decode_component_bytes, percent_decode},
+};
+
+/// The request path exactly as the client sent it, before `Server` normalized it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RawPath(Arc<str>);
+
+impl RawPath {
+    /// The raw path of `req`, if the server normalized it.
+    pub fn from_request(req: &Request) -> Option<&RawPath> {
+        req.extensions().get()
+    }
+
+    /// The path as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RawPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// What to do with percent-escapes in the path and query that don't decode to UTF-8.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Utf8Policy {
+    /// Pass the bytes through; `RequestExt::decoded_path_bytes` and
+    /// `raw_query_pairs` see them as sent.
+    #[default]
+    Raw,
+    /// Replace each invalid sequence with U+FFFD, escaped.
+    Lossy,
+    /// Answer 400.
+    Reject,
+}
+
+/// Rewrites the request path to its normal form, so routers and file lookups can't
+/// be sidestepped by spelling a path differently: percent-escapes are decoded, `.`
+/// and `..` segments resolved (never above the root), and anything that must be
+/// escaped is escaped again, consistently. An escaped `/` becomes a separator.
+/// Fails on an escaped NUL. With `resolve` off, only the UTF-8 policy is applied.
+pub(crate) fn normalize(req: &mut Request, resolve: bool, policy: Utf8Policy) -> Result<(), Error> {
+    let path = req.uri().path();
+    let normal = match path.starts_with('/') {
+        true => normal_path(path, resolve, policy)?,
+        false => None,
+    };
+    let query = match req.uri().query() {
+        Some(query) => normal_query(query, policy)?,
+        None => None,
+    };
+    if normal.is_none() && query.is_none() {
+        return Ok(());
+    }
+
+    let raw = RawPath(Arc::from(path));
+    let path_and_query = match (query.as_deref(), req.uri().query()) {
+        (Some(query), _) | (None, Some(query)) => {
+            format!("{}?{query}", normal.as_deref().unwrap_or(path))
+        }
+        (None, None) => normal.clone().unwrap_or_default(),
+    };
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse()?);
+    *req.uri_mut() = Uri::from_parts(parts).map_err(|_| Error::InvalidPath("malformed path"))?;
+    if normal.is_some() {
+        req.extensions_mut().insert(raw);
+    }
+    Ok(())
+}
+
+/// `None` when the path is already in normal form.
+fn normal_path(path: &str, resolve: bool, policy: Utf8Policy) -> Result<Option<String>, Error> {
+    let decoded = percent_decode(path.as_bytes(), false);
+    let decoded = match (policy, std::str::from_utf8(&decoded)) {
+        (Utf8Policy::Reject, Err(_)) => return Err(Error::InvalidPath("path is not valid utf-8")),
+        (Utf8Policy::Lossy, Err(_)) => String::from_utf8_lossy(&decoded).into_owned().into_bytes(),
+        (Utf8Policy::Raw, _) | (_, Ok(_)) if !resolve => return Ok(None),
+        _ => decoded,
+    };
+    if !resolve {
+        let mut out = String::with_capacity(path.len());
+        for (i, segment) in decoded.split(|&b| b == b'/').enumerate() {
+            if i > 0 {
+                out.push('/');
+            }
+            encode(segment, false, &mut out);
+        }
+        return Ok(Some(out));
+    }
+    if decoded.contains(&0) {
+        return Err(Error::InvalidPath("path contains a NUL byte"));
+    }
+
+    let mut segments: Vec<&[u8]> = vec![];
+    let mut trailing_slash = false;
+    for segment in decoded[1..].split(|&b| b == b'/') {
+        trailing_slash = matches!(segment, b"." | b"..");
+        match segment {
+            b"." => {}
+            b".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut out = String::with_capacity(path.len());
+    for segment in &segments {
+        out.push('/');
+        encode(segment, false, &mut out);
+    }
+    if out.is_empty() || trailing_slash {
+        out.push('/');
+    }
+    Ok((out != path).then_some(out))
+}
+
+/// Re-escapes only the components that aren't UTF-8, leaving the rest as sent.
+fn normal_query(query: &str, policy: Utf8Policy) -> Result<Option<String>, Error> {
+    if policy == Utf8Policy::Raw || !query.contains('%') {
+        return Ok(None);
+    }
+    let invalid =
+        |component: &str| std::str::from_utf8(&decode_component_bytes(component)).is_err();
+    let components = query.split(['&', '=']);
+    if !components.clone().any(invalid) {
+        return Ok(None);
+    }
+    if policy == Utf8Policy::Reject {
+        return Err(Error::InvalidQuery("query is not valid utf-8"));
+    }
+
+    let mut out = String::with_capacity(query.len());
+    for (i, pair) in query.split('&').enumerate() {
+        if i > 0 {
+            out.push('&');
+        }
+        for (j, component) in pair.splitn(2, '=').enumerate() {
+            if j > 0 {
+                out.push('=');
+            }
+            match invalid(component) {
+                true => {
+                    let decoded = decode_component_bytes(component);
+                    encode(String::from_utf8_lossy(&decoded).as_bytes(), true, &mut out);
+                }
+                false => out.push_str(component),
+            }
+        }
+    }
+    Ok(Some(out))
+}
+
+/// Leaves the characters RFC 3986 allows in a path segment as they are. In a query
+/// component, the ones that delimit pairs are escaped too.
+fn encode(segment: &[u8], query: bool, out: &mut String) {
+    for &b in segment {
+        match b {
+            b'&' | b'+' | b'=' if !query => out.push(b as char),
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~'
+            | b'!'
+            | b'$'
+            | b'\''
+            | b'('
+            | b')'
+            | b'*'
+            | b','
+            | b';'
+            | b':'
+            | b'@' => out.push(b as char),
+            b => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Method, StatusCode};
+
+    use super::*;
+    use crate::{
+        ResponseExt, ServiceError, ServiceResponse, service_fn, testing::TestClient,
+        testing::TestRequestExt,
+    };
+
+    // The normalized path and query, and the `RawPath` if the path was rewritten.
+    fn run(
+        uri: &str,
+        resolve: bool,
+        policy: Utf8Policy,
+    ) -> Result<(String, Option<String>), Error> {
+        let mut req = Request::test(Method::GET, uri);
+        normalize(&mut req, resolve, policy)?;
+        let raw = RawPath::from_request(&req).map(|raw| raw.to_string());
+        Ok((req.uri().path_and_query().unwrap().to_string(), raw))
+    }
+
+    fn resolved(uri: &str) -> String {
+        run(uri, true, Utf8Policy::Raw).unwrap().0
+    }
+
+    #[test]
+    fn dot_segments() {
+        for (path, normal) in [
+            ("/", "/"),
+            ("/a/b", "/a/b"),
+            ("/a/b/", "/a/b/"),
+            ("/a/./b", "/a/b"),
+            ("/a/../b", "/b"),
+            ("/a/b/..", "/a/"),
+            ("/a/.", "/a/"),
+            ("/..", "/"),
+            ("/../../etc/passwd", "/etc/passwd"),
+            ("/a/%2e%2E/b", "/b"),
+            ("/a/.%2e/.%2E/b", "/b"),
+            ("/a%2f..%2Fb", "/b"),
+            ("//a", "//a"),
+            ("/a/..b/c..", "/a/..b/c.."),
+        ] {
+            assert_eq!(resolved(path), normal, "{path}");
+        }
+    }
+
+    #[test]
+    fn escapes_are_made_consistent() {
+        for (path, normal) in [
+            ("/%41%7e%5F", "/A~_"),
+            ("/a%20b", "/a%20b"),
+            ("/a%3fb%23c", "/a%3Fb%23c"),
+            ("/100%25", "/100%25"),
+            ("/%2525", "/%2525"),
+            ("/a&b=c+d;e:f@g", "/a&b=c+d;e:f@g"),
+            ("/%E2%82%AC", "/%E2%82%AC"),
+            ("/%e2%82%ac", "/%E2%82%AC"),
+            // Incomplete escapes are left alone, only the `%` is escaped.
+            ("/a%", "/a%25"),
+            ("/a%4", "/a%254"),
+            ("/a%zz", "/a%25zz"),
+        ] {
+            assert_eq!(resolved(path), normal, "{path}");
+        }
+    }
+
+    #[test]
+    fn raw_path_and_query_are_kept() {
+        let (uri, raw) = run("/a/../b?x=%2e%2e&y", true, Utf8Policy::Raw).unwrap();
+        assert_eq!(uri, "/b?x=%2e%2e&y");
+        assert_eq!(raw.as_deref(), Some("/a/../b"));
+        // Already normal: nothing is rewritten or recorded.
+        let (uri, raw) = run("/b?x=1", true, Utf8Policy::Raw).unwrap();
+        assert_eq!(uri, "/b?x=1");
+        assert_eq!(raw, None);
+    }
+
+    #[test]
+    fn nul_is_rejected() {
+        assert!(matches!(
+            run("/a%00b", true, Utf8Policy::Raw),
+            Err(Error::InvalidPath(_))
+        ));
+        // Without resolution the policy still re-escapes it.
+        assert_eq!(
+            run("/a%00%ff", false, Utf8Policy::Lossy).unwrap().0,
+            "/a%00%EF%BF%BD"
+        );
+    }
+
+    #[test]
+    fn utf8_policies_for_paths() {
+        assert_eq!(resolved("/a/%ff/../b%fe"), "/a/b%FE");
+        assert_eq!(
+            run("/a/../%ff", true, Utf8Policy::Lossy).unwrap().0,
+            "/%EF%BF%BD"
+        );
+        assert!(matches!(
+            run("/%ff", true, Utf8Policy::Reject),
+            Err(Error::InvalidPath(_))
+        ));
+        assert!(matches!(
+            run("/%ff", false, Utf8Policy::Reject),
+            Err(Error::InvalidPath(_))
+        ));
+        // With resolution off, dot segments and valid escapes stay as sent.
+        for policy in [Utf8Policy::Raw, Utf8Policy::Lossy, Utf8Policy::Reject] {
+            assert_eq!(
+                run("/a/../%41", false, policy).unwrap(),
+                ("/a/../%41".to_string(), None)
+            );
+        }
+        assert_eq!(
+            run("/a/../%ff", false, Utf8Policy::Raw).unwrap(),
+            ("/a/../%ff".to_string(), None)
+        );
+        let (uri, raw) = run("/a/../%ff", false, Utf8Policy::Lossy).unwrap();
+        assert_eq!(uri, "/a/../%EF%BF%BD");
+        assert_eq!(raw.as_deref(), Some("/a/../%ff"));
+    }
+
+    #[test]
+    fn utf8_policies_for_queries() {
+        let query = "a=%ff&b=%E2%82%AC&%fe=c+d&e";
+        assert_eq!(
+            run(&format!("/?{query}"), true, Utf8Policy::Raw).unwrap().0,
+            format!("/?{query}")
+        );
+        let (uri, raw) = run(&format!("/?{query}"), true, Utf8Policy::Lossy).unwrap();
+        assert_eq!(uri, "/?a=%EF%BF%BD&b=%E2%82%AC&%EF%BF%BD=c+d&e");
+        // Only the path is recorded, and it didn't change.
+        assert_eq!(raw, None);
+        assert!(matches!(
+            run(&format!("/?{query}"), true, Utf8Policy::Reject),
+            Err(Error::InvalidQuery(_))
+        ));
+        assert_eq!(
+            run("/?a=%41&b", true, Utf8Policy::Reject).unwrap().0,
+            "/?a=%41&b"
+        );
+        // A replaced component escapes its own delimiters.
+        assert_eq!(
+            run("/?a=%ff%26%3D%2B", true, Utf8Policy::Lossy).unwrap().0,
+            "/?a=%EF%BF%BD%26%3D%2B"
+        );
+    }
+
+    #[test]
+    fn non_origin_forms_are_left_alone() {
+        let mut req = Request::test(Method::OPTIONS, "/");
+        *req.uri_mut() = Uri::from_static("*");
+        normalize(&mut req, true, Utf8Policy::Reject).unwrap();
+        assert_eq!(req.uri(), "*");
+    }
+
+    fn echo() -> impl Clone
+    + tower::Service<
+        Request,
+        Response = ServiceResponse,
+        Error = ServiceError,
+        Future = impl Send,
+    > + Send
+    + Sync
+    + 'static {
+        service_fn(|req: Request| async move {
+            let raw = RawPath::from_request(&req).map(|raw| raw.to_string());
+            Ok::<_, ServiceError>(ServiceResponse::from_string(format!(
+                "{} {}",
+                req.uri(),
+                raw.as_deref().unwrap_or("-")
+            )))
+        })
+    }
+
+    #[tokio::test]
+    async fn server_normalizes_by_default() {
+        let client = TestClient::new(echo());
+        client
+            .get("/static/%2e%2e/secret?q=1")
+            .send()
+            .await
+            .assert_status(StatusCode::OK)
+            .assert_body("/secret?q=1 /static/%2e%2e/secret");
+        client.get("/plain").send().await.assert_body("/plain -");
+        client
+            .get("/a%00")
+            .send()
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn server_policies() {
+        let client =
+            TestClient::with_server(echo(), |server| server.with_path_normalization(false));
+        client
+            .get("/a/../%ff")
+            .send()
+            .await
+            .assert_body("/a/../%ff -");
+
+        let client =
+            TestClient::with_server(echo(), |server| server.with_utf8_policy(Utf8Policy::Lossy));
+        client
+            .get("/%ff?x=%fe")
+            .send()
+            .await
+            .assert_body("/%EF%BF%BD?x=%EF%BF%BD /%ff");
+
+        let client =
+            TestClient::with_server(echo(), |server| server.with_utf8_policy(Utf8Policy::Reject));
+        client
+            .get("/?x=%fe")
+            .send()
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        client
+            .get("/%E2%82%AC")
+            .send()
+            .await
+            .assert_status(StatusCode::OK);
+    }
+}
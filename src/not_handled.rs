@@ -0,0 +1,75 @@
+use hyper::{
+    Method, StatusCode,
+    header::{self, HeaderValue},
+};
+
+use crate::{IntoResponse, ResponseExt, ServiceResponse, method_not_allowed};
+
+/// Why no route took a request, as reported by `Router::not_handled`. The dispatcher
+/// answers 405 and 406 itself, and leaves plain misses to the fallback.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NotHandled {
+    /// No route matched the request.
+    NoMatch,
+    /// Some route matched the path, but only for these methods.
+    MethodNotAllowed(Vec<Method>),
+    /// Some route matched, but produces none of the media types the client accepts.
+    NotAcceptable(Vec<String>),
+}
+
+impl NotHandled {
+    /// The status the request is answered with: 404, 405 or 406.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            NotHandled::NoMatch => StatusCode::NOT_FOUND,
+            NotHandled::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            NotHandled::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+        }
+    }
+
+    /// Combines the reasons of several routes. A route that only failed negotiation
+    /// came closest to handling the request, so 406 wins over 405, which wins over a
+    /// miss; the methods or media types of equal reasons are merged.
+    pub fn merge(self, other: NotHandled) -> NotHandled {
+        match (self, other) {
+            (NotHandled::NotAcceptable(mut a), NotHandled::NotAcceptable(b)) => {
+                for ty in b {
+                    if !a.contains(&ty) {
+                        a.push(ty);
+                    }
+                }
+                NotHandled::NotAcceptable(a)
+            }
+            (NotHandled::MethodNotAllowed(mut a), NotHandled::MethodNotAllowed(b)) => {
+                for method in b {
+                    if !a.contains(&method) {
+                        a.push(method);
+                    }
+                }
+                NotHandled::MethodNotAllowed(a)
+            }
+            (a @ NotHandled::NotAcceptable(_), _) | (_, a @ NotHandled::NotAcceptable(_)) => a,
+            (a @ NotHandled::MethodNotAllowed(_), _) | (_, a @ NotHandled::MethodNotAllowed(_)) => {
+                a
+            }
+            (NotHandled::NoMatch, NotHandled::NoMatch) => NotHandled::NoMatch,
+        }
+    }
+}
+
+impl IntoResponse for NotHandled {
+    fn into_response(self) -> ServiceResponse {
+        match self {
+            NotHandled::NoMatch => ServiceResponse::from_status(StatusCode::NOT_FOUND),
+            NotHandled::MethodNotAllowed(allowed) => method_not_allowed(&allowed),
+            // There is no standard header for what would have been acceptable, so the
+            // alternatives are listed in the body.
+            NotHandled::NotAcceptable(available) => ServiceResponse::from_bytes(format!(
+                "406 Not Acceptable\n\nAvailable: {}\n",
+                available.join(", ")
+            ))
+            .with_status(StatusCode::NOT_ACCEPTABLE)
+            .with_header(header::VARY, HeaderValue::from_static("accept")),
+        }
+    }
+}
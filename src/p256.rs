@@ -0,0 +1,248 @@
+use std::sync::LazyLock;
+
+use crate::{bigint::BigUint, sha256::sha256};
+
+struct Curve {
+    p: BigUint,
+    b: BigUint,
+    n: BigUint,
+    g: Affine,
+}
+
+/// NIST P-256 (secp256r1), where a = -3.
+static P256: LazyLock<Curve> = LazyLock::new(|| Curve {
+    p: BigUint::from_hex("ffffffff00000001000000000000000000000000ffffffffffffffffffffffff"),
+    b: BigUint::from_hex("5ac635d8aa3a93e7b3ebbd55769886bc651d06b0cc53b0f63bce3c3e27d2604b"),
+    n: BigUint::from_hex("ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551"),
+    g: Affine {
+        x: BigUint::from_hex("6b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c296"),
+        y: BigUint::from_hex("4fe342e2fe1a7f9b8ee7eb4a7c0f9e162bce33576b315ececbb6406837bf51f5"),
+    },
+});
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Affine {
+    x: BigUint,
+    y: BigUint,
+}
+
+/// Jacobian coordinates: (X, Y, Z) stands for (X/Z², Y/Z³). `None` is the point at
+/// infinity.
+type Jacobian = Option<(BigUint, BigUint, BigUint)>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct P256PublicKey(Affine);
+
+impl P256PublicKey {
+    /// From the big-endian affine coordinates; fails unless the point is on the curve.
+    pub(crate) fn new(x: &[u8], y: &[u8]) -> Option<P256PublicKey> {
+        let curve = &*P256;
+        let (x, y) = (BigUint::from_be_bytes(x), BigUint::from_be_bytes(y));
+        if x >= curve.p || y >= curve.p {
+            return None;
+        }
+        let p = &curve.p;
+        let three_x = x.mod_add(&x, p).mod_add(&x, p);
+        let rhs = x
+            .mod_mul(&x, p)
+            .mod_mul(&x, p)
+            .mod_sub(&three_x, p)
+            .mod_add(&curve.b, p);
+        (y.mod_mul(&y, p) == rhs).then_some(P256PublicKey(Affine { x, y }))
+    }
+
+    /// ECDSA with SHA-256, the signature being `r || s` as used by JWS.
+    pub(crate) fn verify_sha256(&self, message: &[u8], signature: &[u8]) -> bool {
+        let curve = &*P256;
+        let n = &curve.n;
+        if signature.len() != 64 {
+            return false;
+        }
+        let (r, s) = signature.split_at(32);
+        let (r, s) = (BigUint::from_be_bytes(r), BigUint::from_be_bytes(s));
+        if r.is_zero() || s.is_zero() || r >= *n || s >= *n {
+            return false;
+        }
+        let e = BigUint::from_be_bytes(&sha256(message)).rem(n);
+        let w = s.mod_inv_prime(n);
+        let u1 = e.mod_mul(&w, n);
+        let u2 = r.mod_mul(&w, n);
+        let Some(point) = to_affine(double_scalar(&u1, &curve.g, &u2, &self.0)) else {
+            return false;
+        };
+        point.x.rem(n) == r
+    }
+}
+
+/// u1·a + u2·b in one pass over the bits (Shamir's trick).
+fn double_scalar(u1: &BigUint, a: &Affine, u2: &BigUint, b: &Affine) -> Jacobian {
+    let mut acc: Jacobian = None;
+    for i in (0..u1.bits().max(u2.bits())).rev() {
+        acc = double(&acc);
+        if u1.bit(i) {
+            acc = add(&acc, a);
+        }
+        if u2.bit(i) {
+            acc = add(&acc, b);
+        }
+    }
+    acc
+}
+
+fn double(point: &Jacobian) -> Jacobian {
+    let (x, y, z) = point.as_ref()?;
+    let p = &P256.p;
+    if y.is_zero() {
+        return None;
+    }
+    let delta = z.mod_mul(z, p);
+    let gamma = y.mod_mul(y, p);
+    let beta = x.mod_mul(&gamma, p);
+    let t = x.mod_sub(&delta, p).mod_mul(&x.mod_add(&delta, p), p);
+    let alpha = t.mod_add(&t, p).mod_add(&t, p);
+    let beta4 = beta.mod_add(&beta, p).mod_add(&beta.mod_add(&beta, p), p);
+    let x3 = alpha
+        .mod_mul(&alpha, p)
+        .mod_sub(&beta4.mod_add(&beta4, p), p);
+    let yz = y.mod_add(z, p);
+    let z3 = yz.mod_mul(&yz, p).mod_sub(&gamma, p).mod_sub(&delta, p);
+    let gamma2 = gamma.mod_mul(&gamma, p);
+    let gamma8 = (0..3).fold(gamma2, |g, _| g.mod_add(&g, p));
+    let y3 = alpha.mod_mul(&beta4.mod_sub(&x3, p), p).mod_sub(&gamma8, p);
+    Some((x3, y3, z3))
+}
+
+/// Adds an affine point to a Jacobian one.
+fn add(point: &Jacobian, other: &Affine) -> Jacobian {
+    let Some((x1, y1, z1)) = point else {
+        return Some((other.x.clone(), other.y.clone(), BigUint::from_u32(1)));
+    };
+    let p = &P256.p;
+    let z1z1 = z1.mod_mul(z1, p);
+    let u2 = other.x.mod_mul(&z1z1, p);
+    let s2 = other.y.mod_mul(&z1z1.mod_mul(z1, p), p);
+    if *x1 == u2 {
+        return match *y1 == s2 {
+            true => double(point),
+            false => None,
+        };
+    }
+    let h = u2.mod_sub(x1, p);
+    let r = s2.mod_sub(y1, p);
+    let hh = h.mod_mul(&h, p);
+    let hhh = hh.mod_mul(&h, p);
+    let v = x1.mod_mul(&hh, p);
+    let x3 = r
+        .mod_mul(&r, p)
+        .mod_sub(&hhh, p)
+        .mod_sub(&v.mod_add(&v, p), p);
+    let y3 = r
+        .mod_mul(&v.mod_sub(&x3, p), p)
+        .mod_sub(&y1.mod_mul(&hhh, p), p);
+    let z3 = z1.mod_mul(&h, p);
+    Some((x3, y3, z3))
+}
+
+fn to_affine(point: Jacobian) -> Option<Affine> {
+    let (x, y, z) = point?;
+    let p = &P256.p;
+    let z_inv = z.mod_inv_prime(p);
+    let z_inv2 = z_inv.mod_mul(&z_inv, p);
+    Some(Affine {
+        x: x.mod_mul(&z_inv2, p),
+        y: y.mod_mul(&z_inv2.mod_mul(&z_inv, p), p),
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::base64;
+
+    // A key and signatures made by an independent implementation (Python's
+    // `cryptography`); the out-of-range ones reuse a valid signature's other half.
+    pub(crate) const EC_X: &str = "nRfITJ57lR7H2fc6U_ahjEblqjhVrc8GilVBdvtVmlo";
+    pub(crate) const EC_Y: &str = "94oCbghNW-pkhvv6UUGOy4M4wl2D_MAb3xP0T3cSwUQ";
+    pub(crate) const ES256_TOKEN: &str = concat!(
+        "eyJhbGciOiJFUzI1NiIsInR5cCI6IkpXVCJ9.eyJzdWIiOiJhbGljZSIsImV4cCI",
+        "6NDEwMjQ0NDgwMH0.OFvG2aXukQMo2A_vTC8Jpptbsl1IEC6vGTKOuAtwMUtkP4g",
+        "QZNDDvmBQw1BFEjbWMzwCxd4mXxQmkrVgme8H8g",
+    );
+    const ES256_R_ZERO: &str = concat!(
+        "eyJhbGciOiJFUzI1NiJ9.eyJzdWIiOiJhbGljZSIsImV4cCI6NDEwMjQ0NDgwMH0",
+        ".AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAABiYP6_YgzAdyCkgJQA-4N",
+        "ZAAUj2XAg3NmsDsdLrt4yxg",
+    );
+    const ES256_R_IS_N: &str = concat!(
+        "eyJhbGciOiJFUzI1NiJ9.eyJzdWIiOiJhbGljZSIsImV4cCI6NDEwMjQ0NDgwMH0",
+        "._____wAAAAD__________7zm-q2nF56E87nKwvxjJVFiYP6_YgzAdyCkgJQA-4N",
+        "ZAAUj2XAg3NmsDsdLrt4yxg",
+    );
+    const ES256_S_ZERO: &str = concat!(
+        "eyJhbGciOiJFUzI1NiJ9.eyJzdWIiOiJhbGljZSIsImV4cCI6NDEwMjQ0NDgwMH0",
+        ".y2mp-l4MhXoH6YGon68pWOJP6bSvahtygem4z3RCUrkAAAAAAAAAAAAAAAAAAAA",
+        "AAAAAAAAAAAAAAAAAAAAAAA",
+    );
+    const ES256_S_IS_N: &str = concat!(
+        "eyJhbGciOiJFUzI1NiJ9.eyJzdWIiOiJhbGljZSIsImV4cCI6NDEwMjQ0NDgwMH0",
+        ".y2mp-l4MhXoH6YGon68pWOJP6bSvahtygem4z3RCUrn_____AAAAAP_________",
+        "_vOb6racXnoTzucrC_GMlUQ",
+    );
+    const ES256_OTHER_HEADER: &str = concat!(
+        "eyJhbGciOiJFUzI1NiJ9.eyJzdWIiOiJhbGljZSIsImV4cCI6NDEwMjQ0NDgwMH0",
+        ".y2mp-l4MhXoH6YGon68pWOJP6bSvahtygem4z3RCUrliYP6_YgzAdyCkgJQA-4N",
+        "ZAAUj2XAg3NmsDsdLrt4yxg",
+    );
+
+    fn key() -> P256PublicKey {
+        P256PublicKey::new(
+            &base64::decode(EC_X).unwrap(),
+            &base64::decode(EC_Y).unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn verify(token: &str) -> bool {
+        let (message, signature) = token.rsplit_once('.').unwrap();
+        key().verify_sha256(message.as_bytes(), &base64::decode(signature).unwrap())
+    }
+
+    #[test]
+    fn verifies_known_signatures() {
+        assert!(verify(ES256_TOKEN));
+        assert!(verify(ES256_OTHER_HEADER));
+    }
+
+    #[test]
+    fn rejects_out_of_range_scalars() {
+        for token in [ES256_R_ZERO, ES256_R_IS_N, ES256_S_ZERO, ES256_S_IS_N] {
+            assert!(!verify(token));
+        }
+    }
+
+    #[test]
+    fn rejects_tampering() {
+        let (message, signature) = ES256_TOKEN.rsplit_once('.').unwrap();
+        let signature = base64::decode(signature).unwrap();
+        let key = key();
+        assert!(!key.verify_sha256(&message.as_bytes()[1..], &signature));
+        for i in [0, 31, 32, 63] {
+            let mut flipped = signature.clone();
+            flipped[i] ^= 0x80;
+            assert!(!key.verify_sha256(message.as_bytes(), &flipped));
+        }
+        assert!(!key.verify_sha256(message.as_bytes(), &signature[..63]));
+    }
+
+    #[test]
+    fn refuses_points_off_the_curve() {
+        let x = base64::decode(EC_X).unwrap();
+        let mut y = base64::decode(EC_Y).unwrap();
+        y[31] ^= 1;
+        assert!(P256PublicKey::new(&x, &y).is_none());
+        let p = P256.p.to_be_bytes(32);
+        assert!(P256PublicKey::new(&p, &base64::decode(EC_Y).unwrap()).is_none());
+        let g = &P256.g;
+        assert!(P256PublicKey::new(&g.x.to_be_bytes(32), &g.y.to_be_bytes(32)).is_some());
+    }
+}
@@ -0,0 +1,201 @@
+use std::{fmt, str::FromStr, sync::Arc};
+
+use crate::{MountPath, Request, Router, pattern::Pattern};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Segment {
+    Static(String),
+    Param(String),
+    Constrained(String, Arc<Pattern>),
+    CatchAll(String),
+}
+
+/// Templates accept `:name` or `{name}` parameters, `{name:regex}` parameters that must
+/// match the whole segment, and a trailing `*name` capturing the rest of the path.
+/// Invalid templates are programming errors and panic at construction.
+pub(crate) fn parse_template(template: &str) -> Vec<Segment> {
+    let segments: Vec<Segment> = split_path(template)
+        .map(|seg| {
+            if let Some(name) = seg.strip_prefix(':') {
+                return Segment::Param(name.to_string());
+            }
+            if let Some(name) = seg.strip_prefix('*') {
+                return Segment::CatchAll(name.to_string());
+            }
+            let Some(inner) = seg.strip_prefix('{').and_then(|s| s.strip_suffix('}')) else {
+                return Segment::Static(seg.to_string());
+            };
+            match inner.split_once(':') {
+                Some((name, source)) => match Pattern::new(source) {
+                    Ok(pattern) => Segment::Constrained(name.to_string(), Arc::new(pattern)),
+                    Err(e) => panic!("invalid pattern in route `{template}`: {e}"),
+                },
+                None => Segment::Param(inner.to_string()),
+            }
+        })
+        .collect();
+    if let Some(i) = segments
+        .iter()
+        .position(|s| matches!(s, Segment::CatchAll(_)))
+        && i != segments.len() - 1
+    {
+        panic!("catch-all must be the last segment in route `{template}`");
+    }
+    segments
+}
+
+/// Matches paths against one route template made of static, `:name`, `{name}`,
+/// `{name:regex}` and trailing `*name` segments.
+#[derive(Clone, Debug)]
+pub struct PathRouter {
+    template: Arc<str>,
+    segments: Vec<Segment>,
+}
+
+impl PathRouter {
+    /// A router for `template`. Panics if the template is invalid.
+    pub fn new(template: impl AsRef<str>) -> PathRouter {
+        PathRouter {
+            template: Arc::from(template.as_ref()),
+            segments: parse_template(template.as_ref()),
+        }
+    }
+
+    /// The template the router was built from.
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// The captured parameters when `path` matches the template.
+    pub fn match_path(&self, path: &str) -> Option<PathParams> {
+        let mut params = PathParams::default();
+        let mut parts = split_path(path);
+
+        for segment in &self.segments {
+            if let Segment::CatchAll(name) = segment {
+                let rest: Vec<&str> = parts.by_ref().collect();
+                params.push(name.clone(), rest.join("/"));
+                break;
+            }
+            let part = parts.next()?;
+            match segment {
+                Segment::Static(s) if s == part => {}
+                Segment::Static(_) => return None,
+                Segment::Param(_) | Segment::Constrained(..) if part.is_empty() => return None,
+                Segment::Param(name) => params.push(name.clone(), part.to_string()),
+                Segment::Constrained(name, pattern) if pattern.is_match(part) => {
+                    params.push(name.clone(), part.to_string())
+                }
+                Segment::Constrained(..) => return None,
+                Segment::CatchAll(_) => unreachable!(),
+            }
+        }
+
+        match parts.next() {
+            Some(_) => None,
+            None => Some(params),
+        }
+    }
+}
+
+impl Router for PathRouter {
+    fn matches(&self, req: &Request) -> bool {
+        self.match_path(req.uri().path()).is_some()
+    }
+
+    fn route(&self, req: &mut Request) -> bool {
+        let Some(params) = self.match_path(req.uri().path()) else {
+            return false;
+        };
+        record_match(req, params, &self.template);
+        true
+    }
+}
+
+pub(crate) fn record_match(req: &mut Request, params: PathParams, template: &Arc<str>) {
+    match req.extensions_mut().get_mut::<PathParams>() {
+        Some(existing) => existing.params.extend(params.params),
+        None => {
+            req.extensions_mut().insert(params);
+        }
+    }
+    let matched = match MountPath::from_request(req) {
+        Some(mount) if &**template == "/" => Arc::from(mount.as_str()),
+        Some(mount) => Arc::from(format!("{mount}{template}")),
+        None => template.clone(),
+    };
+    req.extensions_mut().insert(MatchedPath(matched));
+}
+
+/// The template of the route that matched, including any mount prefix, copied onto
+/// the response for outer layers.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MatchedPath(Arc<str>);
+
+impl MatchedPath {
+    /// The matched route template for `req`.
+    pub fn from_request(req: &Request) -> Option<&MatchedPath> {
+        req.extensions().get()
+    }
+
+    /// The template as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for MatchedPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+pub(crate) fn split_path(path: &str) -> std::str::Split<'_, char> {
+    path.strip_prefix('/').unwrap_or(path).split('/')
+}
+
+/// The parameters a route template captured, percent-decoded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PathParams {
+    params: Vec<(String, String)>,
+}
+
+impl PathParams {
+    /// The parameters captured for `req`.
+    pub fn from_request(req: &Request) -> Option<&PathParams> {
+        req.extensions().get()
+    }
+
+    /// The value captured for `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .rev()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Parses the value captured for `name`; `None` if there is no such parameter.
+    pub fn parse<T: FromStr>(&self, name: &str) -> Option<Result<T, T::Err>> {
+        self.get(name).map(str::parse)
+    }
+
+    /// The captured names and values, in template order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// The number of captured parameters.
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    /// Whether nothing was captured.
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, name: String, value: String) {
+        self.params.push((name, value));
+    }
+}
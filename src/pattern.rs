@@ -0,0 +1,268 @@
+use std::fmt;
+
+/// A small regular expression engine for route constraints: literals, `.`, classes
+/// (`[a-z]`, `[^/]`, `\d`, `\w`, `\s`), groups with `|`, and the `*`, `+`, `?`,
+/// `{m}`, `{m,}` and `{m,n}` quantifiers. Patterns always match the whole segment.
+#[derive(Clone)]
+pub(crate) struct Pattern {
+    source: String,
+    root: Vec<Seq>,
+}
+
+type Seq = Vec<Piece>;
+
+#[derive(Clone)]
+struct Piece {
+    atom: Atom,
+    min: u32,
+    max: Option<u32>,
+}
+
+#[derive(Clone)]
+enum Atom {
+    Char(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Group(Vec<Seq>),
+}
+
+impl Pattern {
+    pub(crate) fn new(source: &str) -> Result<Pattern, &'static str> {
+        let trimmed = source.strip_prefix('^').unwrap_or(source);
+        let trimmed = match trimmed.strip_suffix('$') {
+            Some(rest) if !rest.ends_with('\\') => rest,
+            _ => trimmed,
+        };
+        let mut parser = Parser {
+            chars: trimmed.chars().collect(),
+            pos: 0,
+        };
+        let root = parser.alternation()?;
+        if parser.pos != parser.chars.len() {
+            return Err("unbalanced `)`");
+        }
+        Ok(Pattern {
+            source: source.to_string(),
+            root,
+        })
+    }
+
+    pub(crate) fn is_match(&self, input: &str) -> bool {
+        let input: Vec<char> = input.chars().collect();
+        alternation(&self.root, &input, 0, &mut |end| end == input.len())
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Pattern").field(&self.source).finish()
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for Pattern {}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn alternation(&mut self) -> Result<Vec<Seq>, &'static str> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn sequence(&mut self) -> Result<Seq, &'static str> {
+        let mut seq = vec![];
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            let (min, max) = self.quantifier()?;
+            seq.push(Piece { atom, min, max });
+        }
+        Ok(seq)
+    }
+
+    fn atom(&mut self) -> Result<Atom, &'static str> {
+        match self.next().ok_or("unexpected end of pattern")? {
+            '.' => Ok(Atom::Any),
+            '(' => {
+                // Non-capturing groups are accepted for familiarity.
+                if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                }
+                let group = self.alternation()?;
+                match self.next() {
+                    Some(')') => Ok(Atom::Group(group)),
+                    _ => Err("unclosed `(`"),
+                }
+            }
+            '[' => self.class(),
+            '\\' => self.escape(),
+            '*' | '+' | '?' | '{' => Err("quantifier without a preceding atom"),
+            c => Ok(Atom::Char(c)),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Atom, &'static str> {
+        let c = self.next().ok_or("trailing `\\`")?;
+        let (ranges, negated) = match c {
+            'd' | 'D' => (vec![('0', '9')], c == 'D'),
+            'w' | 'W' => (
+                vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')],
+                c == 'W',
+            ),
+            's' | 'S' => (
+                vec![(' ', ' '), ('\t', '\t'), ('\n', '\n'), ('\r', '\r')],
+                c == 'S',
+            ),
+            c => return Ok(Atom::Char(c)),
+        };
+        Ok(Atom::Class { ranges, negated })
+    }
+
+    fn class(&mut self) -> Result<Atom, &'static str> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = vec![];
+        let mut first = true;
+        loop {
+            let c = self.next().ok_or("unclosed `[`")?;
+            let start = match c {
+                ']' if !first => break,
+                '\\' => match self.escape()? {
+                    Atom::Char(c) => c,
+                    Atom::Class {
+                        ranges: escaped,
+                        negated: false,
+                    } => {
+                        ranges.extend(escaped);
+                        first = false;
+                        continue;
+                    }
+                    _ => return Err("negated escape inside a class"),
+                },
+                c => c,
+            };
+            first = false;
+            let end = match (self.peek(), self.chars.get(self.pos + 1)) {
+                (Some('-'), Some(&end)) if end != ']' => {
+                    self.pos += 2;
+                    end
+                }
+                _ => start,
+            };
+            if end < start {
+                return Err("invalid class range");
+            }
+            ranges.push((start, end));
+        }
+        Ok(Atom::Class { ranges, negated })
+    }
+
+    fn quantifier(&mut self) -> Result<(u32, Option<u32>), &'static str> {
+        let bounds = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                let close = self.chars[self.pos..]
+                    .iter()
+                    .position(|&c| c == '}')
+                    .ok_or("unclosed `{`")?;
+                let body: String = self.chars[self.pos + 1..self.pos + close].iter().collect();
+                let number = |s: &str| s.trim().parse::<u32>().map_err(|_| "invalid repetition");
+                let bounds = match body.split_once(',') {
+                    None => (number(&body)?, Some(number(&body)?)),
+                    Some((min, "")) => (number(min)?, None),
+                    Some((min, max)) => (number(min)?, Some(number(max)?)),
+                };
+                if bounds.1.is_some_and(|max| max < bounds.0) {
+                    return Err("invalid repetition");
+                }
+                self.pos += close;
+                bounds
+            }
+            _ => return Ok((1, Some(1))),
+        };
+        self.pos += 1;
+        Ok(bounds)
+    }
+}
+
+/// Backtracking matcher in continuation-passing style: each step calls `k` with every
+/// position it can end at until one of them leads to a full match.
+fn alternation(alts: &[Seq], s: &[char], pos: usize, k: &mut dyn FnMut(usize) -> bool) -> bool {
+    alts.iter().any(|seq| sequence(seq, s, pos, k))
+}
+
+fn sequence(seq: &[Piece], s: &[char], pos: usize, k: &mut dyn FnMut(usize) -> bool) -> bool {
+    let Some((piece, rest)) = seq.split_first() else {
+        return k(pos);
+    };
+    repeat(piece, 0, s, pos, &mut |end| sequence(rest, s, end, k))
+}
+
+fn repeat(
+    piece: &Piece,
+    count: u32,
+    s: &[char],
+    pos: usize,
+    k: &mut dyn FnMut(usize) -> bool,
+) -> bool {
+    // Greedy: try one more repetition first. Empty repetitions past the minimum
+    // would loop forever, so they are not retried.
+    if piece.max.is_none_or(|max| count < max)
+        && atom(&piece.atom, s, pos, &mut |end| {
+            (end != pos || count < piece.min) && repeat(piece, count + 1, s, end, k)
+        })
+    {
+        return true;
+    }
+    count >= piece.min && k(pos)
+}
+
+fn atom(atom: &Atom, s: &[char], pos: usize, k: &mut dyn FnMut(usize) -> bool) -> bool {
+    match atom {
+        Atom::Char(c) => s.get(pos) == Some(c) && k(pos + 1),
+        Atom::Any => pos < s.len() && k(pos + 1),
+        Atom::Class { ranges, negated } => match s.get(pos) {
+            Some(&c) => {
+                ranges.iter().any(|&(lo, hi)| (lo..=hi).contains(&c)) != *negated && k(pos + 1)
+            }
+            None => false,
+        },
+        Atom::Group(alts) => alternation(alts, s, pos, k),
+    }
+}
@@ -0,0 +1,102 @@
+//! Request predicates for `Route::when`. Each one is a `Router` that only inspects
+//! the request, so they also work as standalone routers and combine with `not`.
+
+use hyper::{Method, header::HeaderName};
+
+use crate::{NotHandled, Request, RequestExt, Router, Routing};
+
+/// Matches requests carrying header `name`.
+pub fn header(name: HeaderName) -> impl Router + Clone {
+    move |req: &Request| req.headers().contains_key(&name)
+}
+
+/// Matches requests whose header `name` equals `value`.
+pub fn header_eq(name: HeaderName, value: impl Into<String>) -> impl Router + Clone {
+    let value = value.into();
+    move |req: &Request| {
+        req.headers()
+            .get_all(&name)
+            .iter()
+            .any(|v| v.as_bytes() == value.as_bytes())
+    }
+}
+
+/// `type/subtype` or `type/*`, ignoring parameters such as `charset`.
+pub fn content_type(mime: impl Into<String>) -> impl Router + Clone {
+    let mime = mime.into().to_ascii_lowercase();
+    move |req: &Request| {
+        req.content_type()
+            .is_some_and(|ct| match mime.strip_suffix("/*") {
+                Some(ty) => ct.mime().split_once('/').is_some_and(|(t, _)| t == ty),
+                None => ct.mime() == mime,
+            })
+    }
+}
+
+/// `?name`, `?name=` and any value other than `0` or `false` set the flag.
+pub fn query_flag(name: impl Into<String>) -> impl Router + Clone {
+    let name = name.into();
+    move |req: &Request| {
+        req.query_pairs()
+            .any(|(k, v)| k == name && v != "0" && !v.eq_ignore_ascii_case("false"))
+    }
+}
+
+/// Matches requests whose query has `name` set to `value`.
+pub fn query_eq(name: impl Into<String>, value: impl Into<String>) -> impl Router + Clone {
+    let (name, value) = (name.into(), value.into());
+    move |req: &Request| req.query_pairs().any(|(k, v)| k == name && v == value)
+}
+
+/// Matches requests `predicate` doesn't.
+pub fn not<P: Router + Clone>(predicate: P) -> impl Router + Clone {
+    move |req: &Request| !predicate.matches(req)
+}
+
+/// A router that only routes requests matching `predicate` as well.
+pub struct When<R, P> {
+    router: R,
+    predicate: P,
+}
+
+impl<R, P> When<R, P> {
+    /// Routes through `router` only when `predicate` matches.
+    pub fn new(router: R, predicate: P) -> When<R, P> {
+        When { router, predicate }
+    }
+}
+
+impl<R: Router, P: Router> Router for When<R, P> {
+    fn matches(&self, req: &Request) -> bool {
+        self.predicate.matches(req) && self.router.matches(req)
+    }
+
+    fn route(&self, req: &mut Request) -> bool {
+        self.predicate.matches(req) && self.router.route(req)
+    }
+
+    fn route_or_respond(&self, req: &mut Request) -> Routing {
+        match self.predicate.matches(req) {
+            true => self.router.route_or_respond(req),
+            false => Routing::Pass,
+        }
+    }
+
+    fn allowed_methods(&self, req: &Request) -> Option<&[Method]> {
+        match self.predicate.matches(req) {
+            true => self.router.allowed_methods(req),
+            false => None,
+        }
+    }
+
+    fn methods(&self) -> Option<&[Method]> {
+        self.router.methods()
+    }
+
+    fn not_handled(&self, req: &Request) -> Option<NotHandled> {
+        match self.predicate.matches(req) {
+            true => self.router.not_handled(req),
+            false => None,
+        }
+    }
+}
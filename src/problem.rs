@@ -0,0 +1,135 @@
+use hyper::{
+    StatusCode,
+    header::{self, HeaderValue},
+};
+
+use crate::{Error, IntoResponse, ResponseExt, ServiceResponse, json};
+
+/// The `Content-Type` of problem responses.
+pub const APPLICATION_PROBLEM_JSON: &str = "application/problem+json";
+
+/// An RFC 7807 problem document. Extension members are stored as rendered JSON.
+#[derive(Clone, Debug)]
+pub struct Problem {
+    status: StatusCode,
+    type_uri: Option<String>,
+    title: Option<String>,
+    detail: Option<String>,
+    instance: Option<String>,
+    extensions: Vec<(String, String)>,
+}
+
+impl Problem {
+    /// A problem with `status` and no other members.
+    pub fn new(status: StatusCode) -> Problem {
+        Problem {
+            status,
+            type_uri: None,
+            title: status.canonical_reason().map(str::to_string),
+            detail: None,
+            instance: None,
+            extensions: vec![],
+        }
+    }
+
+    /// Sets the `type` URI identifying the kind of problem.
+    pub fn with_type(mut self, type_uri: impl Into<String>) -> Problem {
+        self.type_uri = Some(type_uri.into());
+        self
+    }
+
+    /// Sets the `title`, a short summary of the kind of problem.
+    pub fn with_title(mut self, title: impl Into<String>) -> Problem {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Sets the `detail`, explaining this occurrence.
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Problem {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Sets the `instance` URI identifying this occurrence.
+    pub fn with_instance(mut self, instance: impl Into<String>) -> Problem {
+        self.instance = Some(instance.into());
+        self
+    }
+
+    /// Adds a string extension member.
+    pub fn with_extension(mut self, name: impl Into<String>, value: &str) -> Problem {
+        let mut rendered = String::new();
+        json::push_string(&mut rendered, value);
+        self.extensions.push((name.into(), rendered));
+        self
+    }
+
+    /// `value` must already be valid JSON, e.g. a number, array or object.
+    pub fn with_raw_extension(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Problem {
+        self.extensions.push((name.into(), value.into()));
+        self
+    }
+
+    /// The status code, also sent as the `status` member.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The problem as a JSON object.
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{");
+        if let Some(type_uri) = &self.type_uri {
+            out.push_str("\"type\":");
+            json::push_string(&mut out, type_uri);
+            out.push(',');
+        }
+        if let Some(title) = &self.title {
+            out.push_str("\"title\":");
+            json::push_string(&mut out, title);
+            out.push(',');
+        }
+        out.push_str("\"status\":");
+        out.push_str(self.status.as_str());
+        for (name, value) in [("detail", &self.detail), ("instance", &self.instance)] {
+            if let Some(value) = value {
+                out.push_str(",\"");
+                out.push_str(name);
+                out.push_str("\":");
+                json::push_string(&mut out, value);
+            }
+        }
+        for (name, value) in &self.extensions {
+            out.push(',');
+            json::push_string(&mut out, name);
+            out.push(':');
+            out.push_str(value);
+        }
+        out.push('}');
+        out
+    }
+}
+
+impl From<Error> for Problem {
+    fn from(e: Error) -> Problem {
+        match e {
+            // The message of an arbitrary service error isn't meant for clients.
+            Error::Service(_) => Problem::new(e.status()),
+            e => Problem::new(e.status()).with_detail(e.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> ServiceResponse {
+        ServiceResponse::from_string(self.to_json())
+            .with_status(self.status)
+            .with_header(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(APPLICATION_PROBLEM_JSON),
+            )
+    }
+}
@@ -0,0 +1,216 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use futures::TryStreamExt;
+use http_body_util::BodyStream;
+use hyper::{
+    HeaderMap, StatusCode, Uri, Version,
+    header::{self, HeaderName, HeaderValue},
+};
+use tower::Service as TowerService;
+
+use crate::{
+    ConnectionInfo, Request, RequestExt, ResponseExt, ServiceBoxFuture, ServiceError,
+    ServiceResponse, Upstream, UpstreamPool, make_body_from_stream, trace,
+};
+
+const HOP_BY_HOP: [HeaderName; 8] = [
+    header::CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+type PathRewrite = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// The error for an upstream URL that isn't `http://host[:port][/path]`.
+#[derive(Debug)]
+pub struct InvalidUpstream(pub(crate) String);
+
+impl fmt::Display for InvalidUpstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid upstream `{}`: expected http://host[:port][/path]",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidUpstream {}
+
+/// Forwards requests to an upstream HTTP server, streaming bodies both ways. The
+/// request path, after any mount prefix was stripped, is appended to the upstream's
+/// base path. Fails with 502 when the upstream is unreachable and 504 when it takes
+/// longer than the timeout to respond.
+#[derive(Clone)]
+pub struct Proxy {
+    pool: UpstreamPool,
+    timeout: Duration,
+    preserve_host: bool,
+    rewrite: Option<PathRewrite>,
+}
+
+impl Proxy {
+    /// A proxy to the single upstream at `upstream`, with a 30 second timeout.
+    pub fn new(upstream: &str) -> Result<Proxy, InvalidUpstream> {
+        let upstream = Upstream::new(upstream)?;
+        Ok(Proxy::from_pool(
+            UpstreamPool::builder().with_upstream(upstream).build(),
+        ))
+    }
+
+    /// Clones of the pool share its connections and upstream health.
+    pub fn from_pool(pool: UpstreamPool) -> Proxy {
+        Proxy {
+            pool,
+            timeout: Duration::from_secs(30),
+            preserve_host: false,
+            rewrite: None,
+        }
+    }
+
+    /// Time allowed for the upstream to send its response headers.
+    pub fn with_timeout(mut self, timeout: Duration) -> Proxy {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Pass the client's `Host` through instead of the upstream's authority.
+    pub fn with_preserve_host(mut self, preserve: bool) -> Proxy {
+        self.preserve_host = preserve;
+        self
+    }
+
+    /// Rewrites the path sent upstream, applied after the mount prefix is stripped.
+    pub fn with_path_rewrite(
+        mut self,
+        rewrite: impl Fn(&str) -> String + Send + Sync + 'static,
+    ) -> Proxy {
+        self.rewrite = Some(Arc::new(rewrite));
+        self
+    }
+
+    fn upstream_uri(&self, upstream: &Upstream, req: &Request) -> Option<Uri> {
+        let path = match &self.rewrite {
+            Some(rewrite) => rewrite(req.uri().path()),
+            None => req.uri().path().to_string(),
+        };
+        let query = req
+            .uri()
+            .query()
+            .map(|q| format!("?{q}"))
+            .unwrap_or_default();
+        format!(
+            "http://{}{}{path}{query}",
+            upstream.authority(),
+            upstream.base_path()
+        )
+        .parse()
+        .ok()
+    }
+
+    fn prepare(&self, upstream: &Upstream, mut req: Request) -> Option<Request> {
+        let uri = self.upstream_uri(upstream, &req)?;
+        let client_host = req.authority().map(str::to_string);
+        let proto = req.scheme().to_string();
+        let client_ip = ConnectionInfo::from_request(&req).and_then(|info| info.remote_addr());
+
+        let headers = req.headers_mut();
+        strip_hop_by_hop(headers);
+        if let Some(ip) = client_ip {
+            let forwarded = match headers.get(&X_FORWARDED_FOR).and_then(|v| v.to_str().ok()) {
+                Some(prior) => format!("{prior}, {}", ip.ip()),
+                None => ip.ip().to_string(),
+            };
+            if let Ok(value) = HeaderValue::from_str(&forwarded) {
+                headers.insert(X_FORWARDED_FOR, value);
+            }
+        }
+        if let Ok(value) = HeaderValue::from_str(&proto) {
+            headers.insert(X_FORWARDED_PROTO, value);
+        }
+        let client_host = client_host.and_then(|h| HeaderValue::from_str(&h).ok());
+        if let Some(host) = &client_host {
+            headers.insert(X_FORWARDED_HOST, host.clone());
+        }
+        let host = match (self.preserve_host, client_host) {
+            (true, Some(host)) => host,
+            _ => HeaderValue::from_str(upstream.authority()).ok()?,
+        };
+        headers.insert(header::HOST, host);
+
+        *req.uri_mut() = uri;
+        *req.version_mut() = Version::HTTP_11;
+        Some(req)
+    }
+}
+
+pub(crate) fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let listed: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|name| name.trim().parse().ok())
+        .collect();
+    for name in HOP_BY_HOP.iter().chain(&listed) {
+        headers.remove(name);
+    }
+}
+
+impl TowerService<Request> for Proxy {
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let lease = self.pool.pick();
+        let Some(req) = self.prepare(lease.upstream(), req) else {
+            return Box::pin(async { Ok(ServiceResponse::from_status(StatusCode::BAD_GATEWAY)) });
+        };
+        let client = self.pool.client().clone();
+        let timeout = self.timeout;
+        Box::pin(async move {
+            let resp = match tokio::time::timeout(timeout, client.request(req)).await {
+                Ok(Ok(resp)) => resp,
+                Ok(Err(e)) => {
+                    trace::warn("upstream request failed", &e);
+                    lease.failed();
+                    return Ok(ServiceResponse::from_status(e.status()));
+                }
+                Err(_) => {
+                    lease.failed();
+                    return Ok(ServiceResponse::from_status(StatusCode::GATEWAY_TIMEOUT));
+                }
+            };
+            lease.succeeded();
+            let (mut parts, body) = resp.into_parts();
+            strip_hop_by_hop(&mut parts.headers);
+            // The lease is held until the body is done so least-connections sees
+            // streaming responses as busy.
+            let body = BodyStream::new(body).map_ok(move |frame| {
+                let _ = &lease;
+                frame
+            });
+            Ok(ServiceResponse::from_parts(
+                parts,
+                make_body_from_stream(body),
+            ))
+        })
+    }
+}
@@ -0,0 +1,74 @@
+use std::borrow::Cow;
+
+/// An iterator over the decoded `key=value` pairs of a query string. Empty pairs are skipped; a pair without `=` has an empty value.
+pub struct QueryPairs<'a> {
+    inner: std::str::Split<'a, char>,
+}
+
+impl<'a> QueryPairs<'a> {
+    /// The pairs of `query`, which excludes the `?`.
+    pub fn new(query: &'a str) -> QueryPairs<'a> {
+        QueryPairs {
+            inner: query.split('&'),
+        }
+    }
+}
+
+impl<'a> Iterator for QueryPairs<'a> {
+    type Item = (Cow<'a, str>, Cow<'a, str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pair = self.inner.find(|p| !p.is_empty())?;
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        Some((decode_component(key), decode_component(value)))
+    }
+}
+
+/// Percent-decodes a query component, turning `+` into a space and replacing bytes that aren't UTF-8.
+pub fn decode_component(s: &str) -> Cow<'_, str> {
+    if !s.bytes().any(|b| b == b'%' || b == b'+') {
+        return Cow::Borrowed(s);
+    }
+    let decoded = percent_decode(s.as_bytes(), true);
+    Cow::Owned(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+/// Like `decode_component`, without assuming the bytes are UTF-8.
+pub fn decode_component_bytes(s: &str) -> Cow<'_, [u8]> {
+    if !s.bytes().any(|b| b == b'%' || b == b'+') {
+        return Cow::Borrowed(s.as_bytes());
+    }
+    Cow::Owned(percent_decode(s.as_bytes(), true))
+}
+
+pub(crate) fn percent_decode(input: &[u8], plus_as_space: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b'%' if i + 2 < input.len() => {
+                match (hex_value(input[i + 1]), hex_value(input[i + 2])) {
+                    (Some(hi), Some(lo)) => {
+                        out.push(hi << 4 | lo);
+                        i += 3;
+                        continue;
+                    }
+                    _ => out.push(b'%'),
+                }
+            }
+            b'+' if plus_as_space => out.push(b' '),
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    out
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
@@ -0,0 +1,108 @@
+use std::cell::Cell;
+
+/// Fills `buf` from the OS's cryptographic source, for anything an attacker must not
+/// guess (session ids, CSRF tokens). Panics if there is no such source, rather than
+/// quietly handing out predictable secrets.
+pub(crate) fn fill(buf: &mut [u8]) {
+    if let Err(e) = os::fill(buf) {
+        panic!("no OS entropy source available: {e}");
+    }
+}
+
+pub(crate) fn hex_token(len: usize) -> String {
+    let mut buf = vec![0u8; len];
+    fill(&mut buf);
+    buf.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Cheap per-thread randomness for values that only need to be unique or evenly
+/// spread (request ids, sampling), not unpredictable. Seeded from the OS once per
+/// thread.
+pub(crate) fn fast_u64() -> u64 {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new({
+            let mut seed = [0u8; 8];
+            fill(&mut seed);
+            u64::from_le_bytes(seed)
+        });
+    }
+    // splitmix64
+    STATE.with(|state| {
+        let s = state.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+        state.set(s);
+        let z = (s ^ (s >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        let z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    })
+}
+
+pub(crate) fn fast_fill(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+        chunk.copy_from_slice(&fast_u64().to_le_bytes()[..chunk.len()]);
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod os {
+    use std::io;
+
+    pub(super) fn fill(mut buf: &mut [u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            // SAFETY: the pointer and length describe `buf`, which outlives the call.
+            let n = unsafe { libc::getrandom(buf.as_mut_ptr().cast(), buf.len(), 0) };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                match e.kind() {
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(e),
+                }
+            }
+            buf = &mut buf[n as usize..];
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+mod os {
+    use std::{fs::File, io, io::Read, sync::OnceLock};
+
+    /// Opened once and shared; reads of `/dev/urandom` need no locking.
+    pub(super) fn fill(buf: &mut [u8]) -> io::Result<()> {
+        static URANDOM: OnceLock<io::Result<File>> = OnceLock::new();
+        match URANDOM.get_or_init(|| File::open("/dev/urandom")) {
+            Ok(file) => (&*file).read_exact(buf),
+            Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_covers_the_whole_buffer() {
+        // 1 KiB of zeros from a working source is vanishingly unlikely.
+        let mut buf = [0u8; 1024];
+        fill(&mut buf);
+        assert!(buf.iter().filter(|&&b| b == 0).count() < 64);
+    }
+
+    #[test]
+    fn tokens_are_hex_and_distinct() {
+        let a = hex_token(16);
+        let b = hex_token(16);
+        assert_eq!(a.len(), 32);
+        assert!(a.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn fast_values_are_spread() {
+        let values: std::collections::HashSet<u64> = (0..1000).map(|_| fast_u64()).collect();
+        assert_eq!(values.len(), 1000);
+        let even = (0..10_000).filter(|_| fast_u64() & 1 == 0).count();
+        assert!((4_000..6_000).contains(&even));
+    }
+}
@@ -0,0 +1,227 @@
+use std::ops::RangeInclusive;
+
+use bytes::Bytes;
+use hyper::{
+    StatusCode,
+    header::{self, HeaderValue},
+};
+
+use crate::{Request, ResponseExt, ServiceResponse};
+
+/// The part of a representation a `Range` header asks for. Only single byte ranges are honoured.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    /// No range, or one this parser doesn't serve: send the whole body.
+    Full,
+    /// An inclusive byte range within the body.
+    Partial(RangeInclusive<u64>),
+    /// A range starting past the end of the body: answer 416.
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// The range requested for a body of `len` bytes.
+    pub fn from_request(req: &Request, len: u64) -> ByteRange {
+        match req
+            .headers()
+            .get(header::RANGE)
+            .and_then(|v| v.to_str().ok())
+        {
+            Some(value) => ByteRange::parse(value, len),
+            None => ByteRange::Full,
+        }
+    }
+
+    /// Parses a `Range` header value for a body of `len` bytes.
+    pub fn parse(value: &str, len: u64) -> ByteRange {
+        let value = value.trim();
+        let Some(spec) = value
+            .get(..6)
+            .filter(|unit| unit.eq_ignore_ascii_case("bytes="))
+            .map(|_| &value[6..])
+        else {
+            return ByteRange::Full;
+        };
+        if spec.contains(',') {
+            return ByteRange::Full;
+        }
+        let Some((start, end)) = spec.trim().split_once('-') else {
+            return ByteRange::Full;
+        };
+
+        let range = match (start.trim(), end.trim()) {
+            ("", "") => return ByteRange::Full,
+            ("", suffix) => match number(suffix) {
+                Some(0) => return ByteRange::Unsatisfiable,
+                Some(suffix) => len.saturating_sub(suffix)..=len.saturating_sub(1),
+                None => return ByteRange::Full,
+            },
+            (start, "") => match number(start) {
+                Some(start) => start..=len.saturating_sub(1),
+                None => return ByteRange::Full,
+            },
+            (start, end) => match (number(start), number(end)) {
+                (Some(start), Some(end)) if start <= end => start..=end.min(len.saturating_sub(1)),
+                _ => return ByteRange::Full,
+            },
+        };
+
+        match len == 0 || *range.start() >= len {
+            true => ByteRange::Unsatisfiable,
+            false => ByteRange::Partial(range),
+        }
+    }
+
+    /// The `Content-Range` for this range of a `len`-byte body; `None` for `Full`.
+    pub fn content_range(&self, len: u64) -> Option<HeaderValue> {
+        let value = match self {
+            ByteRange::Full => return None,
+            ByteRange::Partial(range) => format!("bytes {}-{}/{len}", range.start(), range.end()),
+            ByteRange::Unsatisfiable => format!("bytes */{len}"),
+        };
+        HeaderValue::from_str(&value).ok()
+    }
+}
+
+/// Digits only: `parse` would also take a sign.
+fn number(s: &str) -> Option<u64> {
+    match !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+        true => s.parse().ok(),
+        false => None,
+    }
+}
+
+/// Answers with `body`, or the part of it the request's `Range` asks for.
+pub fn ranged_bytes(req: &Request, body: impl Into<Bytes>) -> ServiceResponse {
+    let body = body.into();
+    let len = body.len() as u64;
+    let range = ByteRange::from_request(req, len);
+
+    let resp = match &range {
+        ByteRange::Full => ServiceResponse::from_bytes(body),
+        ByteRange::Partial(r) => {
+            ServiceResponse::from_bytes(body.slice(*r.start() as usize..=*r.end() as usize))
+                .with_status(StatusCode::PARTIAL_CONTENT)
+        }
+        ByteRange::Unsatisfiable => {
+            ServiceResponse::empty().with_status(StatusCode::RANGE_NOT_SATISFIABLE)
+        }
+    };
+
+    with_range_headers(resp, &range, len)
+}
+
+pub(crate) fn with_range_headers(
+    resp: ServiceResponse,
+    range: &ByteRange,
+    len: u64,
+) -> ServiceResponse {
+    let resp = resp.with_header(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    match range.content_range(len) {
+        Some(content_range) => resp.with_header(header::CONTENT_RANGE, content_range),
+        None => resp,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Method;
+
+    use super::*;
+    use crate::{ServiceError, service_fn, testing::TestClient, testing::TestRequestExt};
+
+    fn parse(value: &str) -> ByteRange {
+        ByteRange::parse(value, 10)
+    }
+
+    #[test]
+    fn satisfiable_ranges() {
+        assert_eq!(parse("bytes=0-4"), ByteRange::Partial(0..=4));
+        assert_eq!(parse("bytes=3-3"), ByteRange::Partial(3..=3));
+        assert_eq!(parse("bytes=5-"), ByteRange::Partial(5..=9));
+        assert_eq!(parse("bytes=-3"), ByteRange::Partial(7..=9));
+        // Ends and suffixes past the representation are clamped to it.
+        assert_eq!(parse("bytes=8-100"), ByteRange::Partial(8..=9));
+        assert_eq!(parse("bytes=-100"), ByteRange::Partial(0..=9));
+        assert_eq!(parse(" Bytes= 2 - 4 "), ByteRange::Partial(2..=4));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(parse("bytes=10-"), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=10-20"), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=-0"), ByteRange::Unsatisfiable);
+        assert_eq!(ByteRange::parse("bytes=0-", 0), ByteRange::Unsatisfiable);
+        assert_eq!(ByteRange::parse("bytes=-5", 0), ByteRange::Unsatisfiable);
+    }
+
+    // Anything we can't or won't honor is answered with the full representation.
+    #[test]
+    fn ignored_ranges() {
+        for value in [
+            "",
+            "bytes=",
+            "bytes=-",
+            "bytes=4-2",
+            "bytes=0-1,3-4",
+            "bytes=a-b",
+            "bytes=+1-2",
+            "bytes=1--2",
+            "bytes=0x1-2",
+            "items=0-4",
+            "bytes 0-4",
+            "bytes=18446744073709551616-",
+        ] {
+            assert_eq!(parse(value), ByteRange::Full, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn content_range_values() {
+        assert_eq!(ByteRange::Full.content_range(10), None);
+        assert_eq!(
+            ByteRange::Partial(2..=4).content_range(10).unwrap(),
+            "bytes 2-4/10"
+        );
+        assert_eq!(
+            ByteRange::Unsatisfiable.content_range(10).unwrap(),
+            "bytes */10"
+        );
+        let mut req = Request::test(Method::GET, "/");
+        assert_eq!(ByteRange::from_request(&req, 10), ByteRange::Full);
+        req.headers_mut()
+            .insert(header::RANGE, HeaderValue::from_static("bytes=1-2"));
+        assert_eq!(ByteRange::from_request(&req, 10), ByteRange::Partial(1..=2));
+    }
+
+    #[tokio::test]
+    async fn ranged_responses() {
+        let client = TestClient::new(service_fn(|req: Request| async move {
+            Ok::<_, ServiceError>(ranged_bytes(&req, "0123456789"))
+        }));
+        client
+            .get("/")
+            .send()
+            .await
+            .assert_status(StatusCode::OK)
+            .assert_header("accept-ranges", "bytes")
+            .assert_body("0123456789");
+        client
+            .get("/")
+            .header("range", "bytes=-4")
+            .send()
+            .await
+            .assert_status(StatusCode::PARTIAL_CONTENT)
+            .assert_header("content-range", "bytes 6-9/10")
+            .assert_header("content-length", "4")
+            .assert_body("6789");
+        client
+            .get("/")
+            .header("range", "bytes=20-")
+            .send()
+            .await
+            .assert_status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .assert_header("content-range", "bytes */10")
+            .assert_body("");
+    }
+}
@@ -0,0 +1,488 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use hyper::{
+    StatusCode,
+    header::{self, HeaderName, HeaderValue},
+};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    ConnectionInfo, Request, ResponseExt, ServiceBoxFuture, ServiceError, ServiceResponse,
+    StoreFuture, cookies::CookieJar, trace,
+};
+
+const X_RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+const X_RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+const X_RATELIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+/// Stale entries are swept from the memory store once it grows past this many keys,
+/// and again each time it doubles in size after a sweep.
+const SWEEP_THRESHOLD: usize = 4096;
+
+type KeyFn = Arc<dyn Fn(&Request) -> Option<String> + Send + Sync>;
+
+/// How requests are counted against a `RatePolicy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateAlgorithm {
+    /// Allows bursts up to `limit`, refilling evenly over `period`.
+    TokenBucket,
+    /// Counts requests in the current window, weighting the previous one by how much of it still overlaps.
+    SlidingWindow,
+}
+
+/// How many requests a key may make, and over what time.
+#[derive(Clone, Copy, Debug)]
+pub struct RatePolicy {
+    /// The counting algorithm.
+    pub algorithm: RateAlgorithm,
+    /// Requests allowed per `period`.
+    pub limit: u32,
+    /// The period the limit applies to.
+    pub period: Duration,
+}
+
+/// The outcome of counting one request.
+#[derive(Clone, Copy, Debug)]
+pub struct RateDecision {
+    /// Whether the request may proceed.
+    pub allowed: bool,
+    /// The policy's limit.
+    pub limit: u32,
+    /// Requests left before the key is limited.
+    pub remaining: u32,
+    /// Time until the key is back at its full allowance.
+    pub reset: Duration,
+    /// Time until the next request would be allowed; zero when `allowed`.
+    pub retry_after: Duration,
+}
+
+/// Counts requests per key; implement it to share limits across servers.
+pub trait RateStore: Send + Sync + 'static {
+    /// Counts one request for `key` under `policy`.
+    fn hit(&self, key: &str, policy: &RatePolicy) -> StoreFuture<RateDecision>;
+}
+
+/// Keeps counters in process memory; clones share the same counters.
+#[derive(Clone, Default)]
+pub struct MemoryRateStore {
+    entries: Arc<Mutex<Entries>>,
+}
+
+struct Entries {
+    buckets: HashMap<String, Bucket>,
+    sweep_at: usize,
+}
+
+impl Default for Entries {
+    fn default() -> Entries {
+        Entries {
+            buckets: HashMap::new(),
+            sweep_at: SWEEP_THRESHOLD,
+        }
+    }
+}
+
+enum Bucket {
+    Tokens {
+        tokens: f64,
+        updated: Instant,
+    },
+    Window {
+        start: Instant,
+        current: u32,
+        previous: u32,
+    },
+}
+
+impl MemoryRateStore {
+    /// An empty store.
+    pub fn new() -> MemoryRateStore {
+        MemoryRateStore::default()
+    }
+}
+
+impl RateStore for MemoryRateStore {
+    fn hit(&self, key: &str, policy: &RatePolicy) -> StoreFuture<RateDecision> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.buckets.len() >= entries.sweep_at {
+            entries
+                .buckets
+                .retain(|_, bucket| !bucket.is_stale(now, policy.period));
+            entries.sweep_at = (entries.buckets.len() * 2).max(SWEEP_THRESHOLD);
+        }
+        let bucket = entries
+            .buckets
+            .entry(key.to_string())
+            .or_insert_with(|| Bucket::new(policy, now));
+        let decision = bucket.hit(policy, now);
+        Box::pin(async move { Ok(decision) })
+    }
+}
+
+impl Bucket {
+    fn new(policy: &RatePolicy, now: Instant) -> Bucket {
+        match policy.algorithm {
+            RateAlgorithm::TokenBucket => Bucket::Tokens {
+                tokens: policy.limit as f64,
+                updated: now,
+            },
+            RateAlgorithm::SlidingWindow => Bucket::Window {
+                start: now,
+                current: 0,
+                previous: 0,
+            },
+        }
+    }
+
+    fn is_stale(&self, now: Instant, period: Duration) -> bool {
+        match self {
+            Bucket::Tokens { updated, .. } => now.duration_since(*updated) >= period,
+            Bucket::Window { start, .. } => now.duration_since(*start) >= period * 2,
+        }
+    }
+
+    fn hit(&mut self, policy: &RatePolicy, now: Instant) -> RateDecision {
+        // Policies built by hand can skip the checks in `RateLimitLayer::new`.
+        if policy.limit == 0 || policy.period.is_zero() {
+            return RateDecision {
+                allowed: false,
+                limit: policy.limit,
+                remaining: 0,
+                reset: policy.period,
+                retry_after: policy.period,
+            };
+        }
+        let limit = policy.limit as f64;
+        let period = policy.period.as_secs_f64();
+        match self {
+            Bucket::Tokens { tokens, updated } => {
+                let rate = limit / period;
+                *tokens = (*tokens + now.duration_since(*updated).as_secs_f64() * rate).min(limit);
+                *updated = now;
+                let allowed = *tokens >= 1.0;
+                if allowed {
+                    *tokens -= 1.0;
+                }
+                RateDecision {
+                    allowed,
+                    limit: policy.limit,
+                    remaining: tokens.floor() as u32,
+                    reset: Duration::from_secs_f64((limit - *tokens) / rate),
+                    retry_after: match allowed {
+                        true => Duration::ZERO,
+                        false => Duration::from_secs_f64((1.0 - *tokens) / rate),
+                    },
+                }
+            }
+            Bucket::Window {
+                start,
+                current,
+                previous,
+            } => {
+                let windows = now.duration_since(*start).as_secs_f64() / period;
+                if windows >= 2.0 {
+                    *previous = 0;
+                    *current = 0;
+                    *start = now;
+                } else if windows >= 1.0 {
+                    *previous = *current;
+                    *current = 0;
+                    *start += policy.period;
+                }
+                let elapsed = now.duration_since(*start);
+                let weight = 1.0 - elapsed.as_secs_f64() / period;
+                // Approximates a sliding window by weighting the previous fixed window.
+                let estimate = *previous as f64 * weight + *current as f64;
+                let allowed = estimate + 1.0 <= limit;
+                if allowed {
+                    *current += 1;
+                }
+                let used = estimate + allowed as u8 as f64;
+                let until_next_window = policy.period.saturating_sub(elapsed);
+                RateDecision {
+                    allowed,
+                    limit: policy.limit,
+                    remaining: (limit - used).max(0.0).floor() as u32,
+                    reset: until_next_window + policy.period * (*current > 0) as u32,
+                    retry_after: match allowed {
+                        true => Duration::ZERO,
+                        false => window_retry(*current, *previous, elapsed, policy),
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// How long until the weighted estimate leaves room for one more request.
+fn window_retry(current: u32, previous: u32, elapsed: Duration, policy: &RatePolicy) -> Duration {
+    let limit = policy.limit as f64;
+    let period = policy.period;
+    if current as f64 + 1.0 > limit {
+        // This window is full, so wait for it to end and decay as the previous one.
+        let weight = ((limit - 1.0) / current.max(1) as f64).clamp(0.0, 1.0);
+        period.saturating_sub(elapsed) + period.mul_f64(1.0 - weight)
+    } else {
+        let weight = ((limit - 1.0 - current as f64) / previous.max(1) as f64).clamp(0.0, 1.0);
+        period.mul_f64(1.0 - weight).saturating_sub(elapsed)
+    }
+}
+
+/// Limits requests per key, answering `429 Too Many Requests` with `Retry-After` once a key runs out.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    policy: RatePolicy,
+    key: KeyFn,
+    store: Arc<dyn RateStore>,
+}
+
+impl RateLimitLayer {
+    /// Allows `limit` requests per `period` for each client ip, using a token bucket.
+    /// Panics if `limit` or `period` is zero.
+    pub fn new(limit: u32, period: Duration) -> RateLimitLayer {
+        assert!(limit > 0, "a rate limit must allow at least one request");
+        assert!(!period.is_zero(), "a rate limit period must not be zero");
+        RateLimitLayer {
+            policy: RatePolicy {
+                algorithm: RateAlgorithm::TokenBucket,
+                limit,
+                period,
+            },
+            key: Arc::new(|req| {
+                ConnectionInfo::from_request(req)
+                    .and_then(ConnectionInfo::client_ip)
+                    .map(|ip| ip.to_string())
+            }),
+            store: Arc::new(MemoryRateStore::new()),
+        }
+    }
+
+    /// Sets the counting algorithm.
+    pub fn with_algorithm(mut self, algorithm: RateAlgorithm) -> RateLimitLayer {
+        self.policy.algorithm = algorithm;
+        self
+    }
+
+    /// Requests for which the key function returns `None` are not limited.
+    pub fn with_key<F>(mut self, key: F) -> RateLimitLayer
+    where
+        F: Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Arc::new(key);
+        self
+    }
+
+    /// Keys requests by the value of header `name`.
+    pub fn with_header_key(self, name: HeaderName) -> RateLimitLayer {
+        self.with_key(move |req| {
+            req.headers()
+                .get(&name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        })
+    }
+
+    /// Keys requests by the value of cookie `name`.
+    pub fn with_cookie_key(self, name: impl Into<String>) -> RateLimitLayer {
+        let name = name.into();
+        self.with_key(move |req| CookieJar::from_request(req).get(&name).map(str::to_string))
+    }
+
+    /// Sets where counters are kept.
+    pub fn with_store(mut self, store: impl RateStore) -> RateLimitLayer {
+        self.store = Arc::new(store);
+        self
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> RateLimit<S> {
+        RateLimit {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// The service produced by `RateLimitLayer`.
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    layer: RateLimitLayer,
+}
+
+impl<S> TowerService<Request> for RateLimit<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let Some(key) = (self.layer.key)(&req) else {
+            return Box::pin(self.inner.call(req));
+        };
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let decision = self.layer.store.hit(&key, &self.layer.policy);
+        Box::pin(async move {
+            let decision = match decision.await {
+                Ok(decision) => decision,
+                // An unavailable store fails open rather than rejecting all traffic.
+                Err(e) => {
+                    trace::warn("rate limit store failed, allowing the request", &e);
+                    return inner.call(req).await;
+                }
+            };
+            let resp = match decision.allowed {
+                true => inner.call(req).await?,
+                false => ServiceResponse::from_status(StatusCode::TOO_MANY_REQUESTS)
+                    .with_header(header::RETRY_AFTER, seconds(decision.retry_after)),
+            };
+            Ok(resp
+                .with_header(X_RATELIMIT_LIMIT, decision.limit.into())
+                .with_header(X_RATELIMIT_REMAINING, decision.remaining.into())
+                .with_header(X_RATELIMIT_RESET, seconds(decision.reset)))
+        })
+    }
+}
+
+fn seconds(duration: Duration) -> HeaderValue {
+    HeaderValue::from((duration.as_secs_f64().ceil() as u64).max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Method;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{service_fn, testing::TestRequestExt};
+
+    fn policy(algorithm: RateAlgorithm) -> RatePolicy {
+        RatePolicy {
+            algorithm,
+            limit: 2,
+            period: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn token_bucket_refills() {
+        let policy = policy(RateAlgorithm::TokenBucket);
+        let start = Instant::now();
+        let mut bucket = Bucket::new(&policy, start);
+        assert!(bucket.hit(&policy, start).allowed);
+        assert!(bucket.hit(&policy, start).allowed);
+        let denied = bucket.hit(&policy, start);
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after, Duration::from_secs(5));
+
+        let later = start + Duration::from_secs(5);
+        let decision = bucket.hit(&policy, later);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert!(!bucket.hit(&policy, later).allowed);
+    }
+
+    #[test]
+    fn sliding_window_decays() {
+        let policy = policy(RateAlgorithm::SlidingWindow);
+        let start = Instant::now();
+        let mut bucket = Bucket::new(&policy, start);
+        assert!(bucket.hit(&policy, start).allowed);
+        assert!(bucket.hit(&policy, start).allowed);
+        assert!(!bucket.hit(&policy, start).allowed);
+        // Halfway into the next window the previous one still counts for one request.
+        let later = start + Duration::from_secs(15);
+        assert!(bucket.hit(&policy, later).allowed);
+        assert!(!bucket.hit(&policy, later).allowed);
+        assert!(bucket.hit(&policy, start + Duration::from_secs(30)).allowed);
+    }
+
+    #[test]
+    fn degenerate_policies_deny() {
+        let policy = RatePolicy {
+            limit: 0,
+            ..policy(RateAlgorithm::TokenBucket)
+        };
+        let now = Instant::now();
+        let decision = Bucket::new(&policy, now).hit(&policy, now);
+        assert!(!decision.allowed);
+        assert_eq!(decision.remaining, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one request")]
+    fn zero_limit_is_rejected() {
+        RateLimitLayer::new(0, Duration::from_secs(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "period must not be zero")]
+    fn zero_period_is_rejected() {
+        RateLimitLayer::new(1, Duration::ZERO);
+    }
+
+    #[test]
+    fn sweeps_are_amortised() {
+        let store = MemoryRateStore::new();
+        let policy = policy(RateAlgorithm::TokenBucket);
+        for i in 0..SWEEP_THRESHOLD + 1 {
+            drop(store.hit(&i.to_string(), &policy));
+        }
+        // Nothing was stale, so the next sweep waits for the store to double.
+        let entries = store.entries.lock().unwrap();
+        assert_eq!(entries.buckets.len(), SWEEP_THRESHOLD + 1);
+        assert_eq!(entries.sweep_at, SWEEP_THRESHOLD * 2);
+    }
+
+    #[tokio::test]
+    async fn limited_requests_get_retry_after() {
+        let service = RateLimitLayer::new(1, Duration::from_secs(60))
+            .with_header_key(HeaderName::from_static("x-client"))
+            .layer(service_fn(|_: Request| async {
+                Ok::<_, ServiceError>(ServiceResponse::empty())
+            }));
+        let request = || {
+            let mut req = Request::test(Method::GET, "/");
+            req.headers_mut()
+                .insert("x-client", HeaderValue::from_static("a"));
+            req
+        };
+        let resp = service.clone().oneshot(request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[X_RATELIMIT_LIMIT], "1");
+        assert_eq!(resp.headers()[X_RATELIMIT_REMAINING], "0");
+
+        let resp = service.clone().oneshot(request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers()[header::RETRY_AFTER], "60");
+
+        // Requests without a key are not counted.
+        let resp = service
+            .oneshot(Request::test(Method::GET, "/"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,192 @@
+//! Swapping the acceptor of a running server, so certificates can be rotated without
+//! a restart. Only new connections see the new acceptor; established ones keep the
+//! session they negotiated.
+
+use std::{
+    io,
+    path::PathBuf,
+    sync::{Arc, RwLock, Weak},
+    time::{Duration, SystemTime},
+};
+
+use crate::{AcceptFuture, Acceptor, BoxedIo, trace};
+
+/// An acceptor that can be replaced while the server runs; clones share it. Pass
+/// `acceptor()` to `Server::with_dyn_acceptor`, then call `reload` with one built from
+/// the new certificate, or let `watch_files` do so when the files change.
+#[derive(Clone)]
+pub struct ReloadableAcceptor {
+    current: Arc<RwLock<Acceptor>>,
+}
+
+impl ReloadableAcceptor {
+    /// Starts out with `acceptor`.
+    pub fn new(acceptor: Acceptor) -> ReloadableAcceptor {
+        ReloadableAcceptor {
+            current: Arc::new(RwLock::new(acceptor)),
+        }
+    }
+
+    /// Uses `acceptor` for every connection accepted from now on.
+    pub fn reload(&self, acceptor: Acceptor) {
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = acceptor;
+    }
+
+    /// Runs the current acceptor on `stream`.
+    pub fn accept(&self, stream: BoxedIo) -> AcceptFuture {
+        let acceptor = self
+            .current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        acceptor(stream)
+    }
+
+    /// An acceptor that defers to whichever one is current when a connection arrives.
+    pub fn acceptor(&self) -> Acceptor {
+        let reloadable = self.clone();
+        Arc::new(move |stream| reloadable.accept(stream))
+    }
+
+    /// Checks the modification times of `paths` every `interval` and reloads with
+    /// `build` when any of them changes, e.g. for a certificate and its key. A failed
+    /// build keeps the current acceptor and is retried on the next change. Stops once
+    /// every clone of this acceptor is dropped. Must be called within a Tokio runtime.
+    pub fn watch_files<F>(
+        &self,
+        paths: impl IntoIterator<Item = impl Into<PathBuf>>,
+        interval: Duration,
+        build: F,
+    ) where
+        F: Fn() -> io::Result<Acceptor> + Send + 'static,
+    {
+        let paths: Vec<PathBuf> = paths.into_iter().map(Into::into).collect();
+        let current = Arc::downgrade(&self.current);
+        tokio::spawn(watch(current, paths, interval, build));
+    }
+}
+
+async fn watch<F>(
+    current: Weak<RwLock<Acceptor>>,
+    paths: Vec<PathBuf>,
+    interval: Duration,
+    build: F,
+) where
+    F: Fn() -> io::Result<Acceptor>,
+{
+    let mut seen = modified(&paths).await;
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        let Some(current) = current.upgrade() else {
+            return;
+        };
+        // A file caught mid-rotation may be missing; wait for the next look.
+        let Some(now) = modified(&paths).await else {
+            continue;
+        };
+        if seen.as_ref() == Some(&now) {
+            continue;
+        }
+        match build() {
+            Ok(acceptor) => {
+                *current.write().unwrap_or_else(|e| e.into_inner()) = acceptor;
+                seen = Some(now);
+            }
+            Err(e) => trace::warn("reloading the acceptor failed, keeping the current one", &e),
+        }
+    }
+}
+
+async fn modified(paths: &[PathBuf]) -> Option<Vec<SystemTime>> {
+    let mut times = Vec::with_capacity(paths.len());
+    for path in paths {
+        times.push(tokio::fs::metadata(path).await.ok()?.modified().ok()?);
+    }
+    Some(times)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::{acceptor_fn, random};
+
+    type Seen = Arc<Mutex<Vec<&'static str>>>;
+
+    fn tagged(seen: &Seen, tag: &'static str) -> Acceptor {
+        let seen = seen.clone();
+        acceptor_fn(move |stream: BoxedIo| {
+            seen.lock().unwrap().push(tag);
+            async move { Ok(stream) }
+        })
+    }
+
+    async fn connect(acceptor: &Acceptor) {
+        let (client, server) = tokio::io::duplex(64);
+        let (mut io, _) = acceptor(Box::new(server)).await.unwrap();
+        io.write_all(b"ok").await.unwrap();
+        drop(client);
+    }
+
+    #[tokio::test]
+    async fn reload_affects_new_connections() {
+        let seen = Seen::default();
+        let reloadable = ReloadableAcceptor::new(tagged(&seen, "old"));
+        let acceptor = reloadable.acceptor();
+        connect(&acceptor).await;
+        reloadable.clone().reload(tagged(&seen, "new"));
+        connect(&acceptor).await;
+        connect(&acceptor).await;
+        assert_eq!(*seen.lock().unwrap(), ["old", "new", "new"]);
+    }
+
+    #[tokio::test]
+    async fn watches_files() {
+        let path = std::env::temp_dir().join(format!("libserver-cert-{}", random::hex_token(8)));
+        std::fs::write(&path, "v1").unwrap();
+        let seen = Seen::default();
+        let reloadable = ReloadableAcceptor::new(tagged(&seen, "v1"));
+        let attempts = Arc::new(Mutex::new(0));
+        let (watched, counter) = (seen.clone(), attempts.clone());
+        let cert = path.clone();
+        reloadable.watch_files([&path], Duration::from_millis(10), move || {
+            *counter.lock().unwrap() += 1;
+            match std::fs::read_to_string(&cert)?.as_str() {
+                "v2" => Ok(tagged(&watched, "v2")),
+                _ => Err(io::Error::other("not a certificate")),
+            }
+        });
+
+        let touch = |contents: &str, offset: u64| {
+            std::fs::write(&path, contents).unwrap();
+            let at = SystemTime::now() + Duration::from_secs(offset);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(at)
+                .unwrap();
+        };
+        let acceptor = reloadable.acceptor();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(*attempts.lock().unwrap(), 0);
+
+        // A broken file keeps the old acceptor.
+        touch("garbage", 5);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        connect(&acceptor).await;
+        assert!(*attempts.lock().unwrap() >= 1);
+
+        touch("v2", 10);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        connect(&acceptor).await;
+        assert_eq!(*seen.lock().unwrap(), ["v1", "v2"]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+use std::borrow::Cow;
+
+use hyper::{Uri, header};
+
+use crate::{
+    Accept, Authorization, ConnectionInfo, ContentLength, ContentType, Error, IfNoneMatch,
+    QueryPairs, TypedHeader,
+    cookies::CookieJar,
+    query::{decode_component_bytes, percent_decode},
+};
+
+/// Accessors for `Request`.
+pub trait RequestExt {
+    /// The raw query string, without the `?`.
+    fn query(&self) -> Option<&str>;
+
+    /// Origin-form requests carry neither in the URI, so these fall back to the
+    /// connection (including trusted forwarding headers) and the Host header.
+    fn scheme(&self) -> &str;
+
+    /// The host and port the request was made to.
+    fn authority(&self) -> Option<&str>;
+
+    /// The path and query as sent, `/` when absent.
+    fn path_and_query(&self) -> &str;
+
+    /// The full URI, scheme and authority included, when they are known.
+    fn absolute_uri(&self) -> Option<Uri> {
+        let path = self.path_and_query();
+        format!("{}://{}{path}", self.scheme(), self.authority()?)
+            .parse()
+            .ok()
+    }
+
+    /// The decoded `key=value` pairs of the query string.
+    fn query_pairs(&self) -> QueryPairs<'_> {
+        QueryPairs::new(self.query().unwrap_or(""))
+    }
+
+    /// The cookies sent in `Cookie` headers.
+    fn cookies(&self) -> CookieJar;
+
+    /// Decodes a typed header; `None` when absent or malformed.
+    fn typed_header<H: TypedHeader>(&self) -> Option<H>;
+
+    /// The `Content-Type` header.
+    fn content_type(&self) -> Option<ContentType> {
+        self.typed_header()
+    }
+
+    /// The `Content-Length` header.
+    fn content_length(&self) -> Option<u64> {
+        self.typed_header::<ContentLength>().map(|len| len.0)
+    }
+
+    /// The `Accept` header.
+    fn accept(&self) -> Option<Accept> {
+        self.typed_header()
+    }
+
+    /// The `Authorization` header.
+    fn authorization(&self) -> Option<Authorization> {
+        self.typed_header()
+    }
+
+    /// The `If-None-Match` header.
+    fn if_none_match(&self) -> Option<IfNoneMatch> {
+        self.typed_header()
+    }
+
+    /// The percent-decoded path, with each way of treating bytes that aren't UTF-8.
+    /// Under `Utf8Policy::Reject` or `Lossy` the server has already made sure all
+    /// three agree.
+    fn decoded_path(&self) -> Result<Cow<'_, str>, Error> {
+        match self.decoded_path_bytes() {
+            Cow::Borrowed(bytes) => std::str::from_utf8(bytes).map(Cow::Borrowed),
+            Cow::Owned(bytes) => String::from_utf8(bytes)
+                .map(Cow::Owned)
+                .map_err(|e| e.utf8_error()),
+        }
+        .map_err(|_| Error::InvalidPath("path is not valid utf-8"))
+    }
+
+    /// The percent-decoded path, replacing bytes that aren't UTF-8.
+    fn decoded_path_lossy(&self) -> Cow<'_, str> {
+        match self.decoded_path_bytes() {
+            Cow::Borrowed(bytes) => String::from_utf8_lossy(bytes),
+            Cow::Owned(bytes) => Cow::Owned(String::from_utf8_lossy(&bytes).into_owned()),
+        }
+    }
+
+    /// The percent-decoded path as bytes.
+    fn decoded_path_bytes(&self) -> Cow<'_, [u8]> {
+        let path = self.path_and_query().split('?').next().unwrap_or("/");
+        match path.contains('%') {
+            true => Cow::Owned(percent_decode(path.as_bytes(), false)),
+            false => Cow::Borrowed(path.as_bytes()),
+        }
+    }
+
+    /// `query_pairs` replaces bytes that aren't UTF-8; these fail on them or keep them.
+    fn try_query_pairs(&self) -> Result<Vec<(String, String)>, Error> {
+        self.raw_query_pairs()
+            .into_iter()
+            .map(|(key, value)| Ok((String::from_utf8(key)?, String::from_utf8(value)?)))
+            .collect::<Result<_, std::string::FromUtf8Error>>()
+            .map_err(|_| Error::InvalidQuery("query is not valid utf-8"))
+    }
+
+    /// The query pairs percent-decoded to bytes.
+    fn raw_query_pairs(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.query()
+            .unwrap_or("")
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (
+                    decode_component_bytes(key).into_owned(),
+                    decode_component_bytes(value).into_owned(),
+                )
+            })
+            .collect()
+    }
+}
+
+impl<B> RequestExt for hyper::Request<B> {
+    fn query(&self) -> Option<&str> {
+        self.uri().query()
+    }
+
+    fn scheme(&self) -> &str {
+        match (
+            self.uri().scheme_str(),
+            self.extensions().get::<ConnectionInfo>(),
+        ) {
+            (_, Some(info)) if info.scheme.is_some() => info.scheme(),
+            (Some(scheme), _) => scheme,
+            (None, Some(info)) => info.scheme(),
+            (None, None) => "http",
+        }
+    }
+
+    fn authority(&self) -> Option<&str> {
+        let forwarded = self
+            .extensions()
+            .get::<ConnectionInfo>()
+            .and_then(ConnectionInfo::forwarded_host);
+        forwarded
+            .or_else(|| self.uri().authority().map(|a| a.as_str()))
+            .or_else(|| {
+                self.headers()
+                    .get(header::HOST)
+                    .and_then(|v| v.to_str().ok())
+            })
+    }
+
+    fn path_and_query(&self) -> &str {
+        self.uri().path_and_query().map_or("/", |pq| pq.as_str())
+    }
+
+    fn cookies(&self) -> CookieJar {
+        CookieJar::parse(
+            self.headers()
+                .get_all(header::COOKIE)
+                .into_iter()
+                .filter_map(|v| v.to_str().ok()),
+        )
+    }
+
+    fn typed_header<H: TypedHeader>(&self) -> Option<H> {
+        let name = H::name();
+        match self.headers().contains_key(&name) {
+            true => H::decode(self.headers().get_all(name).iter()),
+            false => None,
+        }
+    }
+}
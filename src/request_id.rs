@@ -0,0 +1,171 @@
+use std::{fmt, sync::Arc};
+
+use hyper::{
+    header::{HeaderName, HeaderValue},
+    http::request::Parts,
+};
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    Error, FromRequestParts, Request, ServiceBoxFuture, ServiceError, ServiceResponse, random,
+};
+
+/// The header `RequestIdLayer` reads and sets by default.
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// The id of the current request, added to its extensions by `RequestIdLayer`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RequestId(HeaderValue);
+
+impl RequestId {
+    /// Wraps an id value.
+    pub fn new(value: HeaderValue) -> RequestId {
+        RequestId(value)
+    }
+
+    /// The id `RequestIdLayer` attached to `req`.
+    pub fn from_request(req: &Request) -> Option<&RequestId> {
+        req.extensions().get()
+    }
+
+    /// The id as a string; empty if it isn't visible ASCII.
+    pub fn as_str(&self) -> &str {
+        self.0.to_str().unwrap_or_default()
+    }
+
+    /// The id as a header value.
+    pub fn header_value(&self) -> &HeaderValue {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromRequestParts for RequestId {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<RequestId, Error> {
+        parts
+            .extensions
+            .get()
+            .cloned()
+            .ok_or(Error::MissingExtension("RequestId"))
+    }
+}
+
+/// A random (version 4) UUID. Unique, but not unpredictable: don't use it as a secret.
+pub fn uuid_v4() -> String {
+    let mut b = [0u8; 16];
+    random::fast_fill(&mut b);
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let hex: String = b.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+type Generator = Arc<dyn Fn() -> String + Send + Sync>;
+
+/// Keeps the client's request id, or generates one, and echoes it on the response.
+#[derive(Clone)]
+pub struct RequestIdLayer {
+    header: HeaderName,
+    generator: Generator,
+}
+
+impl Default for RequestIdLayer {
+    fn default() -> RequestIdLayer {
+        RequestIdLayer::new()
+    }
+}
+
+impl RequestIdLayer {
+    /// Uses `X-Request-Id` and random UUIDs.
+    pub fn new() -> RequestIdLayer {
+        RequestIdLayer {
+            header: X_REQUEST_ID,
+            generator: Arc::new(uuid_v4),
+        }
+    }
+
+    /// Reads and writes the id in `header` instead.
+    pub fn with_header(mut self, header: HeaderName) -> RequestIdLayer {
+        self.header = header;
+        self
+    }
+
+    /// Generates new ids with `generator`.
+    pub fn with_generator(
+        mut self,
+        generator: impl Fn() -> String + Send + Sync + 'static,
+    ) -> RequestIdLayer {
+        self.generator = Arc::new(generator);
+        self
+    }
+}
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(&self, inner: S) -> RequestIdService<S> {
+        RequestIdService {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// The service produced by `RequestIdLayer`.
+#[derive(Clone)]
+pub struct RequestIdService<S> {
+    inner: S,
+    config: RequestIdLayer,
+}
+
+impl<S> TowerService<Request> for RequestIdService<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        let header = self.config.header.clone();
+        let id = match req.headers().get(&header) {
+            Some(value) if !value.is_empty() => value.clone(),
+            _ => {
+                let value = HeaderValue::from_str(&(self.config.generator)())
+                    .unwrap_or_else(|_| HeaderValue::from_static("invalid-request-id"));
+                req.headers_mut().insert(header.clone(), value.clone());
+                value
+            }
+        };
+        req.extensions_mut().insert(RequestId(id.clone()));
+
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let mut resp = fut.await?;
+            resp.headers_mut().entry(header).or_insert(id);
+            Ok(resp)
+        })
+    }
+}
@@ -0,0 +1,354 @@
+use std::{convert::Infallible, future::Future, io};
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use http_body_util::BodyExt;
+use hyper::{
+    HeaderMap, StatusCode,
+    body::Frame,
+    header::{self, HeaderName, HeaderValue},
+    http::{self, response::Builder},
+};
+
+use crate::{
+    APPLICATION_JSON, BodyInner, BodyWriter, BoxedBodyStream, Connection, Error, Json,
+    ServiceResponse, TypedHeader,
+    cookies::{Cookie, CookieJar},
+    make_body_from_stream, single_frame_body,
+    sse::{DEFAULT_KEEP_ALIVE, Event, event_stream},
+    trace, writer,
+};
+
+/// Constructors and builder methods for `ServiceResponse`.
+pub trait ResponseExt: Sized {
+    /// A 200 response with `body`.
+    fn from_bytes(body: impl Into<Bytes> + Send + 'static) -> Self;
+
+    /// A 200 response with a text body.
+    fn from_string(body: impl Into<String>) -> Self {
+        Self::from_bytes(body.into())
+    }
+
+    /// A 200 response streaming `stream` as its body.
+    fn from_stream<S>(stream: S) -> Self
+    where
+        S: Stream<Item = BodyInner> + Send + 'static;
+
+    /// A response with `status` and its reason phrase as a plain-text body.
+    fn from_status(status: StatusCode) -> Self {
+        let body = match status.canonical_reason() {
+            Some(reason) => format!("{} {reason}", status.as_u16()),
+            None => status.as_u16().to_string(),
+        };
+        Self::from_string(body).with_status(status)
+    }
+
+    /// A 200 response with an empty body.
+    fn empty() -> Self {
+        Self::from_stream(futures::stream::empty())
+    }
+
+    /// A response whose body is written through the returned writer, usually from a
+    /// spawned task while the response is already on its way to the client.
+    fn writer() -> (Self, BodyWriter) {
+        let (writer, body) = writer::channel();
+        (Self::from_stream(body), writer)
+    }
+
+    /// A 200 response with `body` and `Content-Type: application/json`.
+    fn json(body: impl Into<Bytes> + Send + 'static) -> Self {
+        Self::from_bytes(body).with_header(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(APPLICATION_JSON),
+        )
+    }
+
+    /// A Server-Sent Events response, sending a keep-alive comment while `events` is idle.
+    fn sse<S>(events: S) -> Self
+    where
+        S: Stream<Item = Event> + Send + 'static,
+    {
+        Self::from_stream(event_stream(events, Some(DEFAULT_KEEP_ALIVE)))
+            .with_header(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/event-stream"),
+            )
+            .with_header(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"))
+    }
+
+    /// Falls back to a 500 when `location` is not a valid header value.
+    fn redirect(status: StatusCode, location: &str) -> Self {
+        match HeaderValue::try_from(location) {
+            Ok(location) => Self::empty()
+                .with_status(status)
+                .with_header(header::LOCATION, location),
+            Err(_) => Self::from_status(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+
+    /// A 301 redirect to `location`.
+    fn moved_permanently(location: &str) -> Self {
+        Self::redirect(StatusCode::MOVED_PERMANENTLY, location)
+    }
+
+    /// A 302 redirect to `location`.
+    fn found(location: &str) -> Self {
+        Self::redirect(StatusCode::FOUND, location)
+    }
+
+    /// A 303 redirect to `location`.
+    fn see_other(location: &str) -> Self {
+        Self::redirect(StatusCode::SEE_OTHER, location)
+    }
+
+    /// A 307 redirect to `location`.
+    fn temporary_redirect(location: &str) -> Self {
+        Self::redirect(StatusCode::TEMPORARY_REDIRECT, location)
+    }
+
+    /// A 308 redirect to `location`.
+    fn permanent_redirect(location: &str) -> Self {
+        Self::redirect(StatusCode::PERMANENT_REDIRECT, location)
+    }
+
+    /// Sets the status code.
+    fn with_status(self, status: StatusCode) -> Self;
+
+    /// Sets a header, replacing any existing values.
+    fn with_header(self, name: HeaderName, value: HeaderValue) -> Self;
+
+    /// Appends a `Set-Cookie` header for `cookie`.
+    fn with_cookie(self, cookie: &Cookie) -> Self;
+
+    /// Sends the headers `trailers` resolves to after the last body frame. Over
+    /// HTTP/1.1 this forces chunked encoding, and only fields named in a `Trailer`
+    /// response header reach clients that sent `TE: trailers`.
+    fn with_trailers<F>(self, trailers: F) -> Self
+    where
+        F: Future<Output = HeaderMap> + Send + 'static;
+
+    /// Sets a typed header, replacing any existing values.
+    fn with_typed_header<H: TypedHeader>(self, header: &H) -> Self {
+        self.with_header(H::name(), header.encode())
+    }
+
+    /// Asks an HTTP/1 client to close the connection once this response is read,
+    /// and has the server close it after sending.
+    fn with_connection_close(self) -> Self {
+        self.with_typed_header(&Connection::close())
+    }
+
+    /// Appends a `Set-Cookie` header for every cookie the jar added or removed.
+    fn with_cookies(self, jar: &CookieJar) -> Self {
+        jar.delta()
+            .fold(self, |resp, cookie| resp.with_cookie(cookie))
+    }
+
+    /// Wraps or replaces the body's frame stream. Headers are left alone, so a
+    /// transform that changes the length must fix up `Content-Length` itself.
+    fn map_body<F, S>(self, f: F) -> Self
+    where
+        F: FnOnce(BoxedBodyStream) -> S,
+        S: Stream<Item = BodyInner> + Send + 'static;
+
+    /// Reads the whole body into memory, discarding any trailers.
+    fn into_bytes(self) -> impl Future<Output = io::Result<Bytes>> + Send;
+}
+
+impl ResponseExt for ServiceResponse {
+    /// The length is known up front, so it is declared instead of chunk-encoding a
+    /// single frame.
+    fn from_bytes(body: impl Into<Bytes> + Send + 'static) -> Self {
+        let body = body.into();
+        let len = HeaderValue::from(body.len());
+        ServiceResponse::new(single_frame_body(body)).with_header(header::CONTENT_LENGTH, len)
+    }
+
+    fn from_stream<S>(stream: S) -> Self
+    where
+        S: Stream<Item = BodyInner> + Send + 'static,
+    {
+        ServiceResponse::new(make_body_from_stream(stream))
+    }
+
+    fn with_status(mut self, status: StatusCode) -> Self {
+        *self.status_mut() = status;
+        self
+    }
+
+    fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers_mut().insert(name, value);
+        self
+    }
+
+    fn with_cookie(mut self, cookie: &Cookie) -> Self {
+        match cookie.to_header_value() {
+            Some(value) => {
+                self.headers_mut().append(header::SET_COOKIE, value);
+            }
+            None => trace::invalid_cookie(cookie.name()),
+        }
+        self
+    }
+
+    fn with_trailers<F>(self, trailers: F) -> Self
+    where
+        F: Future<Output = HeaderMap> + Send + 'static,
+    {
+        let (mut parts, body) = self.into_parts();
+        parts.headers.remove(header::CONTENT_LENGTH);
+        let body = make_body_from_stream(body.chain(futures::stream::once(async move {
+            Ok(Frame::trailers(trailers.await))
+        })));
+        ServiceResponse::from_parts(parts, body)
+    }
+
+    fn map_body<F, S>(self, f: F) -> Self
+    where
+        F: FnOnce(BoxedBodyStream) -> S,
+        S: Stream<Item = BodyInner> + Send + 'static,
+    {
+        self.map(|body| make_body_from_stream(f(Box::new(body))))
+    }
+
+    async fn into_bytes(self) -> io::Result<Bytes> {
+        Ok(BodyExt::collect(self.into_body()).await?.to_bytes())
+    }
+}
+
+const TEXT_PLAIN: HeaderValue = HeaderValue::from_static("text/plain; charset=utf-8");
+const OCTET_STREAM: HeaderValue = HeaderValue::from_static("application/octet-stream");
+
+/// Conversion into a response, for values handlers return.
+pub trait IntoResponse {
+    /// The response for this value.
+    fn into_response(self) -> ServiceResponse;
+}
+
+impl IntoResponse for ServiceResponse {
+    fn into_response(self) -> ServiceResponse {
+        self
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> ServiceResponse {
+        Error::into_response(self)
+    }
+}
+
+impl IntoResponse for Infallible {
+    fn into_response(self) -> ServiceResponse {
+        match self {}
+    }
+}
+
+impl IntoResponse for () {
+    fn into_response(self) -> ServiceResponse {
+        ServiceResponse::empty()
+    }
+}
+
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> ServiceResponse {
+        ServiceResponse::from_status(self)
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> ServiceResponse {
+        ServiceResponse::from_string(self).with_header(header::CONTENT_TYPE, TEXT_PLAIN)
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> ServiceResponse {
+        ServiceResponse::from_bytes(self).with_header(header::CONTENT_TYPE, TEXT_PLAIN)
+    }
+}
+
+impl IntoResponse for &'static [u8] {
+    fn into_response(self) -> ServiceResponse {
+        Bytes::from_static(self).into_response()
+    }
+}
+
+impl IntoResponse for Bytes {
+    fn into_response(self) -> ServiceResponse {
+        ServiceResponse::from_bytes(self).with_header(header::CONTENT_TYPE, OCTET_STREAM)
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> ServiceResponse {
+        Bytes::from(self).into_response()
+    }
+}
+
+impl<T: Into<Bytes> + Send + 'static> IntoResponse for Json<T> {
+    fn into_response(self) -> ServiceResponse {
+        ServiceResponse::json(self.0)
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for (StatusCode, T) {
+    fn into_response(self) -> ServiceResponse {
+        self.1.into_response().with_status(self.0)
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for (StatusCode, HeaderMap, T) {
+    fn into_response(self) -> ServiceResponse {
+        let mut resp = self.2.into_response().with_status(self.0);
+        resp.headers_mut().extend(self.1);
+        resp
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self) -> ServiceResponse {
+        match self {
+            Ok(value) => value.into_response(),
+            Err(e) => e.into_response(),
+        }
+    }
+}
+
+/// Finishes an `http::response::Builder` with a body.
+pub trait BuilderExt {
+    /// Finishes the response with `body`, setting `Content-Length` unless already set.
+    fn bytes(self, body: impl Into<Bytes> + Send + 'static) -> http::Result<ServiceResponse>;
+
+    /// Finishes the response streaming `stream` as its body.
+    fn stream<S>(self, stream: S) -> http::Result<ServiceResponse>
+    where
+        S: Stream<Item = BodyInner> + Send + 'static;
+
+    /// Finishes the response with an empty body.
+    fn empty(self) -> http::Result<ServiceResponse>;
+}
+
+impl BuilderExt for Builder {
+    fn bytes(self, body: impl Into<Bytes> + Send + 'static) -> http::Result<ServiceResponse> {
+        let body = body.into();
+        let declared = self
+            .headers_ref()
+            .is_some_and(|headers| headers.contains_key(header::CONTENT_LENGTH));
+        match declared {
+            true => self,
+            false => self.header(header::CONTENT_LENGTH, body.len()),
+        }
+        .body(single_frame_body(body))
+    }
+
+    fn stream<S>(self, stream: S) -> http::Result<ServiceResponse>
+    where
+        S: Stream<Item = BodyInner> + Send + 'static,
+    {
+        self.body(make_body_from_stream(stream))
+    }
+
+    fn empty(self) -> http::Result<ServiceResponse> {
+        self.stream(futures::stream::empty())
+    }
+}
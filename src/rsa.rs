@@ -0,0 +1,120 @@
+use crate::{bigint::BigUint, sha256::sha256};
+
+/// DER prefix of a PKCS #1 DigestInfo for SHA-256.
+const SHA256_DIGEST_INFO: [u8; 19] = [
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RsaPublicKey {
+    n: BigUint,
+    e: BigUint,
+}
+
+impl RsaPublicKey {
+    /// Keys shorter than 2048 bits are refused.
+    pub(crate) fn new(n: &[u8], e: &[u8]) -> Option<RsaPublicKey> {
+        let n = BigUint::from_be_bytes(n);
+        let e = BigUint::from_be_bytes(e);
+        (n.bits() >= 2048 && e.bits() > 1 && e.bit(0)).then_some(RsaPublicKey { n, e })
+    }
+
+    /// RSASSA-PKCS1-v1_5 with SHA-256.
+    pub(crate) fn verify_sha256(&self, message: &[u8], signature: &[u8]) -> bool {
+        let k = self.n.bits().div_ceil(8);
+        if signature.len() != k {
+            return false;
+        }
+        let s = BigUint::from_be_bytes(signature);
+        if s >= self.n {
+            return false;
+        }
+        let em = s.mod_pow(&self.e, &self.n).to_be_bytes(k);
+        let mut expected = vec![0x00, 0x01];
+        expected.resize(k - SHA256_DIGEST_INFO.len() - 32 - 1, 0xff);
+        expected.push(0x00);
+        expected.extend_from_slice(&SHA256_DIGEST_INFO);
+        expected.extend_from_slice(&sha256(message));
+        em == expected
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::base64;
+
+    // A 2048-bit key and a token signed with it by an independent implementation
+    // (Python's `cryptography`).
+    pub(crate) const RSA_N: &str = concat!(
+        "vk1BGeWKx-3H7z09ZXb5XHC0hHPHwR22PawPvSLARoVI_z1lSQAUH284h6QYURkk",
+        "WSzzCMDvUT1bvKyikMxNYExxxhEZrpZWPl5L5_eYyNbCY1XuN2oLQHHgB1t04Ec3",
+        "_9gfxVZ6u-3yQArLJibOkOcRSSdfRDvMbuGMfId1mdBCZksJZVO7tlCqbnLYpQJ4",
+        "8A9T_XcQpkLS_fpMXG89s3JZbVQOwcOWxWfDCMJNw3gie0NJMqBtuFQim5X9um6F",
+        "n0tjhgtnk_GAhgUEORJ2IDSID7SW_iawss-2d-Z3Hy3kh3fqZBXWz1e7s9XSEE8F",
+        "wQDFnnMZ47UQohKV7U-GPQ",
+    );
+    pub(crate) const RSA_E: &str = "AQAB";
+    pub(crate) const RS256_TOKEN: &str = concat!(
+        "eyJhbGciOiJSUzI1NiIsInR5cCI6IkpXVCJ9.eyJzdWIiOiJhbGljZSIsImV4cCI",
+        "6NDEwMjQ0NDgwMH0.fXU9z5DVK2GnVHSXDQgL0moi8Iv5I8aAmR7Xb4imwxh8PKI",
+        "qVSlI7nQyte_WqUqskhwJjLdYzZJzX5Pjq-UJZ6_lgjPNNm-UaJQw91F4mWvytR1",
+        "UT-MFrxUSbIG27_ta9MD5MYicw1WLVtqqURy9jxYSgbwDwi4KJhLdMD3FS7ZXkcq",
+        "Xt7eMhottUIreZxLjh_LpKfmpmUeuPkjwb3sJmYF5jOXIe5nt5KbfJnWo_mz8UL4",
+        "mtZETcncEkkBLS3CxxMBiDllTjfglESZyTrw0YMzn3m42KoOFqTMAo7J5OaC_rLn",
+        "f31XU-6ibZruzHXx3-Ez2q79W41eg_Q1h4RHV7w",
+    );
+
+    fn key() -> RsaPublicKey {
+        RsaPublicKey::new(
+            &base64::decode(RSA_N).unwrap(),
+            &base64::decode(RSA_E).unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn split(token: &str) -> (&[u8], Vec<u8>) {
+        let (message, signature) = token.rsplit_once('.').unwrap();
+        (message.as_bytes(), base64::decode(signature).unwrap())
+    }
+
+    #[test]
+    fn verifies_a_known_signature() {
+        let (message, signature) = split(RS256_TOKEN);
+        assert!(key().verify_sha256(message, &signature));
+    }
+
+    #[test]
+    fn rejects_tampered_messages_and_signatures() {
+        let key = key();
+        let (message, signature) = split(RS256_TOKEN);
+        let mut tampered = message.to_vec();
+        tampered[0] ^= 1;
+        assert!(!key.verify_sha256(&tampered, &signature));
+        let mut flipped = signature.clone();
+        flipped[100] ^= 1;
+        assert!(!key.verify_sha256(message, &flipped));
+        assert!(!key.verify_sha256(message, &signature[1..]));
+        let mut longer = vec![0];
+        longer.extend_from_slice(&signature);
+        assert!(!key.verify_sha256(message, &longer));
+    }
+
+    #[test]
+    fn rejects_signatures_not_below_the_modulus() {
+        let n = base64::decode(RSA_N).unwrap();
+        let (message, _) = split(RS256_TOKEN);
+        assert!(!key().verify_sha256(message, &n));
+        assert!(!key().verify_sha256(message, &vec![0xff; n.len()]));
+    }
+
+    #[test]
+    fn refuses_weak_keys() {
+        let n = base64::decode(RSA_N).unwrap();
+        assert!(RsaPublicKey::new(&n[1..], &[1, 0, 1]).is_none());
+        assert!(RsaPublicKey::new(&n, &[1]).is_none());
+        assert!(RsaPublicKey::new(&n, &[1, 0, 0]).is_none());
+        assert!(RsaPublicKey::new(&n, &[3]).is_some());
+    }
+}
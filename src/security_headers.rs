@@ -0,0 +1,178 @@
+use std::{sync::Arc, time::Duration};
+
+use hyper::header::{self, HeaderName, HeaderValue};
+use tower::{Layer, Service as TowerService};
+
+use crate::{Request, ServiceBoxFuture, ServiceError, ServiceResponse};
+
+const PERMISSIONS_POLICY: HeaderName = HeaderName::from_static("permissions-policy");
+
+/// The value of `X-Frame-Options`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameOptions {
+    /// The page may not be framed at all.
+    Deny,
+    /// Only pages of the same origin may frame it.
+    SameOrigin,
+}
+
+/// Adds hardening headers to every response that doesn't already carry them. By
+/// default that is `X-Content-Type-Options: nosniff`, `X-Frame-Options: DENY` and a
+/// `strict-origin-when-cross-origin` referrer policy; HSTS, CSP and a permissions
+/// policy are opt-in since they depend on the site.
+#[derive(Clone)]
+pub struct SecurityHeadersLayer {
+    headers: Arc<[(HeaderName, HeaderValue)]>,
+}
+
+impl Default for SecurityHeadersLayer {
+    fn default() -> SecurityHeadersLayer {
+        SecurityHeadersLayer::new()
+    }
+}
+
+impl SecurityHeadersLayer {
+    /// The default headers; see `SecurityHeadersLayer`.
+    pub fn new() -> SecurityHeadersLayer {
+        SecurityHeadersLayer {
+            headers: Arc::from([]),
+        }
+        .with_header(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        )
+        .with_frame_options(Some(FrameOptions::Deny))
+        .with_referrer_policy("strict-origin-when-cross-origin")
+    }
+
+    /// Replaces any earlier value for the same header.
+    pub fn with_header(self, name: HeaderName, value: HeaderValue) -> SecurityHeadersLayer {
+        let mut layer = self.without_header(name.clone());
+        let mut headers = layer.headers.to_vec();
+        headers.push((name, value));
+        layer.headers = headers.into();
+        layer
+    }
+
+    /// Stops sending header `name`.
+    pub fn without_header(mut self, name: HeaderName) -> SecurityHeadersLayer {
+        let headers: Vec<_> = self
+            .headers
+            .iter()
+            .filter(|(n, _)| *n != name)
+            .cloned()
+            .collect();
+        self.headers = headers.into();
+        self
+    }
+
+    /// Browsers only honour HSTS over HTTPS, and preload lists require at least a
+    /// year with subdomains included.
+    pub fn with_hsts(
+        self,
+        max_age: Duration,
+        include_subdomains: bool,
+        preload: bool,
+    ) -> SecurityHeadersLayer {
+        let mut value = format!("max-age={}", max_age.as_secs());
+        if include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if preload {
+            value.push_str("; preload");
+        }
+        self.with_header(
+            header::STRICT_TRANSPORT_SECURITY,
+            HeaderValue::from_str(&value).expect("valid header value"),
+        )
+    }
+
+    /// Like the other policy setters, panics if `policy` isn't a valid header value.
+    pub fn with_content_security_policy(self, policy: &str) -> SecurityHeadersLayer {
+        self.with_policy(header::CONTENT_SECURITY_POLICY, policy)
+    }
+
+    /// Sets `Content-Security-Policy-Report-Only`, reporting violations without enforcing.
+    pub fn with_csp_report_only(self, policy: &str) -> SecurityHeadersLayer {
+        self.with_policy(header::CONTENT_SECURITY_POLICY_REPORT_ONLY, policy)
+    }
+
+    /// Sets `Referrer-Policy`.
+    pub fn with_referrer_policy(self, policy: &str) -> SecurityHeadersLayer {
+        self.with_policy(header::REFERRER_POLICY, policy)
+    }
+
+    /// Sets `Permissions-Policy`.
+    pub fn with_permissions_policy(self, policy: &str) -> SecurityHeadersLayer {
+        self.with_policy(PERMISSIONS_POLICY, policy)
+    }
+
+    /// `None` leaves framing to the CSP's `frame-ancestors`.
+    pub fn with_frame_options(self, options: Option<FrameOptions>) -> SecurityHeadersLayer {
+        match options {
+            Some(FrameOptions::Deny) => {
+                self.with_header(header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY"))
+            }
+            Some(FrameOptions::SameOrigin) => self.with_header(
+                header::X_FRAME_OPTIONS,
+                HeaderValue::from_static("SAMEORIGIN"),
+            ),
+            None => self.without_header(header::X_FRAME_OPTIONS),
+        }
+    }
+
+    fn with_policy(self, name: HeaderName, policy: &str) -> SecurityHeadersLayer {
+        let value = HeaderValue::from_str(policy)
+            .unwrap_or_else(|_| panic!("invalid value for {name}: {policy:?}"));
+        self.with_header(name, value)
+    }
+}
+
+impl<S> Layer<S> for SecurityHeadersLayer {
+    type Service = SecurityHeaders<S>;
+
+    fn layer(&self, inner: S) -> SecurityHeaders<S> {
+        SecurityHeaders {
+            inner,
+            headers: self.headers.clone(),
+        }
+    }
+}
+
+/// The service produced by `SecurityHeadersLayer`.
+#[derive(Clone)]
+pub struct SecurityHeaders<S> {
+    inner: S,
+    headers: Arc<[(HeaderName, HeaderValue)]>,
+}
+
+impl<S> TowerService<Request> for SecurityHeaders<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let fut = self.inner.call(req);
+        let headers = self.headers.clone();
+        Box::pin(async move {
+            let mut resp = fut.await?;
+            for (name, value) in headers.iter() {
+                resp.headers_mut()
+                    .entry(name)
+                    .or_insert_with(|| value.clone());
+            }
+            Ok(resp)
+        })
+    }
+}
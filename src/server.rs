@@ -0,0 +1,928 @@
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, atomic::Ordering},
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::path::Path;
+
+use futures::StreamExt;
+use hyper::{Version, body::Incoming};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, ToSocketAddrs},
+    runtime::Handle,
+    sync::{mpsc, watch},
+    task::JoinSet,
+};
+use tower::{Service as TowerService, ServiceExt};
+
+#[cfg(unix)]
+use crate::listener::UnixSocket;
+use crate::{
+    Body, BodyLimit, BodyTimeouts, ConnectionInfo, ConnectionLimits, Error, ErrorContext,
+    ErrorHandler, Extensions, HeaderLimit, Health, Http1Config, Http2Config, ListenerConfig,
+    Request, RequestEnd, ResponseExt, RuntimeConfig, ServiceBoxFuture, ServiceError,
+    ServiceResponse, State, TlsSession, Utf8Policy,
+    error::{self, ServerErrorHandler},
+    hooks::{DisconnectGuard, Hooks, PendingEnd},
+    keep_alive::KeepAlive,
+    limits::{ConnectionLimiter, PendingPermit},
+    listener::{self, AcceptErrorKind, Listener},
+    make_body_from_stream, normalize, trace,
+};
+
+type ShutdownSignal = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+type Accepted = (BoxedIo, ConnectionInfo, PendingPermit, Handle);
+type AcceptErrorHandler = Arc<dyn Fn(&io::Error) + Send + Sync>;
+
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(10);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+/// A connection stream the server can serve: any async byte stream.
+pub trait Io: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Io for T {}
+
+/// A type-erased connection stream.
+pub type BoxedIo = Box<dyn Io>;
+/// The future an `Acceptor` returns.
+pub type AcceptFuture =
+    Pin<Box<dyn Future<Output = io::Result<(BoxedIo, TlsSession)>> + Send + 'static>>;
+/// Wraps each accepted stream before HTTP is spoken on it, e.g. for a TLS handshake.
+pub type Acceptor = Arc<dyn Fn(BoxedIo) -> AcceptFuture + Send + Sync + 'static>;
+
+/// Erases an acceptor's stream type.
+pub fn acceptor_fn<F, Fut, I>(acceptor: F) -> Acceptor
+where
+    F: Fn(BoxedIo) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = io::Result<I>> + Send + 'static,
+    I: Io,
+{
+    tls_acceptor_fn(move |stream| {
+        let fut = acceptor(stream);
+        async move { Ok((fut.await?, TlsSession::new())) }
+    })
+}
+
+/// Erases the stream type of an acceptor that also reports its handshake, e.g. the
+/// client certificate it verified.
+pub fn tls_acceptor_fn<F, Fut, I>(acceptor: F) -> Acceptor
+where
+    F: Fn(BoxedIo) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = io::Result<(I, TlsSession)>> + Send + 'static,
+    I: Io,
+{
+    Arc::new(move |stream| {
+        let fut = acceptor(stream);
+        Box::pin(async move {
+            let (io, session) = fut.await?;
+            Ok((Box::new(io) as BoxedIo, session))
+        })
+    })
+}
+
+/// An HTTP server: listeners plus connection settings, run with `serve` or `run`.
+pub struct Server {
+    listeners: Vec<Listener>,
+    acceptor: Option<Acceptor>,
+    http1: Http1Config,
+    http2: Option<Http2Config>,
+    shutdown: Option<ShutdownSignal>,
+    drain_timeout: Option<Duration>,
+    header_read_timeout: Option<Duration>,
+    body_timeouts: BodyTimeouts,
+    limits: ConnectionLimits,
+    health: Option<Health>,
+    on_error: Option<ErrorHandler>,
+    on_accept_error: Option<AcceptErrorHandler>,
+    hooks: Hooks,
+    body_limit: Option<BodyLimit>,
+    header_limit: Option<HeaderLimit>,
+    normalize_paths: bool,
+    utf8_policy: Utf8Policy,
+    runtime: RuntimeConfig,
+    state: Extensions,
+}
+
+impl Server {
+    /// Binds a TCP listener to `addr` with the default socket options.
+    pub async fn bind(addr: impl ToSocketAddrs) -> io::Result<Server> {
+        Ok(Server::from_listener(TcpListener::bind(addr).await?))
+    }
+
+    /// Binds with explicit socket options, e.g. `SO_REUSEPORT` with several acceptors.
+    pub async fn bind_with(addr: impl ToSocketAddrs, config: ListenerConfig) -> io::Result<Server> {
+        let mut last_err = None;
+        for addr in tokio::net::lookup_host(addr).await? {
+            match listener::bind_tcp(addr, &config) {
+                Ok(listeners) => return Ok(Server::with_listeners(listeners)),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any address",
+            )
+        }))
+    }
+
+    /// For `Server::run`, which provides the runtime.
+    pub fn bind_blocking(
+        addr: impl std::net::ToSocketAddrs,
+        config: ListenerConfig,
+    ) -> io::Result<Server> {
+        let mut last_err = None;
+        for addr in addr.to_socket_addrs()? {
+            match listener::bind_detached(addr, &config) {
+                Ok(listeners) => return Ok(Server::with_listeners(listeners)),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any address",
+            )
+        }))
+    }
+
+    /// Binds a Unix domain socket at `path`.
+    #[cfg(unix)]
+    pub async fn bind_unix(path: impl AsRef<Path>) -> io::Result<Server> {
+        let socket = UnixSocket::bind(path.as_ref()).await?;
+        Ok(Server::with_listeners(vec![Listener::Unix(socket)]))
+    }
+
+    /// Serves on an already bound TCP listener.
+    pub fn from_listener(listener: TcpListener) -> Server {
+        Server::with_listeners(vec![Listener::Tcp(listener, ListenerConfig::default())])
+    }
+
+    pub(crate) fn with_listeners(listeners: Vec<Listener>) -> Server {
+        Server {
+            listeners,
+            acceptor: None,
+            http1: Http1Config::default(),
+            http2: Some(Http2Config::default()),
+            shutdown: None,
+            drain_timeout: None,
+            header_read_timeout: Some(Duration::from_secs(30)),
+            body_timeouts: BodyTimeouts::default(),
+            limits: ConnectionLimits::default(),
+            health: None,
+            on_error: None,
+            on_accept_error: None,
+            hooks: Hooks::default(),
+            body_limit: None,
+            header_limit: None,
+            normalize_paths: true,
+            utf8_policy: Utf8Policy::default(),
+            runtime: RuntimeConfig::default(),
+            state: Extensions::new(),
+        }
+    }
+
+    /// The address of the first listener.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listeners[0].local_addr()
+    }
+
+    /// Sets the file mode of the Unix socket the server is bound to.
+    #[cfg(unix)]
+    pub fn set_unix_permissions(&self, mode: u32) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        match &self.listeners[0] {
+            Listener::Unix(socket) => {
+                std::fs::set_permissions(socket.path(), std::fs::Permissions::from_mode(mode))
+            }
+            Listener::Tcp(..) | Listener::Detached(..) | Listener::Memory(_) => {
+                Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "server is not bound to a unix socket",
+                ))
+            }
+        }
+    }
+
+    /// Runs `acceptor` on every accepted stream and serves HTTP on what it returns. A failed accept closes that connection only.
+    pub fn with_acceptor<F, Fut, I>(self, acceptor: F) -> Server
+    where
+        F: Fn(BoxedIo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = io::Result<I>> + Send + 'static,
+        I: Io,
+    {
+        self.with_dyn_acceptor(acceptor_fn(acceptor))
+    }
+
+    /// Like `with_acceptor`, for acceptors that verify client certificates: the
+    /// `TlsSession` they return is exposed as `ConnectionInfo::peer_certificate`.
+    pub fn with_tls_acceptor<F, Fut, I>(self, acceptor: F) -> Server
+    where
+        F: Fn(BoxedIo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = io::Result<(I, TlsSession)>> + Send + 'static,
+        I: Io,
+    {
+        self.with_dyn_acceptor(tls_acceptor_fn(acceptor))
+    }
+
+    /// Like `with_acceptor`, for an already type-erased acceptor such as
+    /// `ReloadableAcceptor::acceptor`.
+    pub fn with_dyn_acceptor(mut self, acceptor: Acceptor) -> Server {
+        self.acceptor = Some(acceptor);
+        self
+    }
+
+    /// Sets the HTTP/1 connection settings.
+    pub fn with_http1(mut self, config: Http1Config) -> Server {
+        self.http1 = config;
+        self
+    }
+
+    /// Sets the HTTP/2 connection settings.
+    pub fn with_http2(mut self, config: Http2Config) -> Server {
+        self.http2 = Some(config);
+        self
+    }
+
+    /// Serves HTTP/1 only, without HTTP/2 prior knowledge.
+    pub fn with_http1_only(mut self) -> Server {
+        self.http2 = None;
+        self
+    }
+
+    /// Stops accepting once `signal` completes, then waits for open connections to finish.
+    pub fn with_graceful_shutdown(
+        mut self,
+        signal: impl Future<Output = ()> + Send + 'static,
+    ) -> Server {
+        self.shutdown = Some(Box::pin(signal));
+        self
+    }
+
+    /// Bounds how long a graceful shutdown waits before dropping remaining connections.
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Server {
+        self.drain_timeout = Some(timeout);
+        self
+    }
+
+    /// Closes connections that don't send a complete request head within `timeout`; 30 seconds by default, `None` to disable.
+    pub fn with_header_read_timeout(mut self, timeout: Option<Duration>) -> Server {
+        self.header_read_timeout = timeout;
+        self
+    }
+
+    /// Sets the timeouts attached to every request body, read by `collect_request` and friends.
+    pub fn with_body_timeouts(mut self, timeouts: BodyTimeouts) -> Server {
+        self.body_timeouts = timeouts;
+        self
+    }
+
+    /// Caps how many connections are served at once.
+    pub fn with_connection_limits(mut self, limits: ConnectionLimits) -> Server {
+        self.limits = limits;
+        self
+    }
+
+    /// Default limit for request bodies read through `request_frames`; routes can
+    /// override it with `BodyLimitLayer`.
+    pub fn with_body_limit(mut self, limit: BodyLimit) -> Server {
+        self.body_limit = Some(limit);
+        self
+    }
+
+    /// Requests over the limits are answered with 431 or 414 and never reach the
+    /// service.
+    pub fn with_header_limit(mut self, limit: HeaderLimit) -> Server {
+        self.header_limit = Some(limit);
+        self
+    }
+
+    /// On by default: request paths are normalized before the service sees them,
+    /// with the original kept as `RawPath`. Requests whose path contains an escaped
+    /// NUL are answered with 400.
+    pub fn with_path_normalization(mut self, enabled: bool) -> Server {
+        self.normalize_paths = enabled;
+        self
+    }
+
+    /// Percent-escapes in the path or query that don't decode to UTF-8 are passed
+    /// through by default. Lossy replacement rewrites the request URI, keeping the
+    /// original path as `RawPath`.
+    pub fn with_utf8_policy(mut self, policy: Utf8Policy) -> Server {
+        self.utf8_policy = policy;
+        self
+    }
+
+    /// Only used by `run`; `serve` runs on the caller's runtime.
+    pub fn with_runtime(mut self, config: RuntimeConfig) -> Server {
+        self.runtime = config;
+        self
+    }
+
+    /// Readiness reports "draining" from the moment graceful shutdown begins.
+    pub fn with_health(mut self, health: Health) -> Server {
+        self.health = Some(health);
+        self
+    }
+
+    /// Renders crate errors that reach the connection, and those of any `Service`
+    /// built without its own error mapper.
+    pub fn on_error<F>(mut self, handler: F) -> Server
+    where
+        F: Fn(Error, &ErrorContext) -> ServiceResponse + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(handler));
+        self
+    }
+
+    /// Called for every failed `accept()`. The server keeps accepting either way:
+    /// aborted handshakes are retried at once, anything else (such as running out of
+    /// file descriptors) pauses the acceptor with a backoff of up to a second.
+    pub fn on_accept_error<F>(mut self, handler: F) -> Server
+    where
+        F: Fn(&io::Error) + Send + Sync + 'static,
+    {
+        self.on_accept_error = Some(Arc::new(handler));
+        self
+    }
+
+    /// Runs once a connection is established (after the acceptor, if any). Values
+    /// added to `extensions` are copied into every request on the connection, which
+    /// makes them a place to tag connections.
+    pub fn on_connect<F>(mut self, hook: F) -> Server
+    where
+        F: Fn(&ConnectionInfo, &mut Extensions) + Send + Sync + 'static,
+    {
+        self.hooks.on_connect = Some(Arc::new(hook));
+        self
+    }
+
+    /// Runs when a connection closes.
+    pub fn on_disconnect<F>(mut self, hook: F) -> Server
+    where
+        F: Fn(&ConnectionInfo) + Send + Sync + 'static,
+    {
+        self.hooks.on_disconnect = Some(Arc::new(hook));
+        self
+    }
+
+    /// Runs when a request arrives, before routing.
+    pub fn on_request_start<F>(mut self, hook: F) -> Server
+    where
+        F: Fn(&Request) + Send + Sync + 'static,
+    {
+        self.hooks.on_request_start = Some(Arc::new(hook));
+        self
+    }
+
+    /// Runs when the response body is done, so `latency` covers streaming it.
+    pub fn on_request_end<F>(mut self, hook: F) -> Server
+    where
+        F: Fn(&RequestEnd) + Send + Sync + 'static,
+    {
+        self.hooks.on_request_end = Some(Arc::new(hook));
+        self
+    }
+
+    /// Makes `state` available to every router and service as `State<T>`. Calling this
+    /// again with the same `T` replaces the earlier value.
+    pub fn with_state<T: Send + Sync + 'static>(mut self, state: Arc<T>) -> Server {
+        self.state.insert(State(state));
+        self
+    }
+
+    /// Serves until the shutdown signal fires. Fails once no listener is accepting any
+    /// more, e.g. because none could be registered with the runtime; connections
+    /// already open are drained first either way.
+    pub async fn serve<S>(self, service: S) -> io::Result<()>
+    where
+        S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.serve_on(service, vec![Handle::current()]).await
+    }
+
+    /// Builds the runtimes set with `with_runtime` and serves on them until the
+    /// server shuts down. Must not be called from within a runtime. TCP listeners
+    /// bound under another runtime are moved over; other listeners can't be.
+    pub fn run<S>(mut self, service: S) -> io::Result<()>
+    where
+        S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        let config = self.runtime;
+        self.listeners = std::mem::take(&mut self.listeners)
+            .into_iter()
+            .map(Listener::detach)
+            .collect::<io::Result<_>>()?;
+
+        if !config.per_worker {
+            let mut builder = tokio::runtime::Builder::new_multi_thread();
+            if let Some(threads) = config.worker_threads {
+                builder.worker_threads(threads);
+            }
+            if let Some(threads) = config.max_blocking_threads {
+                builder.max_blocking_threads(threads);
+            }
+            return builder.enable_all().build()?.block_on(self.serve(service));
+        }
+
+        let workers = config.workers();
+        if self.listeners.len() < workers {
+            let (addr, mut listener_config) = match &self.listeners[0] {
+                Listener::Detached(listener, config) => (listener.local_addr()?, *config),
+                _ => unreachable!("listeners were detached above"),
+            };
+            listener_config.acceptors = workers - self.listeners.len();
+            self.listeners
+                .extend(listener::bind_detached(addr, &listener_config)?);
+        }
+
+        let mut handles = Vec::with_capacity(workers);
+        let mut threads = Vec::with_capacity(workers);
+        for i in 0..workers {
+            let mut builder = tokio::runtime::Builder::new_current_thread();
+            if let Some(threads) = config.max_blocking_threads {
+                builder.max_blocking_threads(threads);
+            }
+            let runtime = builder.enable_all().build()?;
+            handles.push(runtime.handle().clone());
+            let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+            let thread = std::thread::Builder::new()
+                .name(format!("libserver-worker-{i}"))
+                .spawn(move || {
+                    runtime.block_on(stopped).ok();
+                })?;
+            threads.push((stop, thread));
+        }
+
+        let main = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let result = main.block_on(self.serve_on(service, handles));
+        for (stop, thread) in threads {
+            drop(stop);
+            thread.join().ok();
+        }
+        result
+    }
+
+    /// Acceptors are spread over `runtimes`, and each connection is served on the
+    /// runtime that accepted it.
+    async fn serve_on<S>(self, service: S, runtimes: Vec<Handle>) -> io::Result<()>
+    where
+        S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        let Server {
+            listeners,
+            acceptor,
+            http1,
+            http2,
+            shutdown,
+            drain_timeout,
+            header_read_timeout,
+            body_timeouts,
+            limits,
+            health,
+            on_error,
+            on_accept_error,
+            hooks,
+            body_limit,
+            header_limit,
+            normalize_paths,
+            utf8_policy,
+            runtime: _,
+            state,
+        } = self;
+        if listeners.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "server has no listeners",
+            ));
+        }
+
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .header_read_timeout(header_read_timeout);
+        http1.apply(&mut builder);
+        if let Some(limit) = &header_limit {
+            limit.apply(&mut builder);
+        }
+        match &http2 {
+            Some(config) => config.apply(&mut builder),
+            None => builder = builder.http1_only(),
+        }
+        let builder = Arc::new(builder);
+
+        let service = ConnectionService {
+            inner: service,
+            info: ConnectionInfo::default(),
+            body_timeouts,
+            on_error,
+            hooks,
+            http1,
+            keep_alive: None,
+            body_limit,
+            header_limit,
+            normalize_paths,
+            utf8_policy,
+            state,
+        };
+        let mut shutdown = shutdown.unwrap_or_else(|| Box::pin(futures::future::pending()));
+        let (draining, drain_rx) = watch::channel(());
+        let mut conns = JoinSet::new();
+        let limiter = Arc::new(ConnectionLimiter::new(limits));
+
+        let (accepted_tx, mut accepted_rx) = mpsc::channel(listeners.len());
+        let mut acceptors = JoinSet::new();
+        for (listener, runtime) in listeners.into_iter().zip(runtimes.iter().cycle()) {
+            acceptors.spawn_on(
+                run_acceptor(
+                    listener,
+                    limiter.clone(),
+                    on_accept_error.clone(),
+                    accepted_tx.clone(),
+                ),
+                runtime,
+            );
+        }
+        drop(accepted_tx);
+
+        // Set once every acceptor has exited, so nothing will ever be accepted again.
+        let mut stopped = None;
+        let mut first_error = None;
+        loop {
+            tokio::select! {
+                Some((stream, info, permit, runtime)) = accepted_rx.recv() => {
+                    conns.spawn_on(
+                        serve_connection(
+                            stream,
+                            info,
+                            permit,
+                            acceptor.clone(),
+                            builder.clone(),
+                            service.clone(),
+                            drain_rx.clone(),
+                        ),
+                        &runtime,
+                    );
+                }
+                Some(_) = conns.join_next(), if !conns.is_empty() => {}
+                Some(res) = acceptors.join_next(), if !acceptors.is_empty() => {
+                    let e = match res {
+                        Ok(Ok(())) => None,
+                        Ok(Err(e)) => Some(e),
+                        Err(e) => Some(io::Error::other(e)),
+                    };
+                    if first_error.is_none() {
+                        first_error = e;
+                    }
+                    if acceptors.is_empty() {
+                        stopped = Some(first_error.take().unwrap_or_else(|| {
+                            io::Error::other("every listener stopped accepting")
+                        }));
+                        break;
+                    }
+                }
+                _ = &mut shutdown => break,
+            }
+        }
+
+        if let Some(health) = &health {
+            health.set_draining();
+        }
+        // Aborting the acceptors closes their listeners.
+        acceptors.shutdown().await;
+        draining.send_replace(());
+
+        let drain = async { while conns.join_next().await.is_some() {} };
+        match drain_timeout {
+            Some(timeout) => {
+                if tokio::time::timeout(timeout, drain).await.is_err() {
+                    conns.shutdown().await;
+                }
+            }
+            None => drain.await,
+        }
+
+        match stopped {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+async fn run_acceptor(
+    listener: Listener,
+    limiter: Arc<ConnectionLimiter>,
+    on_error: Option<AcceptErrorHandler>,
+    accepted: mpsc::Sender<Accepted>,
+) -> io::Result<()> {
+    let listener = match listener.register() {
+        Ok(listener) => listener,
+        Err(e) => {
+            match &on_error {
+                Some(handler) => handler(&e),
+                None => trace::warn("registering listener failed", &e),
+            }
+            return Err(e);
+        }
+    };
+    let runtime = Handle::current();
+    let mut backoff = None;
+    loop {
+        let reserved = limiter.reserve().await;
+        let (stream, info) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                match &on_error {
+                    Some(handler) => handler(&e),
+                    None => trace::warn("accepting connection failed", &e),
+                }
+                if AcceptErrorKind::of(&e) != AcceptErrorKind::Connection {
+                    let delay = backoff.map_or(ACCEPT_BACKOFF_MIN, |delay: Duration| {
+                        (delay * 2).min(ACCEPT_BACKOFF_MAX)
+                    });
+                    backoff = Some(delay);
+                    // Don't hold a connection slot while paused.
+                    drop(reserved);
+                    tokio::time::sleep(delay).await;
+                }
+                continue;
+            }
+        };
+        backoff = None;
+        let ip = info.remote_addr().map(|addr| addr.ip());
+        if let Some(permit) = limiter.admit(reserved, ip)
+            && accepted
+                .send((stream, info, permit, runtime.clone()))
+                .await
+                .is_err()
+        {
+            return Ok(());
+        }
+    }
+}
+
+async fn serve_connection<S>(
+    stream: BoxedIo,
+    mut info: ConnectionInfo,
+    permit: PendingPermit,
+    acceptor: Option<Acceptor>,
+    builder: Arc<auto::Builder<TokioExecutor>>,
+    service: ConnectionService<S>,
+    mut draining: watch::Receiver<()>,
+) where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let _permit = permit.ready().await;
+
+    let io = match acceptor {
+        Some(acceptor) => match acceptor(stream).await {
+            Ok((io, session)) => {
+                info.tls = true;
+                info.peer_certificate = session.peer_certificate().map(Arc::new);
+                io
+            }
+            Err(e) => {
+                trace::connection_error("TLS handshake failed", &e);
+                return;
+            }
+        },
+        None => stream,
+    };
+
+    let mut state = service.state.clone();
+    if let Some(hook) = &service.hooks.on_connect {
+        hook(&info, &mut state);
+    }
+    let _disconnect = DisconnectGuard(
+        service
+            .hooks
+            .on_disconnect
+            .clone()
+            .map(|hook| (hook, info.clone())),
+    );
+
+    let keep_alive = KeepAlive::new(&service.http1);
+    let service = ConnectionService {
+        info,
+        state,
+        keep_alive: keep_alive.clone(),
+        ..service
+    };
+    let conn = builder.serve_connection_with_upgrades(TokioIo::new(io), service);
+    tokio::pin!(conn);
+    let idle = async {
+        match &keep_alive {
+            Some(keep_alive) => keep_alive.idle().await,
+            None => futures::future::pending().await,
+        }
+    };
+
+    let res = tokio::select! {
+        res = conn.as_mut() => res,
+        _ = draining.changed() => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+        _ = idle => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
+
+    if let Err(e) = res {
+        trace::connection_error("connection failed", &e);
+    }
+}
+
+#[derive(Clone)]
+struct ConnectionService<S> {
+    inner: S,
+    info: ConnectionInfo,
+    body_timeouts: BodyTimeouts,
+    on_error: Option<ErrorHandler>,
+    hooks: Hooks,
+    http1: Http1Config,
+    keep_alive: Option<Arc<KeepAlive>>,
+    body_limit: Option<BodyLimit>,
+    header_limit: Option<HeaderLimit>,
+    normalize_paths: bool,
+    utf8_policy: Utf8Policy,
+    state: Extensions,
+}
+
+impl<S> hyper::service::Service<hyper::Request<Incoming>> for ConnectionService<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn call(&self, req: hyper::Request<Incoming>) -> Self::Future {
+        let mut req = req.map(Body::Incoming);
+        req.extensions_mut().insert(self.info.clone());
+        req.extensions_mut().insert(self.body_timeouts);
+        if !self.state.is_empty() {
+            req.extensions_mut().extend(self.state.clone());
+        }
+        let invalid_path = match (self.normalize_paths, self.utf8_policy) {
+            (false, Utf8Policy::Raw) => None,
+            (resolve, policy) => normalize::normalize(&mut req, resolve, policy).err(),
+        };
+        if let Some(hook) = &self.hooks.on_request_start {
+            hook(&req);
+        }
+        let end = self
+            .hooks
+            .on_request_end
+            .clone()
+            .map(|hook| PendingEnd::new(hook, &req));
+        let (close, active) = match &self.keep_alive {
+            Some(keep_alive) if req.version() >= Version::HTTP_2 => {
+                keep_alive.disable();
+                (false, None)
+            }
+            Some(keep_alive) => keep_alive.start(),
+            None => (false, None),
+        };
+        let limit = self.body_limit;
+        let exceeded = limit.map(|limit| limit.apply(&mut req));
+        let handler = self.on_error.clone();
+        let cx = handler.as_ref().map(|handler| {
+            req.extensions_mut()
+                .insert(ServerErrorHandler(handler.clone()));
+            ErrorContext::from_request(&req)
+        });
+        let rejected =
+            invalid_path.or_else(|| self.header_limit.and_then(|limit| limit.check(&req).err()));
+        let fut: ServiceBoxFuture = match rejected {
+            Some(e) => {
+                let resp = error::error_response(&req, e);
+                Box::pin(async move { Ok(resp) })
+            }
+            None => Box::pin(self.inner.clone().oneshot(req)),
+        };
+        Box::pin(async move {
+            // Errors that got past the route mappers (or a service that isn't a
+            // `Service`) still get a response rather than a dropped connection.
+            let resp = match (fut.await, handler, cx) {
+                (Ok(resp), _, _) => resp,
+                (Err(e), Some(handler), Some(cx)) => handler(Error::from_service(e), &cx),
+                (Err(e), _, _) => {
+                    trace::service_error(&e);
+                    Error::from_service(e).into_response()
+                }
+            };
+            let resp = match (limit, exceeded) {
+                (Some(limit), Some(exceeded)) => {
+                    limit.finish(resp, exceeded.load(Ordering::Relaxed))
+                }
+                _ => resp,
+            };
+            let resp = match end {
+                Some(end) => end.attach(resp),
+                None => resp,
+            };
+            let resp = if close {
+                resp.with_connection_close()
+            } else {
+                resp
+            };
+            Ok(match active {
+                Some(active) => resp.map(|body| {
+                    make_body_from_stream(body.inspect(move |_| {
+                        let _ = &active;
+                    }))
+                }),
+                None => resp,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ListenerConfig, random, service_fn};
+
+    fn service()
+    -> impl TowerService<
+        Request,
+        Response = ServiceResponse,
+        Error = ServiceError,
+        Future = impl Send,
+    > + Clone
+    + Send
+    + Sync
+    + 'static {
+        service_fn(|_: Request| async { Ok::<_, ServiceError>(ServiceResponse::empty()) })
+    }
+
+    #[tokio::test]
+    async fn fails_without_listeners() {
+        let err = Server::with_listeners(vec![])
+            .serve(service())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    // A regular file can't be polled, so registering it with the runtime fails.
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn fails_once_every_acceptor_has_exited() {
+        use std::os::fd::{FromRawFd, IntoRawFd};
+
+        let path = std::env::temp_dir().join(format!("libserver-{}", random::hex_token(8)));
+        let file = std::fs::File::create(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // SAFETY: the descriptor is owned and open; only registration touches it.
+        let listener = unsafe { std::net::TcpListener::from_raw_fd(file.into_raw_fd()) };
+        listener.set_nonblocking(true).unwrap();
+        let server = Server::with_listeners(vec![Listener::Detached(
+            listener,
+            ListenerConfig::default(),
+        )]);
+        let res = tokio::time::timeout(Duration::from_secs(5), server.serve(service())).await;
+        let err = res.expect("serve returned").unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+    }
+}
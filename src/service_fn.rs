@@ -0,0 +1,198 @@
+use std::{
+    future::Future,
+    task::{Context, Poll},
+};
+
+use tower::Service as TowerService;
+
+use crate::{
+    IntoResponse, Request, Router, Routing, ServiceBoxFuture, ServiceError, ServiceResponse,
+    extract::body_limit,
+};
+
+/// A service from an async closure that receives the whole request. Use `handler_fn`
+/// instead to take extractors as arguments.
+#[derive(Clone, Copy)]
+pub struct ServiceFn<F> {
+    f: F,
+}
+
+/// Wraps an async closure taking the request as a service.
+pub fn service_fn<F, Fut, R>(f: F) -> ServiceFn<F>
+where
+    F: Fn(Request) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Result<R, ServiceError>> + Send + 'static,
+    R: IntoResponse,
+{
+    ServiceFn { f }
+}
+
+impl<F, Fut, R> TowerService<Request> for ServiceFn<F>
+where
+    F: Fn(Request) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Result<R, ServiceError>> + Send + 'static,
+    R: IntoResponse,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let fut = (self.f)(req);
+        Box::pin(async move { fut.await.map(IntoResponse::into_response) })
+    }
+}
+
+/// A service from a synchronous closure, run on tokio's blocking pool so CPU-heavy
+/// work or blocking libraries don't hold up other requests. The body is read first,
+/// within the request's `BodyLimit`, and handed over as `Body::Full`. A panic in the
+/// closure resumes on the calling task, where `CatchPanic` can see it.
+#[derive(Clone, Copy)]
+pub struct BlockingServiceFn<F> {
+    f: F,
+}
+
+/// Wraps a blocking closure as a service run on tokio's blocking pool.
+pub fn blocking_service_fn<F, R>(f: F) -> BlockingServiceFn<F>
+where
+    F: Fn(Request) -> Result<R, ServiceError> + Clone + Send + Sync + 'static,
+    R: IntoResponse,
+{
+    BlockingServiceFn { f }
+}
+
+impl<F, R> TowerService<Request> for BlockingServiceFn<F>
+where
+    F: Fn(Request) -> Result<R, ServiceError> + Clone + Send + Sync + 'static,
+    R: IntoResponse,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        let f = self.f.clone();
+        Box::pin(async move {
+            let limit = body_limit(&req);
+            req.body_mut().buffer(limit).await?;
+            let task = tokio::task::spawn_blocking(move || f(req).map(IntoResponse::into_response));
+            match task.await {
+                Ok(result) => result,
+                Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+}
+
+/// Routing runs before the body is read and must not block, so router closures are
+/// synchronous. Plain closures are already routers; this names the type so it can be
+/// cloned and stored.
+#[derive(Clone, Copy)]
+pub struct RouterFn<F> {
+    f: F,
+}
+
+/// Wraps a closure as a router.
+pub fn router_fn<F>(f: F) -> RouterFn<F>
+where
+    F: Fn(&Request) -> bool + Send + Sync + 'static,
+{
+    RouterFn { f }
+}
+
+impl<F> Router for RouterFn<F>
+where
+    F: Fn(&Request) -> bool + Send + Sync + 'static,
+{
+    fn matches(&self, req: &Request) -> bool {
+        (self.f)(req)
+    }
+}
+
+/// A router that matches when `f` returns a value, and hands the value to the
+/// service as a request extension (`Extension<T>` in handlers). Mostly useful as the
+/// second half of `Route::chain`, where `f` can read what the first router added.
+#[derive(Clone, Copy)]
+pub struct ContextFn<F> {
+    f: F,
+}
+
+/// A router that matches when `f` returns a value, attached as an extension.
+pub fn context_fn<F, T>(f: F) -> ContextFn<F>
+where
+    F: Fn(&Request) -> Option<T> + Send + Sync + 'static,
+    T: Clone + Send + Sync + 'static,
+{
+    ContextFn { f }
+}
+
+impl<F, T> Router for ContextFn<F>
+where
+    F: Fn(&Request) -> Option<T> + Send + Sync + 'static,
+    T: Clone + Send + Sync + 'static,
+{
+    fn matches(&self, req: &Request) -> bool {
+        (self.f)(req).is_some()
+    }
+
+    fn route(&self, req: &mut Request) -> bool {
+        match (self.f)(req) {
+            Some(context) => {
+                req.extensions_mut().insert(context);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A router that never passes: it either takes the request, handing `f`'s value to
+/// the service as a request extension, or answers it with `f`'s response, such as a
+/// 401 challenge. Chain it after the routers that decide whether the route applies.
+#[derive(Clone, Copy)]
+pub struct GuardFn<F> {
+    f: F,
+}
+
+/// A router that takes the request when `f` returns a value, or answers with `f`'s response.
+pub fn guard_fn<F, T>(f: F) -> GuardFn<F>
+where
+    F: Fn(&Request) -> Result<T, ServiceResponse> + Send + Sync + 'static,
+    T: Clone + Send + Sync + 'static,
+{
+    GuardFn { f }
+}
+
+impl<F, T> Router for GuardFn<F>
+where
+    F: Fn(&Request) -> Result<T, ServiceResponse> + Send + Sync + 'static,
+    T: Clone + Send + Sync + 'static,
+{
+    fn matches(&self, _req: &Request) -> bool {
+        true
+    }
+
+    /// Callers that can't send a response see a rejection as a pass.
+    fn route(&self, req: &mut Request) -> bool {
+        matches!(self.route_or_respond(req), Routing::Matched)
+    }
+
+    fn route_or_respond(&self, req: &mut Request) -> Routing {
+        match (self.f)(req) {
+            Ok(context) => {
+                req.extensions_mut().insert(context);
+                Routing::Matched
+            }
+            Err(resp) => Routing::Respond(resp),
+        }
+    }
+}
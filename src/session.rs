@@ -0,0 +1,437 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use tower::{Layer, Service as TowerService};
+
+use crate::{
+    Request, RequestExt, ResponseExt, ServiceBoxFuture, ServiceError, ServiceResponse,
+    cookies::{Cookie, SameSite},
+    query::percent_decode,
+    random,
+};
+
+const SESSION_ID_BYTES: usize = 32;
+
+/// The key-value pairs stored for a session.
+pub type SessionData = HashMap<String, String>;
+/// The future returned by `SessionStore` methods.
+pub type StoreFuture<T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'static>>;
+
+/// Persistence for session data, keyed by session id.
+pub trait SessionStore: Send + Sync + 'static {
+    /// The data saved under `id`, or `None` if there is no such session.
+    fn load(&self, id: &str) -> StoreFuture<Option<SessionData>>;
+
+    /// Saves `data` under `id`, replacing what was there.
+    fn save(&self, id: &str, data: SessionData) -> StoreFuture<()>;
+
+    /// Forgets the session `id`.
+    fn delete(&self, id: &str) -> StoreFuture<()>;
+}
+
+/// Keeps sessions in process memory; clones share the same sessions.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    sessions: Arc<Mutex<HashMap<String, (SessionData, Instant)>>>,
+    ttl: Option<Duration>,
+}
+
+impl MemoryStore {
+    /// A store whose sessions never expire.
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    /// Drops sessions not saved within `ttl`.
+    pub fn with_ttl(mut self, ttl: Duration) -> MemoryStore {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, HashMap<String, (SessionData, Instant)>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> StoreFuture<Option<SessionData>> {
+        let mut sessions = self.sessions();
+        if let Some(ttl) = self.ttl {
+            sessions.retain(|_, (_, saved)| saved.elapsed() < ttl);
+        }
+        let data = sessions.get(id).map(|(data, _)| data.clone());
+        Box::pin(async move { Ok(data) })
+    }
+
+    fn save(&self, id: &str, data: SessionData) -> StoreFuture<()> {
+        self.sessions()
+            .insert(id.to_string(), (data, Instant::now()));
+        Box::pin(async { Ok(()) })
+    }
+
+    fn delete(&self, id: &str) -> StoreFuture<()> {
+        self.sessions().remove(id);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Stores each session as a file named after its id, one `key=value` line per entry.
+#[derive(Clone)]
+pub struct FileStore {
+    dir: Arc<PathBuf>,
+}
+
+impl FileStore {
+    /// A store keeping its files in `dir`, which must exist.
+    pub fn new(dir: impl Into<PathBuf>) -> FileStore {
+        FileStore {
+            dir: Arc::new(dir.into()),
+        }
+    }
+
+    fn path(&self, id: &str) -> io::Result<PathBuf> {
+        match !id.is_empty() && id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            true => Ok(self.dir.join(id)),
+            false => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid session id",
+            )),
+        }
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> StoreFuture<Option<SessionData>> {
+        let path = self.path(id);
+        Box::pin(async move {
+            let contents = match tokio::fs::read_to_string(path?).await {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            let decode = |s: &str| {
+                String::from_utf8_lossy(&percent_decode(s.as_bytes(), false)).into_owned()
+            };
+            Ok(Some(
+                contents
+                    .lines()
+                    .filter_map(|line| line.split_once('='))
+                    .map(|(k, v)| (decode(k), decode(v)))
+                    .collect(),
+            ))
+        })
+    }
+
+    fn save(&self, id: &str, data: SessionData) -> StoreFuture<()> {
+        let path = self.path(id);
+        let dir = self.dir.clone();
+        Box::pin(async move {
+            let path = path?;
+            let mut contents = String::new();
+            for (k, v) in &data {
+                contents.push_str(&encode(k));
+                contents.push('=');
+                contents.push_str(&encode(v));
+                contents.push('\n');
+            }
+            tokio::fs::create_dir_all(&*dir).await?;
+            let tmp = path.with_extension("tmp");
+            tokio::fs::write(&tmp, contents).await?;
+            tokio::fs::rename(&tmp, &path).await
+        })
+    }
+
+    fn delete(&self, id: &str) -> StoreFuture<()> {
+        let path = self.path(id);
+        Box::pin(async move {
+            match tokio::fs::remove_file(path?).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+fn encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '%' | '=' | '\n' | '\r' => out.push_str(&format!("%{:02X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[derive(Default)]
+struct SessionState {
+    id: Option<String>,
+    data: SessionData,
+    changed: bool,
+    regenerate: bool,
+    destroyed: bool,
+}
+
+/// The session of the current request, added to its extensions by `SessionLayer`. Changes are saved once the response is produced.
+#[derive(Clone)]
+pub struct Session(Arc<Mutex<SessionState>>);
+
+impl Session {
+    /// The session `SessionLayer` attached to `req`.
+    pub fn from_request(req: &Request) -> Option<Session> {
+        req.extensions().get().cloned()
+    }
+
+    fn state(&self) -> MutexGuard<'_, SessionState> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The session id, or `None` for a session not yet saved.
+    pub fn id(&self) -> Option<String> {
+        self.state().id.clone()
+    }
+
+    /// The value stored under `key`.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.state().data.get(key).cloned()
+    }
+
+    /// Stores `value` under `key`.
+    pub fn insert(&self, key: impl Into<String>, value: impl Into<String>) {
+        let mut state = self.state();
+        state.data.insert(key.into(), value.into());
+        state.changed = true;
+    }
+
+    /// Removes and returns the value under `key`.
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.state();
+        state.changed = true;
+        state.data.remove(key)
+    }
+
+    /// Removes every value, keeping the session id.
+    pub fn clear(&self) {
+        let mut state = self.state();
+        state.data.clear();
+        state.changed = true;
+    }
+
+    /// Issues a new id on save, e.g. after login, so a pre-existing id can't be fixated.
+    pub fn regenerate(&self) {
+        let mut state = self.state();
+        state.regenerate = true;
+        state.changed = true;
+    }
+
+    /// Deletes the session from the store and expires its cookie.
+    pub fn destroy(&self) {
+        let mut state = self.state();
+        state.data.clear();
+        state.destroyed = true;
+    }
+}
+
+/// Loads the session named by a cookie before each request and saves it afterwards.
+#[derive(Clone)]
+pub struct SessionLayer {
+    store: Arc<dyn SessionStore>,
+    cookie: Cookie,
+}
+
+impl SessionLayer {
+    /// Sessions kept in `store`, under an `HttpOnly`, `SameSite=Lax` cookie named `session`.
+    pub fn new(store: impl SessionStore) -> SessionLayer {
+        SessionLayer {
+            store: Arc::new(store),
+            cookie: Cookie::new("session", "")
+                .with_path("/")
+                .with_http_only(true)
+                .with_same_site(SameSite::Lax),
+        }
+    }
+
+    /// The template's name and attributes are used for the session cookie; its value is ignored.
+    pub fn with_cookie(mut self, cookie: Cookie) -> SessionLayer {
+        self.cookie = cookie;
+        self
+    }
+}
+
+impl<S> Layer<S> for SessionLayer {
+    type Service = SessionService<S>;
+
+    fn layer(&self, inner: S) -> SessionService<S> {
+        SessionService {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+/// The service produced by `SessionLayer`.
+#[derive(Clone)]
+pub struct SessionService<S> {
+    inner: S,
+    config: SessionLayer,
+}
+
+impl<S> TowerService<Request> for SessionService<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        let id = req
+            .cookies()
+            .get(self.config.cookie.name())
+            .map(str::to_string);
+        // Swap in a fresh clone so the ready inner service is the one that gets called.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let SessionLayer { store, cookie } = self.config.clone();
+
+        Box::pin(async move {
+            // A cookie that doesn't name a loadable session (forged, malformed, or from
+            // a store that has since failed) gets a fresh session rather than an error.
+            let data = match &id {
+                Some(id) => store.load(id).await.unwrap_or_default(),
+                None => None,
+            };
+            let session = Session(Arc::new(Mutex::new(SessionState {
+                id: data.as_ref().and(id.clone()),
+                data: data.unwrap_or_default(),
+                ..SessionState::default()
+            })));
+            req.extensions_mut().insert(session.clone());
+
+            let resp = inner.call(req).await?;
+
+            let state = std::mem::take(&mut *session.state());
+            if state.destroyed {
+                if let Some(id) = &state.id {
+                    store.delete(id).await?;
+                }
+                let removal = Cookie::removal(cookie.name());
+                return Ok(match id {
+                    Some(_) => resp.with_cookie(&removal),
+                    None => resp,
+                });
+            }
+            if !state.changed {
+                return Ok(resp);
+            }
+
+            let new_id = match (&state.id, state.regenerate) {
+                (Some(id), false) => id.clone(),
+                (old, _) => {
+                    if let Some(old) = old {
+                        store.delete(old).await?;
+                    }
+                    random::hex_token(SESSION_ID_BYTES)
+                }
+            };
+            store.save(&new_id, state.data).await?;
+            Ok(match state.id.as_deref() == Some(new_id.as_str()) {
+                true => resp,
+                false => resp.with_cookie(&cookie.with_value(new_id)),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower::util::BoxCloneSyncService;
+
+    use super::*;
+    use crate::{service_fn, testing::TestClient};
+
+    fn counter(store: impl SessionStore) -> TestClient {
+        let service = SessionLayer::new(store).layer(service_fn(|req: Request| async move {
+            let session = Session::from_request(&req).expect("session layer");
+            let count = session.get("n").map_or(0, |n| n.parse::<u32>().unwrap()) + 1;
+            session.insert("n", count.to_string());
+            Ok::<_, ServiceError>(count.to_string())
+        }));
+        TestClient::new(BoxCloneSyncService::new(service))
+    }
+
+    fn session_cookie(set_cookie: &str) -> &str {
+        set_cookie.split(';').next().unwrap()
+    }
+
+    #[tokio::test]
+    async fn round_trips_through_the_cookie() {
+        let client = counter(MemoryStore::new());
+        let first = client.get("/").send().await;
+        first.assert_body("1");
+        let cookie = session_cookie(first.header("set-cookie").unwrap()).to_string();
+        let second = client.get("/").header("cookie", cookie).send().await;
+        second.assert_body("2");
+        assert!(second.header("set-cookie").is_none());
+    }
+
+    #[tokio::test]
+    async fn unknown_ids_get_a_fresh_session() {
+        let client = counter(MemoryStore::new());
+        let resp = client
+            .get("/")
+            .header("cookie", "session=00ff")
+            .send()
+            .await;
+        resp.assert_body("1");
+        let cookie = session_cookie(resp.header("set-cookie").unwrap());
+        assert_ne!(cookie, "session=00ff");
+        assert_eq!(cookie.len(), "session=".len() + 2 * SESSION_ID_BYTES);
+    }
+
+    #[tokio::test]
+    async fn invalid_file_store_ids_get_a_fresh_session() {
+        let dir = std::env::temp_dir().join(format!("libserver-session-{}", std::process::id()));
+        let client = counter(FileStore::new(&dir));
+        for id in ["..%2Fetc", "not-hex", "\"\""] {
+            let resp = client
+                .get("/")
+                .header("cookie", format!("session={id}"))
+                .send()
+                .await;
+            resp.assert_status(hyper::StatusCode::OK).assert_body("1");
+            assert!(resp.header("set-cookie").is_some());
+        }
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn file_store_rejects_non_hex_ids() {
+        let store = FileStore::new("/tmp");
+        assert!(store.path("abc123").is_ok());
+        for id in ["", "../x", "abc/def", "xyz"] {
+            assert_eq!(
+                store.path(id).unwrap_err().kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+    }
+}
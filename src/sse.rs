@@ -0,0 +1,104 @@
+//! Server-sent events: the `Event` type and helpers for `text/event-stream`
+//! responses. See `ResponseExt::sse`.
+
+use std::{fmt::Write, time::Duration};
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use hyper::body::Frame;
+
+use crate::BodyInner;
+
+/// How often `ResponseExt::sse` sends a keep-alive comment on an idle stream.
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// A Server-Sent Event. Line breaks in `id` and `event` are dropped; `data` spanning lines is sent as several `data` fields.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Event {
+    /// The `id` field, which the client sends back as `Last-Event-ID` on reconnect.
+    pub id: Option<String>,
+    /// The `event` field, naming the event type.
+    pub event: Option<String>,
+    /// The `data` field.
+    pub data: Option<String>,
+    /// The `retry` field, the client's reconnection delay.
+    pub retry: Option<Duration>,
+}
+
+impl Event {
+    /// An event carrying `data`.
+    pub fn data(data: impl Into<String>) -> Event {
+        Event {
+            data: Some(data.into()),
+            ..Event::default()
+        }
+    }
+
+    /// Sets the `id` field.
+    pub fn with_id(mut self, id: impl Into<String>) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets the `event` field.
+    pub fn with_event(mut self, event: impl Into<String>) -> Event {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// Sets the `retry` field.
+    pub fn with_retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// The event in wire format, ending in a blank line.
+    pub fn to_bytes(&self) -> Bytes {
+        let mut out = String::new();
+        if let Some(event) = &self.event {
+            push_field(&mut out, "event", event);
+        }
+        if let Some(id) = &self.id {
+            push_field(&mut out, "id", id);
+        }
+        if let Some(retry) = self.retry {
+            writeln!(out, "retry: {}", retry.as_millis()).expect("writing to a string");
+        }
+        if let Some(data) = &self.data {
+            for line in data.split('\n') {
+                push_field(&mut out, "data", line.strip_suffix('\r').unwrap_or(line));
+            }
+        }
+        out.push('\n');
+        Bytes::from(out)
+    }
+}
+
+fn push_field(out: &mut String, name: &str, value: &str) {
+    let value: String = value
+        .chars()
+        .filter(|c| !matches!(c, '\r' | '\n'))
+        .collect();
+    writeln!(out, "{name}: {value}").expect("writing to a string");
+}
+
+/// The body frames for `events`, with a keep-alive comment after each `keep_alive` of silence.
+pub fn event_stream<S>(
+    events: S,
+    keep_alive: Option<Duration>,
+) -> impl Stream<Item = BodyInner> + Send + 'static
+where
+    S: Stream<Item = Event> + Send + 'static,
+{
+    let events = Box::pin(events);
+    futures::stream::unfold(events, move |mut events| async move {
+        let next = match keep_alive {
+            Some(interval) => tokio::select! {
+                event = events.next() => event.map(|e| e.to_bytes()),
+                _ = tokio::time::sleep(interval) => Some(Bytes::from_static(b":\n\n")),
+            },
+            None => events.next().await.map(|e| e.to_bytes()),
+        };
+        next.map(|bytes| (Ok(Frame::data(bytes)), events))
+    })
+}
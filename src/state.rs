@@ -0,0 +1,126 @@
+use std::{fmt, ops::Deref, sync::Arc};
+
+use hyper::http::request::Parts;
+use tower::{Layer, Service as TowerService};
+
+use crate::{Error, FromRequestParts, Request};
+
+/// Application state shared by every request. Install it once with `Server::with_state`
+/// or `StateLayer`; each request carries a clone of the same `Arc`.
+pub struct State<T>(pub Arc<T>);
+
+impl<T> State<T> {
+    /// Shares `state` through a new `Arc`.
+    pub fn new(state: T) -> State<T> {
+        State(Arc::new(state))
+    }
+}
+
+impl<T: Send + Sync + 'static> State<T> {
+    /// The state of type `T` attached to `req`.
+    pub fn from_request(req: &Request) -> Option<&State<T>> {
+        req.extensions().get()
+    }
+}
+
+impl<T> Clone for State<T> {
+    fn clone(&self) -> State<T> {
+        State(self.0.clone())
+    }
+}
+
+impl<T> Deref for State<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for State<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("State").field(&self.0).finish()
+    }
+}
+
+impl<T: Send + Sync + 'static> FromRequestParts for State<T> {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts) -> Result<State<T>, Error> {
+        parts
+            .extensions
+            .get()
+            .cloned()
+            .ok_or(Error::MissingExtension(std::any::type_name::<T>()))
+    }
+}
+
+/// Attaches a `State<T>` to every request reaching the services it wraps.
+pub struct StateLayer<T> {
+    state: State<T>,
+}
+
+impl<T> StateLayer<T> {
+    /// Attaches `state` to every request.
+    pub fn new(state: Arc<T>) -> StateLayer<T> {
+        StateLayer {
+            state: State(state),
+        }
+    }
+}
+
+impl<T> Clone for StateLayer<T> {
+    fn clone(&self) -> StateLayer<T> {
+        StateLayer {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<S, T> Layer<S> for StateLayer<T> {
+    type Service = StateService<S, T>;
+
+    fn layer(&self, inner: S) -> StateService<S, T> {
+        StateService {
+            inner,
+            state: self.state.clone(),
+        }
+    }
+}
+
+/// The service produced by `StateLayer`.
+pub struct StateService<S, T> {
+    inner: S,
+    state: State<T>,
+}
+
+impl<S: Clone, T> Clone for StateService<S, T> {
+    fn clone(&self) -> StateService<S, T> {
+        StateService {
+            inner: self.inner.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<S, T> TowerService<Request> for StateService<S, T>
+where
+    S: TowerService<Request>,
+    T: Send + Sync + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        req.extensions_mut().insert(self.state.clone());
+        self.inner.call(req)
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    fs::Metadata,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use futures::TryStreamExt;
+use hyper::{
+    Method, StatusCode,
+    body::Frame,
+    header::{self, HeaderValue},
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use tower::{Service as TowerService, ServiceExt, util::BoxCloneSyncService};
+
+use crate::{
+    DynService, NOT_FOUND, Request, ResponseExt, ServiceBoxFuture, ServiceError, ServiceResponse,
+    conditional::last_modified_value,
+    etag_for_metadata, is_fresh, not_modified,
+    query::percent_decode,
+    range::{ByteRange, with_range_headers},
+};
+
+/// Serves files from a directory, with ETags, `Last-Modified` and byte ranges.
+#[derive(Clone)]
+pub struct StaticFiles {
+    dir: Arc<PathBuf>,
+    prefix: Arc<str>,
+    fallback: DynService,
+}
+
+impl StaticFiles {
+    /// Serves the files under `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles {
+            dir: Arc::new(dir.into()),
+            prefix: Arc::from(""),
+            fallback: BoxCloneSyncService::new(NOT_FOUND),
+        }
+    }
+
+    /// Serves files only below the URL path `prefix`, which is stripped before lookup.
+    pub fn with_prefix(mut self, prefix: impl AsRef<str>) -> StaticFiles {
+        self.prefix = Arc::from(prefix.as_ref().trim_end_matches('/'));
+        self
+    }
+
+    /// Sets the service for requests that don't name a file; 404 by default.
+    pub fn with_fallback<S>(mut self, fallback: S) -> StaticFiles
+    where
+        S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.fallback = BoxCloneSyncService::new(fallback);
+        self
+    }
+
+    /// The file for a request path, or `None` when it is outside the prefix or tries to leave the directory.
+    pub fn resolve(&self, req_path: &str) -> Option<PathBuf> {
+        let rest = req_path.strip_prefix(&*self.prefix)?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+
+        let mut path = (*self.dir).clone();
+        for segment in rest.split('/').filter(|s| !s.is_empty()) {
+            let segment = String::from_utf8(percent_decode(segment.as_bytes(), false)).ok()?;
+            if segment == "." || segment == ".." || segment.contains(['/', '\\', '\0']) {
+                return None;
+            }
+            path.push(segment);
+        }
+        Some(path)
+    }
+}
+
+impl TowerService<Request> for StaticFiles {
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let path = match req.method() {
+            &Method::GET | &Method::HEAD => self.resolve(req.uri().path()),
+            _ => None,
+        };
+        let fallback = self.fallback.clone();
+
+        Box::pin(async move {
+            let Some((mut file, path, meta)) = open_file(path).await else {
+                return fallback.oneshot(req).await;
+            };
+            let len = meta.len();
+            let etag = etag_for_metadata(&meta);
+            let modified = meta.modified().ok();
+            if is_fresh(&req, Some(&etag), modified) {
+                return Ok(not_modified(Some(&etag), modified));
+            }
+            let range = ByteRange::from_request(&req, len);
+            let resp = match &range {
+                ByteRange::Full => {
+                    ServiceResponse::from_stream(ReaderStream::new(file).map_ok(Frame::data))
+                        .with_header(header::CONTENT_LENGTH, HeaderValue::from(len))
+                }
+                ByteRange::Partial(r) => {
+                    file.seek(SeekFrom::Start(*r.start())).await?;
+                    let part_len = r.end() - r.start() + 1;
+                    ServiceResponse::from_stream(
+                        ReaderStream::new(file.take(part_len)).map_ok(Frame::data),
+                    )
+                    .with_status(StatusCode::PARTIAL_CONTENT)
+                    .with_header(header::CONTENT_LENGTH, HeaderValue::from(part_len))
+                }
+                ByteRange::Unsatisfiable => {
+                    ServiceResponse::empty().with_status(StatusCode::RANGE_NOT_SATISFIABLE)
+                }
+            };
+            let mut resp = resp
+                .with_header(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(mime_type(&path)),
+                )
+                .with_typed_header(&etag);
+            if let Some(modified) = modified {
+                resp = resp.with_header(header::LAST_MODIFIED, last_modified_value(modified));
+            }
+            Ok(with_range_headers(resp, &range, len))
+        })
+    }
+}
+
+async fn open_file(path: Option<PathBuf>) -> Option<(tokio::fs::File, PathBuf, Metadata)> {
+    let mut path = path?;
+    let mut meta = tokio::fs::metadata(&path).await.ok()?;
+    if meta.is_dir() {
+        path.push("index.html");
+        meta = tokio::fs::metadata(&path).await.ok()?;
+    }
+    if !meta.is_file() {
+        return None;
+    }
+    let file = tokio::fs::File::open(&path).await.ok()?;
+    Some((file, path, meta))
+}
+
+/// The `Content-Type` for a file, guessed from its extension.
+pub fn mime_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    match ext.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") => "audio/ogg",
+        Some("wav") => "audio/wav",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,283 @@
+//! Helpers for testing services: requests to call them with directly, and a
+//! `TestClient` that talks to them through a real `Server`.
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    HeaderMap, Method, StatusCode,
+    header::{self, HeaderName, HeaderValue},
+};
+use hyper_util::rt::TokioIo;
+use tokio::{sync::mpsc, task::JoinHandle};
+use tower::Service as TowerService;
+
+use crate::{
+    Body, BoxedIo, ConnectionInfo, JsonValue, Request, Server, ServiceError, ServiceResponse,
+    body::APPLICATION_JSON, listener::Listener,
+};
+
+/// Builds requests for calling a service directly, without a server:
+/// `Request::test(Method::GET, "/x")`. The request carries a default
+/// `ConnectionInfo` and an empty body; replace it with `Body::from_bytes` or
+/// `Body::from_stream` as needed.
+pub trait TestRequestExt: Sized {
+    /// A request for `method` and `path` with a `Host: localhost` header.
+    fn test(method: Method, path: &str) -> Self;
+
+    /// Replaces the body.
+    fn with_body(self, body: impl Into<Body>) -> Self;
+}
+
+impl TestRequestExt for Request {
+    /// Panics if `path` is not a valid request target.
+    fn test(method: Method, path: &str) -> Request {
+        let mut req = Request::new(Body::Empty);
+        *req.method_mut() = method;
+        *req.uri_mut() = path.parse().expect("invalid request path");
+        req.headers_mut()
+            .insert(header::HOST, HeaderValue::from_static("localhost"));
+        req.extensions_mut().insert(ConnectionInfo::default());
+        req
+    }
+
+    fn with_body(mut self, body: impl Into<Body>) -> Request {
+        *self.body_mut() = body.into();
+        self
+    }
+}
+
+/// Runs a service behind a `Server` that accepts connections from in-memory pipes
+/// instead of a socket, so every request goes through the same HTTP handling,
+/// limits and hooks as in production. Must be created inside a tokio runtime; the
+/// server stops when the client is dropped.
+pub struct TestClient {
+    connect: mpsc::Sender<BoxedIo>,
+    server: JoinHandle<()>,
+}
+
+impl TestClient {
+    /// Serves `service` with the default server settings.
+    pub fn new<S>(service: S) -> TestClient
+    where
+        S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        TestClient::with_server(service, |server| server)
+    }
+
+    /// `configure` gets the unbound server to add state, limits or hooks to.
+    pub fn with_server<S, F>(service: S, configure: F) -> TestClient
+    where
+        S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+        F: FnOnce(Server) -> Server,
+    {
+        let (connect, accepted) = mpsc::channel(1);
+        let server = configure(Server::with_listeners(vec![Listener::Memory(
+            accepted.into(),
+        )]));
+        let server = tokio::spawn(async move {
+            server.serve(service).await.ok();
+        });
+        TestClient { connect, server }
+    }
+
+    /// Starts a GET request.
+    pub fn get(&self, path: &str) -> TestRequest<'_> {
+        self.request(Method::GET, path)
+    }
+
+    /// Starts a POST request.
+    pub fn post(&self, path: &str) -> TestRequest<'_> {
+        self.request(Method::POST, path)
+    }
+
+    /// Starts a PUT request.
+    pub fn put(&self, path: &str) -> TestRequest<'_> {
+        self.request(Method::PUT, path)
+    }
+
+    /// Starts a PATCH request.
+    pub fn patch(&self, path: &str) -> TestRequest<'_> {
+        self.request(Method::PATCH, path)
+    }
+
+    /// Starts a DELETE request.
+    pub fn delete(&self, path: &str) -> TestRequest<'_> {
+        self.request(Method::DELETE, path)
+    }
+
+    /// Panics if `path` is not a valid request target.
+    pub fn request(&self, method: Method, path: &str) -> TestRequest<'_> {
+        let mut req = hyper::Request::new(Bytes::new());
+        *req.method_mut() = method;
+        *req.uri_mut() = path.parse().expect("invalid request path");
+        TestRequest { client: self, req }
+    }
+}
+
+impl Drop for TestClient {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// A request being built by a `TestClient`, sent with `send`.
+pub struct TestRequest<'a> {
+    client: &'a TestClient,
+    req: hyper::Request<Bytes>,
+}
+
+impl TestRequest<'_> {
+    /// Panics on an invalid name or value.
+    pub fn header<K, V>(mut self, name: K, value: V) -> Self
+    where
+        K: TryInto<HeaderName>,
+        V: TryInto<HeaderValue>,
+    {
+        let (Ok(name), Ok(value)) = (name.try_into(), value.try_into()) else {
+            panic!("invalid header");
+        };
+        self.req.headers_mut().append(name, value);
+        self
+    }
+
+    /// Sets the body.
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        *self.req.body_mut() = body.into();
+        self
+    }
+
+    /// Sets `value` as the body, with `Content-Type: application/json`.
+    pub fn json(mut self, value: &JsonValue) -> Self {
+        self.req.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(APPLICATION_JSON),
+        );
+        self.body(value.to_string())
+    }
+
+    /// Sends the request on a fresh connection and reads the whole response. Panics
+    /// if the server can't be reached or drops the connection.
+    pub async fn send(self) -> TestResponse {
+        let (mut parts, body) = self.req.into_parts();
+        parts
+            .headers
+            .entry(header::HOST)
+            .or_insert(HeaderValue::from_static("localhost"));
+        let req = hyper::Request::from_parts(parts, Full::new(body));
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        self.client
+            .connect
+            .send(Box::new(server))
+            .await
+            .expect("test server stopped");
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(client))
+            .await
+            .expect("test connection failed");
+        tokio::spawn(async move {
+            conn.await.ok();
+        });
+
+        let resp = sender.send_request(req).await.expect("request failed");
+        let (parts, body) = resp.into_parts();
+        let body = body
+            .collect()
+            .await
+            .expect("response body failed")
+            .to_bytes();
+        TestResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body,
+        }
+    }
+}
+
+/// A response read in full, with assertions that panic at the caller's location.
+#[derive(Clone, Debug)]
+pub struct TestResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl TestResponse {
+    /// The status code.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The headers.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// A header value, if present and valid UTF-8.
+    pub fn header(&self, name: impl AsRef<str>) -> Option<&str> {
+        self.headers.get(name.as_ref())?.to_str().ok()
+    }
+
+    /// The body.
+    pub fn bytes(&self) -> &Bytes {
+        &self.body
+    }
+
+    /// The body as text, replacing invalid UTF-8.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// The body parsed as JSON. Panics if it isn't.
+    #[track_caller]
+    pub fn json(&self) -> JsonValue {
+        match std::str::from_utf8(&self.body)
+            .ok()
+            .and_then(JsonValue::parse)
+        {
+            Some(value) => value,
+            None => panic!("response body is not JSON: {:?}", self.text()),
+        }
+    }
+
+    /// Panics unless the status is `status`.
+    #[track_caller]
+    pub fn assert_status(&self, status: StatusCode) -> &TestResponse {
+        assert_eq!(
+            self.status,
+            status,
+            "unexpected status, body: {:?}",
+            self.text()
+        );
+        self
+    }
+
+    /// Panics unless header `name` has the single value `value`.
+    #[track_caller]
+    pub fn assert_header(&self, name: impl AsRef<str>, value: &str) -> &TestResponse {
+        let name = name.as_ref();
+        assert_eq!(self.header(name), Some(value), "unexpected `{name}` header");
+        self
+    }
+
+    /// Panics unless the body is `body`.
+    #[track_caller]
+    pub fn assert_body(&self, body: impl AsRef<[u8]>) -> &TestResponse {
+        assert_eq!(
+            self.body.as_ref(),
+            body.as_ref(),
+            "unexpected body: {:?}",
+            self.text()
+        );
+        self
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use hyper::StatusCode;
+use tower::{Layer, Service as TowerService};
+
+use crate::{Request, ResponseExt, ServiceBoxFuture, ServiceError, ServiceResponse};
+
+/// Answers requests whose service takes longer than a set time to respond.
+#[derive(Clone, Copy, Debug)]
+pub struct TimeoutLayer {
+    duration: Duration,
+    status: StatusCode,
+}
+
+impl TimeoutLayer {
+    /// Gives services `duration` to respond, answering 408 after it.
+    pub fn new(duration: Duration) -> TimeoutLayer {
+        TimeoutLayer {
+            duration,
+            status: StatusCode::REQUEST_TIMEOUT,
+        }
+    }
+
+    /// Sets the status sent on timeout.
+    pub fn with_status(mut self, status: StatusCode) -> TimeoutLayer {
+        self.status = status;
+        self
+    }
+}
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = Timeout<S>;
+
+    fn layer(&self, inner: S) -> Timeout<S> {
+        Timeout {
+            inner,
+            duration: self.duration,
+            status: self.status,
+        }
+    }
+}
+
+/// The service produced by `TimeoutLayer`.
+#[derive(Clone)]
+pub struct Timeout<S> {
+    inner: S,
+    duration: Duration,
+    status: StatusCode,
+}
+
+impl<S> TowerService<Request> for Timeout<S>
+where
+    S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>,
+    S::Future: Send + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let fut = tokio::time::timeout(self.duration, self.inner.call(req));
+        let status = self.status;
+        Box::pin(async move {
+            match fut.await {
+                Ok(res) => res,
+                Err(_) => Ok(ServiceResponse::from_status(status)),
+            }
+        })
+    }
+}
@@ -0,0 +1,144 @@
+use std::{fmt, future::Future};
+
+#[cfg(feature = "tracing")]
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[cfg(feature = "tracing")]
+use futures::{FutureExt, Stream, StreamExt};
+#[cfg(feature = "tracing")]
+use tracing::{Instrument, Span, field};
+
+#[cfg(feature = "tracing")]
+use crate::{
+    BodyInner, BoxedBodyStream, RequestId, ServiceResponse, X_REQUEST_ID, make_body_from_stream,
+};
+use crate::{Request, ServiceBoxFuture, ServiceError};
+
+#[cfg(feature = "tracing")]
+pub(crate) type RequestSpan = Span;
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct RequestSpan;
+
+#[cfg(feature = "tracing")]
+pub(crate) fn request_span(req: &Request) -> RequestSpan {
+    let span = tracing::info_span!(
+        "request",
+        method = %req.method(),
+        path = %req.uri().path(),
+        request_id = field::Empty,
+    );
+    let id = match RequestId::from_request(req) {
+        Some(id) => Some(id.as_str()),
+        None => req
+            .headers()
+            .get(X_REQUEST_ID)
+            .and_then(|v| v.to_str().ok()),
+    };
+    if let Some(id) = id {
+        span.record("request_id", id);
+    }
+    span
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn request_span(_req: &Request) -> RequestSpan {
+    RequestSpan
+}
+
+/// Runs routing inside the span and keeps it attached to the service future and response body.
+#[cfg(feature = "tracing")]
+pub(crate) fn in_span(span: RequestSpan, f: impl FnOnce() -> ServiceBoxFuture) -> ServiceBoxFuture {
+    let fut = span.in_scope(f);
+    let body_span = span.clone();
+    Box::pin(
+        fut.map(|res| {
+            res.map(|resp| {
+                let (parts, body) = resp.into_parts();
+                let body = InstrumentedBody {
+                    inner: Box::new(body),
+                    span: body_span,
+                };
+                ServiceResponse::from_parts(parts, make_body_from_stream(body))
+            })
+        })
+        .instrument(span),
+    )
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn in_span(
+    _span: RequestSpan,
+    f: impl FnOnce() -> ServiceBoxFuture,
+) -> ServiceBoxFuture {
+    f()
+}
+
+pub(crate) fn service_error(e: &ServiceError) {
+    #[cfg(feature = "tracing")]
+    tracing::error!(error = %e, "service failed");
+    #[cfg(not(feature = "tracing"))]
+    println!("{e}");
+}
+
+pub(crate) fn service_panic(message: &str) {
+    #[cfg(feature = "tracing")]
+    tracing::error!(panic = message, "service panicked");
+    #[cfg(not(feature = "tracing"))]
+    println!("service panicked: {message}");
+}
+
+/// A failure the server recovered from (by falling back, failing open or dropping
+/// the work) that an operator should still hear about.
+pub(crate) fn warn(message: &str, e: &dyn fmt::Display) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(error = %e, "{message}");
+    #[cfg(not(feature = "tracing"))]
+    println!("{message}: {e}");
+}
+
+/// A failure on one connection, usually the client's doing (a reset, a bad TLS
+/// handshake). Only reported through `tracing`, since busy servers see plenty.
+pub(crate) fn connection_error(message: &str, e: &dyn fmt::Display) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(error = %e, "{message}");
+    #[cfg(not(feature = "tracing"))]
+    let _ = (message, e);
+}
+
+pub(crate) fn invalid_cookie(name: &str) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(cookie = name, "dropping cookie with an invalid value");
+    #[cfg(not(feature = "tracing"))]
+    println!("dropping cookie with an invalid value: {name}");
+}
+
+/// Carries the current request span into work spawned on its behalf.
+pub(crate) fn spawn<F>(fut: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    #[cfg(feature = "tracing")]
+    tokio::spawn(fut.in_current_span());
+    #[cfg(not(feature = "tracing"))]
+    tokio::spawn(fut);
+}
+
+#[cfg(feature = "tracing")]
+struct InstrumentedBody {
+    inner: BoxedBodyStream,
+    span: Span,
+}
+
+#[cfg(feature = "tracing")]
+impl Stream for InstrumentedBody {
+    type Item = BodyInner;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<BodyInner>> {
+        let this = &mut *self;
+        let _enter = this.span.enter();
+        this.inner.poll_next_unpin(cx)
+    }
+}
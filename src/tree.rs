@@ -0,0 +1,546 @@
+use std::{error, fmt, sync::Arc};
+
+use hyper::Method;
+use tower::{Service as TowerService, ServiceExt, util::BoxCloneSyncService};
+
+use crate::{
+    DynService, IntoResponse, NOT_FOUND, NotHandled, PathParams, Request, ServiceBoxFuture,
+    ServiceError, ServiceResponse, options_response,
+    path::{Segment, parse_template, record_match, split_path},
+    pattern::Pattern,
+    with_implied_methods, without_body,
+};
+
+/// The error for a route that overlaps one already in a `RouteTree`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteConflict {
+    /// The template being inserted.
+    pub template: String,
+    /// The template it conflicts with.
+    pub existing: String,
+}
+
+impl fmt::Display for RouteConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "route `{}` conflicts with existing route `{}`",
+            self.template, self.existing
+        )
+    }
+}
+
+impl error::Error for RouteConflict {}
+
+#[derive(Clone)]
+struct Endpoint {
+    methods: Option<Vec<Method>>,
+    service: DynService,
+}
+
+#[derive(Clone, Default)]
+struct Node {
+    statics: Vec<(String, Node)>,
+    constrained: Vec<(String, Arc<Pattern>, Node)>,
+    param: Option<(String, Box<Node>)>,
+    catch_all: Option<(String, Box<Node>)>,
+    template: Option<Arc<str>>,
+    endpoints: Vec<Endpoint>,
+}
+
+impl Node {
+    fn endpoint(&self, method: &Method) -> Option<&Endpoint> {
+        self.endpoints.iter().find(|e| {
+            e.methods
+                .as_ref()
+                .is_none_or(|methods| methods.contains(method))
+        })
+    }
+
+    fn endpoint_methods(&self, allowed: &mut Vec<Method>) {
+        for method in self
+            .endpoints
+            .iter()
+            .flat_map(|e| e.methods.iter().flatten())
+        {
+            if !allowed.contains(method) {
+                allowed.push(method.clone());
+            }
+        }
+    }
+
+    /// Every method registered anywhere below this node, for `OPTIONS *`.
+    fn collect_methods(&self, allowed: &mut Vec<Method>) {
+        self.endpoint_methods(allowed);
+        let children = self
+            .statics
+            .iter()
+            .map(|(_, child)| child)
+            .chain(self.constrained.iter().map(|(_, _, child)| child))
+            .chain(self.param.iter().map(|(_, child)| &**child))
+            .chain(self.catch_all.iter().map(|(_, child)| &**child));
+        for child in children {
+            child.collect_methods(allowed);
+        }
+    }
+
+    /// The template a new route at `segments` would conflict with, if any.
+    fn conflict(&self, segments: &[Segment], methods: Option<&[Method]>) -> Option<String> {
+        let Some((segment, rest)) = segments.split_first() else {
+            let overlapping = self.endpoints.iter().any(|e| match (&e.methods, methods) {
+                (Some(a), Some(b)) => a.iter().any(|m| b.contains(m)),
+                _ => true,
+            });
+            return self
+                .template
+                .as_deref()
+                .filter(|_| overlapping)
+                .map(str::to_string);
+        };
+        let (existing, child) = match segment {
+            Segment::Static(part) => {
+                let (_, child) = self.statics.iter().find(|(s, _)| s == part)?;
+                return child.conflict(rest, methods);
+            }
+            Segment::Constrained(name, pattern) => {
+                let (existing, pattern, child) =
+                    self.constrained.iter().find(|(_, p, _)| p == pattern)?;
+                if existing != name {
+                    let segment = format!("{{{existing}:{}}}", pattern.as_str());
+                    return Some(describe(child, &segment));
+                }
+                return child.conflict(rest, methods);
+            }
+            Segment::Param(name) => match &self.param {
+                Some((existing, child)) if existing != name => (format!(":{existing}"), child),
+                param => return param.as_ref()?.1.conflict(rest, methods),
+            },
+            Segment::CatchAll(name) => match &self.catch_all {
+                Some((existing, child)) if existing != name => (format!("*{existing}"), child),
+                catch_all => return catch_all.as_ref()?.1.conflict(rest, methods),
+            },
+        };
+        Some(describe(child, &existing))
+    }
+
+    /// The node for `segments`, created as needed. Callers check `conflict` first.
+    fn insert(&mut self, segments: Vec<Segment>) -> &mut Node {
+        let mut node = self;
+        for segment in segments {
+            node = match segment {
+                Segment::Static(part) => {
+                    let i = match node.statics.iter().position(|(s, _)| *s == part) {
+                        Some(i) => i,
+                        None => {
+                            node.statics.push((part, Node::default()));
+                            node.statics.len() - 1
+                        }
+                    };
+                    &mut node.statics[i].1
+                }
+                Segment::Constrained(name, pattern) => {
+                    let i = match node.constrained.iter().position(|(_, p, _)| *p == pattern) {
+                        Some(i) => i,
+                        None => {
+                            node.constrained.push((name, pattern, Node::default()));
+                            node.constrained.len() - 1
+                        }
+                    };
+                    &mut node.constrained[i].2
+                }
+                Segment::Param(name) => {
+                    &mut node.param.get_or_insert_with(|| (name, Box::default())).1
+                }
+                Segment::CatchAll(name) => {
+                    &mut node
+                        .catch_all
+                        .get_or_insert_with(|| (name, Box::default()))
+                        .1
+                }
+            };
+        }
+        node
+    }
+
+    /// Candidates are tried from most to least specific: static segments, constrained
+    /// parameters in registration order, plain parameters, then the catch-all,
+    /// backtracking whenever a branch has no match further down.
+    fn find<'a>(&'a self, parts: &[&str], params: &mut Vec<(String, String)>) -> Option<&'a Node> {
+        let Some((part, rest)) = parts.split_first() else {
+            if self.template.is_some() {
+                return Some(self);
+            }
+            return self.find_catch_all(parts, params);
+        };
+        if let Some((_, child)) = self.statics.iter().find(|(s, _)| s == part)
+            && let Some(found) = child.find(rest, params)
+        {
+            return Some(found);
+        }
+        if !part.is_empty() {
+            for (name, pattern, child) in &self.constrained {
+                if pattern.is_match(part)
+                    && let Some(found) = child.descend(name, part, rest, params)
+                {
+                    return Some(found);
+                }
+            }
+            if let Some((name, child)) = &self.param
+                && let Some(found) = child.descend(name, part, rest, params)
+            {
+                return Some(found);
+            }
+        }
+        self.find_catch_all(parts, params)
+    }
+
+    fn descend<'a>(
+        &'a self,
+        name: &str,
+        part: &str,
+        rest: &[&str],
+        params: &mut Vec<(String, String)>,
+    ) -> Option<&'a Node> {
+        params.push((name.to_string(), part.to_string()));
+        let found = self.find(rest, params);
+        if found.is_none() {
+            params.pop();
+        }
+        found
+    }
+
+    fn find_catch_all<'a>(
+        &'a self,
+        parts: &[&str],
+        params: &mut Vec<(String, String)>,
+    ) -> Option<&'a Node> {
+        let (name, child) = self.catch_all.as_ref()?;
+        child.template.as_ref()?;
+        params.push((name.clone(), parts.join("/")));
+        Some(child)
+    }
+}
+
+/// Routes by walking a tree of path segments, so dispatch cost follows the path length
+/// rather than the number of routes. Templates use the same syntax as `PathRouter`.
+#[derive(Clone)]
+pub struct RouteTree {
+    root: Node,
+    fallback: DynService,
+}
+
+impl Default for RouteTree {
+    fn default() -> RouteTree {
+        RouteTree::new()
+    }
+}
+
+impl RouteTree {
+    /// An empty tree that answers 404.
+    pub fn new() -> RouteTree {
+        RouteTree {
+            root: Node::default(),
+            fallback: BoxCloneSyncService::new(NOT_FOUND),
+        }
+    }
+
+    /// Panics on conflicting routes; use `try_insert` to handle them.
+    pub fn with_route<S>(mut self, template: &str, service: S) -> RouteTree
+    where
+        S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        if let Err(e) = self.try_insert(template, None, service) {
+            panic!("{e}");
+        }
+        self
+    }
+
+    /// Adds a route answering only `methods` at `template`. Panics on conflicting routes.
+    pub fn with_method_route<S>(
+        mut self,
+        template: &str,
+        methods: impl IntoIterator<Item = Method>,
+        service: S,
+    ) -> RouteTree
+    where
+        S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        let methods = methods.into_iter().collect();
+        if let Err(e) = self.try_insert(template, Some(methods), service) {
+            panic!("{e}");
+        }
+        self
+    }
+
+    /// Sets the service for requests no route matches.
+    pub fn with_fallback<S>(mut self, fallback: S) -> RouteTree
+    where
+        S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.fallback = BoxCloneSyncService::new(fallback);
+        self
+    }
+
+    /// `methods: None` accepts every method. Two routes conflict when they would match
+    /// the same paths for a common method, or name a parameter differently at the
+    /// same position. Constrained parameters with different patterns may overlap; the
+    /// first registered wins.
+    pub fn try_insert<S>(
+        &mut self,
+        template: &str,
+        methods: Option<Vec<Method>>,
+        service: S,
+    ) -> Result<(), RouteConflict>
+    where
+        S: TowerService<Request, Response = ServiceResponse, Error = ServiceError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        // Checking before inserting leaves the tree untouched on conflict.
+        let segments = parse_template(template);
+        if let Some(existing) = self.root.conflict(&segments, methods.as_deref()) {
+            return Err(RouteConflict {
+                template: template.to_string(),
+                existing,
+            });
+        }
+        let node = self.root.insert(segments);
+        node.template.get_or_insert_with(|| Arc::from(template));
+        node.endpoints.push(Endpoint {
+            methods,
+            service: BoxCloneSyncService::new(service),
+        });
+        Ok(())
+    }
+}
+
+/// Some template registered below a parameter, for conflict messages.
+fn describe(node: &Node, segment: &str) -> String {
+    match &node.template {
+        Some(template) => template.to_string(),
+        None => format!(".../{segment}/..."),
+    }
+}
+
+impl TowerService<Request> for RouteTree {
+    type Response = ServiceResponse;
+    type Error = ServiceError;
+    type Future = ServiceBoxFuture;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        let parts: Vec<&str> = split_path(req.uri().path()).collect();
+        let mut params = vec![];
+        if req.method() == Method::OPTIONS && req.uri().path() == "*" {
+            let mut allowed = vec![];
+            self.root.collect_methods(&mut allowed);
+            let resp = options_response(&with_implied_methods(allowed));
+            return Box::pin(async { Ok(resp) });
+        }
+        let Some(node) = self.root.find(&parts, &mut params) else {
+            return Box::pin(self.fallback.clone().oneshot(req));
+        };
+        let mut head = false;
+        let mut endpoint = node.endpoint(req.method());
+        if endpoint.is_none() && req.method() == Method::HEAD {
+            endpoint = node.endpoint(&Method::GET);
+            head = endpoint.is_some();
+        }
+        let Some(endpoint) = endpoint else {
+            let mut allowed: Vec<Method> = vec![];
+            node.endpoint_methods(&mut allowed);
+            let allowed = with_implied_methods(allowed);
+            if req.method() == Method::OPTIONS {
+                let resp = options_response(&allowed);
+                return Box::pin(async { Ok(resp) });
+            }
+            let reason = NotHandled::MethodNotAllowed(allowed);
+            return Box::pin(async move { Ok(reason.into_response()) });
+        };
+        let service = endpoint.service.clone();
+        let template = node.template.clone().expect("endpoints have a template");
+        let mut matched = PathParams::default();
+        for (name, value) in params {
+            matched.push(name, value);
+        }
+        record_match(&mut req, matched, &template);
+        let fut = service.oneshot(req);
+        match head {
+            true => Box::pin(async move { Ok(without_body(fut.await?)) }),
+            false => Box::pin(fut),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower::{Layer, ServiceExt, limit::ConcurrencyLimitLayer};
+
+    use super::*;
+    use crate::{
+        PathEqRouter, ResponseExt, Route, ServiceBuilder, service_fn,
+        testing::{TestClient, TestRequestExt},
+    };
+
+    fn limited() -> impl TowerService<
+        Request,
+        Response = ServiceResponse,
+        Error = ServiceError,
+        Future: Send + 'static,
+    > + Clone
+    + Send
+    + Sync
+    + 'static {
+        // `ConcurrencyLimit` panics when called without a permit from `poll_ready`.
+        ConcurrencyLimitLayer::new(1).layer(service_fn(|_: Request| async {
+            Ok::<_, ServiceError>(ServiceResponse::from_string("ok"))
+        }))
+    }
+
+    fn named(name: &'static str) -> DynService {
+        BoxCloneSyncService::new(service_fn(move |_: Request| async move {
+            Ok::<_, ServiceError>(ServiceResponse::from_string(name))
+        }))
+    }
+
+    async fn body(tree: &RouteTree, method: Method, path: &str) -> String {
+        let resp = tree
+            .clone()
+            .oneshot(Request::test(method, path))
+            .await
+            .unwrap();
+        String::from_utf8(resp.into_bytes().await.unwrap().to_vec()).unwrap()
+    }
+
+    fn size(node: &Node) -> usize {
+        let children = node
+            .statics
+            .iter()
+            .map(|(_, child)| child)
+            .chain(node.constrained.iter().map(|(_, _, child)| child))
+            .chain(node.param.iter().map(|(_, child)| &**child))
+            .chain(node.catch_all.iter().map(|(_, child)| &**child));
+        1 + node.endpoints.len() + children.map(size).sum::<usize>()
+    }
+
+    #[tokio::test]
+    async fn specific_segments_win() {
+        let tree = RouteTree::new()
+            .with_route("/files/*rest", named("catch-all"))
+            .with_route("/files/:id", named("param"))
+            .with_route("/files/{id:[0-9]+}", named("constrained"))
+            .with_route("/files/new", named("static"));
+        assert_eq!(body(&tree, Method::GET, "/files/new").await, "static");
+        assert_eq!(body(&tree, Method::GET, "/files/42").await, "constrained");
+        assert_eq!(body(&tree, Method::GET, "/files/abc").await, "param");
+        assert_eq!(body(&tree, Method::GET, "/files/a/b").await, "catch-all");
+    }
+
+    #[tokio::test]
+    async fn methods_split_a_template() {
+        let mut tree = RouteTree::new()
+            .with_method_route("/items", [Method::GET], named("list"))
+            .with_method_route("/items", [Method::POST], named("create"));
+        assert_eq!(body(&tree, Method::GET, "/items").await, "list");
+        assert_eq!(body(&tree, Method::POST, "/items").await, "create");
+        let e = tree
+            .try_insert("/items", Some(vec![Method::POST]), named("again"))
+            .unwrap_err();
+        assert_eq!(e.existing, "/items");
+    }
+
+    #[tokio::test]
+    async fn conflicts_leave_the_tree_unchanged() {
+        let mut tree = RouteTree::new()
+            .with_route("/users/:id", named("user"))
+            .with_route("/users/:id/posts", named("posts"))
+            .with_route("/blobs/{key:[a-f]+}", named("blob"))
+            .with_route("/static/*path", named("static"));
+        let before = size(&tree.root);
+        for template in [
+            "/users/:id",
+            "/users/:name/posts/new",
+            "/users/:name/extra/deep",
+            "/blobs/{hash:[a-f]+}/meta",
+            "/static/*rest",
+        ] {
+            let e = tree.try_insert(template, None, named("new")).unwrap_err();
+            assert_eq!(e.template, template);
+            assert_eq!(size(&tree.root), before, "{template}");
+        }
+        let e = tree
+            .try_insert("/users/:name", None, named("new"))
+            .unwrap_err();
+        assert_eq!(e.existing, "/users/:id");
+        assert_eq!(body(&tree, Method::GET, "/users/7/posts").await, "posts");
+        assert_eq!(
+            body(&tree, Method::GET, "/users/7/posts/new").await,
+            "404 Not Found"
+        );
+
+        tree.try_insert("/users/:id/posts/new", None, named("new"))
+            .unwrap();
+        assert_eq!(body(&tree, Method::GET, "/users/7/posts/new").await, "new");
+    }
+
+    #[tokio::test]
+    async fn tree_routes_are_polled_ready() {
+        let tree = RouteTree::new()
+            .with_route("/limited", limited())
+            .with_fallback(limited());
+        for path in ["/limited", "/limited", "/elsewhere"] {
+            let resp = tree
+                .clone()
+                .oneshot(Request::test(Method::GET, path))
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), hyper::StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn service_routes_are_polled_ready() {
+        let route = Route::from_parts(PathEqRouter::new("/limited"), limited()).make_dyn();
+        let resp = route
+            .call(Request::test(Method::GET, "/limited"))
+            .ok()
+            .unwrap()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), hyper::StatusCode::OK);
+
+        let service = ServiceBuilder::new().with_dyn_route(route).build();
+        let client = TestClient::new(service);
+        for _ in 0..2 {
+            client
+                .get("/limited")
+                .send()
+                .await
+                .assert_status(hyper::StatusCode::OK);
+        }
+    }
+}
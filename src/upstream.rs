@@ -0,0 +1,358 @@
+use std::{
+    sync::{
+        Arc, Mutex, OnceLock, Weak,
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use hyper::Uri;
+use tokio::time::Instant;
+
+use crate::{Client, InvalidUpstream};
+
+/// How an `UpstreamPool` picks the upstream for each request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Balance {
+    /// Each upstream in turn.
+    #[default]
+    RoundRobin,
+    /// The upstream with the fewest requests in flight.
+    LeastConnections,
+    /// Smooth weighted round robin, so heavier upstreams are interleaved rather
+    /// than picked in bursts.
+    Weighted,
+}
+
+/// An HTTP server requests can be proxied to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Upstream {
+    authority: Arc<str>,
+    base_path: Arc<str>,
+    weight: u32,
+}
+
+impl Upstream {
+    /// An upstream at `url`, an `http://` URL whose path becomes a prefix for proxied paths.
+    pub fn new(url: &str) -> Result<Upstream, InvalidUpstream> {
+        let invalid = || InvalidUpstream(url.to_string());
+        let uri: Uri = url.parse().map_err(|_| invalid())?;
+        let (Some("http"), Some(authority)) = (uri.scheme_str(), uri.authority()) else {
+            return Err(invalid());
+        };
+        Ok(Upstream {
+            authority: Arc::from(authority.as_str()),
+            base_path: Arc::from(uri.path().trim_end_matches('/')),
+            weight: 1,
+        })
+    }
+
+    /// Sets the weight used by `Balance::Weighted`; at least 1.
+    pub fn with_weight(mut self, weight: u32) -> Upstream {
+        self.weight = weight.max(1);
+        self
+    }
+
+    /// The host and port requests are sent to.
+    pub fn authority(&self) -> &str {
+        &self.authority
+    }
+
+    /// The path prefix, without a trailing slash.
+    pub fn base_path(&self) -> &str {
+        &self.base_path
+    }
+
+    /// The weight used by `Balance::Weighted`.
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+}
+
+#[derive(Clone, Debug)]
+struct HealthCheck {
+    path: String,
+    interval: Duration,
+    timeout: Duration,
+}
+
+struct Member {
+    upstream: Upstream,
+    active: AtomicUsize,
+    failures: AtomicU32,
+    healthy: AtomicBool,
+    ejected_until: Mutex<Option<Instant>>,
+}
+
+impl Member {
+    fn available(&self, now: Instant) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+            && self
+                .ejected_until
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .is_none_or(|until| now >= until)
+    }
+}
+
+struct PoolInner {
+    members: Vec<Member>,
+    balance: Balance,
+    next: AtomicUsize,
+    current_weights: Mutex<Vec<i64>>,
+    max_failures: u32,
+    eject_for: Duration,
+    health_check: Option<HealthCheck>,
+    checker: OnceLock<()>,
+    client: Client,
+}
+
+/// A set of interchangeable upstreams for `Proxy::from_pool`. Upstreams are ejected
+/// after `max_failures` consecutive connection errors or timeouts and readmitted once
+/// `eject_for` has passed, or as soon as an active health check succeeds. When every
+/// upstream is out, requests are spread over all of them rather than refused.
+#[derive(Clone)]
+pub struct UpstreamPool {
+    inner: Arc<PoolInner>,
+}
+
+/// Configures an `UpstreamPool`, created with `UpstreamPool::builder`.
+pub struct UpstreamPoolBuilder {
+    upstreams: Vec<Upstream>,
+    balance: Balance,
+    max_failures: u32,
+    eject_for: Duration,
+    health_check: Option<HealthCheck>,
+    client: Option<Client>,
+}
+
+impl UpstreamPool {
+    /// An empty pool using round robin, ejecting upstreams for 30 seconds after 3 failures.
+    pub fn builder() -> UpstreamPoolBuilder {
+        UpstreamPoolBuilder {
+            upstreams: vec![],
+            balance: Balance::RoundRobin,
+            max_failures: 3,
+            eject_for: Duration::from_secs(30),
+            health_check: None,
+            client: None,
+        }
+    }
+
+    /// The upstreams not currently ejected.
+    pub fn healthy(&self) -> Vec<&Upstream> {
+        let now = Instant::now();
+        self.inner
+            .members
+            .iter()
+            .filter(|m| m.available(now))
+            .map(|m| &m.upstream)
+            .collect()
+    }
+
+    pub(crate) fn client(&self) -> &Client {
+        &self.inner.client
+    }
+
+    pub(crate) fn pick(&self) -> Lease {
+        self.start_health_checks();
+        let inner = &self.inner;
+        let now = Instant::now();
+        let mut candidates: Vec<usize> = (0..inner.members.len())
+            .filter(|&i| inner.members[i].available(now))
+            .collect();
+        if candidates.is_empty() {
+            candidates = (0..inner.members.len()).collect();
+        }
+        let turn = inner.next.fetch_add(1, Ordering::Relaxed);
+        let index = match inner.balance {
+            Balance::RoundRobin => candidates[turn % candidates.len()],
+            Balance::LeastConnections => {
+                // Rotate the starting point so ties don't all land on the first upstream.
+                let offset = turn % candidates.len();
+                let rotated = candidates[offset..].iter().chain(&candidates[..offset]);
+                *rotated
+                    .min_by_key(|&&i| inner.members[i].active.load(Ordering::Relaxed))
+                    .expect("pool has upstreams")
+            }
+            Balance::Weighted => {
+                let mut current = inner
+                    .current_weights
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner());
+                let total: i64 = candidates
+                    .iter()
+                    .map(|&i| inner.members[i].upstream.weight as i64)
+                    .sum();
+                for &i in &candidates {
+                    current[i] += inner.members[i].upstream.weight as i64;
+                }
+                let best = *candidates
+                    .iter()
+                    .max_by_key(|&&i| (current[i], std::cmp::Reverse(i)))
+                    .expect("pool has upstreams");
+                current[best] -= total;
+                best
+            }
+        };
+        inner.members[index].active.fetch_add(1, Ordering::Relaxed);
+        Lease {
+            pool: self.clone(),
+            index,
+        }
+    }
+
+    fn start_health_checks(&self) {
+        let Some(check) = self.inner.health_check.clone() else {
+            return;
+        };
+        self.inner.checker.get_or_init(|| {
+            let pool = Arc::downgrade(&self.inner);
+            tokio::spawn(run_health_checks(pool, check));
+        });
+    }
+}
+
+impl UpstreamPoolBuilder {
+    /// Adds an upstream.
+    pub fn with_upstream(mut self, upstream: Upstream) -> UpstreamPoolBuilder {
+        self.upstreams.push(upstream);
+        self
+    }
+
+    /// Sets how upstreams are picked.
+    pub fn with_balance(mut self, balance: Balance) -> UpstreamPoolBuilder {
+        self.balance = balance;
+        self
+    }
+
+    /// Consecutive failures before an upstream is ejected, and for how long.
+    pub fn with_ejection(mut self, max_failures: u32, eject_for: Duration) -> UpstreamPoolBuilder {
+        self.max_failures = max_failures.max(1);
+        self.eject_for = eject_for;
+        self
+    }
+
+    /// Probes `path` on every upstream each `interval`; any 2xx or 3xx status counts
+    /// as healthy. Checks start with the first proxied request.
+    pub fn with_health_check(
+        mut self,
+        path: impl Into<String>,
+        interval: Duration,
+    ) -> UpstreamPoolBuilder {
+        self.health_check = Some(HealthCheck {
+            path: path.into(),
+            interval,
+            timeout: interval.min(Duration::from_secs(5)),
+        });
+        self
+    }
+
+    /// Defaults to a client without its own timeout, leaving that to `Proxy`.
+    pub fn with_client(mut self, client: Client) -> UpstreamPoolBuilder {
+        self.client = Some(client);
+        self
+    }
+
+    /// Panics without any upstreams.
+    pub fn build(self) -> UpstreamPool {
+        assert!(
+            !self.upstreams.is_empty(),
+            "an upstream pool needs at least one upstream"
+        );
+        let members: Vec<Member> = self
+            .upstreams
+            .into_iter()
+            .map(|upstream| Member {
+                upstream,
+                active: AtomicUsize::new(0),
+                failures: AtomicU32::new(0),
+                healthy: AtomicBool::new(true),
+                ejected_until: Mutex::new(None),
+            })
+            .collect();
+        let client = self
+            .client
+            .unwrap_or_else(|| Client::builder().with_timeout(None).build());
+        UpstreamPool {
+            inner: Arc::new(PoolInner {
+                current_weights: Mutex::new(vec![0; members.len()]),
+                members,
+                balance: self.balance,
+                next: AtomicUsize::new(0),
+                max_failures: self.max_failures,
+                eject_for: self.eject_for,
+                health_check: self.health_check,
+                checker: OnceLock::new(),
+                client,
+            }),
+        }
+    }
+}
+
+async fn run_health_checks(pool: Weak<PoolInner>, check: HealthCheck) {
+    let mut interval = tokio::time::interval(check.interval);
+    loop {
+        interval.tick().await;
+        let Some(pool) = pool.upgrade() else {
+            return;
+        };
+        for member in &pool.members {
+            let uri = format!(
+                "http://{}{}{}",
+                member.upstream.authority, member.upstream.base_path, check.path
+            );
+            let healthy = matches!(
+                tokio::time::timeout(check.timeout, pool.client.get(&uri)).await,
+                Ok(Ok(resp)) if resp.status().is_success() || resp.status().is_redirection()
+            );
+            member.healthy.store(healthy, Ordering::Relaxed);
+            if healthy {
+                member.failures.store(0, Ordering::Relaxed);
+                *member
+                    .ejected_until
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner()) = None;
+            }
+        }
+    }
+}
+
+/// One request's hold on an upstream, counted for least-connections until dropped.
+pub(crate) struct Lease {
+    pool: UpstreamPool,
+    index: usize,
+}
+
+impl Lease {
+    pub(crate) fn upstream(&self) -> &Upstream {
+        &self.pool.inner.members[self.index].upstream
+    }
+
+    pub(crate) fn succeeded(&self) {
+        self.pool.inner.members[self.index]
+            .failures
+            .store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn failed(&self) {
+        let inner = &self.pool.inner;
+        let member = &inner.members[self.index];
+        let failures = member.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= inner.max_failures {
+            member.failures.store(0, Ordering::Relaxed);
+            *member
+                .ejected_until
+                .lock()
+                .unwrap_or_else(|e| e.into_inner()) = Some(Instant::now() + inner.eject_for);
+        }
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.pool.inner.members[self.index]
+            .active
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
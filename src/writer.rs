@@ -0,0 +1,123 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll, ready},
+};
+
+use bytes::Bytes;
+use futures::Stream;
+use hyper::body::Frame;
+use tokio::{io::AsyncWrite, sync::mpsc};
+use tokio_util::sync::PollSender;
+
+use crate::BodyInner;
+
+/// Chunks buffered between the writer and the connection before writes wait.
+const CAPACITY: usize = 8;
+/// Larger writes are split, so one write can't buffer an unbounded amount.
+const MAX_CHUNK: usize = 64 * 1024;
+
+/// The sending half of a response from `ResponseExt::writer`. Writes wait while the
+/// client is behind and fail with `BrokenPipe` once it has gone. Call `shutdown` to
+/// end the body; a writer dropped before that aborts the response, so the client can
+/// tell it was cut short.
+pub struct BodyWriter {
+    tx: PollSender<BodyInner>,
+    finished: Arc<AtomicBool>,
+}
+
+pub(crate) fn channel() -> (BodyWriter, impl Stream<Item = BodyInner> + Send + 'static) {
+    let (tx, mut rx) = mpsc::channel(CAPACITY);
+    let finished = Arc::new(AtomicBool::new(false));
+    let writer = BodyWriter {
+        tx: PollSender::new(tx),
+        finished: finished.clone(),
+    };
+    let mut ended = false;
+    let body = futures::stream::poll_fn(move |cx| {
+        if ended {
+            return Poll::Ready(None);
+        }
+        match ready!(rx.poll_recv(cx)) {
+            Some(frame) => Poll::Ready(Some(frame)),
+            None => {
+                ended = true;
+                match finished.load(Ordering::Acquire) {
+                    true => Poll::Ready(None),
+                    false => Poll::Ready(Some(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "response writer dropped before shutdown",
+                    )))),
+                }
+            }
+        }
+    });
+    (writer, body)
+}
+
+fn disconnected() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected")
+}
+
+impl BodyWriter {
+    /// Sends `bytes` as one chunk, without copying it.
+    pub async fn send(&mut self, bytes: Bytes) -> io::Result<()> {
+        futures::future::poll_fn(|cx| self.tx.poll_reserve(cx))
+            .await
+            .map_err(|_| disconnected())?;
+        self.tx
+            .send_item(Ok(Frame::data(bytes)))
+            .map_err(|_| disconnected())
+    }
+
+    /// Ends the response with an error, which resets the stream rather than ending
+    /// the body cleanly.
+    pub async fn abort(mut self, e: io::Error) {
+        if futures::future::poll_fn(|cx| self.tx.poll_reserve(cx))
+            .await
+            .is_ok()
+        {
+            self.tx.send_item(Err(e)).ok();
+        }
+    }
+
+    /// Whether the client has gone away, so further writes would fail.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
+
+impl AsyncWrite for BodyWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        ready!(self.tx.poll_reserve(cx)).map_err(|_| disconnected())?;
+        let len = buf.len().min(MAX_CHUNK);
+        let frame = Frame::data(Bytes::copy_from_slice(&buf[..len]));
+        self.tx.send_item(Ok(frame)).map_err(|_| disconnected())?;
+        Poll::Ready(Ok(len))
+    }
+
+    /// Chunks are handed to the connection as soon as they are written.
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.tx.is_closed() {
+            true => Poll::Ready(Err(disconnected())),
+            false => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.finished.store(true, Ordering::Release);
+        self.tx.close();
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,636 @@
+//! WebSocket upgrades (RFC 6455) and a message-level connection type.
+
+use std::{future::Future, io};
+
+use bytes::{Buf, Bytes, BytesMut};
+use hyper::{
+    Method, StatusCode,
+    header::{self, HeaderMap, HeaderValue},
+    upgrade::OnUpgrade,
+};
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{BoxedIo, Error, Request, ResponseExt, ServiceResponse, base64, sha1::sha1, trace};
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Whether `req` asks to upgrade to a WebSocket.
+pub fn is_upgrade_request(req: &Request) -> bool {
+    header_has_token(req.headers(), header::CONNECTION, "upgrade")
+        && header_has_token(req.headers(), header::UPGRADE, "websocket")
+}
+
+fn header_has_token(headers: &HeaderMap, name: header::HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+/// A validated WebSocket handshake, answered with `on_upgrade`.
+pub struct WebSocketUpgrade {
+    accept: HeaderValue,
+    protocols: Vec<String>,
+    protocol: Option<HeaderValue>,
+    max_message_size: usize,
+    on_upgrade: OnUpgrade,
+}
+
+impl WebSocketUpgrade {
+    /// Checks the handshake headers of `req` and takes hold of its upgrade.
+    pub fn from_request(req: &mut Request) -> Result<WebSocketUpgrade, Error> {
+        if req.method() != Method::GET {
+            return Err(Error::WebSocketHandshake("request method must be GET"));
+        }
+        if !is_upgrade_request(req) {
+            return Err(Error::WebSocketHandshake(
+                "missing websocket upgrade headers",
+            ));
+        }
+        if req.headers().get(header::SEC_WEBSOCKET_VERSION) != Some(&HeaderValue::from_static("13"))
+        {
+            return Err(Error::WebSocketHandshake("unsupported websocket version"));
+        }
+        let key = req
+            .headers()
+            .get(header::SEC_WEBSOCKET_KEY)
+            .ok_or(Error::WebSocketHandshake("missing Sec-WebSocket-Key"))?;
+
+        let mut input = key.as_bytes().to_vec();
+        input.extend_from_slice(ACCEPT_GUID.as_bytes());
+        let accept = HeaderValue::from_str(&base64::encode(&sha1(&input)))
+            .expect("base64 is a valid header value");
+
+        let protocols = req
+            .headers()
+            .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect();
+
+        Ok(WebSocketUpgrade {
+            accept,
+            protocols,
+            protocol: None,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            on_upgrade: hyper::upgrade::on(req),
+        })
+    }
+
+    /// The subprotocols the client offered in `Sec-WebSocket-Protocol`.
+    pub fn requested_protocols(&self) -> &[String] {
+        &self.protocols
+    }
+
+    /// Selects `protocol` if the client offered it; otherwise no subprotocol is sent.
+    pub fn with_protocol(mut self, protocol: &str) -> WebSocketUpgrade {
+        self.protocol = self
+            .protocols
+            .iter()
+            .any(|p| p == protocol)
+            .then(|| HeaderValue::from_str(protocol).ok())
+            .flatten();
+        self
+    }
+
+    /// Fails messages larger than `max_message_size` bytes once reassembled.
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> WebSocketUpgrade {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// The `101 Switching Protocols` response; `f` runs with the socket once the upgrade completes.
+    pub fn on_upgrade<F, Fut>(self, f: F) -> ServiceResponse
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let WebSocketUpgrade {
+            accept,
+            protocol,
+            max_message_size,
+            on_upgrade,
+            ..
+        } = self;
+
+        trace::spawn(async move {
+            match on_upgrade.await {
+                Ok(upgraded) => {
+                    let io = Box::new(TokioIo::new(upgraded));
+                    f(WebSocket::new(io, max_message_size)).await
+                }
+                Err(e) => trace::connection_error("websocket upgrade failed", &e),
+            }
+        });
+
+        let resp = ServiceResponse::empty()
+            .with_status(StatusCode::SWITCHING_PROTOCOLS)
+            .with_header(header::CONNECTION, HeaderValue::from_static("upgrade"))
+            .with_header(header::UPGRADE, HeaderValue::from_static("websocket"))
+            .with_header(header::SEC_WEBSOCKET_ACCEPT, accept);
+        match protocol {
+            Some(protocol) => resp.with_header(header::SEC_WEBSOCKET_PROTOCOL, protocol),
+            None => resp,
+        }
+    }
+}
+
+/// A WebSocket message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    /// A UTF-8 text message.
+    Text(String),
+    /// A binary message.
+    Binary(Bytes),
+    /// A ping; `recv` answers it with a pong on its own.
+    Ping(Bytes),
+    /// A pong.
+    Pong(Bytes),
+    /// A close frame, with its code and reason if the peer sent them.
+    Close(Option<CloseFrame>),
+}
+
+/// The payload of a close frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloseFrame {
+    /// The status code, e.g. 1000 for a normal closure.
+    pub code: u16,
+    /// A human-readable reason.
+    pub reason: String,
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Bytes,
+}
+
+/// An established WebSocket connection.
+pub struct WebSocket {
+    io: BoxedIo,
+    buf: BytesMut,
+    partial: Option<(u8, BytesMut)>,
+    max_message_size: usize,
+    close_sent: bool,
+    closed: bool,
+}
+
+impl WebSocket {
+    fn new(io: BoxedIo, max_message_size: usize) -> WebSocket {
+        WebSocket {
+            io,
+            buf: BytesMut::new(),
+            partial: None,
+            max_message_size,
+            close_sent: false,
+            closed: false,
+        }
+    }
+
+    /// The next message, or `None` once the connection is closed. A close from the peer is acknowledged before it is returned.
+    pub async fn recv(&mut self) -> io::Result<Option<Message>> {
+        loop {
+            if self.closed {
+                return Ok(None);
+            }
+            let Some(frame) = self.read_frame().await? else {
+                self.closed = true;
+                return Ok(None);
+            };
+
+            match frame.opcode {
+                OP_TEXT | OP_BINARY if self.partial.is_some() => {
+                    return Err(protocol_error("expected a continuation frame"));
+                }
+                OP_TEXT | OP_BINARY if frame.fin => {
+                    return into_message(frame.opcode, frame.payload).map(Some);
+                }
+                OP_TEXT | OP_BINARY => {
+                    self.partial = Some((frame.opcode, BytesMut::from(&frame.payload[..])));
+                }
+                OP_CONTINUATION => {
+                    let Some((_, buf)) = self.partial.as_mut() else {
+                        return Err(protocol_error("unexpected continuation frame"));
+                    };
+                    if buf.len() + frame.payload.len() > self.max_message_size {
+                        return Err(protocol_error("message exceeds the maximum size"));
+                    }
+                    buf.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        let (opcode, buf) = self.partial.take().expect("partial message");
+                        return into_message(opcode, buf.freeze()).map(Some);
+                    }
+                }
+                OP_PING => {
+                    self.write_frame(OP_PONG, &frame.payload).await?;
+                    return Ok(Some(Message::Ping(frame.payload)));
+                }
+                OP_PONG => return Ok(Some(Message::Pong(frame.payload))),
+                OP_CLOSE => {
+                    let close = parse_close(frame.payload)?;
+                    if !self.close_sent {
+                        let code = close.as_ref().map(|c| c.code).unwrap_or(1000);
+                        self.write_frame(OP_CLOSE, &code.to_be_bytes()).await?;
+                        self.close_sent = true;
+                    }
+                    self.closed = true;
+                    return Ok(Some(Message::Close(close)));
+                }
+                _ => return Err(protocol_error("unknown opcode")),
+            }
+        }
+    }
+
+    /// Sends `msg`; fails once a close frame has been sent.
+    pub async fn send(&mut self, msg: Message) -> io::Result<()> {
+        if self.close_sent {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "websocket close frame already sent",
+            ));
+        }
+        match msg {
+            Message::Text(text) => self.write_frame(OP_TEXT, text.as_bytes()).await,
+            Message::Binary(data) => self.write_frame(OP_BINARY, &data).await,
+            Message::Ping(data) => self.write_frame(OP_PING, &data).await,
+            Message::Pong(data) => self.write_frame(OP_PONG, &data).await,
+            Message::Close(frame) => {
+                let mut payload = vec![];
+                if let Some(frame) = frame {
+                    payload.extend_from_slice(&frame.code.to_be_bytes());
+                    payload.extend_from_slice(frame.reason.as_bytes());
+                }
+                self.close_sent = true;
+                self.write_frame(OP_CLOSE, &payload).await
+            }
+        }
+    }
+
+    /// Sends a close frame with `code` and `reason`.
+    pub async fn close(&mut self, code: u16, reason: impl Into<String>) -> io::Result<()> {
+        self.send(Message::Close(Some(CloseFrame {
+            code,
+            reason: reason.into(),
+        })))
+        .await
+    }
+
+    async fn fill(&mut self, len: usize) -> io::Result<bool> {
+        while self.buf.len() < len {
+            if self.io.read_buf(&mut self.buf).await? == 0 {
+                return match self.buf.is_empty() {
+                    true => Ok(false),
+                    false => Err(io::ErrorKind::UnexpectedEof.into()),
+                };
+            }
+        }
+        Ok(true)
+    }
+
+    async fn read_frame(&mut self) -> io::Result<Option<Frame>> {
+        if !self.fill(2).await? {
+            return Ok(None);
+        }
+        let (b0, b1) = (self.buf[0], self.buf[1]);
+        if b0 & 0x70 != 0 {
+            return Err(protocol_error("reserved bits set"));
+        }
+        if b1 & 0x80 == 0 {
+            return Err(protocol_error("client frames must be masked"));
+        }
+        let fin = b0 & 0x80 != 0;
+        let opcode = b0 & 0x0F;
+
+        let (len, header_len) = match b1 & 0x7F {
+            126 => {
+                self.fill(4).await?;
+                (u16::from_be_bytes([self.buf[2], self.buf[3]]) as u64, 4)
+            }
+            127 => {
+                self.fill(10).await?;
+                let mut len = [0u8; 8];
+                len.copy_from_slice(&self.buf[2..10]);
+                (u64::from_be_bytes(len), 10)
+            }
+            len => (len as u64, 2),
+        };
+
+        if opcode & 0x08 != 0 && (!fin || len > 125) {
+            return Err(protocol_error("invalid control frame"));
+        }
+        if len > self.max_message_size as u64 {
+            return Err(protocol_error("message exceeds the maximum size"));
+        }
+
+        let len = len as usize;
+        self.fill(header_len + 4 + len).await?;
+        self.buf.advance(header_len);
+        let mask = [self.buf[0], self.buf[1], self.buf[2], self.buf[3]];
+        self.buf.advance(4);
+
+        let mut payload = self.buf.split_to(len);
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+
+        Ok(Some(Frame {
+            fin,
+            opcode,
+            payload: payload.freeze(),
+        }))
+    }
+
+    async fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 10);
+        frame.push(0x80 | opcode);
+        match payload.len() {
+            len @ 0..=125 => frame.push(len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        self.io.write_all(&frame).await?;
+        self.io.flush().await
+    }
+}
+
+fn into_message(opcode: u8, payload: Bytes) -> io::Result<Message> {
+    match opcode {
+        OP_TEXT => String::from_utf8(payload.to_vec())
+            .map(Message::Text)
+            .map_err(|_| protocol_error("text message is not valid utf-8")),
+        _ => Ok(Message::Binary(payload)),
+    }
+}
+
+fn parse_close(payload: Bytes) -> io::Result<Option<CloseFrame>> {
+    match payload.len() {
+        0 => Ok(None),
+        1 => Err(protocol_error("invalid close frame")),
+        _ => {
+            let code = u16::from_be_bytes([payload[0], payload[1]]);
+            let reason = std::str::from_utf8(&payload[2..])
+                .map_err(|_| protocol_error("close reason is not valid utf-8"))?;
+            Ok(Some(CloseFrame {
+                code,
+                reason: reason.to_string(),
+            }))
+        }
+    }
+}
+
+fn protocol_error(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    use super::*;
+    use crate::{ServiceError, service_fn, testing::TestClient, testing::TestRequestExt};
+
+    // A client frame, masked as clients must.
+    fn frame(b0: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut out = vec![b0];
+        match payload.len() {
+            len @ 0..=125 => out.push(0x80 | len as u8),
+            len @ 126..=0xFFFF => {
+                out.push(0x80 | 126);
+                out.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                out.push(0x80 | 127);
+                out.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        out.extend_from_slice(&mask);
+        out.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        out
+    }
+
+    fn socket(max_message_size: usize) -> (WebSocket, DuplexStream) {
+        let (client, server) = tokio::io::duplex(1 << 20);
+        (WebSocket::new(Box::new(server), max_message_size), client)
+    }
+
+    async fn recv_after(bytes: &[u8]) -> io::Result<Option<Message>> {
+        let (mut ws, mut client) = socket(1024);
+        client.write_all(bytes).await.unwrap();
+        drop(client);
+        ws.recv().await
+    }
+
+    async fn read_exact(client: &mut DuplexStream, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        client.read_exact(&mut buf).await.unwrap();
+        buf
+    }
+
+    #[tokio::test]
+    async fn reads_messages_of_every_length_encoding() {
+        for len in [0, 125, 126, 1000, 0xFFFF, 0x10000] {
+            let (mut ws, mut client) = socket(1 << 20);
+            let payload = vec![b'x'; len];
+            let bytes = frame(0x82, &payload);
+            // Written in pieces, so headers and payloads straddle reads.
+            let writer = tokio::spawn(async move {
+                for piece in bytes.chunks(7) {
+                    client.write_all(piece).await.unwrap();
+                }
+                client
+            });
+            let msg = ws.recv().await.unwrap().unwrap();
+            assert_eq!(msg, Message::Binary(Bytes::from(payload)), "length {len}");
+            writer.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn reassembles_fragments_around_control_frames() {
+        let (mut ws, mut client) = socket(1024);
+        let mut bytes = frame(0x01, "héllo, ".as_bytes());
+        bytes.extend(frame(0x89, b"are you there"));
+        bytes.extend(frame(0x00, b"wor"));
+        bytes.extend(frame(0x80, b"ld"));
+        client.write_all(&bytes).await.unwrap();
+        assert_eq!(
+            ws.recv().await.unwrap(),
+            Some(Message::Ping(Bytes::from("are you there")))
+        );
+        assert_eq!(
+            read_exact(&mut client, 15).await,
+            [&[0x8A, 13][..], b"are you there"].concat()
+        );
+        assert_eq!(
+            ws.recv().await.unwrap(),
+            Some(Message::Text("héllo, world".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn closing_handshake() {
+        let (mut ws, mut client) = socket(1024);
+        client
+            .write_all(&frame(0x88, &[&1001u16.to_be_bytes()[..], b"bye"].concat()))
+            .await
+            .unwrap();
+        let close = CloseFrame {
+            code: 1001,
+            reason: "bye".into(),
+        };
+        assert_eq!(ws.recv().await.unwrap(), Some(Message::Close(Some(close))));
+        assert_eq!(read_exact(&mut client, 4).await, [0x88, 2, 0x03, 0xE9]);
+        assert_eq!(ws.recv().await.unwrap(), None);
+        let e = ws.send(Message::Text("late".into())).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[tokio::test]
+    async fn writes_unmasked_frames() {
+        let (mut ws, mut client) = socket(1024);
+        ws.send(Message::Text("hi".into())).await.unwrap();
+        assert_eq!(read_exact(&mut client, 4).await, [0x81, 2, b'h', b'i']);
+        ws.send(Message::Binary(Bytes::from(vec![7; 300])))
+            .await
+            .unwrap();
+        let header = read_exact(&mut client, 4).await;
+        assert_eq!(header, [0x82, 126, 0x01, 0x2C]);
+        assert_eq!(read_exact(&mut client, 300).await, vec![7; 300]);
+        ws.close(1000, "").await.unwrap();
+        assert_eq!(read_exact(&mut client, 4).await, [0x88, 2, 0x03, 0xE8]);
+    }
+
+    #[tokio::test]
+    async fn rejects_protocol_violations() {
+        let unmasked = [0x81, 0x02, b'h', b'i'];
+        let long_ping = frame(0x89, &[0; 126]);
+        let fragmented_ping = frame(0x09, b"x");
+        let stray_continuation = frame(0x80, b"x");
+        let interleaved = [frame(0x01, b"a"), frame(0x81, b"b")].concat();
+        let reserved = frame(0xC1, b"x");
+        let unknown = frame(0x83, b"x");
+        let bad_utf8 = frame(0x81, &[0xC3, 0x28]);
+        let bad_close = frame(0x88, &[3]);
+        let too_large = frame(0x82, &[0; 1025]);
+        let too_large_fragmented = [frame(0x02, &[0; 1000]), frame(0x80, &[0; 25])].concat();
+        for bytes in [
+            &unmasked[..],
+            &long_ping,
+            &fragmented_ping,
+            &stray_continuation,
+            &interleaved,
+            &reserved,
+            &unknown,
+            &bad_utf8,
+            &bad_close,
+            &too_large,
+            &too_large_fragmented,
+        ] {
+            let e = recv_after(bytes).await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{bytes:02x?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn end_of_stream() {
+        assert_eq!(recv_after(&[]).await.unwrap(), None);
+        let truncated = &frame(0x81, b"hello")[..5];
+        let e = recv_after(truncated).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    fn upgrade_request() -> Request {
+        let mut req = Request::test(Method::GET, "/ws");
+        let headers = req.headers_mut();
+        headers.insert(
+            header::CONNECTION,
+            HeaderValue::from_static("keep-alive, Upgrade"),
+        );
+        headers.insert(header::UPGRADE, HeaderValue::from_static("websocket"));
+        headers.insert(
+            header::SEC_WEBSOCKET_VERSION,
+            HeaderValue::from_static("13"),
+        );
+        headers.insert(
+            header::SEC_WEBSOCKET_KEY,
+            HeaderValue::from_static("dGhlIHNhbXBsZSBub25jZQ=="),
+        );
+        req
+    }
+
+    #[test]
+    fn handshake_validation() {
+        let mut req = upgrade_request();
+        assert!(WebSocketUpgrade::from_request(&mut req).is_ok());
+        let broken: [fn(&mut Request); 4] = [
+            |req| *req.method_mut() = Method::POST,
+            |req| {
+                req.headers_mut().remove(header::UPGRADE);
+            },
+            |req| {
+                req.headers_mut()
+                    .insert(header::SEC_WEBSOCKET_VERSION, HeaderValue::from_static("8"));
+            },
+            |req| {
+                req.headers_mut().remove(header::SEC_WEBSOCKET_KEY);
+            },
+        ];
+        for breakage in broken {
+            let mut req = upgrade_request();
+            breakage(&mut req);
+            assert!(matches!(
+                WebSocketUpgrade::from_request(&mut req),
+                Err(Error::WebSocketHandshake(_))
+            ));
+        }
+    }
+
+    // The accept value is the one from RFC 6455, section 1.3.
+    #[tokio::test]
+    async fn answers_the_handshake() {
+        let client = TestClient::new(service_fn(|mut req: Request| async move {
+            let upgrade = WebSocketUpgrade::from_request(&mut req)?;
+            assert_eq!(upgrade.requested_protocols(), ["chat", "superchat"]);
+            Ok::<_, ServiceError>(upgrade.with_protocol("chat").on_upgrade(|_| async {}))
+        }));
+        let resp = client
+            .get("/ws")
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .header("sec-websocket-protocol", "chat, superchat")
+            .send()
+            .await;
+        resp.assert_status(StatusCode::SWITCHING_PROTOCOLS)
+            .assert_header("sec-websocket-accept", "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+            .assert_header("sec-websocket-protocol", "chat");
+        client
+            .get("/ws")
+            .send()
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+    }
+}